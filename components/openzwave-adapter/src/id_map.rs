@@ -25,6 +25,19 @@ impl<Kind, Type> IdMap<Kind, Type> where Type: Eq + Clone, Kind: Clone {
         find_result.map(|&(ref id, _)| id.clone())
     }
 
+    pub fn items(&self) -> Vec<(TaxoId<Kind>, Type)> {
+        let guard = self.map.read().unwrap(); // we have bigger problems if we're poisoned
+        guard.clone()
+    }
+
+    /// Remove the entries whose Openzwave object matches `predicate`, returning their ids.
+    pub fn remove_matching<F>(&mut self, predicate: F) -> Vec<TaxoId<Kind>> where F: Fn(&Type) -> bool {
+        let mut guard = self.map.write().unwrap(); // we have bigger problems if we're poisoned
+        let (removed, kept) : (Vec<_>, Vec<_>) = guard.drain(..).partition(|&(_, ref item)| predicate(item));
+        *guard = kept;
+        removed.into_iter().map(|(id, _)| id).collect()
+    }
+
     pub fn find_ozw_from_taxo_id(&self, needle: &TaxoId<Kind>) -> Option<Type> {
         let guard = self.map.read().unwrap(); // we have bigger problems if we're poisoned
         let find_result = guard.iter().find(|&&(ref id, _)| id == needle);
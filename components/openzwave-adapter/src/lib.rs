@@ -1,3 +1,4 @@
+extern crate chrono;
 extern crate openzwave_stateful as openzwave;
 extern crate foxbox_taxonomy as taxonomy;
 extern crate transformable_channels;
@@ -19,6 +20,8 @@ use openzwave::{ ConfigPath, InitOptions, ZWaveManager, ZWaveNotification };
 use openzwave::{ CommandClass, ValueGenre, ValueType, ValueID };
 use openzwave::{ Controller, Node };
 
+use chrono::UTC;
+
 use std::error;
 use std::fmt;
 use std::{ fs, io };
@@ -27,6 +30,7 @@ use std::thread;
 use std::sync::mpsc;
 use std::sync::{ Arc, Mutex };
 use std::collections::HashMap;
+use std::time::Duration;
 
 use id_map::IdMap;
 use watchers::Watchers;
@@ -121,13 +125,116 @@ impl RangeChecker for Range {
     }
 }
 
+const ADAPTER_NAME: &'static str = "OpenZwave Adapter";
+const ADAPTER_VENDOR: &'static str = "Mozilla";
+
+// Indexes of the values of the SensorMultilevel command class, i.e. the type of sensor.
+const SENSOR_TEMPERATURE: u8 = 1;
+const SENSOR_LUMINANCE: u8 = 3;
+const SENSOR_HUMIDITY: u8 = 5;
+
+// SwitchMultilevel levels are in [0, 99], 99 being fully on.
+const MAX_LEVEL: f64 = 99.;
+
+// Openzwave doesn't notify us when a node goes to sleep or is marked as dead, so we check
+// regularly.
+const NODE_STATUS_INTERVAL_SECS: u64 = 60;
+
+// Openzwave doesn't notify us either when the controller gives up on an inclusion or an
+// exclusion, so we stop reporting it after this long.
+const CONTROLLER_COMMAND_TIMEOUT_SECS: u64 = 60;
+
+/// Numeric values, exposed as extension channels.
+///
+/// Except for `Level`, values are passed as reported by the device: temperatures in its
+/// unit, luminance in lux or percent, humidity and battery level in percent, power in W
+/// and energy in kWh.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum NumericKind {
+    /// The level of a dimmer, in [0, 1].
+    Level,
+    Temperature,
+    Luminance,
+    Humidity,
+    Power,
+    Energy,
+    BatteryLevel,
+}
+
+impl NumericKind {
+    fn from_ozw_vid(vid: &ValueID) -> Option<Self> {
+        match (vid.get_type(), vid.get_command_class(), vid.get_index()) {
+            (ValueType::ValueType_Byte,    Some(CommandClass::SwitchMultilevel), 0)                  => Some(NumericKind::Level),
+            (ValueType::ValueType_Decimal, Some(CommandClass::SensorMultilevel), SENSOR_TEMPERATURE) => Some(NumericKind::Temperature),
+            (ValueType::ValueType_Decimal, Some(CommandClass::SensorMultilevel), SENSOR_LUMINANCE)   => Some(NumericKind::Luminance),
+            (ValueType::ValueType_Decimal, Some(CommandClass::SensorMultilevel), SENSOR_HUMIDITY)    => Some(NumericKind::Humidity),
+            // Meter indexes depend on the type of meter, but units don't.
+            (ValueType::ValueType_Decimal, Some(CommandClass::Meter), _) => {
+                match &*vid.get_units() {
+                    "W"   => Some(NumericKind::Power),
+                    "kWh" => Some(NumericKind::Energy),
+                    _     => None
+                }
+            }
+            (ValueType::ValueType_Byte,    Some(CommandClass::Battery), 0) => Some(NumericKind::BatteryLevel),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            NumericKind::Level        => "Level",
+            NumericKind::Temperature  => "Temperature",
+            NumericKind::Luminance    => "Luminance",
+            NumericKind::Humidity     => "Humidity",
+            NumericKind::Power        => "Power",
+            NumericKind::Energy       => "Energy",
+            NumericKind::BatteryLevel => "BatteryLevel",
+        }
+    }
+
+    fn get_type(&self) -> Type {
+        match *self {
+            NumericKind::Temperature => Type::Temperature,
+            _ => Type::ExtNumeric
+        }
+    }
+
+    fn channel_kind(&self) -> ChannelKind {
+        ChannelKind::Extension {
+            vendor: TaxoId::new(ADAPTER_VENDOR),
+            adapter: TaxoId::new(ADAPTER_NAME),
+            kind: TaxoId::new(self.name()),
+            typ: self.get_type(),
+        }
+    }
+
+    fn as_taxo_value(&self, vid: &ValueID, value: f64) -> Value {
+        match *self {
+            NumericKind::Temperature if vid.get_units() == "F" => Value::Temperature(Temperature::F(value)),
+            NumericKind::Temperature => Value::Temperature(Temperature::C(value)),
+            NumericKind::Level => self.as_ext_value(value / MAX_LEVEL),
+            _ => self.as_ext_value(value),
+        }
+    }
+
+    fn as_ext_value(&self, value: f64) -> Value {
+        Value::ExtNumeric(ExtValue {
+            value: value,
+            vendor: TaxoId::new(ADAPTER_VENDOR),
+            adapter: TaxoId::new(ADAPTER_NAME),
+            kind: TaxoId::new(self.name()),
+        })
+    }
+}
+
 fn taxo_kind_from_ozw_vid(vid: &ValueID) -> Option<ChannelKind> {
     match (vid.get_type(), vid.get_command_class(), vid.get_index()) {
         (ValueType::ValueType_Bool, Some(CommandClass::DoorLock),     0) => Some(ChannelKind::DoorLocked),
         (ValueType::ValueType_Bool, Some(CommandClass::SensorBinary), _) => Some(ChannelKind::OpenClosed),
-        // (ValueType::ValueType_Bool, Some(_)) => Some(ChannelKind::OnOff), TODO Find a proper type
+        (ValueType::ValueType_Bool, Some(CommandClass::SwitchBinary), 0) => Some(ChannelKind::LightOn),
         // Unrecognized command class or type - we don't know what to do with it.
-        _ => None
+        _ => NumericKind::from_ozw_vid(vid).map(|kind| kind.channel_kind())
     }
 }
 
@@ -140,7 +247,7 @@ fn ozw_vid_as_taxo_value(vid: &ValueID) -> Option<Value> {
         ValueType::ValueType_Bool => {
             if let Ok(value) = vid.as_bool() {
                 match taxo_kind_from_ozw_vid(vid) {
-                    // Some(ChannelKind::OnOff)  => Some(Value::OnOff(if value {OnOff::On} else {OnOff::Off})), // TODO support switches
+                    Some(ChannelKind::LightOn)  => Some(Value::OnOff(if value {OnOff::On} else {OnOff::Off})),
                     Some(ChannelKind::OpenClosed)  => Some(Value::OpenClosed(if value {OpenClosed::Open} else {OpenClosed::Closed})),
                     Some(ChannelKind::DoorLocked)  => Some(Value::DoorLocked(if value {DoorLocked::Locked} else {DoorLocked::Unlocked})),
                     _ => None,
@@ -149,6 +256,18 @@ fn ozw_vid_as_taxo_value(vid: &ValueID) -> Option<Value> {
                 None
             }
         },
+        ValueType::ValueType_Byte => {
+            match (NumericKind::from_ozw_vid(vid), vid.as_byte()) {
+                (Some(kind), Ok(value)) => Some(kind.as_taxo_value(vid, value as f64)),
+                _ => None
+            }
+        },
+        ValueType::ValueType_Decimal => {
+            match (NumericKind::from_ozw_vid(vid), vid.as_float()) {
+                (Some(kind), Ok(value)) => Some(kind.as_taxo_value(vid, value as f64)),
+                _ => None
+            }
+        },
         _ => None,   // TODO: Support more ValueType's
     }
 }
@@ -161,12 +280,21 @@ fn set_ozw_vid_from_taxo_value(vid: &ValueID, value: Value) -> Result<(), TaxoEr
     let result = match vid.get_type() {
         ValueType::ValueType_Bool => {
             match value {
-                //Value::OnOff(onOff) => { vid.set_bool(onOff == OnOff::On) } // TODO support switches
+                Value::OnOff(on_off) => { vid.set_bool(on_off == OnOff::On) }
                 Value::OpenClosed(open_closed) => { vid.set_bool(open_closed == OpenClosed::Open) }
                 Value::DoorLocked(locked_unlocked) => { vid.set_bool(locked_unlocked == DoorLocked::Locked) }
                 _ => { return Err(TaxoError::InvalidValue(value)) } // TODO InvalidType would be better but we'll need to fix specific types for specific TaxoIds
             }
         }
+        ValueType::ValueType_Byte => {
+            match (NumericKind::from_ozw_vid(vid), value) {
+                (Some(NumericKind::Level), Value::ExtNumeric(ref level)) => {
+                    let level = level.value.max(0.).min(1.);
+                    vid.set_byte((level * MAX_LEVEL).round() as u8)
+                }
+                (_, value) => { return Err(TaxoError::InvalidValue(value)) }
+            }
+        }
         _ => { return Err(TaxoError::InternalError(InternalError::GenericError(format!("Unsupported OZW type: {:?}", vid.get_type())))) }
     };
 
@@ -188,8 +316,155 @@ fn start_including(ozw: &ZWaveManager, home_id: u32, value: &Value) -> Result<()
     }
 }
 
+fn expect_unit(value: &Value) -> Result<(), TaxoError> {
+    match *value {
+        Value::Unit => Ok(()),
+        _ => Err(TaxoError::TypeError(TypeError { expected: Type::Unit, got: value.get_type() }))
+    }
+}
+
+fn start_excluding(ozw: &ZWaveManager, home_id: u32, value: &Value) -> Result<(), TaxoError> {
+    try!(expect_unit(value));
+    try!(
+        ozw.remove_node(home_id)
+            .map_err(|e| TaxoError::InternalError(InternalError::GenericError(format!("Error while excluding node on network {}: {}", home_id, e))))
+    );
+    info!("[OpenZWaveAdapter] Controller on network {} is awaiting an exclude, please do the appropriate steps to exclude a device.", home_id);
+    Ok(())
+}
+
+fn cancel_command(ozw: &ZWaveManager, home_id: u32, value: &Value) -> Result<(), TaxoError> {
+    try!(expect_unit(value));
+    try!(
+        ozw.cancel_controller_command(home_id)
+            .map_err(|e| TaxoError::InternalError(InternalError::GenericError(format!("Error while cancelling the command on network {}: {}", home_id, e))))
+    );
+    info!("[OpenZWaveAdapter] Cancelled the pending command of the controller on network {}.", home_id);
+    Ok(())
+}
+
+fn heal_network(ozw: &ZWaveManager, home_id: u32, value: &Value) -> Result<(), TaxoError> {
+    try!(expect_unit(value));
+    // Also update the return routes, so that nodes can reach the controller.
+    try!(
+        ozw.heal_network(home_id, true)
+            .map_err(|e| TaxoError::InternalError(InternalError::GenericError(format!("Error while healing network {}: {}", home_id, e))))
+    );
+    info!("[OpenZWaveAdapter] Healing network {}.", home_id);
+    Ok(())
+}
+
+/// The state of a controller, as far as the commands sent through the adapter are concerned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ControllerState {
+    Idle,
+    Including,
+    Excluding,
+}
+
+impl ControllerState {
+    fn as_taxo_value(&self) -> Value {
+        let state = match *self {
+            ControllerState::Idle => "idle",
+            ControllerState::Including => "including",
+            ControllerState::Excluding => "excluding",
+        };
+        Value::String(Arc::new(state.to_owned()))
+    }
+}
+
+fn node_status_as_taxo_value(node: &Node) -> Value {
+    let status = if node.is_failed() {
+        "dead"
+    } else if !node.is_listening_device() && !node.is_awake() {
+        "sleeping"
+    } else {
+        "alive"
+    };
+    Value::String(Arc::new(status.to_owned()))
+}
+
+fn adapter_kind(kind: &str, typ: Type) -> ChannelKind {
+    ChannelKind::Extension {
+        vendor: TaxoId::new(ADAPTER_VENDOR),
+        adapter: TaxoId::new(ADAPTER_NAME),
+        kind: TaxoId::new(kind),
+        typ: typ,
+    }
+}
+
+fn controller_state_id(home_id: u32) -> TaxoId<Channel> {
+    TaxoId::new(&format!("OpenZWave-controller-{:08x}-state", home_id))
+}
+
+fn node_status_id(node: &Node) -> TaxoId<Channel> {
+    TaxoId::new(&format!("OpenZWave-{:08x}-{:02x}-status", node.get_home_id(), node.get_id()))
+}
+
+fn node_last_seen_id(node: &Node) -> TaxoId<Channel> {
+    TaxoId::new(&format!("OpenZWave-{:08x}-{:02x}-last-seen", node.get_home_id(), node.get_id()))
+}
+
 type ValueCache = HashMap<TaxoId<Channel>, Value>;
 
+/// Record the new value of a channel, and send the relevant events to its watchers.
+fn update_value(watchers: &Mutex<Watchers>, value_cache: &Mutex<ValueCache>, taxo_id: &TaxoId<Channel>, taxo_value: Value) {
+    let previous_value = {
+        let mut cache = value_cache.lock().unwrap();
+        let previous = cache.get(taxo_id).cloned();
+        cache.insert(taxo_id.clone(), taxo_value.clone());
+        previous
+    };
+
+    let watchers = watchers.lock().unwrap();
+
+    let watchers = match watchers.get_from_taxo_id(taxo_id) {
+        Some(watchers) => watchers,
+        _ => return
+    };
+
+    for &(ref range, ref sender) in &watchers {
+        debug!("[OpenzwaveAdapter::update_value] Iterating over watcher {:?} {:?}", taxo_id, range);
+
+        let should_send_value = range.should_send(&taxo_value, EventType::Enter);
+
+        if let Some(ref previous_value) = previous_value {
+            let should_send_previous = range.should_send(previous_value, EventType::Exit);
+            // If the new and the old values are both in the same range, we
+            // need to send nothing.
+            if should_send_value && should_send_previous { continue }
+
+            if should_send_previous {
+                debug!("[OpenzwaveAdapter::update_value] Sending event Exit {:?} {:?}", taxo_id, taxo_value);
+                let sender = sender.lock().unwrap();
+                sender.send(
+                    WatchEvent::Exit { id: taxo_id.clone(), value: taxo_value.clone() }
+                ).unwrap_or_else(|_| {
+                    error!("Couldn't send the exit event {{ id: {:?}, value: {:?} }}", taxo_id, taxo_value);
+                });
+            }
+        }
+
+        if should_send_value {
+            debug!("[OpenzwaveAdapter::update_value] Sending event Enter {:?} {:?}", taxo_id, taxo_value);
+            let sender = sender.lock().unwrap();
+            sender.send(
+                WatchEvent::Enter { id: taxo_id.clone(), value: taxo_value.clone() }
+            ).unwrap_or_else(|_| {
+                error!("Couldn't send the enter event {{ id: {:?}, value: {:?} }}", taxo_id, taxo_value);
+            });
+        }
+    }
+}
+
+/// Like `update_value`, but only if the value changed.
+fn update_value_if_changed(watchers: &Mutex<Watchers>, value_cache: &Mutex<ValueCache>, taxo_id: &TaxoId<Channel>, taxo_value: Value) {
+    if value_cache.lock().unwrap().get(taxo_id) == Some(&taxo_value) {
+        return;
+    }
+    update_value(watchers, value_cache, taxo_id, taxo_value);
+}
+
 pub struct OpenzwaveAdapter {
     id: TaxoId<AdapterId>,
     name: String,
@@ -203,6 +478,14 @@ pub struct OpenzwaveAdapter {
     value_cache: Arc<Mutex<ValueCache>>,
     controller_map: IdMap<ServiceId, Controller>,
     include_map: IdMap<Channel, Controller>,
+    exclude_map: IdMap<Channel, Controller>,
+    cancel_map: IdMap<Channel, Controller>,
+    heal_map: IdMap<Channel, Controller>,
+    controller_state_map: IdMap<Channel, Controller>,
+    node_status_map: IdMap<Channel, Node>,
+    /// For each network, the number of commands sent to its controller so far, so that
+    /// timeouts only reset the state of the latest command.
+    controller_commands: Arc<Mutex<HashMap<u32, usize>>>,
 }
 
 fn ensure_directory<T: AsRef<Path> + ?Sized>(directory: &T) -> Result<(), Error> {
@@ -246,11 +529,11 @@ impl OpenzwaveAdapter {
             result => result
         });
 
-        let name = String::from("OpenZwave Adapter");
+        let name = String::from(ADAPTER_NAME);
         let adapter = Arc::new(OpenzwaveAdapter {
             id: TaxoId::new(&name),
             name: name,
-            vendor: String::from("Mozilla"),
+            vendor: String::from(ADAPTER_VENDOR),
             version: [1, 0, 0, 0],
             ozw: ozw,
             node_map: IdMap::new(),
@@ -260,10 +543,17 @@ impl OpenzwaveAdapter {
             value_cache: Arc::new(Mutex::new(HashMap::new())),
             controller_map: IdMap::new(),
             include_map: IdMap::new(),
+            exclude_map: IdMap::new(),
+            cancel_map: IdMap::new(),
+            heal_map: IdMap::new(),
+            controller_state_map: IdMap::new(),
+            node_status_map: IdMap::new(),
+            controller_commands: Arc::new(Mutex::new(HashMap::new())),
         });
 
         try!(box_manager.add_adapter(adapter.clone()));
         adapter.spawn_notification_thread(rx, box_manager);
+        adapter.spawn_node_status_thread();
 
         info!("[OpenzwaveAdapter] Started.");
 
@@ -278,6 +568,11 @@ impl OpenzwaveAdapter {
         let mut setter_map = self.setter_map.clone();
         let mut controller_map = self.controller_map.clone();
         let mut include_map = self.include_map.clone();
+        let mut exclude_map = self.exclude_map.clone();
+        let mut cancel_map = self.cancel_map.clone();
+        let mut heal_map = self.heal_map.clone();
+        let mut controller_state_map = self.controller_state_map.clone();
+        let mut node_status_map = self.node_status_map.clone();
 
         let watchers = self.watchers.clone();
         let value_cache = self.value_cache.clone();
@@ -310,6 +605,35 @@ impl OpenzwaveAdapter {
                         }).unwrap_or_else(|e| {
                             error!("Couldn't add the setter {}: {}", include_setter_id, e);
                         });
+
+                        let commands = vec![("exclude", ChannelKind::ZwaveExclude, &mut exclude_map),
+                                            ("cancel", ChannelKind::ZwaveCancel, &mut cancel_map),
+                                            ("heal", ChannelKind::ZwaveHeal, &mut heal_map)];
+                        for (suffix, kind, map) in commands {
+                            let setter_id = TaxoId::new(&format!("OpenZWave-controller-{:08x}-{}", home_id, suffix));
+                            map.push(setter_id.clone(), controller);
+
+                            box_manager.add_channel(Channel {
+                                kind: kind,
+                                supports_send: true,
+                                ..Channel::empty(&setter_id, &service_id, &adapter_id)
+                            }).unwrap_or_else(|e| {
+                                error!("Couldn't add the setter {}: {}", setter_id, e);
+                            });
+                        }
+
+                        let state_getter_id = controller_state_id(home_id);
+                        controller_state_map.push(state_getter_id.clone(), controller);
+                        update_value(&watchers, &value_cache, &state_getter_id, ControllerState::Idle.as_taxo_value());
+
+                        box_manager.add_channel(Channel {
+                            kind: ChannelKind::ZwaveControllerState,
+                            supports_fetch: true,
+                            supports_watch: true,
+                            ..Channel::empty(&state_getter_id, &service_id, &adapter_id)
+                        }).unwrap_or_else(|e| {
+                            error!("Couldn't add the getter {}: {}", state_getter_id, e);
+                        });
                     }
                     ZWaveNotification::NodeNew(_node)               => {}
                     ZWaveNotification::NodeAdded(node)              => {
@@ -326,13 +650,62 @@ impl OpenzwaveAdapter {
                         box_manager.add_service(service).unwrap_or_else(|e| {
                             error!("Couldn't add the service {}: {}", service_name, e);
                         });
+
+                        // The status is one of "alive", "dead" and "sleeping".
+                        let status_getter_id = node_status_id(&node);
+                        node_status_map.push(status_getter_id.clone(), node);
+                        update_value(&watchers, &value_cache, &status_getter_id, node_status_as_taxo_value(&node));
+
+                        box_manager.add_channel(Channel {
+                            kind: adapter_kind("ZwaveNodeStatus", Type::String),
+                            supports_fetch: true,
+                            supports_watch: true,
+                            ..Channel::empty(&status_getter_id, &service_id, &adapter_id)
+                        }).unwrap_or_else(|e| {
+                            error!("Couldn't add the getter {}: {}", status_getter_id, e);
+                        });
+
+                        // The last time a value of the node changed, unknown until it does.
+                        let last_seen_getter_id = node_last_seen_id(&node);
+                        node_status_map.push(last_seen_getter_id.clone(), node);
+
+                        box_manager.add_channel(Channel {
+                            kind: adapter_kind("ZwaveLastSeen", Type::TimeStamp),
+                            supports_fetch: true,
+                            supports_watch: true,
+                            ..Channel::empty(&last_seen_getter_id, &service_id, &adapter_id)
+                        }).unwrap_or_else(|e| {
+                            error!("Couldn't add the getter {}: {}", last_seen_getter_id, e);
+                        });
+
+                        // Including a node ends the command.
+                        update_value_if_changed(&watchers, &value_cache, &controller_state_id(node.get_home_id()),
+                            ControllerState::Idle.as_taxo_value());
                     }
                     ZWaveNotification::NodeNaming(_node)             => {
                         // unfortunately we can't change a service' properties :(
                         // https://github.com/fxbox/taxonomy/issues/97
                         // When it's done we can move the properties change from above to here.
                     }
-                    ZWaveNotification::NodeRemoved(_node)           => {}
+                    ZWaveNotification::NodeRemoved(node)            => {
+                        // Forget everything about the node, so that we stop checking its status.
+                        for id in node_status_map.remove_matching(|item| *item == node) {
+                            value_cache.lock().unwrap().remove(&id);
+                        }
+                        for id in getter_map.remove_matching(|vid| vid.get_node() == node) {
+                            value_cache.lock().unwrap().remove(&id);
+                        }
+                        setter_map.remove_matching(|vid| vid.get_node() == node);
+                        for service_id in node_map.remove_matching(|item| *item == node) {
+                            box_manager.remove_service(&service_id).unwrap_or_else(|e| {
+                                error!("Couldn't remove the service {}: {}", service_id, e);
+                            });
+                        }
+
+                        // Excluding a node ends the command.
+                        update_value_if_changed(&watchers, &value_cache, &controller_state_id(node.get_home_id()),
+                            ControllerState::Idle.as_taxo_value());
+                    }
                     ZWaveNotification::ValueAdded(vid)              => {
                         if vid.get_genre() != ValueGenre::ValueGenre_User { continue }
 
@@ -372,10 +745,13 @@ impl OpenzwaveAdapter {
                         }
                     }
                     ZWaveNotification::ValueChanged(vid)          => {
-                        match vid.get_type() {
-                            ValueType::ValueType_Bool => {},
-                            _ => continue // ignore non-bool vals for now
-                        };
+                        let node = vid.get_node();
+                        if node_map.find_taxo_id_from_ozw(&node).is_some() {
+                            update_value(&watchers, &value_cache, &node_last_seen_id(&node),
+                                Value::TimeStamp(TimeStamp::from_datetime(UTC::now())));
+                            update_value_if_changed(&watchers, &value_cache, &node_status_id(&node),
+                                node_status_as_taxo_value(&node));
+                        }
 
                         let taxo_id = match getter_map.find_taxo_id_from_ozw(&vid) {
                             Some(taxo_id) => taxo_id,
@@ -387,52 +763,7 @@ impl OpenzwaveAdapter {
                             _ => continue
                         };
 
-                        let watchers = watchers.lock().unwrap();
-
-                        let watchers = match watchers.get_from_taxo_id(&taxo_id) {
-                            Some(watchers) => watchers,
-                            _ => continue
-                        };
-
-                        let previous_value = {
-                            let mut cache = value_cache.lock().unwrap();
-                            let previous = cache.get(&taxo_id).cloned();
-                            cache.insert(taxo_id.clone(), taxo_value.clone());
-                            previous
-                        };
-
-                        for &(ref range, ref sender) in &watchers {
-                            debug!("[OpenzwaveAdapter::ValueChanged] Iterating over watcher {:?} {:?}", taxo_id, range);
-
-                            let should_send_value = range.should_send(&taxo_value, EventType::Enter);
-
-                            if let Some(ref previous_value) = previous_value {
-                                let should_send_previous = range.should_send(previous_value, EventType::Exit);
-                                // If the new and the old values are both in the same range, we
-                                // need to send nothing.
-                                if should_send_value && should_send_previous { continue }
-
-                                if should_send_previous {
-                                    debug!("Openzwave::Adapter::ValueChanged Sending event Exit {:?} {:?}", taxo_id, taxo_value);
-                                    let sender = sender.lock().unwrap();
-                                    sender.send(
-                                        WatchEvent::Exit { id: taxo_id.clone(), value: taxo_value.clone() }
-                                    ).unwrap_or_else(|_| {
-                                        error!("Couldn't send the exit event {{ id: {:?}, value: {:?} }}", taxo_id, taxo_value);
-                                    });
-                                }
-                            }
-
-                            if should_send_value {
-                                debug!("[OpenzwaveAdapter::ValueChanged] Sending event Enter {:?} {:?}", taxo_id, taxo_value);
-                                let sender = sender.lock().unwrap();
-                                sender.send(
-                                    WatchEvent::Enter { id: taxo_id.clone(), value: taxo_value.clone() }
-                                ).unwrap_or_else(|_| {
-                                    error!("Couldn't send the enter event {{ id: {:?}, value: {:?} }}", taxo_id, taxo_value);
-                                });
-                            }
-                        }
+                        update_value(&watchers, &value_cache, &taxo_id, taxo_value);
                     }
                     ZWaveNotification::ValueRemoved(_value)         => {}
                     ZWaveNotification::AwakeNodesQueried(ref controller) | ZWaveNotification::AllNodesQueried(ref controller) => {
@@ -445,6 +776,56 @@ impl OpenzwaveAdapter {
             }
         });
     }
+
+    /// Whether `id` is a channel reporting the state of a controller or the status of a node,
+    /// whose value is maintained by the adapter rather than Openzwave.
+    fn is_status_channel(&self, id: &TaxoId<Channel>) -> bool {
+        self.controller_state_map.find_ozw_from_taxo_id(id).is_some() ||
+            self.node_status_map.find_ozw_from_taxo_id(id).is_some()
+    }
+
+    fn set_controller_state(&self, home_id: u32, state: ControllerState) {
+        update_value_if_changed(&self.watchers, &self.value_cache, &controller_state_id(home_id),
+            state.as_taxo_value());
+
+        let command = {
+            let mut commands = self.controller_commands.lock().unwrap();
+            let command = commands.entry(home_id).or_insert(0);
+            *command += 1;
+            *command
+        };
+        if state == ControllerState::Idle {
+            return;
+        }
+
+        let controller_commands = self.controller_commands.clone();
+        let watchers = self.watchers.clone();
+        let value_cache = self.value_cache.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(CONTROLLER_COMMAND_TIMEOUT_SECS));
+            if controller_commands.lock().unwrap().get(&home_id) == Some(&command) {
+                info!("[OpenZWaveAdapter] Assuming that the controller on network {} gave up the pending command.", home_id);
+                update_value_if_changed(&watchers, &value_cache, &controller_state_id(home_id),
+                    ControllerState::Idle.as_taxo_value());
+            }
+        });
+    }
+
+    fn spawn_node_status_thread(&self) {
+        let node_map = self.node_map.clone();
+        let watchers = self.watchers.clone();
+        let value_cache = self.value_cache.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(NODE_STATUS_INTERVAL_SECS));
+                for (_, node) in node_map.items() {
+                    update_value_if_changed(&watchers, &value_cache, &node_status_id(&node),
+                        node_status_as_taxo_value(&node));
+                }
+            }
+        });
+    }
 }
 
 impl taxonomy::adapter::Adapter for OpenzwaveAdapter {
@@ -466,6 +847,11 @@ impl taxonomy::adapter::Adapter for OpenzwaveAdapter {
 
     fn fetch_values(&self, mut set: Vec<TaxoId<Channel>>, _: User) -> ResultMap<TaxoId<Channel>, Option<Value>, TaxoError> {
         set.drain(..).map(|id| {
+            if self.is_status_channel(&id) {
+                let value = self.value_cache.lock().unwrap().get(&id).cloned();
+                return (id, Ok(value));
+            }
+
             let ozw_vid = self.getter_map.find_ozw_from_taxo_id(&id);
 
             let taxo_value: Option<Option<Value>> = ozw_vid.map(|ozw_vid: ValueID| {
@@ -483,7 +869,28 @@ impl taxonomy::adapter::Adapter for OpenzwaveAdapter {
             if let Some(ozw_vid) = self.setter_map.find_ozw_from_taxo_id(&id) {
                 (id, set_ozw_vid_from_taxo_value(&ozw_vid, value))
             } else if let Some(ozw_controller) = self.include_map.find_ozw_from_taxo_id(&id) {
-                (id, start_including(&self.ozw, ozw_controller.get_home_id(), &value))
+                let home_id = ozw_controller.get_home_id();
+                let result = start_including(&self.ozw, home_id, &value);
+                if result.is_ok() {
+                    self.set_controller_state(home_id, ControllerState::Including);
+                }
+                (id, result)
+            } else if let Some(ozw_controller) = self.exclude_map.find_ozw_from_taxo_id(&id) {
+                let home_id = ozw_controller.get_home_id();
+                let result = start_excluding(&self.ozw, home_id, &value);
+                if result.is_ok() {
+                    self.set_controller_state(home_id, ControllerState::Excluding);
+                }
+                (id, result)
+            } else if let Some(ozw_controller) = self.cancel_map.find_ozw_from_taxo_id(&id) {
+                let home_id = ozw_controller.get_home_id();
+                let result = cancel_command(&self.ozw, home_id, &value);
+                if result.is_ok() {
+                    self.set_controller_state(home_id, ControllerState::Idle);
+                }
+                (id, result)
+            } else if let Some(ozw_controller) = self.heal_map.find_ozw_from_taxo_id(&id) {
+                (id, heal_network(&self.ozw, ozw_controller.get_home_id(), &value))
             } else {
                 (id.clone(), Err(TaxoError::InternalError(InternalError::NoSuchChannel(id))))
             }
@@ -493,7 +900,8 @@ impl taxonomy::adapter::Adapter for OpenzwaveAdapter {
     fn register_watch(&self, mut values: Vec<(TaxoId<Channel>, Option<Value>, Box<ExtSender<WatchEvent<Value>>>)>) -> Vec<(TaxoId<Channel>, Result<Box<AdapterWatchGuard>, TaxoError>)> {
         debug!("[OpenzwaveAdapter::register_watch] Should register some watchers");
         values.drain(..).filter_map(|(id, range, sender)| {
-            if self.getter_map.find_ozw_from_taxo_id(&id).is_none() {
+            let is_status_channel = self.is_status_channel(&id);
+            if self.getter_map.find_ozw_from_taxo_id(&id).is_none() && !is_status_channel {
                 return Some((id.clone(), Err(TaxoError::OperationNotSupported(Operation::Watch, id))))
             }
 
@@ -515,21 +923,24 @@ impl taxonomy::adapter::Adapter for OpenzwaveAdapter {
             let value_result: Result<Box<AdapterWatchGuard>, TaxoError> = Ok(Box::new(watch_guard));
 
             // if there is a set value already, let's send it.
-            let ozw_value: Option<ValueID> = self.getter_map.find_ozw_from_taxo_id(&id);
-            if let Some(value) = ozw_value {
-                if value.is_set() && value.get_type() == ValueType::ValueType_Bool {
-                    if let Some(value) = ozw_vid_as_taxo_value(&value) {
-                        self.value_cache.lock().unwrap().insert(id.clone(), value.clone());
-                        if range.should_send(&value, EventType::Enter) {
-                            debug!("[OpenzwaveAdapter::register_watch] Sending event Enter {:?} {:?}", id, value);
-                            let sender = sender.lock().unwrap();
-                            sender.send(
-                                WatchEvent::Enter { id: id.clone(), value: value.clone() }
-                            ).unwrap_or_else(|_| {
-                                error!("Couldn't send the enter event {{ id: {:?}, value: {:?} }}", id, value);
-                            });
-                        }
-                    }
+            let current_value = if is_status_channel {
+                self.value_cache.lock().unwrap().get(&id).cloned()
+            } else {
+                let ozw_value: Option<ValueID> = self.getter_map.find_ozw_from_taxo_id(&id);
+                ozw_value.and_then(|value| {
+                    if value.is_set() { ozw_vid_as_taxo_value(&value) } else { None }
+                })
+            };
+            if let Some(value) = current_value {
+                self.value_cache.lock().unwrap().insert(id.clone(), value.clone());
+                if range.should_send(&value, EventType::Enter) {
+                    debug!("[OpenzwaveAdapter::register_watch] Sending event Enter {:?} {:?}", id, value);
+                    let sender = sender.lock().unwrap();
+                    sender.send(
+                        WatchEvent::Enter { id: id.clone(), value: value.clone() }
+                    ).unwrap_or_else(|_| {
+                        error!("Couldn't send the enter event {{ id: {:?}, value: {:?} }}", id, value);
+                    });
                 }
             }
 
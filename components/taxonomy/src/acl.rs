@@ -0,0 +1,461 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Access control lists, deciding which users may fetch, send or watch which channels.
+//!
+//! An `AclRule` grants a set of operations to a principal (a user, the members of a role or
+//! anyone) on the channels of a service or on the channels tagged with a tag, either directly
+//! or through the tags of their service.
+//!
+//! Channels that are not targeted by any rule are accessible to everyone. As soon as a channel
+//! is targeted by at least one rule, it becomes protected: only the operations explicitly
+//! granted to a user by one of these rules are allowed for this user. Anonymous requests
+//! (`User::None`) are only matched by rules granting operations to anyone.
+//!
+//! Once the role `admin` has at least one member, only members of `admin` may modify the
+//! rules and the roles. Until then, only the owners of the box may modify them, typically to
+//! set up the first admin.
+//!
+//! Rules and roles are stored in the same database as tags, so they survive reboots.
+
+use api::{ AccessDenied, Error, Operation, User };
+use parse::*;
+use services::*;
+
+use rusqlite::Connection;
+use serde_json;
+
+use std::collections::{ HashMap, HashSet };
+use std::path::PathBuf;
+
+/// The role whose members may modify the access control lists.
+pub static ADMIN_ROLE: &'static str = "admin";
+
+/// Determines whether a user owns the box.
+pub type OwnerCheck = Box<Fn(&User) -> bool + Send + Sync>;
+
+/// The users to whom a rule grants operations.
+///
+/// # JSON
+///
+/// One of `"anyone"`, `{"user": number}` or `{"role": string}`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Principal {
+    /// Every user, including anonymous requests.
+    Anyone,
+
+    /// A single user, by id.
+    User(i32),
+
+    /// All the members of a role.
+    Role(String),
+}
+
+impl Parser<Principal> for Principal {
+    fn description() -> String {
+        "Principal".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        if source.as_string() == Some("anyone") {
+            return Ok(Principal::Anyone);
+        }
+        if let Some(result) = path.push("user", |path| i32::take_opt(path, source, "user")) {
+            return result.map(Principal::User);
+        }
+        if let Some(result) = path.push("role", |path| String::take_opt(path, source, "role")) {
+            return result.map(Principal::Role);
+        }
+        Err(ParseError::type_error("Principal", &path, "\"anyone\", {\"user\"} or {\"role\"}"))
+    }
+}
+
+impl ToJSON for Principal {
+    fn to_json(&self) -> JSON {
+        match *self {
+            Principal::Anyone => JSON::String("anyone".to_owned()),
+            Principal::User(id) => vec![("user", JSON::I64(id as i64))].to_json(),
+            Principal::Role(ref role) => vec![("role", role.to_json())].to_json(),
+        }
+    }
+}
+
+/// The channels on which a rule grants operations.
+///
+/// # JSON
+///
+/// One of `{"service": string}` or `{"tag": string}`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AclTarget {
+    /// All the channels of a service.
+    Service(Id<ServiceId>),
+
+    /// All the channels tagged with a tag, or whose service is tagged with this tag.
+    Tag(Id<TagId>),
+}
+
+impl AclTarget {
+    fn matches(&self, service: &Id<ServiceId>, service_tags: &HashSet<Id<TagId>>, channel_tags: &HashSet<Id<TagId>>) -> bool {
+        match *self {
+            AclTarget::Service(ref id) => id == service,
+            AclTarget::Tag(ref tag) => service_tags.contains(tag) || channel_tags.contains(tag),
+        }
+    }
+}
+
+impl Parser<AclTarget> for AclTarget {
+    fn description() -> String {
+        "AclTarget".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        if let Some(result) = path.push("service", |path| Id::<ServiceId>::take_opt(path, source, "service")) {
+            return result.map(AclTarget::Service);
+        }
+        if let Some(result) = path.push("tag", |path| Id::<TagId>::take_opt(path, source, "tag")) {
+            return result.map(AclTarget::Tag);
+        }
+        Err(ParseError::type_error("AclTarget", &path, "{\"service\"} or {\"tag\"}"))
+    }
+}
+
+impl ToJSON for AclTarget {
+    fn to_json(&self) -> JSON {
+        match *self {
+            AclTarget::Service(ref id) => vec![("service", id.to_json())].to_json(),
+            AclTarget::Tag(ref tag) => vec![("tag", tag.to_json())].to_json(),
+        }
+    }
+}
+
+/// A rule granting operations on some channels to some users.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `principal` (`Principal`, required): the users to whom the operations are granted;
+/// - `target` (`AclTarget`, required): the channels on which the operations are granted;
+/// - `operations` (array of `"Fetch"`, `"Send"` or `"Watch"`, required).
+///
+/// ```
+/// use foxbox_taxonomy::acl::*;
+/// use foxbox_taxonomy::api::Operation;
+/// use foxbox_taxonomy::parse::*;
+///
+/// let source = r#"{
+///   "principal": {"role": "family"},
+///   "target": {"tag": "front door"},
+///   "operations": ["Fetch", "Send"]
+/// }"#;
+///
+/// let rule = AclRule::from_str(source).unwrap();
+/// assert_eq!(rule.principal, Principal::Role("family".to_owned()));
+/// assert_eq!(rule.operations, vec![Operation::Fetch, Operation::Send]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AclRule {
+    pub principal: Principal,
+    pub target: AclTarget,
+    pub operations: Vec<Operation>,
+}
+
+impl Parser<AclRule> for AclRule {
+    fn description() -> String {
+        "AclRule".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let principal = try!(path.push("principal", |path| Principal::take(path, source, "principal")));
+        let target = try!(path.push("target", |path| AclTarget::take(path, source, "target")));
+        let operations = try!(path.push("operations", |path| Vec::<Operation>::take(path, source, "operations")));
+        Ok(AclRule {
+            principal: principal,
+            target: target,
+            operations: operations,
+        })
+    }
+}
+
+impl ToJSON for AclRule {
+    fn to_json(&self) -> JSON {
+        vec![
+            ("principal", self.principal.to_json()),
+            ("target", self.target.to_json()),
+            ("operations", self.operations.to_json()),
+        ].to_json()
+    }
+}
+
+/// The members of a role.
+///
+/// # JSON
+///
+/// An object with fields `role` (string) and `users` (array of user ids).
+///
+/// ```
+/// use foxbox_taxonomy::acl::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// let role = AclRole::from_str(r#"{"role": "family", "users": [1, 2]}"#).unwrap();
+/// assert_eq!(role.users, vec![1, 2]);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct AclRole {
+    pub role: String,
+    pub users: Vec<i32>,
+}
+
+impl Parser<AclRole> for AclRole {
+    fn description() -> String {
+        "AclRole".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let role = try!(path.push("role", |path| String::take(path, source, "role")));
+        let users = try!(path.push("users", |path| Vec::<i32>::take(path, source, "users")));
+        Ok(AclRole {
+            role: role,
+            users: users,
+        })
+    }
+}
+
+impl ToJSON for AclRole {
+    fn to_json(&self) -> JSON {
+        vec![
+            ("role", self.role.to_json()),
+            ("users", JSON::Array(self.users.iter().map(|id| JSON::I64(*id as i64)).collect())),
+        ].to_json()
+    }
+}
+
+/// The access control lists, along with the roles.
+pub struct Acl {
+    rules: Vec<AclRule>,
+    roles: HashMap<String, HashSet<i32>>,
+
+    /// The database used to persist rules and roles, if any.
+    db_path: Option<PathBuf>,
+
+    /// Determines who may modify the rules and roles while the role `admin` has no members.
+    /// If `None`, nobody may.
+    is_owner: Option<OwnerCheck>,
+}
+
+impl Acl {
+    /// Create the access control lists, loading the rules and roles stored in the database.
+    pub fn new(db_path: Option<PathBuf>) -> Self {
+        let mut acl = Acl {
+            rules: vec![],
+            roles: HashMap::new(),
+            db_path: db_path,
+            is_owner: None,
+        };
+        if let Some(db) = acl.open_db() {
+            acl.load(&db);
+        }
+        acl
+    }
+
+    fn open_db(&self) -> Option<Connection> {
+        let path = match self.db_path {
+            None => return None,
+            Some(ref path) => path
+        };
+        let db = match Connection::open(path.clone()) {
+            Err(err) => {
+                error!("Unable to open taxonomy acl database: {}", err);
+                return None;
+            }
+            Ok(db) => db
+        };
+        let created = db.execute("CREATE TABLE IF NOT EXISTS acl_rules (
+                    rule   TEXT NOT NULL PRIMARY KEY
+            )", &[]).and_then(|_| db.execute("CREATE TABLE IF NOT EXISTS acl_roles (
+                    role   TEXT NOT NULL,
+                    user   INTEGER NOT NULL,
+                    PRIMARY KEY (role, user)
+            )", &[]));
+        if let Err(err) = created {
+            error!("Unable to create taxonomy acl database: {}", err);
+            return None;
+        }
+        Some(db)
+    }
+
+    fn load(&mut self, db: &Connection) {
+        let loaded = db.prepare("SELECT rule FROM acl_rules").and_then(|mut stmt| {
+            let mut rules = vec![];
+            for row in try!(stmt.query(&[])) {
+                let source : String = try!(row).get(0);
+                match AclRule::from_str(&source) {
+                    Ok(rule) => rules.push(rule),
+                    Err(err) => error!("Ignoring invalid acl rule {}: {:?}", source, err)
+                }
+            }
+            Ok(rules)
+        });
+        match loaded {
+            Ok(rules) => self.rules = rules,
+            Err(err) => error!("Unable to load acl rules: {}", err)
+        }
+
+        let loaded = db.prepare("SELECT role, user FROM acl_roles").and_then(|mut stmt| {
+            let mut roles = HashMap::new();
+            for row in try!(stmt.query(&[])) {
+                let row = try!(row);
+                roles.entry(row.get::<String>(0)).or_insert_with(HashSet::new).insert(row.get::<i32>(1));
+            }
+            Ok(roles)
+        });
+        match loaded {
+            Ok(roles) => self.roles = roles,
+            Err(err) => error!("Unable to load acl roles: {}", err)
+        }
+    }
+
+    fn is_member(&self, user: &User, role: &str) -> bool {
+        match (user, self.roles.get(role)) {
+            (&User::Id(ref id), Some(members)) => members.contains(id),
+            _ => false
+        }
+    }
+
+    fn principal_matches(&self, principal: &Principal, user: &User) -> bool {
+        match *principal {
+            Principal::Anyone => true,
+            Principal::User(id) => *user == User::Id(id),
+            Principal::Role(ref role) => self.is_member(user, role),
+        }
+    }
+
+    /// Determine whether `user` may perform `operation` on a channel of service `service`.
+    pub fn is_allowed(&self, user: &User, operation: &Operation, service: &Id<ServiceId>,
+        service_tags: &HashSet<Id<TagId>>, channel_tags: &HashSet<Id<TagId>>) -> bool
+    {
+        let mut is_protected = false;
+        for rule in &self.rules {
+            if !rule.target.matches(service, service_tags, channel_tags) {
+                continue;
+            }
+            is_protected = true;
+            if rule.operations.contains(operation) && self.principal_matches(&rule.principal, user) {
+                return true;
+            }
+        }
+        !is_protected
+    }
+
+    /// Set the check determining who owns the box, and may therefore modify the access control
+    /// lists while the role `admin` has no members.
+    pub fn set_owner_check(&mut self, is_owner: OwnerCheck) {
+        self.is_owner = Some(is_owner);
+    }
+
+    /// Determine whether `user` is a member of the role `admin`.
+    pub fn is_admin(&self, user: &User) -> bool {
+        self.is_member(user, ADMIN_ROLE)
+    }
+
+    /// Make sure that `user` may modify the access control lists.
+    fn check_admin(&self, user: &User) -> Result<(), Error> {
+        let has_admins = self.roles.get(ADMIN_ROLE).map_or(false, |members| !members.is_empty());
+        let is_allowed = if has_admins {
+            self.is_member(user, ADMIN_ROLE)
+        } else {
+            self.is_owner.as_ref().map_or(false, |is_owner| is_owner(user))
+        };
+        if !is_allowed {
+            return Err(Error::AccessDenied(AccessDenied::Acl));
+        }
+        Ok(())
+    }
+
+    pub fn get_rules(&self) -> Vec<AclRule> {
+        self.rules.clone()
+    }
+
+    /// Add rules, ignoring the rules that already exist. Returns the number of rules added.
+    pub fn add_rules(&mut self, rules: Vec<AclRule>, user: &User) -> Result<usize, Error> {
+        try!(self.check_admin(user));
+        let db = self.open_db();
+        let mut result = 0;
+        for rule in rules {
+            if self.rules.contains(&rule) {
+                continue;
+            }
+            if let Some(ref db) = db {
+                let source = serde_json::to_string(&rule.to_json()).unwrap();
+                db.execute("INSERT OR IGNORE INTO acl_rules VALUES ($1)", &[&source])
+                  .unwrap_or_else(|err| { error!("Storage add_rules error: {}", err); 0 });
+            }
+            self.rules.push(rule);
+            result += 1;
+        }
+        Ok(result)
+    }
+
+    /// Remove rules. Returns the number of rules removed.
+    pub fn remove_rules(&mut self, rules: Vec<AclRule>, user: &User) -> Result<usize, Error> {
+        try!(self.check_admin(user));
+        let db = self.open_db();
+        let before = self.rules.len();
+        for rule in &rules {
+            if let Some(ref db) = db {
+                let source = serde_json::to_string(&rule.to_json()).unwrap();
+                db.execute("DELETE FROM acl_rules WHERE rule=$1", &[&source])
+                  .unwrap_or_else(|err| { error!("Storage remove_rules error: {}", err); 0 });
+            }
+        }
+        self.rules.retain(|rule| !rules.contains(rule));
+        Ok(before - self.rules.len())
+    }
+
+    pub fn get_roles(&self) -> Vec<AclRole> {
+        self.roles.iter().map(|(role, users)| {
+            let mut users : Vec<_> = users.iter().cloned().collect();
+            users.sort();
+            AclRole {
+                role: role.clone(),
+                users: users,
+            }
+        }).collect()
+    }
+
+    /// Add users to a role. Returns the number of users added.
+    pub fn add_role_members(&mut self, members: AclRole, user: &User) -> Result<usize, Error> {
+        try!(self.check_admin(user));
+        let db = self.open_db();
+        let mut result = 0;
+        for id in members.users {
+            let inserted = self.roles.entry(members.role.clone()).or_insert_with(HashSet::new).insert(id);
+            if !inserted {
+                continue;
+            }
+            if let Some(ref db) = db {
+                db.execute("INSERT OR IGNORE INTO acl_roles VALUES ($1, $2)", &[&members.role, &id])
+                  .unwrap_or_else(|err| { error!("Storage add_role_members error: {}", err); 0 });
+            }
+            result += 1;
+        }
+        Ok(result)
+    }
+
+    /// Remove users from a role. Returns the number of users removed.
+    pub fn remove_role_members(&mut self, members: AclRole, user: &User) -> Result<usize, Error> {
+        try!(self.check_admin(user));
+        let db = self.open_db();
+        let mut result = 0;
+        if let Some(role) = self.roles.get_mut(&members.role) {
+            for id in members.users {
+                if !role.remove(&id) {
+                    continue;
+                }
+                if let Some(ref db) = db {
+                    db.execute("DELETE FROM acl_roles WHERE role=$1 AND user=$2", &[&members.role, &id])
+                      .unwrap_or_else(|err| { error!("Storage remove_role_members error: {}", err); 0 });
+                }
+                result += 1;
+            }
+        }
+        Ok(result)
+    }
+}
@@ -108,6 +108,26 @@ pub enum WatchEvent<V> {
     }
 }
 
+/// The event to send to a watcher of channel `id` once its value changes from `previous`
+/// to `value`, if any. For adapters that find out about new values by themselves, e.g.
+/// by polling their devices.
+pub fn watch_event(id: &Id<Channel>, range: &Option<Range>, previous: Option<&Value>, value: &Value)
+    -> Option<WatchEvent<Value>>
+{
+    match *range {
+        None if previous != Some(value) => Some(WatchEvent::Enter { id: id.clone(), value: value.clone() }),
+        None => None,
+        Some(ref range) => {
+            let was_met = previous.map_or(false, |previous| range.contains(previous));
+            match (was_met, range.contains(value)) {
+                (false, true) => Some(WatchEvent::Enter { id: id.clone(), value: value.clone() }),
+                (true, false) => Some(WatchEvent::Exit { id: id.clone(), value: value.clone() }),
+                _ => None
+            }
+        }
+    }
+}
+
 
 pub trait RawAdapter: Send + Sync {
     /// An id unique to this adapter. This id must persist between
@@ -13,21 +13,24 @@
 //!
 //!
 
+use acl::{ AclRole, AclRule };
+use audit::{ AuditEntry, AuditQuery };
 use io::*;
 use services::*;
 use selector::*;
 pub use util::{ ResultMap, TargetMap, Targetted };
-use values::{ Value, Type, TypeError };
+use values::{ Duration, TimeStamp, Value, Type, TypeError };
 
 use transformable_channels::mpsc::*;
 
 use std::{ error, fmt };
+use std::collections::HashMap;
 use std::error::Error as std_error;
 
 use serde::ser::Serialize;
 use serde_json::value::Serializer;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum Operation {
     Fetch,
     Send,
@@ -44,6 +47,27 @@ impl fmt::Display for Operation {
     }
 }
 
+impl Parser<Operation> for Operation {
+    fn description() -> String {
+        "Operation".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match source.as_string() {
+            Some("Fetch") => Ok(Operation::Fetch),
+            Some("Send") => Ok(Operation::Send),
+            Some("Watch") => Ok(Operation::Watch),
+            Some(other) => Err(ParseError::unknown_constant(other, &path)),
+            None => Err(ParseError::type_error("Operation", &path, "string"))
+        }
+    }
+}
+
+impl ToJSON for Operation {
+    fn to_json(&self) -> JSON {
+        JSON::String(format!("{}", self))
+    }
+}
+
 
 /// An error that arose during interaction with either a device, an adapter or the
 /// adapter manager
@@ -70,6 +94,23 @@ pub enum Error {
 
     // An error happened while attempting to parse a value.
     ParseError(ParseError),
+
+    /// Attempting to perform an operation that the access control lists do not grant
+    /// to the user.
+    AccessDenied(AccessDenied),
+}
+
+/// The operations rejected by the access control lists.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AccessDenied {
+    /// The user may not perform this operation on this channel.
+    Channel(Operation, Id<Channel>),
+
+    /// The user may not modify the access control lists.
+    Acl,
+
+    /// The user may not read the audit log.
+    AuditLog,
 }
 
 impl ToJSON for Error {
@@ -94,6 +135,9 @@ impl fmt::Display for Error {
             Error::InvalidValue(ref value) => write!(f, "{}: {:?}",self.description(), value),
             Error::InternalError(ref err) => write!(f, "{}: {:?}", self.description(), err), // TODO implement Display for InternalError as well
             Error::ParseError(ref err) => write!(f, "{}: {:?}", self.description(), err), // TODO implement Display for ParseError as well
+            Error::AccessDenied(AccessDenied::Channel(ref operation, ref channel)) => write!(f, "{}: {} {}", self.description(), operation, channel),
+            Error::AccessDenied(AccessDenied::Acl) => write!(f, "{}: access control lists", self.description()),
+            Error::AccessDenied(AccessDenied::AuditLog) => write!(f, "{}: audit log", self.description()),
         }
     }
 }
@@ -106,7 +150,8 @@ impl error::Error for Error {
             Error::TypeError(_) => "Attempting to send a value with a wrong type",
             Error::InvalidValue(_) => "Attempting to send an invalid value",
             Error::InternalError(_) => "Internal Error", // TODO implement Error for InternalError as well
-            Error::ParseError(ref err) => err.description()
+            Error::ParseError(ref err) => err.description(),
+            Error::AccessDenied(_) => "Access denied",
         }
     }
 
@@ -192,6 +237,71 @@ pub enum WatchEvent {
     },
 }
 
+/// A value fetched from a channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchedValue {
+    /// The actual value.
+    pub value: Payload,
+
+    pub type_: Type,
+
+    /// The instant at which the value was received from the device. If the value was
+    /// served from the cache, this may be older than the request.
+    pub timestamp: TimeStamp,
+}
+
+impl ToJSON for FetchedValue {
+    fn to_json(&self) -> JSON {
+        vec![
+            ("value", self.value.to_json()),
+            ("timestamp", self.timestamp.to_json()),
+        ].to_json()
+    }
+}
+
+/// An event while watching the topology of the system, i.e. the services and channels that
+/// are registered and their tags.
+#[derive(Serialize, Debug, Clone)]
+pub enum TopologyEvent {
+    /// A service has been added. Note that a service is added before any of its channels.
+    ServiceAdded(Id<ServiceId>),
+
+    /// A service has been removed, along with all its channels.
+    ServiceRemoved(Id<ServiceId>),
+
+    /// A channel has been added to a service.
+    ChannelAdded {
+        service: Id<ServiceId>,
+        channel: Id<Channel>,
+    },
+
+    /// A channel has been removed from a service.
+    ChannelRemoved {
+        service: Id<ServiceId>,
+        channel: Id<Channel>,
+    },
+
+    /// The tags of a service have changed. Payload contains the new set of tags.
+    ServiceTagsChanged {
+        service: Id<ServiceId>,
+        tags: Vec<Id<TagId>>,
+    },
+
+    /// The user-defined properties of a service have changed. Payload contains the new
+    /// set of user-defined properties.
+    ServicePropertiesChanged {
+        service: Id<ServiceId>,
+        properties: HashMap<String, String>,
+    },
+
+    /// The tags of a channel have changed. Payload contains the new set of tags.
+    ChannelTagsChanged {
+        service: Id<ServiceId>,
+        channel: Id<Channel>,
+        tags: Vec<Id<TagId>>,
+    },
+}
+
 /// User identifier that will be passed from the REST API handlers to the
 /// adapters.
 #[derive(Debug, Clone, PartialEq)]
@@ -439,6 +549,85 @@ pub trait API: Send {
     /// A JSON string representing a number.
     fn remove_service_tags(& self, selectors: Vec<ServiceSelector>, tags: Vec<Id<TagId>>) -> usize;
 
+    /// Set user-defined properties on a set of services.
+    ///
+    /// A call to `API::set_service_properties(vec![req1, req2, ...], properties)` will set
+    /// all the `properties` on all the services matching _either_ `req1` or `req2` or ...,
+    /// replacing any previous value, and return the number of services matching any of the
+    /// selectors. User-defined properties persist across reboots.
+    ///
+    /// Note that this call is _not live_. In other words, if services
+    /// are added after the call, they will not be affected.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/services/properties`
+    ///
+    /// ## JSON
+    ///
+    /// A JSON object with the following fields:
+    /// - services: array - an array of `ServiceSelector`;
+    /// - properties: object - string values, indexed by property name.
+    ///
+    /// ```
+    /// # extern crate serde;
+    /// # extern crate serde_json;
+    /// # extern crate foxbox_taxonomy;
+    /// # use foxbox_taxonomy::services::*;
+    /// # use foxbox_taxonomy::selector::*;
+    /// # use std::collections::HashMap;
+    ///
+    /// # fn main() {
+    ///  # let source =
+    /// r#"{
+    ///   "services": [{"id": "id 1"}],
+    ///   "properties": {"name": "Kitchen ceiling", "room": "kitchen"}
+    /// }"#;
+    ///
+    /// # let mut json: JSON = serde_json::from_str(&source).unwrap();
+    /// # Vec::<ServiceSelector>::take(Path::new(), &mut json, "services").unwrap();
+    /// # HashMap::<String, String>::take(Path::new(), &mut json, "properties").unwrap();
+    ///
+    /// # }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing a number.
+    fn set_service_properties(& self, selectors: Vec<ServiceSelector>, properties: HashMap<String, String>) -> usize;
+
+    /// Remove user-defined properties from a set of services.
+    ///
+    /// A call to `API::remove_service_properties(vec![req1, req2, ...], vec![name1, ...])`
+    /// will remove properties `name1`, ... from all the services matching _either_ `req1` or
+    /// `req2` or ... and return the number of services matching any of the selectors.
+    /// Properties set by the adapter are not affected.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/services/properties`
+    ///
+    /// ## JSON
+    ///
+    /// A JSON object with the following fields:
+    /// - services: array - an array of `ServiceSelector`;
+    /// - properties: array - an array of property names.
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing a number.
+    fn remove_service_properties(& self, selectors: Vec<ServiceSelector>, properties: Vec<String>) -> usize;
+
 
     /// Get a list of channels matching some conditions
     fn get_channels(& self, selectors: Vec<ChannelSelector>) -> Vec<Channel>;
@@ -537,11 +726,18 @@ pub trait API: Send {
 
     /// Read the latest value from a set of channels
     ///
+    /// The `AdapterManager` keeps the latest value received from each channel, whether it was
+    /// fetched or watched. If `max_age` is specified and the cached value for a channel is at
+    /// most `max_age` old, this value is returned instead of querying the device. Otherwise, or
+    /// if `max_age` is `None`, the value is fetched from the device.
+    ///
     /// # REST API
     ///
-    /// `GET /api/v1/channels/get`
+    /// `PUT /api/v1/channels/get`
     ///
-    /// This call supports one or more `ChannelSelector`.
+    /// This call supports one or more `ChannelSelector`, either as an array or as an
+    /// object `{"channels": array of ChannelSelector, "max_age": number of seconds}`,
+    /// where `max_age` is optional.
     ///
     /// ```
     /// # extern crate serde;
@@ -569,8 +765,8 @@ pub trait API: Send {
     ///
     /// ## Success
     ///
-    /// The results, per getter.
-    fn fetch_values(&self, Vec<ChannelSelector>, user: User) -> ResultMap<Id<Channel>, Option<(Payload, Type)>, Error>;
+    /// The results, per getter, as objects `{"value": value, "timestamp": date}`.
+    fn fetch_values(&self, Vec<ChannelSelector>, max_age: Option<Duration>, user: User) -> ResultMap<Id<Channel>, Option<FetchedValue>, Error>;
 
     /// Send a bunch of values to a set of channels.
     ///
@@ -652,13 +848,205 @@ pub trait API: Send {
     /// receiving *every single value coming from the channels*. This is very rarely a good idea.
     /// Many devices may reject such requests.
     ///
+    /// Channels that `user` may not watch are not watched, and are reported to `on_event` as
+    /// `WatchEvent::Error` with `AccessDenied`.
+    ///
     /// The watcher is disconnected once the `WatchGuard` returned by this method is dropped.
     ///
     /// # `WebSocket` API
     ///
     /// `/api/v1/channels/watch`
     fn watch_values(& self, watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>,
-            on_event: Box<ExtSender<WatchEvent>>) -> Self::WatchGuard;
+            on_event: Box<ExtSender<WatchEvent>>, user: User) -> Self::WatchGuard;
+
+    /// Watch for services and channels being added or removed, or having their tags changed.
+    ///
+    /// A call to `API::watch_topology(vec![req1, req2, ...], on_event)` will send to `on_event`
+    /// all the `TopologyEvent`s concerning services matching _either_ `req1` or `req2` or ...
+    /// If `selectors` is empty, all services match. Events on channels are sent if their
+    /// service matches.
+    ///
+    /// Unlike `watch_values`, this does not require any cooperation from the adapters.
+    ///
+    /// The watcher is disconnected once the `WatchGuard` returned by this method is dropped.
+    fn watch_topology(&self, selectors: Vec<ServiceSelector>,
+            on_event: Box<ExtSender<TopologyEvent>>) -> Self::WatchGuard;
+
+    /// Get the rules of the access control lists.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/acl/rules`
+    ///
+    /// ## Success
+    ///
+    /// A JSON array of `AclRule`.
+    fn get_acl_rules(&self) -> Vec<AclRule>;
+
+    /// Add rules to the access control lists.
+    ///
+    /// Once a channel is targeted by at least one rule, only the operations granted by these
+    /// rules are allowed on this channel. If role `admin` has members, only these members may
+    /// add rules.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/acl/rules`
+    ///
+    /// ## JSON
+    ///
+    /// A JSON object with the following field:
+    /// - rules: array - an array of `AclRule`.
+    ///
+    /// ```
+    /// # extern crate serde_json;
+    /// # extern crate foxbox_taxonomy;
+    /// # use foxbox_taxonomy::acl::*;
+    /// # use foxbox_taxonomy::parse::*;
+    ///
+    /// # fn main() {
+    ///  # let source =
+    /// r#"{
+    ///   "rules": [{
+    ///     "principal": {"user": 1},
+    ///     "target": {"service": "front door"},
+    ///     "operations": ["Fetch", "Send", "Watch"]
+    ///   }, {
+    ///     "principal": "anyone",
+    ///     "target": {"service": "front door"},
+    ///     "operations": ["Fetch"]
+    ///   }]
+    /// }"#;
+    ///
+    /// # let json: JSON = serde_json::from_str(&source).unwrap();
+    /// # Vec::<AclRule>::take(Path::new(), &json, "rules").unwrap();
+    /// # }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the user may not modify the access control lists, Error 403.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the number of rules added.
+    fn add_acl_rules(&self, rules: Vec<AclRule>, user: User) -> Result<usize, Error>;
+
+    /// Remove rules from the access control lists.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/acl/rules`
+    ///
+    /// ## JSON
+    ///
+    /// Same as `POST /api/v1/acl/rules`.
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the user may not modify the access control lists, Error 403.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the number of rules removed.
+    fn remove_acl_rules(&self, rules: Vec<AclRule>, user: User) -> Result<usize, Error>;
+
+    /// Get the members of all roles.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/acl/roles`
+    ///
+    /// ## Success
+    ///
+    /// A JSON array of `AclRole`.
+    fn get_acl_roles(&self) -> Vec<AclRole>;
+
+    /// Add users to a role.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/acl/roles`
+    ///
+    /// ## JSON
+    ///
+    /// An `AclRole`, e.g. `{"role": "family", "users": [1, 2]}`.
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the user may not modify the access control lists, Error 403.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the number of users added.
+    fn add_acl_role_members(&self, members: AclRole, user: User) -> Result<usize, Error>;
+
+    /// Remove users from a role.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/acl/roles`
+    ///
+    /// ## JSON
+    ///
+    /// Same as `POST /api/v1/acl/roles`.
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the user may not modify the access control lists, Error 403.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the number of users removed.
+    fn remove_acl_role_members(&self, members: AclRole, user: User) -> Result<usize, Error>;
+
+    /// Determine whether the access control lists let `user` perform `operation` on `channel`.
+    ///
+    /// Returns `false` if the channel does not exist.
+    fn is_allowed(&self, user: &User, operation: Operation, channel: &Id<Channel>) -> bool;
+
+    /// Get the entries of the audit log, most recent first.
+    ///
+    /// Every call to `send_values` is recorded, with the user, the channel, the value and
+    /// the result, as well as the executions of Thinkerbell rules.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/audit`
+    ///
+    /// ## JSON
+    ///
+    /// An `AuditQuery`, e.g. `{"tag": "front door", "limit": 20}`.
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the user is not a member of the role `admin` of the access control lists, Error 403.
+    ///
+    /// If the audit log cannot be read, Error 500.
+    ///
+    /// ## Success
+    ///
+    /// A JSON array of `AuditEntry`.
+    fn get_audit_log(&self, query: AuditQuery, user: User) -> Result<Vec<AuditEntry>, Error>;
+
+    /// Record in the audit log that rule `rule_index` of script `script` has executed its
+    /// statements on behalf of `owner`.
+    fn audit_script_execution(&self, script: &str, rule_index: usize, owner: &User);
 
     /// A value that causes a disconnection once it is dropped.
     type WatchGuard;
@@ -0,0 +1,312 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An audit log of the operations that act upon devices.
+//!
+//! The `AdapterManager` records every call to `send_values`, with the user, the channel,
+//! the value and the result, as well as the executions of Thinkerbell rules, with the owner
+//! of the script. This lets users find out who opened the front door, and when. Only members
+//! of the role `admin` of the access control lists may read the log.
+//!
+//! Entries are stored in a `SQLite` table that only keeps the latest `MAX_AUDIT_ENTRIES`.
+
+use api::{ Error, InternalError, User };
+use io::*;
+use parse::*;
+use services::*;
+use values::TimeStamp;
+
+use chrono::UTC;
+use rusqlite::Connection;
+use serde_json;
+
+use std::collections::HashSet;
+use std::i64;
+use std::path::Path as FilePath;
+use std::sync::Mutex;
+
+/// The maximal number of entries kept in the audit log. Older entries are discarded.
+pub const MAX_AUDIT_ENTRIES: i64 = 10_000;
+
+/// Something that happened and was recorded in the audit log.
+#[derive(Clone, Debug)]
+pub enum AuditEvent {
+    /// A value was sent to a channel.
+    Send {
+        channel: Id<Channel>,
+        value: Payload,
+        result: Result<(), Error>,
+    },
+
+    /// A Thinkerbell script executed the statements of one of its rules.
+    ScriptExecution {
+        script: String,
+        rule_index: usize,
+    },
+}
+
+impl ToJSON for AuditEvent {
+    fn to_json(&self) -> JSON {
+        match *self {
+            AuditEvent::Send { ref channel, ref value, ref result } => {
+                let result = match *result {
+                    Ok(()) => JSON::String("Ok".to_owned()),
+                    Err(ref err) => vec![("Error", err.to_json())].to_json()
+                };
+                vec![("Send", vec![
+                    ("channel", channel.to_json()),
+                    ("value", value.to_json()),
+                    ("result", result),
+                ].to_json())].to_json()
+            }
+            AuditEvent::ScriptExecution { ref script, rule_index } => {
+                vec![("ScriptExecution", vec![
+                    ("script", script.to_json()),
+                    ("rule_index", rule_index.to_json()),
+                ].to_json())].to_json()
+            }
+        }
+    }
+}
+
+/// A single entry of the audit log.
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    /// The instant at which the event happened, with a precision of one second.
+    pub timestamp: TimeStamp,
+
+    /// The user who performed the operation, or the owner of the script.
+    pub user: User,
+
+    pub event: AuditEvent,
+}
+
+impl ToJSON for AuditEntry {
+    fn to_json(&self) -> JSON {
+        let user = match self.user {
+            User::None => JSON::Null,
+            User::Id(id) => JSON::I64(id as i64),
+        };
+        vec![
+            ("timestamp", self.timestamp.to_json()),
+            ("user", user),
+            ("event", self.event.to_json()),
+        ].to_json()
+    }
+}
+
+/// A request for the entries of the audit log.
+///
+/// # JSON
+///
+/// An object with fields, all optional:
+///
+/// - `from` (`TimeStamp`): ignore entries recorded before this instant;
+/// - `to` (`TimeStamp`): ignore entries recorded after this instant;
+/// - `user` (integer): only return the entries of this user;
+/// - `channel` (string): only return the values sent to this channel;
+/// - `tag` (string): only return the values sent to channels that had this tag, or whose
+///   service had this tag, at the time of sending;
+/// - `limit` (positive integer): return at most this number of entries.
+///
+/// ```
+/// use foxbox_taxonomy::audit::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// let source = r#"{
+///   "from": "2016-06-01T00:00:00Z",
+///   "tag": "front door",
+///   "limit": 20
+/// }"#;
+///
+/// let query = AuditQuery::from_str(source).unwrap();
+/// assert!(query.from.is_some());
+/// assert!(query.user.is_none());
+/// assert_eq!(query.limit, Some(20));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AuditQuery {
+    pub from: Option<TimeStamp>,
+    pub to: Option<TimeStamp>,
+    pub user: Option<i32>,
+    pub channel: Option<Id<Channel>>,
+    pub tag: Option<Id<TagId>>,
+    pub limit: Option<u32>,
+}
+
+impl Parser<AuditQuery> for AuditQuery {
+    fn description() -> String {
+        "AuditQuery".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        macro_rules! take_opt {
+            ($typ:ty, $name:expr) => (
+                match path.push($name, |path| <$typ>::take_opt(path, source, $name)) {
+                    Some(Ok(value)) => Some(value),
+                    Some(Err(err)) => return Err(err),
+                    None => None
+                }
+            )
+        }
+        Ok(AuditQuery {
+            from: take_opt!(TimeStamp, "from"),
+            to: take_opt!(TimeStamp, "to"),
+            user: take_opt!(i32, "user"),
+            channel: take_opt!(Id<Channel>, "channel"),
+            tag: take_opt!(Id<TagId>, "tag"),
+            limit: take_opt!(u32, "limit"),
+        })
+    }
+}
+
+fn sql_error<E>(err: E) -> Error where E: ::std::fmt::Debug {
+    Error::InternalError(InternalError::GenericError(format!("Audit database error: {:?}", err)))
+}
+
+/// Stores the audit log in a `SQLite` database.
+pub struct AuditLog {
+    db: Mutex<Connection>,
+}
+
+impl AuditLog {
+    /// Open the audit log stored at `path`.
+    ///
+    /// If `path` is `None`, entries are only kept in memory.
+    pub fn new(path: Option<&FilePath>) -> Result<Self, Error> {
+        let db = try!(match path {
+            Some(path) => {
+                debug!("Opening audit database at {}", path.display());
+                Connection::open(path)
+            },
+            None => Connection::open_in_memory()
+        }.map_err(sql_error));
+
+        try!(db.execute("CREATE TABLE IF NOT EXISTS audit (
+                timestamp  INTEGER NOT NULL,
+                user       INTEGER,
+                channel    TEXT,
+                value      TEXT,
+                error      TEXT,
+                script     TEXT,
+                rule       INTEGER
+            )", &[]).map_err(sql_error));
+        try!(db.execute("CREATE TABLE IF NOT EXISTS audit_tags (
+                entry      INTEGER NOT NULL,
+                tag        TEXT NOT NULL
+            )", &[]).map_err(sql_error));
+        try!(db.execute("CREATE INDEX IF NOT EXISTS audit_tags_by_tag ON audit_tags (tag, entry)", &[])
+            .map_err(sql_error));
+
+        Ok(AuditLog {
+            db: Mutex::new(db)
+        })
+    }
+
+    /// Record a value sent to a channel by `user`, along with the tags of the channel and
+    /// its service.
+    pub fn record_send(&self, user: &User, channel: &Id<Channel>, tags: &HashSet<Id<TagId>>,
+        value: &Payload, result: &Result<(), Error>) -> Result<(), Error>
+    {
+        let value = try!(serde_json::to_string(&value.to_json()).map_err(sql_error));
+        let error = match *result {
+            Ok(()) => None,
+            Err(ref err) => Some(try!(serde_json::to_string(&err.to_json()).map_err(sql_error)))
+        };
+        let db = self.db.lock().unwrap();
+        try!(db.execute("INSERT INTO audit (timestamp, user, channel, value, error) VALUES ($1, $2, $3, $4, $5)",
+            &[&Self::now(), &Self::user_id(user), &channel.to_string(), &value, &error])
+            .map_err(sql_error));
+        let entry = db.last_insert_rowid();
+        for tag in tags {
+            try!(db.execute("INSERT INTO audit_tags VALUES ($1, $2)", &[&entry, &tag.to_string()])
+                .map_err(sql_error));
+        }
+        Self::discard_old_entries(&db)
+    }
+
+    /// Record the execution of rule `rule_index` of Thinkerbell script `script`, on behalf of
+    /// `owner`.
+    pub fn record_script_execution(&self, owner: &User, script: &str, rule_index: usize) -> Result<(), Error> {
+        let db = self.db.lock().unwrap();
+        try!(db.execute("INSERT INTO audit (timestamp, user, script, rule) VALUES ($1, $2, $3, $4)",
+            &[&Self::now(), &Self::user_id(owner), &script, &(rule_index as i64)])
+            .map_err(sql_error));
+        Self::discard_old_entries(&db)
+    }
+
+    fn now() -> i64 {
+        UTC::now().timestamp()
+    }
+
+    fn user_id(user: &User) -> Option<i32> {
+        match *user {
+            User::None => None,
+            User::Id(id) => Some(id),
+        }
+    }
+
+    fn discard_old_entries(db: &Connection) -> Result<(), Error> {
+        let limit = db.last_insert_rowid() - MAX_AUDIT_ENTRIES;
+        if limit <= 0 {
+            return Ok(());
+        }
+        try!(db.execute("DELETE FROM audit WHERE rowid <= $1", &[&limit]).map_err(sql_error));
+        try!(db.execute("DELETE FROM audit_tags WHERE entry <= $1", &[&limit]).map_err(sql_error));
+        Ok(())
+    }
+
+    /// Get the entries matching `query`, most recent first.
+    pub fn get_entries(&self, query: AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        let from = query.from.map_or(i64::MIN, |ts| ts.as_datetime().timestamp());
+        let to = query.to.map_or(i64::MAX, |ts| ts.as_datetime().timestamp());
+        let channel = query.channel.map(|id| id.to_string());
+        let tag = query.tag.map(|id| id.to_string());
+        let limit = query.limit.map_or(-1, |limit| limit as i64);
+
+        let db = self.db.lock().unwrap();
+        let mut stmt = try!(db.prepare("SELECT timestamp, user, channel, value, error, script, rule FROM audit
+            WHERE timestamp >= $1 AND timestamp <= $2
+              AND ($3 IS NULL OR user = $3)
+              AND ($4 IS NULL OR channel = $4)
+              AND ($5 IS NULL OR rowid IN (SELECT entry FROM audit_tags WHERE tag = $5))
+            ORDER BY rowid DESC LIMIT $6").map_err(sql_error));
+        let rows = try!(stmt.query(&[&from, &to, &query.user, &channel, &tag, &limit]).map_err(sql_error));
+
+        let mut entries = vec![];
+        for row in rows {
+            let row = try!(row.map_err(sql_error));
+            let timestamp: i64 = row.get(0);
+            let user: Option<i32> = row.get(1);
+            let channel: Option<String> = row.get(2);
+            let event = match channel {
+                Some(channel) => {
+                    let value: String = row.get(3);
+                    let error: Option<String> = row.get(4);
+                    let result = match error {
+                        None => Ok(()),
+                        Some(error) => Err(try!(serde_json::from_str::<Error>(&error).map_err(sql_error)))
+                    };
+                    AuditEvent::Send {
+                        channel: Id::new(&channel),
+                        value: try!(Payload::from_str(&value).map_err(Error::ParseError)),
+                        result: result,
+                    }
+                }
+                None => {
+                    let rule_index: i64 = row.get(6);
+                    AuditEvent::ScriptExecution {
+                        script: row.get(5),
+                        rule_index: rule_index as usize,
+                    }
+                }
+            };
+            entries.push(AuditEntry {
+                timestamp: TimeStamp::from_s(timestamp),
+                user: user.map_or(User::None, User::Id),
+                event: event,
+            });
+        }
+        Ok(entries)
+    }
+}
@@ -4,13 +4,15 @@ use adapter::{ Adapter, AdapterWatchGuard, RawAdapter,  WatchEvent as AdapterWat
 use adapter_utils::RawAdapterForAdapter;
 use transact::InsertInMap;
 
-use api::{ Error, InternalError, TargetMap, Targetted, WatchEvent };
+use acl::{ Acl, AclRole, AclRule, OwnerCheck };
+use api::{ AccessDenied, Error, FetchedValue, InternalError, Operation, ResultMap, TargetMap, Targetted, TopologyEvent, User, WatchEvent };
 use io::*;
 use selector::*;
 use services::*;
 use tag_storage::TagStorage;
 use values::*;
 
+use chrono::{ Duration as ChronoDuration, UTC };
 use sublock::atomlock::*;
 use transformable_channels::mpsc::*;
 
@@ -51,6 +53,60 @@ pub type WatchRequest = AdapterRequest<Vec<(Id<Channel>, Option<(Payload, Type)>
 
 pub type WatchGuardCommit = Vec<(Weak<WatcherData>, Vec<(Id<Channel>, Box<AdapterWatchGuard>)>)>;
 
+/// The latest value received from each channel, either by fetching or by watching.
+///
+/// Watch events are received on threads that do not hold the `MainLock`, so the cache
+/// has its own lock.
+#[derive(Default)]
+pub struct ValueCache {
+    values: Mutex<HashMap<Id<Channel>, FetchedValue>>,
+}
+
+impl ValueCache {
+    pub fn new() -> Self {
+        ValueCache::default()
+    }
+
+    /// Record the latest value received from a channel.
+    pub fn insert(&self, id: Id<Channel>, value: FetchedValue) {
+        self.values.lock().unwrap().insert(id, value);
+    }
+
+    /// Record a value that has just been received from a channel.
+    pub fn insert_now(&self, id: Id<Channel>, value: Payload, type_: Type) {
+        self.insert(id, FetchedValue {
+            value: value,
+            type_: type_,
+            timestamp: TimeStamp::from_datetime(UTC::now()),
+        })
+    }
+
+    /// Get the latest value received from a channel, if it has the expected type and
+    /// is at most `max_age` old.
+    pub fn get(&self, id: &Id<Channel>, type_: &Type, max_age: &Duration) -> Option<FetchedValue> {
+        let values = self.values.lock().unwrap();
+        let value = match values.get(id) {
+            Some(value) if value.type_ == *type_ => value,
+            _ => return None
+        };
+        let max_age : ChronoDuration = max_age.clone().into();
+        if UTC::now() - *value.timestamp.as_datetime() <= max_age {
+            Some(value.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Forget the value of a channel, typically because the channel has been removed.
+    pub fn remove(&self, id: &Id<Channel>) {
+        self.values.lock().unwrap().remove(id);
+    }
+
+    pub fn clear(&self) {
+        self.values.lock().unwrap().clear();
+    }
+}
+
 /// Information on a service.
 ///
 /// Used to build `Service` values.
@@ -64,6 +120,9 @@ struct ServiceData {
     /// Creation time properties.
     properties: HashMap<String, String>,
 
+    /// User-defined properties, as in a `Service`.
+    user_properties: HashMap<String, String>,
+
     /// Information on the channels. Used to build field `channels` of `Service`.
     channels: HashMap<Id<Channel>, Arc<SubCell<ChannelData>>>,
 
@@ -82,6 +141,7 @@ impl ServiceData {
             id: service.id,
             adapter: service.adapter,
             properties: service.properties,
+            user_properties: service.user_properties,
             channels: HashMap::new(),
         }
     }
@@ -90,6 +150,7 @@ impl ServiceData {
             tags: self.tags.borrow().clone(),
             id: self.id.clone(),
             properties: self.properties.clone(),
+            user_properties: self.user_properties.clone(),
             adapter: self.adapter.clone(),
             channels: self.channels.iter().map(|(key, value)| {
                 (key.clone(), (**value).borrow().channel.clone())
@@ -118,6 +179,11 @@ impl<'a> ServiceLike for ServiceView<'a> {
     fn with_tags<F>(&self, f: F) -> bool where F: Fn(&HashSet<Id<TagId>>) -> bool {
         f(&*self.data.tags.borrow())
     }
+    fn property(&self, name: &str) -> Option<&str> {
+        self.data.user_properties.get(name)
+            .or_else(|| self.data.properties.get(name))
+            .map(|value| value as &str)
+    }
     fn has_channels<F>(&self, f: F) -> bool where F: Fn(&Channel) -> bool {
         for chan in self.data.channels.values() {
             if f(&*chan.borrow()) {
@@ -237,6 +303,10 @@ pub struct WatcherData {
     /// The listener for this watch.
     on_event: Mutex<Box<ExtSender<WatchEvent>>>,
 
+    /// The user on behalf of whom the channels are watched, or `None` if the watch
+    /// is not subject to the access control lists.
+    user: Option<User>,
+
     /// A unique key used to locate the `WatcherData` in the
     /// WatchMap.
     key: WatchKey,
@@ -244,6 +314,10 @@ pub struct WatcherData {
     /// The individual guard for each getter currently watched.
     guards: SubCell<HashMap<Id<Channel>, Vec<Box<AdapterWatchGuard>>>>,
 
+    /// The channels that `user` may not watch anymore, because the access control lists
+    /// have changed since they were attached to this watcher. Their events are dropped.
+    denied: Arc<Mutex<HashSet<Id<Channel>>>>,
+
     /// `true` once the WatchGuard has dropped. In this
     /// case, the `WatcherData` will shortly be removed
     /// from the WatchMap.
@@ -263,13 +337,15 @@ impl PartialEq for WatcherData {
 }
 
 impl WatcherData {
-    fn new(liveness: &Arc<Liveness>, key: WatchKey, watch:TargetMap<ChannelSelector, Exactly<(Payload, Type)>>, on_event: Box<ExtSender<WatchEvent>>) -> Self {
+    fn new(liveness: &Arc<Liveness>, key: WatchKey, watch:TargetMap<ChannelSelector, Exactly<(Payload, Type)>>, on_event: Box<ExtSender<WatchEvent>>, user: Option<User>) -> Self {
         WatcherData {
             key: key,
             on_event: Mutex::new(on_event),
+            user: user,
             watch: watch,
             is_dropped: Arc::new(AtomicBool::new(false)),
             guards: SubCell::new(liveness, HashMap::new()),
+            denied: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -300,16 +376,36 @@ impl WatchMap {
             liveness: liveness.clone()
         }
     }
-    fn create(&mut self, watch:TargetMap<ChannelSelector, Exactly<(Payload, Type)>>, on_event: Box<ExtSender<WatchEvent>>) -> Arc<WatcherData> {
+    fn create(&mut self, watch:TargetMap<ChannelSelector, Exactly<(Payload, Type)>>, on_event: Box<ExtSender<WatchEvent>>, user: Option<User>) -> Arc<WatcherData> {
         let id = WatchKey(self.counter);
         self.counter += 1;
-        let watcher = Arc::new(WatcherData::new(&self.liveness, id, watch, on_event));
+        let watcher = Arc::new(WatcherData::new(&self.liveness, id, watch, on_event, user));
         self.watchers.insert(id, watcher.clone());
         watcher
     }
     fn remove(&mut self, key: WatchKey) -> Option<Arc<WatcherData>> {
         self.watchers.remove(&key)
     }
+    /// Generate a key that is not used by any other watcher, without registering
+    /// any `WatcherData`.
+    fn create_key(&mut self) -> WatchKey {
+        let id = WatchKey(self.counter);
+        self.counter += 1;
+        id
+    }
+}
+
+/// All the information on a currently registered topology watch.
+struct TopologyWatcherData {
+    /// The services for which we are interested in topology events.
+    /// If empty, all services match.
+    selectors: Vec<ServiceSelector>,
+
+    /// The listener for this watch.
+    on_event: Mutex<Box<ExtSender<TopologyEvent>>>,
+
+    /// `true` once the WatchGuard has dropped.
+    is_dropped: Arc<AtomicBool>,
 }
 
 pub struct State {
@@ -326,6 +422,13 @@ pub struct State {
     /// and a when a new value is available from a getter channel.
     watchers: Arc<Mutex<WatchMap>>,
 
+    /// The set of topology watchers registered. Keys are generated by `watchers`, so
+    /// they never collide with value watchers.
+    topology_watchers: HashMap<WatchKey, TopologyWatcherData>,
+
+    /// The latest known value of each channel.
+    value_cache: Arc<ValueCache>,
+
     /// Information on whether the lock holding the state is open/closed,
     /// mutable/immutable.
     liveness: Arc<Liveness>,
@@ -335,6 +438,9 @@ pub struct State {
     /// - We read all tags once per lifetime of the manager.
     /// - We write occasionaly when adding or removing tags.
     db_path: Option<PathBuf>,
+
+    /// The access control lists, persisted in the same database as tags.
+    acl: Acl,
 }
 
 impl State {
@@ -350,10 +456,42 @@ impl State {
         };
         for id in service.borrow().channels.keys() {
             let _ignored = self.channel_by_id.remove(id);
+            self.value_cache.remove(id);
+        }
+        {
+            let service = service.borrow();
+            for channel in service.channels.keys() {
+                self.aux_notify_topology(&service, TopologyEvent::ChannelRemoved {
+                    service: id.clone(),
+                    channel: channel.clone(),
+                });
+            }
+            self.aux_notify_topology(&service, TopologyEvent::ServiceRemoved(id.clone()));
         }
         Ok(adapter)
     }
 
+    /// Send a topology event to all the topology watchers interested in `service`.
+    fn aux_notify_topology(&self, service: &ServiceData, event: TopologyEvent) {
+        let view = ServiceView::new(service);
+        for watcher in self.topology_watchers.values() {
+            if watcher.is_dropped.load(Ordering::Relaxed) {
+                continue;
+            }
+            if watcher.selectors.is_empty() || watcher.selectors.iter().any(|selector| selector.matches(&view)) {
+                let _ = watcher.on_event.lock().unwrap().send(event.clone());
+            }
+        }
+    }
+
+    /// Send a topology event to all the topology watchers interested in the service
+    /// with id `service`, if it exists.
+    fn aux_notify_topology_by_id(&self, service: &Id<ServiceId>, event: TopologyEvent) {
+        if let Some(service) = self.service_by_id.get(service) {
+            self.aux_notify_topology(&*service.borrow(), event);
+        }
+    }
+
     fn with_services<F>(&self, selectors: Vec<ServiceSelector>, mut cb: F)
         where F: FnMut(&Arc<SubCell<ServiceData>>, &mut Option<TagStorage>) {
 
@@ -476,6 +614,7 @@ impl State {
     fn aux_channels_may_need_registration(&mut self, channels: Vec<Id<Channel>>) -> WatchRequest {
         debug!(target: "Taxonomy-backend", "checking if channels need to be watched {:?}", channels);
         let adapter_by_id = &self.adapter_by_id;
+        let acl = &self.acl;
         let mut per_adapter = HashMap::new();
         for id in channels {
             match self.channel_by_id.get_mut(&id) {
@@ -513,6 +652,11 @@ impl State {
                                 continue;
                             }
 
+                            if !Self::may_watch(acl, &watcher.user, &*channel_data) {
+                                let _ = on_event.lock().unwrap().send(Self::watch_denied(&id));
+                                continue;
+                            }
+
                             // Register to be informed of future changes.
                             Self::aux_start_channel_watch(&mut watcher.clone(),
                                 &mut *channel_data, &targetted.payload, adapter_by_id, &mut per_adapter)
@@ -550,6 +694,9 @@ impl State {
             service_by_id: HashMap::new(),
             channel_by_id: HashMap::new(),
             watchers: Arc::new(Mutex::new(WatchMap::new(liveness))),
+            topology_watchers: HashMap::new(),
+            value_cache: Arc::new(ValueCache::new()),
+            acl: Acl::new(db_path.clone()),
             db_path: db_path,
        }
     }
@@ -613,7 +760,7 @@ impl State {
         if !service.channels.is_empty() {
             return Err(Error::InternalError(InternalError::InvalidInitialService));
         }
-        let service = ServiceData::new(&self.liveness, service);
+        let mut service = ServiceData::new(&self.liveness, service);
         let mut services_for_this_adapter =
             match self.adapter_by_id.get_mut(&service.adapter) {
                 None => return Err(Error::InternalError(InternalError::NoSuchAdapter(service.adapter.clone()))),
@@ -637,6 +784,12 @@ impl State {
                 for tag in &tags {
                     let _ = tag_set.insert(tag.clone());
                 }
+
+                // Same thing for user-defined properties.
+                match store.get_properties_for(&id) {
+                    Err(err) => return Err(Error::InternalError(InternalError::GenericError(format!("{}", err)))),
+                    Ok(properties) => service.user_properties.extend(properties)
+                };
             }
         }
 
@@ -656,6 +809,7 @@ impl State {
         // If we haven't bailed out yet, leave all this stuff in the maps and sets.
         insert_in_adapters.commit();
         insert_in_services.commit();
+        self.aux_notify_topology_by_id(&id, TopologyEvent::ServiceAdded(id.clone()));
         Ok(())
     }
 
@@ -706,6 +860,7 @@ impl State {
         }
 
         let id = setter.id.clone();
+        let service_id = setter.service.clone();
         let channel_data;
         {
             let service = match self.service_by_id.get_mut(&setter.service) {
@@ -731,6 +886,10 @@ impl State {
             insert_in_service.commit();
             insert_in_setters.commit();
         }
+        self.aux_notify_topology_by_id(&service_id, TopologyEvent::ChannelAdded {
+            service: service_id.clone(),
+            channel: id.clone(),
+        });
         Ok(self.aux_channels_may_need_registration(vec![id]))
     }
 
@@ -748,9 +907,10 @@ impl State {
             Some(channel) => channel
         };
         Self::aux_channel_may_need_unregistration(&mut *channel.borrow_mut(), true);
+        self.value_cache.remove(id);
 
         let service_id = &channel.borrow().channel.service;
-        match self.service_by_id.get_mut(service_id) {
+        let result = match self.service_by_id.get_mut(service_id) {
             None => Err(Error::InternalError(InternalError::NoSuchService(service_id.clone()))),
             Some(service) => {
                 if service.borrow_mut().channels.remove(id).is_none() {
@@ -759,7 +919,14 @@ impl State {
                     Ok(())
                 }
             }
+        };
+        if result.is_ok() {
+            self.aux_notify_topology_by_id(service_id, TopologyEvent::ChannelRemoved {
+                service: service_id.clone(),
+                channel: id.clone(),
+            });
         }
+        result
     }
 
     pub fn get_services(&self, selectors: Vec<ServiceSelector>) -> Vec<Service> {
@@ -774,6 +941,7 @@ impl State {
 
     pub fn add_service_tags(&mut self, selectors: Vec<ServiceSelector>, tags: Vec<Id<TagId>>) -> usize {
         let mut result = 0;
+        let mut changed = vec![];
 
         self.with_services(selectors, |service, store| {
             let service = service.borrow_mut();
@@ -784,16 +952,22 @@ impl State {
                        .unwrap_or_else(|err| { error!("Storage add_tags error: {}", err); });
             }
 
+            let mut has_changed = false;
             for tag in &tags {
-                let _ = tag_set.insert(tag.clone());
+                has_changed |= tag_set.insert(tag.clone());
+            }
+            if has_changed {
+                changed.push(service.id.clone());
             }
             result += 1;
         });
+        self.aux_notify_service_tags_changed(changed);
         result
     }
 
     pub fn remove_service_tags(&mut self, selectors: Vec<ServiceSelector>, tags: Vec<Id<TagId>>) -> usize {
         let mut result = 0;
+        let mut changed = vec![];
         self.with_services(selectors, |service, store| {
             let service = service.borrow_mut();
             let mut tag_set = service.tags.borrow_mut();
@@ -803,14 +977,111 @@ impl State {
                        .unwrap_or_else(|err| { error!("Storage remove_tags error: {}", err); });
             }
 
+            let mut has_changed = false;
             for tag in &tags {
-                let _ = tag_set.remove(tag);
+                has_changed |= tag_set.remove(tag);
+            }
+            if has_changed {
+                changed.push(service.id.clone());
+            }
+            result += 1;
+        });
+        self.aux_notify_service_tags_changed(changed);
+        result
+    }
+
+    /// Notify topology watchers that the tags of some services have changed.
+    fn aux_notify_service_tags_changed(&self, services: Vec<Id<ServiceId>>) {
+        for id in services {
+            if let Some(service) = self.service_by_id.get(&id) {
+                let service = service.borrow();
+                let tags = service.tags.borrow().iter().cloned().collect();
+                self.aux_notify_topology(&service, TopologyEvent::ServiceTagsChanged {
+                    service: id.clone(),
+                    tags: tags,
+                });
+            }
+        }
+    }
+
+    /// Notify topology watchers that the tags of some channels have changed.
+    fn aux_notify_channel_tags_changed(&self, channels: Vec<Id<Channel>>) {
+        for id in channels {
+            if let Some(channel) = self.channel_by_id.get(&id) {
+                let channel = channel.borrow();
+                self.aux_notify_topology_by_id(&channel.service, TopologyEvent::ChannelTagsChanged {
+                    service: channel.service.clone(),
+                    channel: id.clone(),
+                    tags: channel.tags.iter().cloned().collect(),
+                });
+            }
+        }
+    }
+
+    pub fn set_service_properties(&mut self, selectors: Vec<ServiceSelector>, properties: HashMap<String, String>) -> usize {
+        let mut result = 0;
+        let mut changed = vec![];
+        self.with_services(selectors, |service, store| {
+            let mut service = service.borrow_mut();
+
+            if let Some(ref mut storage) = *store {
+                storage.set_properties(&service.id, &properties)
+                       .unwrap_or_else(|err| { error!("Storage set_properties error: {}", err); });
+            }
+
+            let mut has_changed = false;
+            for (name, value) in &properties {
+                if service.user_properties.insert(name.clone(), value.clone()).as_ref() != Some(value) {
+                    has_changed = true;
+                }
+            }
+            if has_changed {
+                changed.push(service.id.clone());
+            }
+            result += 1;
+        });
+        self.aux_notify_service_properties_changed(changed);
+        result
+    }
+
+    pub fn remove_service_properties(&mut self, selectors: Vec<ServiceSelector>, names: Vec<String>) -> usize {
+        let mut result = 0;
+        let mut changed = vec![];
+        self.with_services(selectors, |service, store| {
+            let mut service = service.borrow_mut();
+
+            if let Some(ref mut storage) = *store {
+                storage.remove_properties(&service.id, &names)
+                       .unwrap_or_else(|err| { error!("Storage remove_properties error: {}", err); });
+            }
+
+            let mut has_changed = false;
+            for name in &names {
+                has_changed |= service.user_properties.remove(name).is_some();
+            }
+            if has_changed {
+                changed.push(service.id.clone());
             }
             result += 1;
         });
+        self.aux_notify_service_properties_changed(changed);
         result
     }
 
+    /// Notify topology watchers that the user-defined properties of some services have changed.
+    fn aux_notify_service_properties_changed(&self, services: Vec<Id<ServiceId>>) {
+        for id in services {
+            if let Some(service) = self.service_by_id.get(&id) {
+                let service = service.borrow();
+                let properties = service.user_properties.clone();
+                self.aux_notify_topology(&service, TopologyEvent::ServicePropertiesChanged {
+                    service: id.clone(),
+                    properties: properties,
+                });
+            }
+        }
+    }
+
     pub fn get_channels(&self, selectors: Vec<ChannelSelector>) -> Vec<Channel>
     {
         Self::aux_get_channels(selectors, &self.channel_by_id)
@@ -838,32 +1109,45 @@ impl State {
                 size += 1;
             });
         }
+        self.aux_notify_channel_tags_changed(channels.clone());
         (self.aux_channels_may_need_registration(channels), size)
     }
 
     pub fn remove_channel_tags(&mut self, selectors: Vec<ChannelSelector>, tags: Vec<Id<TagId>>) -> usize {
         let mut result = 0;
-        let db_path = self.db_path.clone();
-        Self::with_channels_mut(selectors, &mut self.channel_by_id, |mut data| {
-            if data.remove_tags(&tags) {
-                if let Some(ref path) = db_path {
-                    let mut store = TagStorage::new(path);
-                    store.remove_tags(&data.id, &tags)
-                         .unwrap_or_else(|err| { error!("Storage remove_tags error: {}", err); });
+        let mut changed = vec![];
+        {
+            let db_path = self.db_path.clone();
+            Self::with_channels_mut(selectors, &mut self.channel_by_id, |mut data| {
+                if data.remove_tags(&tags) {
+                    if let Some(ref path) = db_path {
+                        let mut store = TagStorage::new(path);
+                        store.remove_tags(&data.id, &tags)
+                             .unwrap_or_else(|err| { error!("Storage remove_tags error: {}", err); });
+                    }
+                    changed.push(data.id.clone());
                 }
-            }
-            Self::aux_channel_may_need_unregistration(&mut data, false);
-            result += 1;
-        });
+                Self::aux_channel_may_need_unregistration(&mut data, false);
+                result += 1;
+            });
+        }
+        self.aux_notify_channel_tags_changed(changed);
         result
     }
 
     /// Read the latest value from a set of channels
-    pub fn prepare_fetch_values(&self, selectors: Vec<ChannelSelector>) -> FetchRequest {
+    ///
+    /// Channels that `user` may not fetch are reported as `AccessDenied` errors.
+    pub fn prepare_fetch_values(&self, selectors: Vec<ChannelSelector>, max_age: Option<Duration>, user: &User)
+        -> (FetchRequest, ResultMap<Id<Channel>, Option<FetchedValue>, Error>)
+    {
         // First, prepare the list of actual getters and group it by adapter.
         // Once we have done this, we can release the lock.
         let mut per_adapter : FetchRequest = HashMap::new();
+        let mut cached = HashMap::new();
         let adapter_by_id = &self.adapter_by_id;
+        let value_cache = &self.value_cache;
+        let acl = &self.acl;
         Self::with_channels(selectors, &self.channel_by_id, |data| {
             if !data.supports_fetch {
                 return;
@@ -871,6 +1155,19 @@ impl State {
             use std::collections::hash_map::Entry::*;
             let id = data.channel.id.clone();
             let typ = data.channel.kind.get_type();
+
+            if !acl.is_allowed(user, &Operation::Fetch, &data.channel.service, &*data.service_tags.borrow(), &data.channel.tags) {
+                cached.insert(id.clone(), Err(Error::AccessDenied(AccessDenied::Channel(Operation::Fetch, id))));
+                return;
+            }
+
+            // Don't bother the device if we have a value that is recent enough.
+            if let Some(ref max_age) = max_age {
+                if let Some(value) = value_cache.get(&id, &typ, max_age) {
+                    cached.insert(id, Ok(Some(value)));
+                    return;
+                }
+            }
             match per_adapter.entry(data.adapter.clone()) {
                 Vacant(entry) => {
                     let adapter = match adapter_by_id.get(&data.channel.adapter) {
@@ -890,14 +1187,20 @@ impl State {
                 }
             };
         });
-        per_adapter
+        (per_adapter, cached)
     }
 
 
     /// Send values to a set of channels
-    pub fn prepare_send_values(&self, mut keyvalues: TargetMap<ChannelSelector, Payload>) -> SendRequest {
+    ///
+    /// Channels that `user` may not send to are reported as `AccessDenied` errors, along
+    /// with the value that was rejected.
+    pub fn prepare_send_values(&self, mut keyvalues: TargetMap<ChannelSelector, Payload>, user: &User)
+        -> (SendRequest, HashMap<Id<Channel>, (Payload, Error)>)
+    {
         // First determine the channels and group them by adapter.
         let mut per_adapter = HashMap::new();
+        let mut denied = HashMap::new();
         for Targetted { select: selectors, payload } in keyvalues.drain(..) {
             Self::with_channels(selectors, &self.channel_by_id, |data| {
                 use std::collections::hash_map::Entry::*;
@@ -905,6 +1208,10 @@ impl State {
                     return;
                 }
                 let id = data.channel.id.clone();
+                if !self.acl.is_allowed(user, &Operation::Send, &data.channel.service, &*data.service_tags.borrow(), &data.channel.tags) {
+                    denied.insert(id.clone(), (payload.clone(), Error::AccessDenied(AccessDenied::Channel(Operation::Send, id))));
+                    return;
+                }
                 let value = (payload.clone(), data.kind.get_type());
                 match per_adapter.entry(data.channel.adapter.clone()) {
                     Vacant(entry) => {
@@ -926,7 +1233,108 @@ impl State {
                 }
             })
         }
-        per_adapter
+        (per_adapter, denied)
+    }
+
+    pub fn set_acl_owner_check(&mut self, is_owner: OwnerCheck) {
+        self.acl.set_owner_check(is_owner)
+    }
+
+    pub fn is_acl_admin(&self, user: &User) -> bool {
+        self.acl.is_admin(user)
+    }
+
+    pub fn get_acl_rules(&self) -> Vec<AclRule> {
+        self.acl.get_rules()
+    }
+
+    pub fn add_acl_rules(&mut self, rules: Vec<AclRule>, user: &User) -> Result<usize, Error> {
+        let result = self.acl.add_rules(rules, user);
+        self.aux_refresh_watch_permissions();
+        result
+    }
+
+    pub fn remove_acl_rules(&mut self, rules: Vec<AclRule>, user: &User) -> Result<usize, Error> {
+        let result = self.acl.remove_rules(rules, user);
+        self.aux_refresh_watch_permissions();
+        result
+    }
+
+    pub fn get_acl_roles(&self) -> Vec<AclRole> {
+        self.acl.get_roles()
+    }
+
+    pub fn add_acl_role_members(&mut self, members: AclRole, user: &User) -> Result<usize, Error> {
+        let result = self.acl.add_role_members(members, user);
+        self.aux_refresh_watch_permissions();
+        result
+    }
+
+    pub fn remove_acl_role_members(&mut self, members: AclRole, user: &User) -> Result<usize, Error> {
+        let result = self.acl.remove_role_members(members, user);
+        self.aux_refresh_watch_permissions();
+        result
+    }
+
+    /// Check again whether ongoing watchers may watch their channels, once the access control
+    /// lists have changed. Watchers stop receiving the events of the channels that their user
+    /// may not watch anymore, and are informed with an `AccessDenied` error. They receive them
+    /// again if the access is granted back.
+    fn aux_refresh_watch_permissions(&self) {
+        for watcher in self.watchers.lock().unwrap().watchers.values() {
+            if watcher.user.is_none() {
+                continue;
+            }
+            let mut denied = watcher.denied.lock().unwrap();
+            for id in watcher.guards.borrow().keys() {
+                let may_watch = match self.channel_by_id.get(id) {
+                    None => continue,
+                    Some(data) => Self::may_watch(&self.acl, &watcher.user, &*data.borrow())
+                };
+                if may_watch {
+                    denied.remove(id);
+                } else if denied.insert(id.clone()) {
+                    let _ = watcher.on_event.lock().unwrap().send(Self::watch_denied(id));
+                }
+            }
+        }
+    }
+
+    /// Get the tags of a channel and of its service.
+    pub fn get_channel_and_service_tags(&self, id: &Id<Channel>) -> HashSet<Id<TagId>> {
+        match self.channel_by_id.get(id) {
+            None => HashSet::new(),
+            Some(data) => {
+                let data = data.borrow();
+                data.channel.tags.union(&*data.service_tags.borrow()).cloned().collect()
+            }
+        }
+    }
+
+    /// A registered channel, along with the tags of its service.
+    pub fn get_channel_with_service_tags(&self, id: &Id<Channel>) -> Option<(Channel, HashSet<Id<TagId>>)> {
+        self.channel_by_id.get(id).map(|data| {
+            let data = data.borrow();
+            (data.channel.clone(), data.service_tags.borrow().clone())
+        })
+    }
+
+    /// Determine whether `user` may perform `operation` on `channel`, whose service is tagged
+    /// with `service_tags`, regardless of whether the channel is still registered.
+    pub fn is_allowed_for_channel(&self, user: &User, operation: &Operation, channel: &Channel,
+        service_tags: &HashSet<Id<TagId>>) -> bool
+    {
+        self.acl.is_allowed(user, operation, &channel.service, service_tags, &channel.tags)
+    }
+
+    pub fn is_allowed(&self, user: &User, operation: &Operation, id: &Id<Channel>) -> bool {
+        match self.channel_by_id.get(id) {
+            None => false,
+            Some(data) => {
+                let data = data.borrow();
+                self.acl.is_allowed(user, operation, &data.channel.service, &*data.service_tags.borrow(), &data.channel.tags)
+            }
+        }
     }
 
     fn aux_start_channel_watch(watcher: &mut Arc<WatcherData>,
@@ -982,18 +1390,39 @@ impl State {
         insert_in_getter.commit();
     }
 
+    /// Determine whether a watcher for `user` may watch a channel. If `user` is `None`, the
+    /// watcher is not subject to the access control lists.
+    fn may_watch(acl: &Acl, user: &Option<User>, data: &ChannelData) -> bool {
+        match *user {
+            None => true,
+            Some(ref user) => acl.is_allowed(user, &Operation::Watch, &data.channel.service,
+                &*data.service_tags.borrow(), &data.channel.tags)
+        }
+    }
+
+    fn watch_denied(id: &Id<Channel>) -> WatchEvent {
+        WatchEvent::Error {
+            channel: id.clone(),
+            error: Error::AccessDenied(AccessDenied::Channel(Operation::Watch, id.clone()))
+        }
+    }
+
+    /// Register a watch on a set of channels, on behalf of `user`. Channels that `user` may not
+    /// watch are reported to `on_event` as errors. If `user` is `None`, the watch is not subject
+    /// to the access control lists.
     pub fn prepare_channel_watch(&mut self, mut watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>,
-        on_event: Box<ExtSender<WatchEvent>>) -> (WatchRequest, WatchKey, Arc<AtomicBool>)
+        on_event: Box<ExtSender<WatchEvent>>, user: Option<User>) -> (WatchRequest, WatchKey, Arc<AtomicBool>)
     {
         // Prepare the watcher and store it. Once we leave the lock, every time a channel is
         // added/removed/updated, this will cause us to reexamine whether the channel should
         // be visible to a watcher.
-        let mut watcher = self.watchers.lock().unwrap().create(watch.clone(), on_event.clone());
+        let mut watcher = self.watchers.lock().unwrap().create(watch.clone(), on_event.clone(), user.clone());
         let is_dropped = watcher.is_dropped.clone();
 
         // Regroup per adapter.
         let mut per_adapter = HashMap::new();
         let adapter_by_id = &self.adapter_by_id;
+        let acl = &self.acl;
         for Targetted { select: selectors, payload: filter } in watch.drain(..) {
             // Find out which channels already match the selectors and attach
             // the watcher immediately.
@@ -1002,6 +1431,10 @@ impl State {
                 if !data.channel.supports_watch {
                     return;
                 }
+                if !Self::may_watch(acl, &user, &*data) {
+                    let _ = on_event.send(Self::watch_denied(&data.channel.id));
+                    return;
+                }
                 Self::aux_start_channel_watch(&mut watcher, &mut data, filter,
                     adapter_by_id, &mut per_adapter)
             });
@@ -1012,12 +1445,36 @@ impl State {
         (per_adapter, watcher.key, is_dropped)
     }
 
+    /// The latest known value of each channel.
+    pub fn value_cache(&self) -> Arc<ValueCache> {
+        self.value_cache.clone()
+    }
+
+    /// Register a watch on services and channels being added/removed/retagged.
+    pub fn prepare_topology_watch(&mut self, selectors: Vec<ServiceSelector>,
+        on_event: Box<ExtSender<TopologyEvent>>) -> (WatchKey, Arc<AtomicBool>)
+    {
+        let key = self.watchers.lock().unwrap().create_key();
+        let is_dropped = Arc::new(AtomicBool::new(false));
+        self.topology_watchers.insert(key, TopologyWatcherData {
+            selectors: selectors,
+            on_event: Mutex::new(on_event),
+            is_dropped: is_dropped.clone(),
+        });
+        (key, is_dropped)
+    }
+
     /// Unregister a watch previously registered with `register_channel_watch`.
     ///
     /// This method is dispatched from `WatchGuard::drop()`.
     pub fn stop_watch(&mut self, key: WatchKey) {
         // Note: no matter when we arrive here, `is_dropped` is already set to `true`.
 
+        // Topology watchers are not attached to any channel, so removing them is sufficient.
+        if self.topology_watchers.remove(&key).is_some() {
+            return;
+        }
+
         // Remove `key` from `watchers`. This will prevent the watcher from being registered
         // automatically with any new getter.
         let watcher_data = match self.watchers.lock().unwrap().remove(key) {
@@ -1050,7 +1507,9 @@ impl State {
     }
 
     /// Start watching a set of channels.
-    pub fn start_watch(mut per_adapter: WatchRequest) -> WatchGuardCommit {
+    ///
+    /// Values received by the watch are recorded in `value_cache`.
+    pub fn start_watch(mut per_adapter: WatchRequest, value_cache: &Arc<ValueCache>) -> WatchGuardCommit {
         // In most cases, stop_watch will take place long after start_watch. It is, however,
         // possible that the `WatchGuard` is dropped before start_watch is processed for this
         // channel. In this case, three events take place:
@@ -1086,6 +1545,8 @@ impl State {
                     debug!(target: "Taxonomy-backend", "State::start_watch, the guard has been dropped, is_dropped detected, skipping.");
                     return continue;
                 }
+                let value_cache = value_cache.clone();
+                let denied = watch_data.denied.clone();
                 let on_ok = watch_data.on_event.lock().unwrap().filter_map(move |event| {
                     if is_dropped.load(Ordering::Relaxed) {
                         debug!(target: "Taxonomy-backend", "State::start_watch, the guard has been dropped, is_dropped detected, don't propagate messages.");
@@ -1096,19 +1557,32 @@ impl State {
                         // the call to `stop_watch`.
                         return None;
                     }
+                    let is_denied = match event {
+                        AdapterWatchEvent::Enter { ref id, .. } |
+                        AdapterWatchEvent::Exit { ref id, .. } |
+                        AdapterWatchEvent::Error { ref id, .. } => denied.lock().unwrap().contains(id)
+                    };
+                    if is_denied {
+                        // The user of the watcher may not watch this channel anymore.
+                        return None;
+                    }
                     Some(match event {
-                        AdapterWatchEvent::Enter { id, value: (payload, type_) } =>
+                        AdapterWatchEvent::Enter { id, value: (payload, type_) } => {
+                            value_cache.insert_now(id.clone(), payload.clone(), type_.clone());
                             WatchEvent::EnterRange {
                                 channel: id,
                                 value: payload,
                                 type_: type_
-                            },
-                        AdapterWatchEvent::Exit { id, value: (payload, type_) } =>
+                            }
+                        },
+                        AdapterWatchEvent::Exit { id, value: (payload, type_) } => {
+                            value_cache.insert_now(id.clone(), payload.clone(), type_.clone());
                             WatchEvent::ExitRange {
                                 channel: id,
                                 value: payload,
                                 type_: type_
-                            },
+                            }
+                        },
                         AdapterWatchEvent::Error { id, error } =>
                             WatchEvent::Error {
                                 channel: id,
@@ -1166,5 +1640,7 @@ impl State {
         self.service_by_id.clear();
         self.channel_by_id.clear();
         self.watchers.lock().unwrap().watchers.clear();
+        self.topology_watchers.clear();
+        self.value_cache.clear();
     }
 }
@@ -0,0 +1,337 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Recording the values of channels over time.
+//!
+//! A `HistoryRecorder` watches a set of channels and stores every value they
+//! produce in a database, so that clients can later query, for instance, the
+//! temperature over the last 24 hours.
+//!
+//! Along with the values, the recorder keeps a description of each channel it has
+//! recorded, so that the history of a channel remains available once the channel has
+//! been removed, e.g. because its device was unplugged. To keep the database from
+//! growing forever, only the latest `MAX_HISTORY_ENTRIES` values are kept.
+
+use api::{ API, Error, InternalError, Operation, Targetted, User, WatchEvent };
+use io::*;
+use manager::{ AdapterManager, WatchGuard };
+use selector::*;
+use services::*;
+use values::{ TimeStamp, Type };
+
+use chrono::UTC;
+use rusqlite::Connection;
+use serde_json;
+use transformable_channels::mpsc::*;
+
+use std::collections::{ HashMap, HashSet };
+use std::i64;
+use std::path::Path as FilePath;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+/// A single value recorded for a channel.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// The channel that produced the value.
+    pub channel: Id<Channel>,
+
+    /// The instant at which the value was received, with a precision of one second.
+    pub timestamp: TimeStamp,
+
+    /// The actual value.
+    pub value: Payload,
+
+    pub type_: Type,
+}
+
+impl ToJSON for HistoryEntry {
+    fn to_json(&self) -> JSON {
+        vec![
+            ("channel", self.channel.to_json()),
+            ("timestamp", self.timestamp.to_json()),
+            ("value", self.value.to_json()),
+            ("type", self.type_.to_json()),
+        ].to_json()
+    }
+}
+
+/// A request for the values recorded for some channels.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `channels` (array of `ChannelSelector`, required): the channels to look for;
+/// - `from` (`TimeStamp`, optional): ignore values recorded before this instant;
+/// - `to` (`TimeStamp`, optional): ignore values recorded after this instant;
+/// - `limit` (positive integer, optional): return at most this number of values.
+///
+/// ```
+/// use foxbox_taxonomy::history::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// let source = r#"{
+///   "channels": [{"tags": ["temperature"]}],
+///   "from": "2016-06-01T00:00:00Z",
+///   "limit": 100
+/// }"#;
+///
+/// let query = HistoryQuery::from_str(source).unwrap();
+/// assert_eq!(query.channels.len(), 1);
+/// assert!(query.from.is_some());
+/// assert!(query.to.is_none());
+/// assert_eq!(query.limit, Some(100));
+/// ```
+#[derive(Clone, Debug)]
+pub struct HistoryQuery {
+    pub channels: Vec<ChannelSelector>,
+    pub from: Option<TimeStamp>,
+    pub to: Option<TimeStamp>,
+    pub limit: Option<u32>,
+}
+
+impl Parser<HistoryQuery> for HistoryQuery {
+    fn description() -> String {
+        "HistoryQuery".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let channels = try!(path.push("channels", |path| ChannelSelector::take_vec(path, source, "channels")));
+        let from = match path.push("from", |path| TimeStamp::take_opt(path, source, "from")) {
+            Some(Ok(from)) => Some(from),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let to = match path.push("to", |path| TimeStamp::take_opt(path, source, "to")) {
+            Some(Ok(to)) => Some(to),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let limit = match path.push("limit", |path| u32::take_opt(path, source, "limit")) {
+            Some(Ok(limit)) => Some(limit),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        Ok(HistoryQuery {
+            channels: channels,
+            from: from,
+            to: to,
+            limit: limit
+        })
+    }
+}
+
+/// The maximal number of values kept in the database. Older values are discarded.
+pub const MAX_HISTORY_ENTRIES: i64 = 100_000;
+
+fn sql_error<E>(err: E) -> Error where E: ::std::fmt::Debug {
+    Error::InternalError(InternalError::GenericError(format!("History database error: {:?}", err)))
+}
+
+/// Records the values of channels into a `SQLite` database.
+///
+/// Recording starts as soon as the recorder is created and stops once it is dropped.
+/// As recording is implemented with `API::watch_values`, channels that are added or
+/// tagged later are recorded as soon as they match the selectors.
+pub struct HistoryRecorder {
+    api: Arc<AdapterManager>,
+    db: Arc<Mutex<Connection>>,
+
+    /// Dropping the guard stops the recording.
+    _guard: WatchGuard,
+}
+
+impl HistoryRecorder {
+    /// Start recording the values of all the channels matching `selectors`.
+    ///
+    /// If `path` is `None`, the values are only recorded in memory.
+    pub fn new(api: &Arc<AdapterManager>, path: Option<&FilePath>, selectors: Vec<ChannelSelector>) -> Result<Self, Error> {
+        let db = try!(match path {
+            Some(path) => {
+                debug!("Opening channel history database at {}", path.display());
+                Connection::open(path)
+            },
+            None => Connection::open_in_memory()
+        }.map_err(sql_error));
+
+        try!(db.execute("CREATE TABLE IF NOT EXISTS history (
+                channel    TEXT NOT NULL,
+                timestamp  INTEGER NOT NULL,
+                value      TEXT NOT NULL,
+                type       TEXT NOT NULL
+            )", &[]).map_err(sql_error));
+        try!(db.execute("CREATE INDEX IF NOT EXISTS history_by_channel ON history (channel, timestamp)", &[])
+            .map_err(sql_error));
+        try!(db.execute("CREATE TABLE IF NOT EXISTS history_channels (
+                channel       TEXT NOT NULL PRIMARY KEY,
+                description   TEXT NOT NULL,
+                service_tags  TEXT NOT NULL
+            )", &[]).map_err(sql_error));
+
+        let db = Arc::new(Mutex::new(db));
+
+        let (tx, rx) = channel();
+        // Values are recorded regardless of the access control lists, which are enforced
+        // by `get_history`.
+        let guard = api.watch_values_unrestricted(vec![Targetted::new(selectors, Exactly::Always)], Box::new(tx));
+
+        let db_thread = db.clone();
+        let api_thread = api.clone();
+        thread::spawn(move || {
+            // The descriptions last stored in `history_channels`, to avoid rewriting them
+            // for every value.
+            let mut stored = HashMap::new();
+            for event in rx {
+                if let WatchEvent::EnterRange { channel, value, type_ } = event {
+                    if let Some((description, service_tags)) = api_thread.get_channel_with_service_tags(&channel) {
+                        if let Err(err) = Self::store_channel(&db_thread, &mut stored, &description, &service_tags) {
+                            warn!("Could not record description of channel {}: {:?}", channel, err);
+                        }
+                    }
+                    let entry = HistoryEntry {
+                        channel: channel,
+                        timestamp: TimeStamp::from_datetime(UTC::now()),
+                        value: value,
+                        type_: type_
+                    };
+                    if let Err(err) = Self::store(&db_thread, &entry) {
+                        warn!("Could not record value of channel {}: {:?}", entry.channel, err);
+                    }
+                }
+            }
+        });
+
+        Ok(HistoryRecorder {
+            api: api.clone(),
+            db: db,
+            _guard: guard,
+        })
+    }
+
+    fn store(db: &Mutex<Connection>, entry: &HistoryEntry) -> Result<(), Error> {
+        let value = try!(serde_json::to_string(&entry.value.to_json()).map_err(sql_error));
+        let type_ = try!(serde_json::to_string(&entry.type_.to_json()).map_err(sql_error));
+        let db = db.lock().unwrap();
+        try!(db.execute("INSERT INTO history VALUES ($1, $2, $3, $4)",
+            &[&entry.channel.to_string(), &entry.timestamp.as_datetime().timestamp(), &value, &type_])
+            .map_err(sql_error));
+        Self::discard_old_entries(&db)
+    }
+
+    fn discard_old_entries(db: &Connection) -> Result<(), Error> {
+        let limit = db.last_insert_rowid() - MAX_HISTORY_ENTRIES;
+        if limit <= 0 {
+            return Ok(());
+        }
+        try!(db.execute("DELETE FROM history WHERE rowid <= $1", &[&limit]).map_err(sql_error));
+        Ok(())
+    }
+
+    fn store_channel(db: &Mutex<Connection>, stored: &mut HashMap<Id<Channel>, (String, String)>,
+        channel: &Channel, service_tags: &HashSet<Id<TagId>>) -> Result<(), Error>
+    {
+        let description = try!(serde_json::to_string(channel).map_err(sql_error));
+        let service_tags = try!(serde_json::to_string(service_tags).map_err(sql_error));
+        let record = (description, service_tags);
+        if stored.get(&channel.id) == Some(&record) {
+            return Ok(());
+        }
+        {
+            let db = db.lock().unwrap();
+            try!(db.execute("INSERT OR REPLACE INTO history_channels VALUES ($1, $2, $3)",
+                &[&channel.id.to_string(), &record.0, &record.1])
+                .map_err(sql_error));
+        }
+        stored.insert(channel.id.clone(), record);
+        Ok(())
+    }
+
+    /// The channels that may have recorded values, along with the tags of their service.
+    ///
+    /// Channels that are still registered are described as they are now, the others as
+    /// they were when they last produced a value.
+    fn get_recorded_channels(&self, db: &Connection, selectors: Vec<ChannelSelector>)
+        -> Result<HashMap<Id<Channel>, (Channel, HashSet<Id<TagId>>)>, Error>
+    {
+        let mut channels = HashMap::new();
+        let mut stmt = try!(db.prepare("SELECT description, service_tags FROM history_channels").map_err(sql_error));
+        let rows = try!(stmt.query(&[]).map_err(sql_error));
+        for row in rows {
+            let row = try!(row.map_err(sql_error));
+            let description: String = row.get(0);
+            let service_tags: String = row.get(1);
+            let channel: Channel = try!(serde_json::from_str(&description).map_err(sql_error));
+            let service_tags: HashSet<Id<TagId>> = try!(serde_json::from_str(&service_tags).map_err(sql_error));
+            channels.insert(channel.id.clone(), (channel, service_tags));
+        }
+
+        // Values recorded before the channel descriptions were stored only match
+        // channels that are still registered.
+        for channel in self.api.get_channels(selectors) {
+            if !channels.contains_key(&channel.id) {
+                channels.insert(channel.id.clone(), (channel.clone(), HashSet::new()));
+            }
+        }
+
+        for (id, entry) in &mut channels {
+            if let Some(current) = self.api.get_channel_with_service_tags(id) {
+                *entry = current;
+            }
+        }
+        Ok(channels)
+    }
+
+    /// Get the values recorded for the channels matching `selectors`, between `from`
+    /// and `to` (inclusive, either of which may be omitted).
+    ///
+    /// Entries are returned in chronological order. If `limit` is specified, only
+    /// the first `limit` entries are returned. Channels that `user` may not watch are skipped.
+    ///
+    /// Channels that have been removed since their values were recorded are matched
+    /// against `selectors` as they were when they were last recorded.
+    pub fn get_history(&self, selectors: Vec<ChannelSelector>, from: Option<TimeStamp>, to: Option<TimeStamp>,
+        limit: Option<u32>, user: &User) -> Result<Vec<HistoryEntry>, Error>
+    {
+        let from = from.map_or(i64::MIN, |ts| ts.as_datetime().timestamp());
+        let to = to.map_or(i64::MAX, |ts| ts.as_datetime().timestamp());
+        let sql_limit = limit.map_or(-1, |limit| limit as i64);
+
+        let db = self.db.lock().unwrap();
+        let channels = try!(self.get_recorded_channels(&db, selectors.clone()));
+        let mut stmt = try!(db.prepare("SELECT timestamp, value, type FROM history
+            WHERE channel = $1 AND timestamp >= $2 AND timestamp <= $3
+            ORDER BY timestamp, rowid LIMIT $4").map_err(sql_error));
+
+        let mut entries = vec![];
+        for (id, (channel, service_tags)) in channels {
+            if !selectors.iter().any(|selector| selector.matches(&service_tags, &channel)) {
+                continue;
+            }
+            if !self.api.is_allowed_for_channel(user, Operation::Watch, &channel, &service_tags) {
+                continue;
+            }
+            let rows = try!(stmt.query(&[&id.to_string(), &from, &to, &sql_limit]).map_err(sql_error));
+            for row in rows {
+                let row = try!(row.map_err(sql_error));
+                let timestamp: i64 = row.get(0);
+                let value: String = row.get(1);
+                let type_: String = row.get(2);
+                entries.push(HistoryEntry {
+                    channel: id.clone(),
+                    timestamp: TimeStamp::from_s(timestamp),
+                    value: try!(Payload::from_str(&value).map_err(Error::ParseError)),
+                    type_: try!(Type::from_str(&type_).map_err(Error::ParseError)),
+                });
+            }
+        }
+
+        // Each channel was queried separately, so merge the results. The sort is stable,
+        // which preserves the order of values recorded during the same second.
+        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        if let Some(limit) = limit {
+            entries.truncate(limit as usize);
+        }
+        Ok(entries)
+    }
+}
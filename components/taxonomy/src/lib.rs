@@ -57,6 +57,15 @@ pub mod transact;
 /// Implementation of the database storing tags.
 pub mod tag_storage;
 
+/// Access control lists on channels.
+pub mod acl;
+
+/// Recording who sent what to which channel.
+pub mod audit;
+
+/// Recording the values of channels over time.
+pub mod history;
+
 /// Implementation of a fake adapter, controlled entirely programmatically. Designed to be used
 /// as a component of tests.
 pub mod fake_adapter;
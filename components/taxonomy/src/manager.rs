@@ -5,21 +5,24 @@
 //! - it exposes an implementation of the taxonomy API.
 
 pub use adapter::*;
+use acl::{ AclRole, AclRule, OwnerCheck };
 use api;
-use api::{ API, Error, TargetMap, User };
+use api::{ AccessDenied, API, Error, FetchedValue, Operation, TargetMap, User };
+use audit::{ AuditEntry, AuditLog, AuditQuery };
 use backend::*;
 use io::*;
 use selector::*;
 use services::*;
 use util::is_sync;
-use values::Type;
+use values::{ Duration, TimeStamp, Type };
 
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 use std::path::PathBuf;
 use std::sync::{ Arc, Mutex, Weak };
 use std::sync::atomic::{ AtomicBool, Ordering };
 use std::thread;
 
+use chrono::UTC;
 use sublock::atomlock::*;
 use transformable_channels::mpsc::*;
 
@@ -37,6 +40,13 @@ pub struct AdapterManager {
     back_end: Arc<MainLock<State>>,
 
     tx_watch: Arc<Mutex<RawSender<WatchOp>>>,
+
+    /// The latest known value of each channel. Shared with the back-end, but
+    /// updated without holding the lock.
+    value_cache: Arc<ValueCache>,
+
+    /// The record of values sent and scripts executed. Written without holding the lock.
+    audit: AuditLog,
 }
 
 impl AdapterManager {
@@ -46,11 +56,83 @@ impl AdapterManager {
         // The code should build only if AdapterManager implements Sync.
         is_sync::<AdapterManager>();
 
+        let audit = AuditLog::new(db_path.as_ref().map(|path| path.as_path())).or_else(|err| {
+            error!("Could not open the audit log, recording in memory only: {}", err);
+            AuditLog::new(None)
+        }).unwrap();
         let state = Arc::new(MainLock::new(|liveness| State::new(liveness, db_path)));
-        let tx_watch = Arc::new(Mutex::new(Self::handle_watches(Arc::downgrade(&state))));
+        let value_cache = state.read().unwrap().value_cache();
+        let tx_watch = Arc::new(Mutex::new(Self::handle_watches(Arc::downgrade(&state), value_cache.clone())));
         AdapterManager {
             back_end: state,
             tx_watch: tx_watch,
+            value_cache: value_cache,
+            audit: audit,
+        }
+    }
+
+    /// Set the check determining who owns the box. While the role `admin` has no members,
+    /// only the owners may modify the access control lists.
+    pub fn set_acl_owner_check(&self, is_owner: OwnerCheck) {
+        self.back_end.write().unwrap().set_acl_owner_check(is_owner)
+    }
+
+    /// A channel, along with the tags of its service, if the channel is currently registered.
+    pub fn get_channel_with_service_tags(&self, id: &Id<Channel>) -> Option<(Channel, HashSet<Id<TagId>>)> {
+        self.back_end.read().unwrap().get_channel_with_service_tags(id)
+    }
+
+    /// Like `API::is_allowed`, for a channel that need not be registered anymore, e.g. to
+    /// expose the values it produced before it was removed.
+    pub fn is_allowed_for_channel(&self, user: &User, operation: Operation, channel: &Channel,
+        service_tags: &HashSet<Id<TagId>>) -> bool
+    {
+        self.back_end.read().unwrap().is_allowed_for_channel(user, &operation, channel, service_tags)
+    }
+
+    /// Watch for changes from channels on behalf of the box itself, regardless of the access
+    /// control lists. This is meant for components that enforce the access control lists
+    /// themselves when exposing the values, e.g. the history.
+    pub fn watch_values_unrestricted(&self, watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>,
+        on_event: Box<ExtSender<api::WatchEvent>>) -> WatchGuard
+    {
+        self.aux_watch_values(watch, on_event, None)
+    }
+
+    fn aux_watch_values(&self, watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>,
+        on_event: Box<ExtSender<api::WatchEvent>>, user: Option<User>) -> WatchGuard
+    {
+        let (request, watch_key, is_dropped) =
+        {
+            // Acquire and release write lock.
+            self.back_end.write()
+                .unwrap()
+                .prepare_channel_watch(watch, on_event, user)
+        };
+
+        if !request.is_empty() {
+            debug!(target: "Taxonomy-manager", "manager.watch_values => need to register watches");
+        }
+        self.register_watches(request);
+        WatchGuard::new(self.tx_watch.lock().unwrap().internal_clone(), watch_key, is_dropped)
+    }
+
+    /// Record values sent to channels in the audit log.
+    fn audit_send_values(&self, user: &User, sent: HashMap<Id<Channel>, Payload>,
+        results: &ResultMap<Id<Channel>, (), Error>)
+    {
+        let tags : HashMap<_, _> = {
+            let back_end = self.back_end.read().unwrap();
+            sent.keys().map(|id| (id.clone(), back_end.get_channel_and_service_tags(id))).collect()
+        };
+        for (id, value) in sent {
+            let result = match results.get(&id) {
+                Some(result) => result,
+                None => continue
+            };
+            if let Err(err) = self.audit.record_send(user, &id, &tags[&id], &value, result) {
+                warn!("Could not record value sent to channel {}: {:?}", id, err);
+            }
         }
     }
 }
@@ -199,6 +281,16 @@ impl API for AdapterManager {
         self.back_end.write().unwrap().remove_service_tags(selectors, tags)
     }
 
+    /// Set user-defined properties on a set of services.
+    fn set_service_properties(&self, selectors: Vec<ServiceSelector>, properties: HashMap<String, String>) -> usize {
+        self.back_end.write().unwrap().set_service_properties(selectors, properties)
+    }
+
+    /// Remove user-defined properties from a set of services.
+    fn remove_service_properties(&self, selectors: Vec<ServiceSelector>, properties: Vec<String>) -> usize {
+        self.back_end.write().unwrap().remove_service_properties(selectors, properties)
+    }
+
     /// Get a list of channels matching some conditions
     fn get_channels(&self, selectors: Vec<ChannelSelector>) -> Vec<Channel> {
         self.back_end.read().unwrap().get_channels(selectors)
@@ -246,23 +338,33 @@ impl API for AdapterManager {
         self.back_end.write().unwrap().remove_channel_tags(selectors, tags)
     }
 
-    /// Read the latest value from a set of channels
-    fn fetch_values(&self, selectors: Vec<ChannelSelector>, user: User) ->
-        ResultMap<Id<Channel>, Option<(Payload, Type)>, Error>
+    /// Read the latest value from a set of channels, using cached values if they are
+    /// at most `max_age` old.
+    fn fetch_values(&self, selectors: Vec<ChannelSelector>, max_age: Option<Duration>, user: User) ->
+        ResultMap<Id<Channel>, Option<FetchedValue>, Error>
     {
         // First, prepare the request.
-        let mut request;
-        {
+        let (mut request, mut results) = {
             // Make sure that the lock is released asap.
-            request = self.back_end.read().unwrap().prepare_fetch_values(selectors);
-        }
-        // Now fetch the values
-        let mut results = HashMap::new();
+            self.back_end.read().unwrap().prepare_fetch_values(selectors, max_age, &user)
+        };
+        // Now fetch the values that were not in the cache.
         for (_, (adapter, mut channels)) in request.drain() {
             let channels = channels.drain().collect();
             let got = adapter.fetch_values(channels, user.clone());
-
-            results.extend(got);
+            let timestamp = TimeStamp::from_datetime(UTC::now());
+
+            for (id, result) in got {
+                let result = result.map(|value| value.map(|(payload, type_)| FetchedValue {
+                    value: payload,
+                    type_: type_,
+                    timestamp: timestamp.clone(),
+                }));
+                if let Ok(Some(ref value)) = result {
+                    self.value_cache.insert(id.clone(), value.clone());
+                }
+                results.insert(id, result);
+            }
         }
         results
     }
@@ -272,39 +374,91 @@ impl API for AdapterManager {
         ResultMap<Id<Channel>, (), Error>
     {
         // First, prepare the request.
-        let mut prepared;
-        {
+        let (mut prepared, denied) = {
             // Make sure that the lock is released asap.
-            prepared = self.back_end.read().unwrap().prepare_send_values(keyvalues);
+            self.back_end.read().unwrap().prepare_send_values(keyvalues, &user)
+        };
+
+        let mut results = HashMap::new();
+        let mut sent = HashMap::new();
+        for (id, (value, err)) in denied {
+            sent.insert(id.clone(), value);
+            results.insert(id, Err(err));
         }
 
         // Dispatch to adapter
-        let mut results = HashMap::new();
         for (_, (adapter, request)) in prepared.drain() {
+            for (id, &(ref value, _)) in &request {
+                sent.insert(id.clone(), value.clone());
+            }
             let got = adapter.send_values(request, user.clone());
             results.extend(got);
         }
 
+        self.audit_send_values(&user, sent, &results);
         results
     }
 
     /// Watch for any change
     fn watch_values(&self, watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>,
-        on_event: Box<ExtSender<api::WatchEvent>>) -> Self::WatchGuard
+        on_event: Box<ExtSender<api::WatchEvent>>, user: User) -> Self::WatchGuard
     {
-        let (request, watch_key, is_dropped) =
+        self.aux_watch_values(watch, on_event, Some(user))
+    }
+
+    /// Watch for services and channels being added, removed or retagged.
+    fn watch_topology(&self, selectors: Vec<ServiceSelector>,
+        on_event: Box<ExtSender<api::TopologyEvent>>) -> Self::WatchGuard
+    {
+        let (watch_key, is_dropped) =
         {
             // Acquire and release write lock.
             self.back_end.write()
                 .unwrap()
-                .prepare_channel_watch(watch, on_event)
+                .prepare_topology_watch(selectors, on_event)
         };
+        WatchGuard::new(self.tx_watch.lock().unwrap().internal_clone(), watch_key, is_dropped)
+    }
 
-        if !request.is_empty() {
-            debug!(target: "Taxonomy-manager", "manager.watch_values => need to register watches");
+    fn get_acl_rules(&self) -> Vec<AclRule> {
+        self.back_end.read().unwrap().get_acl_rules()
+    }
+
+    fn add_acl_rules(&self, rules: Vec<AclRule>, user: User) -> Result<usize, Error> {
+        self.back_end.write().unwrap().add_acl_rules(rules, &user)
+    }
+
+    fn remove_acl_rules(&self, rules: Vec<AclRule>, user: User) -> Result<usize, Error> {
+        self.back_end.write().unwrap().remove_acl_rules(rules, &user)
+    }
+
+    fn get_acl_roles(&self) -> Vec<AclRole> {
+        self.back_end.read().unwrap().get_acl_roles()
+    }
+
+    fn add_acl_role_members(&self, members: AclRole, user: User) -> Result<usize, Error> {
+        self.back_end.write().unwrap().add_acl_role_members(members, &user)
+    }
+
+    fn remove_acl_role_members(&self, members: AclRole, user: User) -> Result<usize, Error> {
+        self.back_end.write().unwrap().remove_acl_role_members(members, &user)
+    }
+
+    fn is_allowed(&self, user: &User, operation: Operation, channel: &Id<Channel>) -> bool {
+        self.back_end.read().unwrap().is_allowed(user, &operation, channel)
+    }
+
+    fn get_audit_log(&self, query: AuditQuery, user: User) -> Result<Vec<AuditEntry>, Error> {
+        if !self.back_end.read().unwrap().is_acl_admin(&user) {
+            return Err(Error::AccessDenied(AccessDenied::AuditLog));
+        }
+        self.audit.get_entries(query)
+    }
+
+    fn audit_script_execution(&self, script: &str, rule_index: usize, owner: &User) {
+        if let Err(err) = self.audit.record_script_execution(owner, script, rule_index) {
+            warn!("Could not record execution of rule {} of script {}: {:?}", rule_index, script, err);
         }
-        self.register_watches(request);
-        WatchGuard::new(self.tx_watch.lock().unwrap().internal_clone(), watch_key, is_dropped)
     }
 
     /// A value that causes a disconnection once it is dropped.
@@ -336,7 +490,7 @@ impl AdapterManager {
     }
 
     /// Start the background thread .
-    fn handle_watches(state: Weak<MainLock<State>>) -> RawSender<WatchOp> {
+    fn handle_watches(state: Weak<MainLock<State>>, value_cache: Arc<ValueCache>) -> RawSender<WatchOp> {
         let (tx, rx) = channel();
         let state = state.clone();
         thread::spawn(move || {
@@ -346,7 +500,7 @@ impl AdapterManager {
                     Some(backend) =>
                         match msg {
                             WatchOp::Start(request, tx) => {
-                                let add = State::start_watch(request);
+                                let add = State::start_watch(request, &value_cache);
                                 backend.write().unwrap().register_ongoing_watch(add);
                                 let _ = tx.send(());
                             }
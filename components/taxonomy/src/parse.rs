@@ -307,6 +307,34 @@ impl Parser<u8> for u8 {
     }
 }
 
+impl Parser<u32> for u32 {
+    fn description() -> String {
+        "u32".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match source.as_u64() {
+            None => Err(ParseError::type_error("as u32", &path, "positive integer")),
+            Some(ref val) if *val > u32::max_value() as u64 =>
+                Err(ParseError::type_error("as u32", &path, "positive integer")),
+            Some(ref val) => Ok(*val as u32)
+        }
+    }
+}
+
+impl Parser<i32> for i32 {
+    fn description() -> String {
+        "i32".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match source.as_i64() {
+            None => Err(ParseError::type_error("as i32", &path, "integer")),
+            Some(ref val) if *val > i32::max_value() as i64 || *val < i32::min_value() as i64 =>
+                Err(ParseError::type_error("as i32", &path, "integer")),
+            Some(ref val) => Ok(*val as i32)
+        }
+    }
+}
+
 impl<T> Parser<Vec<T>> for Vec<T> where T: Parser<T> {
     fn description() -> String {
         format!("Array<{}>", T::description())
@@ -335,6 +363,25 @@ impl<T> Parser<Vec<T>> for Vec<T> where T: Parser<T> {
     }
 }
 
+impl<T> Parser<HashMap<String, T>> for HashMap<String, T> where T: Parser<T> {
+    fn description() -> String {
+        format!("Object<{}>", T::description())
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::Object(ref obj) => {
+                let mut result = HashMap::with_capacity(obj.len());
+                for (key, source) in obj {
+                    let value = try!(path.push(key, |path| T::parse(path, source)));
+                    result.insert(key.clone(), value);
+                }
+                Ok(result)
+            }
+            _ => Err(ParseError::type_error(&Self::description() as &str, &path, "object"))
+        }
+    }
+}
+
 /*
 impl<T, U> Parser<(T, U)> for (T, U) where T: Parser<T>, U: Parser<U> {
     fn description() -> String {
@@ -11,7 +11,7 @@ use values::Duration;
 
 use std::cmp;
 use std::hash::Hash;
-use std::collections::HashSet;
+use std::collections::{ HashMap, HashSet };
 
 fn merge<T>(mut a: HashSet<T>, b: Vec<T>) -> HashSet<T> where T: Hash + Eq {
     for x in b {
@@ -30,6 +30,10 @@ pub trait ServiceLike {
     fn id(&self) -> &Id<ServiceId>;
     fn adapter(&self) -> &Id<AdapterId>;
     fn with_tags<F>(&self, f: F) -> bool where F: Fn(&HashSet<Id<TagId>>) -> bool;
+
+    /// The value of a property, looking first at the user-defined properties, then
+    /// at the properties set by the adapter.
+    fn property(&self, name: &str) -> Option<&str>;
     #[deprecated]
     fn has_setters<F>(&self, f: F) -> bool where F: Fn(&Channel) -> bool {
         self.has_channels(|chan| {
@@ -55,6 +59,11 @@ impl ServiceLike for Service {
     fn with_tags<F>(&self, f: F) -> bool where F: Fn(&HashSet<Id<TagId>>) -> bool {
         f(&self.tags)
     }
+    fn property(&self, name: &str) -> Option<&str> {
+        self.user_properties.get(name)
+            .or_else(|| self.properties.get(name))
+            .map(|value| value as &str)
+    }
     fn has_channels<F>(&self, f: F) -> bool where F: Fn(&Channel) -> bool {
         for chan in self.channels.values() {
             if f(chan) {
@@ -84,7 +93,15 @@ impl ServiceLike for Service {
 /// A selector is an object with the following fields:
 ///
 /// - (optional) string `id`: accept only a service with a given id;
+/// - (optional) string `adapter`: accept only services offered by a given adapter;
 /// - (optional) array of string `tags`:  accept only services with all the tags in the array;
+/// - (optional) array of string `without_tags`:  accept only services with none of the tags
+///    in the array;
+/// - (optional) array of string `any_of_tags`:  accept only services with at least one of the
+///    tags in the array;
+/// - (optional) object `properties` (values are `PropertyMatcher`): accept only services whose
+///    properties match all the matchers in the object, e.g. `{"room": "kitchen"}`. User-defined
+///    properties take precedence over the properties set by the adapter;
 /// - (optional) array of objects `getters` (see `ChannelSelector`): accept only services with
 ///    channels matching all the selectors in this array;
 /// - (optional) array of objects `setters` (see `ChannelSelector`): accept only services with
@@ -98,7 +115,11 @@ impl ServiceLike for Service {
 /// // A selector with all fields defined.
 /// let json_selector = "{
 ///   \"id\": \"setter 1\",
+///   \"adapter\": \"adapter 1\",
 ///   \"tags\": [\"tag 1\", \"tag 2\"],
+///   \"without_tags\": [\"tag 5\"],
+///   \"any_of_tags\": [\"tag 6\", \"tag 7\"],
+///   \"properties\": {\"room\": \"kitchen\", \"model\": {\"contains\": \"Hue\"}},
 ///   \"getters\": [{
 ///     \"kind\": \"Ready\"
 ///   }],
@@ -121,9 +142,22 @@ pub struct ServiceSelector {
     /// If `Exactly(id)`, return only the service with the corresponding id.
     pub id: Exactly<Id<ServiceId>>,
 
+    /// If `Exactly(id)`, return only services offered by the adapter with the corresponding id.
+    pub adapter: Exactly<Id<AdapterId>>,
+
     ///  Restrict results to services that have all the tags in `tags`.
     pub tags: HashSet<Id<TagId>>,
 
+    ///  Restrict results to services that have none of the tags in `without_tags`.
+    pub without_tags: HashSet<Id<TagId>>,
+
+    ///  Restrict results to services that have, for each set in `any_of_tags`, at
+    ///  least one of the tags of the set.
+    pub any_of_tags: Vec<HashSet<Id<TagId>>>,
+
+    /// Restrict results to services whose properties match all the matchers in `properties`.
+    pub properties: HashMap<String, PropertyMatcher>,
+
     /// Restrict results to services that have all the getters in `getters`.
     pub getters: Vec<ChannelSelector>,
 
@@ -147,6 +181,13 @@ impl Parser<ServiceSelector> for ServiceSelector {
                 result
             }
         });
+        let adapter = try!(match path.push("adapter", |path| Exactly::take_opt(path, source, "adapter")) {
+            None => Ok(Exactly::Always),
+            Some(result) => {
+                is_empty = false;
+                result
+            }
+        });
         let tags : HashSet<_> = match path.push("tags", |path| Id::take_vec_opt(path, source, "tags")) {
             None => HashSet::new(),
             Some(Ok(mut vec)) => {
@@ -155,6 +196,30 @@ impl Parser<ServiceSelector> for ServiceSelector {
             }
             Some(Err(err)) => return Err(err),
         };
+        let without_tags : HashSet<_> = match path.push("without_tags", |path| Id::take_vec_opt(path, source, "without_tags")) {
+            None => HashSet::new(),
+            Some(Ok(mut vec)) => {
+                is_empty = false;
+                vec.drain(..).collect()
+            }
+            Some(Err(err)) => return Err(err),
+        };
+        let any_of_tags = match path.push("any_of_tags", |path| Id::take_vec_opt(path, source, "any_of_tags")) {
+            None => vec![],
+            Some(Ok(mut vec)) => {
+                is_empty = false;
+                vec![vec.drain(..).collect()]
+            }
+            Some(Err(err)) => return Err(err),
+        };
+        let properties = match path.push("properties", |path| HashMap::<String, PropertyMatcher>::take_opt(path, source, "properties")) {
+            None => HashMap::new(),
+            Some(Ok(properties)) => {
+                is_empty = false;
+                properties
+            }
+            Some(Err(err)) => return Err(err),
+        };
         let getters = match path.push("getters", |path| ChannelSelector::take_vec_opt(path, source, "getters")) {
             None => vec![],
             Some(Ok(vec)) => {
@@ -177,7 +242,11 @@ impl Parser<ServiceSelector> for ServiceSelector {
         } else {
             Ok(ServiceSelector {
                 id: id,
+                adapter: adapter,
                 tags: tags,
+                without_tags: without_tags,
+                any_of_tags: any_of_tags,
+                properties: properties,
                 getters: getters,
                 setters: setters,
                 private: ()
@@ -200,6 +269,14 @@ impl ServiceSelector {
         }
     }
 
+    /// Selector for services offered by a specific adapter.
+    pub fn with_adapter(self, id: Id<AdapterId>) -> Self {
+        ServiceSelector {
+            adapter: self.adapter.and(Exactly::Exactly(id)),
+            .. self
+        }
+    }
+
     ///  Restrict results to services that have all the tags in `tags`.
     pub fn with_tags(self, tags: Vec<Id<TagId>>) -> Self {
         ServiceSelector {
@@ -208,6 +285,35 @@ impl ServiceSelector {
         }
     }
 
+    ///  Restrict results to services that have none of the tags in `tags`.
+    pub fn without_tags(self, tags: Vec<Id<TagId>>) -> Self {
+        ServiceSelector {
+            without_tags: merge(self.without_tags, tags),
+            .. self
+        }
+    }
+
+    ///  Restrict results to services that have at least one of the tags in `tags`.
+    pub fn with_any_of_tags(mut self, tags: Vec<Id<TagId>>) -> Self {
+        self.any_of_tags.push(tags.into_iter().collect());
+        self
+    }
+
+    /// Restrict results to services whose property `name` has value `value`.
+    pub fn with_property(self, name: &str, value: &str) -> Self {
+        self.with_property_matcher(name, PropertyMatcher::Is(value.to_owned()))
+    }
+
+    /// Restrict results to services whose property `name` is accepted by `matcher`.
+    pub fn with_property_matcher(mut self, name: &str, matcher: PropertyMatcher) -> Self {
+        let matcher = match self.properties.remove(name) {
+            None => matcher,
+            Some(previous) => previous.and(matcher)
+        };
+        self.properties.insert(name.to_owned(), matcher);
+        self
+    }
+
     /// Restrict results to services that have all the getters in `getters`.
     pub fn with_getters(mut self, mut getters: Vec<ChannelSelector>) -> Self {
         ServiceSelector {
@@ -226,9 +332,17 @@ impl ServiceSelector {
 
     /// Restrict results to services that are accepted by two selector.
     pub fn and(mut self, mut other: ServiceSelector) -> Self {
+        // Properties restricted by both selectors must satisfy both matchers.
+        for (name, matcher) in other.properties.drain() {
+            self = self.with_property_matcher(&name, matcher);
+        }
         ServiceSelector {
             id: self.id.and(other.id),
+            adapter: self.adapter.and(other.adapter),
             tags: self.tags.union(&other.tags).cloned().collect(),
+            without_tags: self.without_tags.union(&other.without_tags).cloned().collect(),
+            any_of_tags: {self.any_of_tags.append(&mut other.any_of_tags); self.any_of_tags},
+            properties: self.properties,
             getters: {self.getters.append(&mut other.getters); self.getters},
             setters: {self.setters.append(&mut other.setters); self.setters},
             private: (),
@@ -241,9 +355,24 @@ impl ServiceSelector {
         if !self.id.matches(service.id()) {
             return false;
         }
+        if !self.adapter.matches(service.adapter()) {
+            return false;
+        }
         if !service.with_tags(|tags| has_selected_tags(&self.tags, tags)) {
             return false;
         }
+        if !service.with_tags(|tags| has_no_excluded_tags(&self.without_tags, tags)) {
+            return false;
+        }
+        if !service.with_tags(|tags| has_any_of_tags(&self.any_of_tags, tags)) {
+            return false;
+        }
+        let properties_fail = self.properties.iter().any(|(name, matcher)| {
+            !matcher.matches(service.property(name))
+        });
+        if properties_fail {
+            return false;
+        }
         // If any of the getter selectors doesn't find a getter,
         // we don't match.
         let getters_fail = self.getters.iter().any(|selector| {
@@ -295,7 +424,12 @@ impl SelectedBy<ServiceSelector> for Service {
 ///
 /// - (optional) string `id`: accept only a channel with a given id;
 /// - (optional) string `service`: accept only channels of a service with a given id;
+/// - (optional) string `adapter`: accept only channels offered by a given adapter;
 /// - (optional) array of string `tags`:  accept only channels with all the tags in the array;
+/// - (optional) array of string `without_tags`:  accept only channels with none of the tags
+///        in the array;
+/// - (optional) array of string `any_of_tags`:  accept only channels with at least one of the
+///        tags in the array;
 /// - (optional) array of string `service_tags`:  accept only channels of a service with all the
 ///        tags in the array;
 /// - (optional) string|object `kind` (see `ChannelKind`): accept only channels of a given kind.
@@ -309,7 +443,10 @@ impl SelectedBy<ServiceSelector> for Service {
 /// let json_selector = "{                         \
 ///   \"id\": \"setter 1\",                        \
 ///   \"service\": \"service 1\",                  \
+///   \"adapter\": \"adapter 1\",                  \
 ///   \"tags\": [\"tag 1\", \"tag 2\"],            \
+///   \"without_tags\": [\"tag 5\"],               \
+///   \"any_of_tags\": [\"tag 6\", \"tag 7\"],     \
 ///   \"service_tags\": [\"tag 3\", \"tag 4\"],    \
 ///   \"kind\": \"Ready\"                          \
 /// }";
@@ -332,9 +469,19 @@ pub struct ChannelSelector {
     /// service `id`.
     pub parent: Exactly<Id<ServiceId>>,
 
+    /// If `Exactly(id)`, return only channels offered by the adapter with the corresponding id.
+    pub adapter: Exactly<Id<AdapterId>>,
+
     ///  Restrict results to channels that have all the tags in `tags`.
     pub tags: HashSet<Id<TagId>>,
 
+    ///  Restrict results to channels that have none of the tags in `without_tags`.
+    pub without_tags: HashSet<Id<TagId>>,
+
+    ///  Restrict results to channels that have, for each set in `any_of_tags`, at
+    ///  least one of the tags of the set.
+    pub any_of_tags: Vec<HashSet<Id<TagId>>>,
+
     ///  Restrict results to channels offered by a service that has all the tags in `tags`.
     pub service_tags: HashSet<Id<TagId>>,
 
@@ -378,6 +525,29 @@ impl Parser<ChannelSelector> for ChannelSelector {
             }
             Some(Err(err)) => return Err(err),
         };
+        let adapter = try!(match path.push("adapter", |path| Exactly::take_opt(path, source, "adapter")) {
+            None => Ok(Exactly::Always),
+            Some(result) => {
+                is_empty = false;
+                result
+            }
+        });
+        let without_tags : HashSet<_> = match path.push("without_tags", |path| Id::take_vec_opt(path, source, "without_tags")) {
+            None => HashSet::new(),
+            Some(Ok(mut vec)) => {
+                is_empty = false;
+                vec.drain(..).collect()
+            }
+            Some(Err(err)) => return Err(err),
+        };
+        let any_of_tags = match path.push("any_of_tags", |path| Id::take_vec_opt(path, source, "any_of_tags")) {
+            None => vec![],
+            Some(Ok(mut vec)) => {
+                is_empty = false;
+                vec![vec.drain(..).collect()]
+            }
+            Some(Err(err)) => return Err(err),
+        };
         let service_tags : HashSet<_> = match path.push("service_tags", |path| Id::take_vec_opt(path, source, "service_tags")) {
             None => HashSet::new(),
             Some(Ok(mut vec)) => {
@@ -411,7 +581,10 @@ impl Parser<ChannelSelector> for ChannelSelector {
             Ok(ChannelSelector {
                 id: id,
                 parent: service_id,
+                adapter: adapter,
                 tags: tags,
+                without_tags: without_tags,
+                any_of_tags: any_of_tags,
                 service_tags: service_tags,
                 kind: kind,
                 supports_send: supports_send,
@@ -452,6 +625,14 @@ impl ChannelSelector {
         }
     }
 
+    /// Restrict to channels offered by a specific adapter.
+    pub fn with_adapter(self, id: Id<AdapterId>) -> Self {
+        ChannelSelector {
+            adapter: self.adapter.and(Exactly::Exactly(id)),
+            .. self
+        }
+    }
+
     ///  Restrict to channels that have all the tags in `tags`.
     pub fn with_tags(self, tags: Vec<Id<TagId>>) -> Self {
         ChannelSelector {
@@ -460,6 +641,20 @@ impl ChannelSelector {
         }
     }
 
+    ///  Restrict to channels that have none of the tags in `tags`.
+    pub fn without_tags(self, tags: Vec<Id<TagId>>) -> Self {
+        ChannelSelector {
+            without_tags: merge(self.without_tags, tags),
+            .. self
+        }
+    }
+
+    ///  Restrict to channels that have at least one of the tags in `tags`.
+    pub fn with_any_of_tags(mut self, tags: Vec<Id<TagId>>) -> Self {
+        self.any_of_tags.push(tags.into_iter().collect());
+        self
+    }
+
     pub fn with_supports_watch(self, value: Exactly<bool>) -> Self {
         ChannelSelector {
             supports_watch: self.supports_watch.and(value),
@@ -490,11 +685,14 @@ impl ChannelSelector {
     }
 
     /// Restrict to channels that are accepted by two selector.
-    pub fn and(self, other: Self) -> Self {
+    pub fn and(mut self, mut other: Self) -> Self {
         ChannelSelector {
             id: self.id.and(other.id),
             parent: self.parent.and(other.parent),
+            adapter: self.adapter.and(other.adapter),
             tags: self.tags.union(&other.tags).cloned().collect(),
+            without_tags: self.without_tags.union(&other.without_tags).cloned().collect(),
+            any_of_tags: {self.any_of_tags.append(&mut other.any_of_tags); self.any_of_tags},
             service_tags: self.service_tags.union(&other.service_tags).cloned().collect(),
             kind: self.kind.and(other.kind),
             supports_send: self.supports_send.and(other.supports_send),
@@ -512,6 +710,9 @@ impl ChannelSelector {
         if !self.parent.matches(&channel.service) {
             return false;
         }
+        if !self.adapter.matches(&channel.adapter) {
+            return false;
+        }
         if !self.kind.matches(&channel.kind) {
             return false;
         }
@@ -527,6 +728,12 @@ impl ChannelSelector {
         if !has_selected_tags(&self.tags, &channel.tags) {
             return false;
         }
+        if !has_no_excluded_tags(&self.without_tags, &channel.tags) {
+            return false;
+        }
+        if !has_any_of_tags(&self.any_of_tags, &channel.tags) {
+            return false;
+        }
         if !has_selected_tags(&self.service_tags, service_tags) {
             return false;
         }
@@ -601,3 +808,99 @@ fn has_selected_tags(actual: &HashSet<Id<TagId>>, requested: &HashSet<Id<TagId>>
     }
     true
 }
+
+fn has_no_excluded_tags(excluded: &HashSet<Id<TagId>>, requested: &HashSet<Id<TagId>>) -> bool {
+    excluded.is_disjoint(requested)
+}
+
+fn has_any_of_tags(alternatives: &[HashSet<Id<TagId>>], requested: &HashSet<Id<TagId>>) -> bool {
+    alternatives.iter().all(|set| !set.is_disjoint(requested))
+}
+
+/// A condition on the value of a property of a service.
+///
+/// # JSON
+///
+/// One of:
+///
+/// - a string: accept only this value;
+/// - an array of strings: accept any of these values;
+/// - an object `{"contains": string}`: accept values that contain this string;
+/// - an object `{"exists": bool}`: accept any value if `true`, accept only services
+///    that do not have this property if `false`.
+///
+/// ```
+/// use foxbox_taxonomy::selector::*;
+///
+/// assert!(PropertyMatcher::from_str("\"kitchen\"").unwrap().matches(Some("kitchen")));
+/// assert!(PropertyMatcher::from_str("[\"kitchen\", \"dining\"]").unwrap().matches(Some("dining")));
+/// assert!(PropertyMatcher::from_str("{\"contains\": \"Hue\"}").unwrap().matches(Some("Hue white")));
+/// assert!(PropertyMatcher::from_str("{\"exists\": false}").unwrap().matches(None));
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum PropertyMatcher {
+    /// The property has exactly this value.
+    Is(String),
+
+    /// The property has any of these values.
+    AnyOf(Vec<String>),
+
+    /// The value of the property contains this string.
+    Contains(String),
+
+    /// If `true`, the property has any value. If `false`, the property is not set.
+    Exists(bool),
+
+    /// The property is accepted by all these matchers. Obtained by combining selectors
+    /// that both restrict the same property.
+    All(Vec<PropertyMatcher>),
+}
+
+impl PropertyMatcher {
+    /// A matcher accepting the values accepted by both `self` and `other`.
+    pub fn and(self, other: PropertyMatcher) -> Self {
+        let mut matchers = match self {
+            PropertyMatcher::All(matchers) => matchers,
+            matcher => vec![matcher]
+        };
+        match other {
+            PropertyMatcher::All(mut others) => matchers.append(&mut others),
+            other => matchers.push(other)
+        }
+        PropertyMatcher::All(matchers)
+    }
+
+    /// Determine whether a property value is accepted by this matcher.
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (&PropertyMatcher::Exists(exists), _) => exists == value.is_some(),
+            (&PropertyMatcher::All(ref matchers), _) => matchers.iter().all(|matcher| matcher.matches(value)),
+            (_, None) => false,
+            (&PropertyMatcher::Is(ref expected), Some(value)) => expected == value,
+            (&PropertyMatcher::AnyOf(ref expected), Some(value)) => expected.iter().any(|expected| expected == value),
+            (&PropertyMatcher::Contains(ref expected), Some(value)) => value.contains(expected as &str),
+        }
+    }
+}
+
+impl Parser<PropertyMatcher> for PropertyMatcher {
+    fn description() -> String {
+        "PropertyMatcher".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::String(ref value) => Ok(PropertyMatcher::Is(value.clone())),
+            JSON::Array(_) => Ok(PropertyMatcher::AnyOf(try!(Vec::<String>::parse(path, source)))),
+            JSON::Object(ref obj) if obj.len() == 1 => {
+                if obj.contains_key("contains") {
+                    return Ok(PropertyMatcher::Contains(try!(path.push("contains", |path| String::take(path, source, "contains")))));
+                }
+                if obj.contains_key("exists") {
+                    return Ok(PropertyMatcher::Exists(try!(path.push("exists", |path| bool::take(path, source, "exists")))));
+                }
+                Err(ParseError::type_error("PropertyMatcher", &path, "a field contains or exists"))
+            }
+            _ => Err(ParseError::type_error("PropertyMatcher", &path, "string, array or object"))
+        }
+    }
+}
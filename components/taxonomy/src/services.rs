@@ -43,6 +43,7 @@ macro_rules! tag_id {
 /// - adapter: string;
 /// - tags: array of strings;
 /// - properties: object;
+/// - user_properties: object;
 /// - getters: object (keys are string identifiers, for more details on values see Channel<Getter>);
 /// - setters: object (keys are string identifiers, for more details on values see Channel<Setter>);
 ///
@@ -68,6 +69,12 @@ pub struct Service {
     /// For instance, these can be device manufacturer, model, etc.
     pub properties: HashMap<String, String>,
 
+    /// Service properties that are set by the user, e.g. a human-readable name
+    /// or the room in which the device is placed. These properties persist
+    /// across reboots.
+    #[serde(default)]
+    pub user_properties: HashMap<String, String>,
+
     /// Channels connected directly to this service.
     pub channels: HashMap<Id<Channel>, Channel>,
 
@@ -82,6 +89,7 @@ impl Service {
             tags: HashSet::new(),
             channels: HashMap::new(),
             properties: HashMap::new(),
+            user_properties: HashMap::new(),
             id: id.clone(),
             adapter: adapter.clone(),
         }
@@ -95,6 +103,7 @@ impl ToJSON for Service {
             ("adapter", self.adapter.to_json()),
             ("tags", self.tags.to_json()),
             ("properties", self.properties.to_json()),
+            ("user_properties", self.user_properties.to_json()),
             ("channels", self.channels.to_json()),
         ].to_json()
     }
@@ -210,6 +219,66 @@ pub enum ChannelKind {
     /// ```
     ZwaveInclude,
 
+    /// This kind is used when the user wants to exclude a ZWave device
+    /// from the ZWave network.
+    /// # JSON
+    ///
+    /// This kind is represented by string "ZwaveExclude".
+    ///
+    /// ```
+    /// use foxbox_taxonomy::services::*;
+    /// use foxbox_taxonomy::parse::*;
+    ///
+    /// let parsed = ChannelKind::from_str("\"ZwaveExclude\"").unwrap();
+    /// assert_eq!(parsed, ChannelKind::ZwaveExclude);
+    /// ```
+    ZwaveExclude,
+
+    /// This kind is used when the user wants to cancel a pending inclusion
+    /// or exclusion.
+    /// # JSON
+    ///
+    /// This kind is represented by string "ZwaveCancel".
+    ///
+    /// ```
+    /// use foxbox_taxonomy::services::*;
+    /// use foxbox_taxonomy::parse::*;
+    ///
+    /// let parsed = ChannelKind::from_str("\"ZwaveCancel\"").unwrap();
+    /// assert_eq!(parsed, ChannelKind::ZwaveCancel);
+    /// ```
+    ZwaveCancel,
+
+    /// This kind is used when the user wants to heal the ZWave network,
+    /// i.e. to have the devices discover the routes to their neighbours again.
+    /// # JSON
+    ///
+    /// This kind is represented by string "ZwaveHeal".
+    ///
+    /// ```
+    /// use foxbox_taxonomy::services::*;
+    /// use foxbox_taxonomy::parse::*;
+    ///
+    /// let parsed = ChannelKind::from_str("\"ZwaveHeal\"").unwrap();
+    /// assert_eq!(parsed, ChannelKind::ZwaveHeal);
+    /// ```
+    ZwaveHeal,
+
+    /// The service is used to read the state of a ZWave controller, i.e.
+    /// "idle", "including" or "excluding".
+    /// # JSON
+    ///
+    /// This kind is represented by string "ZwaveControllerState".
+    ///
+    /// ```
+    /// use foxbox_taxonomy::services::*;
+    /// use foxbox_taxonomy::parse::*;
+    ///
+    /// let parsed = ChannelKind::from_str("\"ZwaveControllerState\"").unwrap();
+    /// assert_eq!(parsed, ChannelKind::ZwaveControllerState);
+    /// ```
+    ZwaveControllerState,
+
     //
     // # String
     //
@@ -358,6 +427,9 @@ pub enum ChannelKind {
     ThinkerbellRuleSource,
     ThinkerbellRuleOn,
 
+    /// The most recent execution events of a Thinkerbell rule, as a JSON array.
+    ThinkerbellRuleTrace,
+
     /// Capture a new snapshot.
     ///
     /// # JSON
@@ -475,6 +547,10 @@ impl Parser<ChannelKind> for ChannelKind {
                 "OpenClosed" => Ok(ChannelKind::OpenClosed),
                 "DoorLocked" => Ok(ChannelKind::DoorLocked),
                 "ZwaveInclude" => Ok(ChannelKind::ZwaveInclude),
+                "ZwaveExclude" => Ok(ChannelKind::ZwaveExclude),
+                "ZwaveCancel" => Ok(ChannelKind::ZwaveCancel),
+                "ZwaveHeal" => Ok(ChannelKind::ZwaveHeal),
+                "ZwaveControllerState" => Ok(ChannelKind::ZwaveControllerState),
                 "Username" => Ok(ChannelKind::Username),
                 "Password" => Ok(ChannelKind::Password),
                 "Countdown" => Ok(ChannelKind::Countdown),
@@ -485,6 +561,7 @@ impl Parser<ChannelKind> for ChannelKind {
                 "RemoveThinkerbellRule" => Ok(ChannelKind::RemoveThinkerbellRule),
                 "ThinkerbellRuleSource" => Ok(ChannelKind::ThinkerbellRuleSource),
                 "ThinkerbellRuleOn" => Ok(ChannelKind::ThinkerbellRuleOn),
+                "ThinkerbellRuleTrace" => Ok(ChannelKind::ThinkerbellRuleTrace),
                 "RemainingTime" => Ok(ChannelKind::RemainingTime),
                 "OvenTemperature" => Ok(ChannelKind::OvenTemperature),
                 "TakeSnapshot" => Ok(ChannelKind::TakeSnapshot),
@@ -524,6 +601,10 @@ impl ToJSON for ChannelKind {
             OpenClosed => JSON::String("OpenClosed".to_owned()),
             DoorLocked => JSON::String("DoorLocked".to_owned()),
             ZwaveInclude => JSON::String("ZwaveInclude".to_owned()),
+            ZwaveExclude => JSON::String("ZwaveExclude".to_owned()),
+            ZwaveCancel => JSON::String("ZwaveCancel".to_owned()),
+            ZwaveHeal => JSON::String("ZwaveHeal".to_owned()),
+            ZwaveControllerState => JSON::String("ZwaveControllerState".to_owned()),
             Username => JSON::String("Username".to_owned()),
             Password => JSON::String("Password".to_owned()),
             CurrentTime => JSON::String("CurrentTime".to_owned()),
@@ -536,6 +617,7 @@ impl ToJSON for ChannelKind {
             RemoveThinkerbellRule => JSON::String("RemoveThinkerbellRule".to_owned()),
             ThinkerbellRuleSource => JSON::String("ThinkerbellRuleSource".to_owned()),
             ThinkerbellRuleOn => JSON::String("ThinkerbellRuleOn".to_owned()),
+            ThinkerbellRuleTrace => JSON::String("ThinkerbellRuleTrace".to_owned()),
             TakeSnapshot => JSON::String("TakeSnapshot".to_owned()),
             Log => JSON::String("Log".to_owned()),
             WebPushNotify => JSON::String("WebPushNotify".to_owned()),
@@ -562,6 +644,8 @@ impl ChannelKind {
             OpenClosed => Type::OpenClosed,
             DoorLocked => Type::DoorLocked,
             ZwaveInclude => Type::IsSecure,
+            ZwaveExclude | ZwaveCancel | ZwaveHeal => Type::Unit,
+            ZwaveControllerState => Type::String,
             CurrentTime => Type::TimeStamp,
             CurrentTimeOfDay | RemainingTime | Countdown | CountEveryInterval => Type::Duration,
             OvenTemperature => Type::Temperature,
@@ -569,6 +653,7 @@ impl ChannelKind {
             RemoveThinkerbellRule => Type::Unit,
 			ThinkerbellRuleSource => Type::String,
             ThinkerbellRuleOn => Type::OnOff,
+            ThinkerbellRuleTrace => Type::Json,
             Log => Type::String,
             TakeSnapshot => Type::Unit,
 			Username | Password => Type::String,
@@ -2,11 +2,14 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-///! This is the database that holds tags associated to various objects.
-///! It provides an api to manage Id <-> tags relationships.
-///! All users share the same tags for objects.
+///! This is the database that holds tags and user-defined properties associated to
+///! various objects.
+///! It provides an api to manage Id <-> tags and Id <-> properties relationships.
+///! All users share the same tags and properties for objects.
 
 use rusqlite::{ Connection, Result };
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::path::PathBuf;
 use util::{ Id, TagId };
 
@@ -15,10 +18,10 @@ fn escape<T>(string: &Id<T>) -> String {
     format!("{}", string).replace("'", "''")
 }
 
-/// Creates a unique key for a (id, tag) tuple.
+/// Creates a unique key for a (id, tag) or (id, property name) tuple.
 /// `SQlite` integers are i64 so we turn the hashed u64 into a String...
-fn create_key<T>(id: &Id<T>, tag: &Id<TagId>) -> String {
-    use std::hash::{ Hash, Hasher, SipHasher };
+fn create_key<T, U>(id: &Id<T>, tag: &U) -> String where U: Hash {
+    use std::hash::{ Hasher, SipHasher };
 
     let mut hasher = SipHasher::new();
     id.hash(&mut hasher);
@@ -61,6 +64,15 @@ impl TagStorage {
                 panic!("Unable to create taxonomy tags database: {}", err);
             });
 
+        db.execute("CREATE TABLE IF NOT EXISTS properties (
+                    key    TEXT NOT NULL PRIMARY KEY,
+                    id     TEXT NOT NULL,
+                    name   TEXT NOT NULL,
+                    value  TEXT NOT NULL
+            )", &[]).unwrap_or_else(|err| {
+                panic!("Unable to create taxonomy properties database: {}", err);
+            });
+
         self.db = Some(db);
     }
 
@@ -127,6 +139,46 @@ impl TagStorage {
         }
         Ok(subs)
     }
+
+    /// Set a user-defined property, replacing any previous value.
+    pub fn set_property<T>(&mut self, id: &Id<T>, name: &str, value: &str) -> Result<()> {
+        self.ensure_db();
+        try!(self.db.as_ref().unwrap().execute("INSERT OR REPLACE INTO properties VALUES ($1, $2, $3, $4)",
+                        &[&create_key(id, &name), &escape(&id), &name, &value]));
+        Ok(())
+    }
+
+    pub fn set_properties<T>(&mut self, id: &Id<T>, properties: &HashMap<String, String>) -> Result<()> {
+        for (name, value) in properties {
+            try!(self.set_property(id, name, value));
+        }
+        Ok(())
+    }
+
+    pub fn remove_property<T>(&mut self, id: &Id<T>, name: &str) -> Result<()> {
+        self.ensure_db();
+        try!(self.db.as_ref().unwrap().execute("DELETE FROM properties WHERE key=$1", &[&create_key(id, &name)]));
+        Ok(())
+    }
+
+    pub fn remove_properties<T>(&mut self, id: &Id<T>, names: &[String]) -> Result<()> {
+        for name in names {
+            try!(self.remove_property(id, name));
+        }
+        Ok(())
+    }
+
+    pub fn get_properties_for<T>(&mut self, id: &Id<T>) -> Result<HashMap<String, String>> {
+        self.ensure_db();
+        let mut properties = HashMap::new();
+        let mut stmt = try!(self.db.as_ref().unwrap().prepare("SELECT name, value FROM properties WHERE id=$1"));
+        let rows = try!(stmt.query(&[&escape(&id)]));
+        for result_row in rows {
+            let row = try!(result_row);
+            properties.insert(row.get(0), row.get(1));
+        }
+        Ok(properties)
+    }
 }
 
 #[cfg(test)]
@@ -243,3 +295,54 @@ fn storage_test() {
     tags = store.get_tags_for(&id1).unwrap();
     assert_eq!(tags.len(), 0);
 }
+
+#[test]
+#[allow(unused_variables)]
+fn property_storage_test() {
+    use util::ServiceId;
+
+    struct AutoDeleteDb { };
+    impl Drop for AutoDeleteDb {
+        fn drop(&mut self) {
+            remove_test_db();
+        }
+    }
+    let auto_db = AutoDeleteDb { };
+
+    let mut store = TagStorage::new(&get_db_environment());
+
+    let id1 = Id::<ServiceId>::new("first id");
+    let id2 = Id::<ServiceId>::new("second id");
+
+    // Start with an empty db.
+    assert_eq!(store.get_properties_for(&id1).unwrap().len(), 0);
+
+    // Setting a property.
+    store.set_property(&id1, "name", "Kitchen ceiling").unwrap();
+    let properties = store.get_properties_for(&id1).unwrap();
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties.get("name").unwrap(), "Kitchen ceiling");
+
+    // Setting the same property replaces its value.
+    store.set_property(&id1, "name", "Kitchen table").unwrap();
+    let properties = store.get_properties_for(&id1).unwrap();
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties.get("name").unwrap(), "Kitchen table");
+
+    // Properties of different ids are independent.
+    let mut many = HashMap::new();
+    many.insert("name".to_owned(), "Hallway".to_owned());
+    many.insert("floor".to_owned(), "1".to_owned());
+    store.set_properties(&id2, &many).unwrap();
+    assert_eq!(store.get_properties_for(&id2).unwrap(), many);
+    assert_eq!(store.get_properties_for(&id1).unwrap().get("name").unwrap(), "Kitchen table");
+
+    // Removing properties.
+    store.remove_properties(&id2, &["floor".to_owned(), "no such property".to_owned()]).unwrap();
+    let properties = store.get_properties_for(&id2).unwrap();
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties.get("name").unwrap(), "Hallway");
+
+    store.remove_property(&id1, "name").unwrap();
+    assert_eq!(store.get_properties_for(&id1).unwrap().len(), 0);
+}
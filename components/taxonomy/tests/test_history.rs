@@ -0,0 +1,107 @@
+extern crate foxbox_taxonomy;
+
+use foxbox_taxonomy::acl::*;
+use foxbox_taxonomy::api::{ API, Operation, User };
+use foxbox_taxonomy::fake_adapter::*;
+use foxbox_taxonomy::history::*;
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::selector::*;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::*;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Wait until the recorder has stored `count` entries for `selector`.
+fn wait_for_entries(history: &HistoryRecorder, selector: &ChannelSelector, count: usize) -> Vec<HistoryEntry> {
+    for _ in 0..50 {
+        let entries = history.get_history(vec![selector.clone()], None, None, None, &User::None).unwrap();
+        if entries.len() >= count {
+            return entries;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("Timeout while waiting for {} history entries", count);
+}
+
+#[test]
+fn test_history() {
+    println!("");
+
+    let manager = Arc::new(AdapterManager::new(None));
+    let adapter_id = Id::<AdapterId>::new("adapter id");
+    let service_id = Id::<ServiceId>::new("service id");
+    let getter_id_1 = Id::<Channel>::new("getter id 1");
+    let getter_id_2 = Id::<Channel>::new("getter id 2");
+    let tag_history = Id::<TagId>::new("history");
+
+    let adapter = FakeAdapter::new(&adapter_id);
+    let tweak = adapter.get_tweak();
+    manager.add_adapter(Arc::new(adapter)).unwrap();
+    manager.add_service(Service::empty(&service_id, &adapter_id)).unwrap();
+    for id in vec![&getter_id_1, &getter_id_2] {
+        manager.add_channel(Channel {
+            kind: ChannelKind::LightOn,
+            supports_watch: true,
+            .. Channel::empty(id, &service_id, &adapter_id)
+        }).unwrap();
+    }
+
+    let history = HistoryRecorder::new(&manager, None,
+        vec![ChannelSelector::new().with_tags(vec![tag_history.clone()])]).unwrap();
+    let selector_1 = ChannelSelector::new().with_id(getter_id_1.clone());
+    let selector_2 = ChannelSelector::new().with_id(getter_id_2.clone());
+
+    println!("* Initially, nothing is recorded.");
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::None).unwrap().len(), 0);
+
+    println!("* Channels that are tagged later are recorded.");
+    assert_eq!(manager.add_channel_tags(vec![selector_1.clone()], vec![tag_history.clone()]), 1);
+    // Let the watch pick up the new channel.
+    thread::sleep(Duration::from_millis(100));
+
+    tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+    tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::Off)))));
+    tweak(Tweak::InjectGetterValue(getter_id_2.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+
+    let entries = wait_for_entries(&history, &selector_1, 2);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].channel, getter_id_1);
+    assert_eq!(entries[0].type_, Type::OnOff);
+    assert_eq!(entries[0].value.to_value(&entries[0].type_).unwrap(), Value::OnOff(OnOff::On));
+    assert_eq!(entries[1].value.to_value(&entries[1].type_).unwrap(), Value::OnOff(OnOff::Off));
+
+    println!("* Channels that are not selected are not recorded.");
+    assert_eq!(history.get_history(vec![selector_2.clone()], None, None, None, &User::None).unwrap().len(), 0);
+
+    println!("* We can limit the number of entries.");
+    let entries = history.get_history(vec![selector_1.clone()], None, None, Some(1), &User::None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].value.to_value(&entries[0].type_).unwrap(), Value::OnOff(OnOff::On));
+
+    println!("* We can restrict the period.");
+    let past = TimeStamp::from_s(0);
+    let entries = history.get_history(vec![selector_1.clone()], None, Some(past.clone()), None, &User::None).unwrap();
+    assert_eq!(entries.len(), 0);
+    let entries = history.get_history(vec![selector_1.clone()], Some(past), None, None, &User::None).unwrap();
+    assert_eq!(entries.len(), 2);
+
+    println!("* Only users who may watch a channel may see its history.");
+    manager.set_acl_owner_check(Box::new(|user| *user == User::Id(1)));
+    assert_eq!(manager.add_acl_rules(vec![AclRule {
+        principal: Principal::User(1),
+        target: AclTarget::Tag(tag_history.clone()),
+        operations: vec![Operation::Watch],
+    }], User::Id(1)).unwrap(), 1);
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::Id(1)).unwrap().len(), 2);
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::Id(2)).unwrap().len(), 0);
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::None).unwrap().len(), 0);
+
+    println!("* The history of a removed channel remains available, with the same restrictions.");
+    manager.remove_channel(&getter_id_1).unwrap();
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::Id(1)).unwrap().len(), 2);
+    assert_eq!(history.get_history(vec![selector_1.clone()], None, None, None, &User::Id(2)).unwrap().len(), 0);
+    let by_tag = ChannelSelector::new().with_tags(vec![tag_history.clone()]);
+    assert_eq!(history.get_history(vec![by_tag], None, None, None, &User::Id(1)).unwrap().len(), 2);
+}
@@ -1,13 +1,16 @@
+extern crate chrono;
 extern crate foxbox_taxonomy;
 extern crate libc;
 extern crate transformable_channels;
 #[macro_use]
 extern crate assert_matches;
 
+use foxbox_taxonomy::acl::*;
+use foxbox_taxonomy::audit::*;
 use foxbox_taxonomy::io::*;
 use foxbox_taxonomy::manager::*;
 use foxbox_taxonomy::fake_adapter::*;
-use foxbox_taxonomy::api::{ API, Error, InternalError, TargetMap, Targetted, User, WatchEvent as Event };
+use foxbox_taxonomy::api::{ AccessDenied, API, Error, FetchedValue, InternalError, Operation, TargetMap, Targetted, TopologyEvent, User, WatchEvent as Event };
 use foxbox_taxonomy::selector::*;
 use foxbox_taxonomy::services::*;
 use foxbox_taxonomy::values::*;
@@ -43,14 +46,14 @@ impl Transform<(Payload, Type)> for Value {
     }
 }
 
-impl<'a> Transform<Option<Result<Option<Value>, Error>>> for Option<&'a Result<Option<(Payload, Type)>, Error>> {
+impl<'a> Transform<Option<Result<Option<Value>, Error>>> for Option<&'a Result<Option<FetchedValue>, Error>> {
     fn transform(&self) -> Option<Result<Option<Value>, Error>> {
         match *self {
             None => None,
             Some(&Err(ref err)) => Some(Err(err.clone())),
             Some(&Ok(None)) => Some(Ok(None)),
-            Some(&Ok(Some((ref payload, ref type_)))) => {
-                match payload.to_value(type_) {
+            Some(&Ok(Some(ref fetched))) => {
+                match fetched.value.to_value(&fetched.type_) {
                     Err(err) => Some(Err(err)),
                     Ok(ok) => Some(Ok(Some(ok)))
                 }
@@ -312,6 +315,514 @@ fn test_tags_in_db() {
     }
 }
 
+#[test]
+#[allow(unused_variables)]
+fn test_properties_in_db() {
+    // Simple RAII style struct to delete the test db.
+    struct AutoDeleteDb { };
+    impl Drop for AutoDeleteDb {
+        fn drop(&mut self) {
+            remove_test_db();
+        }
+    }
+    let auto_db = AutoDeleteDb { };
+
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let service_id_2 = Id::<ServiceId>::new("service id 2");
+
+    let mut service_1 = Service::empty(&service_id_1, &id_1);
+    service_1.properties.insert("model".to_owned(), "Hue light 3".to_owned());
+    let service_2 = Service::empty(&service_id_2, &id_1);
+
+    let mut properties = HashMap::new();
+    properties.insert("name".to_owned(), "Kitchen ceiling".to_owned());
+    properties.insert("room".to_owned(), "kitchen".to_owned());
+
+    println!("* Start a session, set properties.");
+    {
+        let manager = AdapterManager::new(Some(get_db_environment()));
+        manager.add_adapter(Arc::new(FakeAdapter::new(&id_1))).unwrap();
+        manager.add_service(service_1.clone()).unwrap();
+        manager.add_service(service_2.clone()).unwrap();
+
+        assert_eq!(manager.set_service_properties(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+                                                  properties.clone()), 1);
+
+        println!("* Selectors match on user-defined and adapter properties.");
+        let services = manager.get_services(vec![ServiceSelector::new().with_property("room", "kitchen")]);
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].id, service_id_1);
+        assert_eq!(services[0].user_properties, properties);
+        assert_eq!(services[0].properties.get("model").unwrap(), "Hue light 3");
+
+        let services = manager.get_services(vec![ServiceSelector::new().with_property("model", "Hue light 3")]);
+        assert_eq!(services.len(), 1);
+        assert_eq!(manager.get_services(vec![ServiceSelector::new().with_property("room", "bedroom")]).len(), 0);
+
+        println!("* User-defined properties take precedence over adapter properties.");
+        let mut model = HashMap::new();
+        model.insert("model".to_owned(), "My favorite light".to_owned());
+        manager.set_service_properties(vec![ServiceSelector::new().with_id(service_id_1.clone())], model);
+        assert_eq!(manager.get_services(vec![ServiceSelector::new().with_property("model", "Hue light 3")]).len(), 0);
+        manager.remove_service_properties(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+                                          vec!["model".to_owned()]);
+        assert_eq!(manager.get_services(vec![ServiceSelector::new().with_property("model", "Hue light 3")]).len(), 1);
+
+        println!("* Remove the service, properties should be persisted.");
+        manager.remove_service(&service_id_1).unwrap();
+        assert_eq!(manager.get_services(vec![]).len(), 1);
+    }
+
+    println!("* Start a new session, properties should be restored.");
+    {
+        let manager = AdapterManager::new(Some(get_db_environment()));
+        manager.add_adapter(Arc::new(FakeAdapter::new(&id_1))).unwrap();
+        manager.add_service(service_1.clone()).unwrap();
+        let services = manager.get_services(vec![ServiceSelector::new().with_id(service_id_1.clone())]);
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].user_properties, properties);
+
+        println!("* Removing properties also removes them from the database.");
+        assert_eq!(manager.remove_service_properties(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+                                                     vec!["room".to_owned()]), 1);
+        manager.remove_service(&service_id_1).unwrap();
+        manager.add_service(service_1.clone()).unwrap();
+        let services = manager.get_services(vec![ServiceSelector::new().with_id(service_id_1.clone())]);
+        assert_eq!(services[0].user_properties.len(), 1);
+        assert_eq!(services[0].user_properties.get("name").unwrap(), "Kitchen ceiling");
+    }
+}
+
+#[test]
+#[allow(unused_variables)]
+fn test_acl() {
+    // Simple RAII style struct to delete the test db.
+    struct AutoDeleteDb { };
+    impl Drop for AutoDeleteDb {
+        fn drop(&mut self) {
+            remove_test_db();
+        }
+    }
+    let auto_db = AutoDeleteDb { };
+
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let service_id_2 = Id::<ServiceId>::new("service id 2");
+    let getter_id_1 = Id::<Channel>::new("getter id 1");
+    let setter_id_1 = Id::<Channel>::new("setter id 1");
+    let setter_id_2 = Id::<Channel>::new("setter id 2");
+    let tag_door = Id::<TagId>::new("door");
+
+    let getter_1 = Channel {
+        kind: ChannelKind::LightOn,
+        supports_fetch: true,
+        supports_watch: true,
+        .. Channel::empty(&getter_id_1, &service_id_1, &id_1)
+    };
+    let setter_1 = Channel {
+        kind: ChannelKind::LightOn,
+        supports_send: true,
+        .. Channel::empty(&setter_id_1, &service_id_1, &id_1)
+    };
+    let setter_2 = Channel {
+        kind: ChannelKind::LightOn,
+        supports_send: true,
+        .. Channel::empty(&setter_id_2, &service_id_2, &id_1)
+    };
+
+    let data_on = Payload::from_value(&Value::OnOff(OnOff::On), &Type::OnOff).unwrap();
+    let alice = User::Id(1);
+    let bob = User::Id(2);
+    let rule_door = AclRule {
+        principal: Principal::Role("family".to_owned()),
+        target: AclTarget::Tag(tag_door.clone()),
+        operations: vec![Operation::Fetch, Operation::Send],
+    };
+
+    println!("* Start a session, add rules.");
+    {
+        let manager = AdapterManager::new(Some(get_db_environment()));
+        let adapter = FakeAdapter::new(&id_1);
+        let tweak = adapter.get_tweak();
+        manager.add_adapter(Arc::new(adapter)).unwrap();
+        manager.add_service(Service::empty(&service_id_1, &id_1)).unwrap();
+        manager.add_service(Service::empty(&service_id_2, &id_1)).unwrap();
+        manager.add_channel(getter_1.clone()).unwrap();
+        manager.add_channel(setter_1.clone()).unwrap();
+        manager.add_channel(setter_2.clone()).unwrap();
+        tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+
+        println!("* Without rules, everybody may access everything.");
+        assert!(manager.is_allowed(&User::None, Operation::Send, &setter_id_1));
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, User::None);
+        assert_matches!(data.get(&getter_id_1), Some(&Ok(Some(_))));
+
+        println!("* While there are no admins, only the owner may change the rules.");
+        assert_matches!(manager.add_acl_rules(vec![rule_door.clone()], bob.clone()),
+            Err(Error::AccessDenied(AccessDenied::Acl)));
+        manager.set_acl_owner_check(Box::new(|user| *user == User::Id(2)));
+        assert_matches!(manager.add_acl_rules(vec![rule_door.clone()], User::None),
+            Err(Error::AccessDenied(AccessDenied::Acl)));
+        assert_matches!(manager.add_acl_rules(vec![rule_door.clone()], alice.clone()),
+            Err(Error::AccessDenied(AccessDenied::Acl)));
+        assert_matches!(manager.add_acl_role_members(AclRole {
+            role: ADMIN_ROLE.to_owned(),
+            users: vec![1]
+        }, alice.clone()), Err(Error::AccessDenied(AccessDenied::Acl)));
+        assert_eq!(manager.get_acl_rules().len(), 0);
+
+        println!("* Once a service is targeted by a rule, only the operations granted are allowed.");
+        manager.add_service_tags(vec![ServiceSelector::new().with_id(service_id_1.clone())], vec![tag_door.clone()]);
+        assert_eq!(manager.add_acl_rules(vec![rule_door.clone()], bob.clone()).unwrap(), 1);
+        assert_eq!(manager.add_acl_rules(vec![rule_door.clone()], bob.clone()).unwrap(), 0);
+        assert_eq!(manager.get_acl_rules(), vec![rule_door.clone()]);
+
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, alice.clone());
+        assert_matches!(data.get(&getter_id_1), Some(&Err(Error::AccessDenied(AccessDenied::Channel(Operation::Fetch, _)))));
+
+        let data = manager.send_values(target_map(vec![(vec![ChannelSelector::new()], data_on.clone())]), alice.clone());
+        assert_eq!(data.len(), 2);
+        assert_matches!(data.get(&setter_id_1), Some(&Err(Error::AccessDenied(AccessDenied::Channel(Operation::Send, _)))));
+        assert_matches!(data.get(&setter_id_2), Some(&Ok(())));
+
+        println!("* Members of the role are granted the operations.");
+        assert_eq!(manager.add_acl_role_members(AclRole {
+            role: "family".to_owned(),
+            users: vec![1]
+        }, bob.clone()).unwrap(), 1);
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, alice.clone());
+        assert_matches!(data.get(&getter_id_1), Some(&Ok(Some(_))));
+        let data = manager.send_values(target_map(vec![(vec![ChannelSelector::new()], data_on.clone())]), alice.clone());
+        assert_matches!(data.get(&setter_id_1), Some(&Ok(())));
+        assert!(!manager.is_allowed(&alice, Operation::Watch, &getter_id_1));
+        assert!(!manager.is_allowed(&bob, Operation::Fetch, &getter_id_1));
+        assert!(!manager.is_allowed(&User::None, Operation::Fetch, &getter_id_1));
+
+        println!("* Watching a channel requires the Watch operation.");
+        let (tx_watch, rx_watch) = channel();
+        let _guard = manager.watch_values(target_map(vec![(
+            vec![ChannelSelector::new().with_id(getter_id_1.clone())],
+            Exactly::Always
+        )]), Box::new(tx_watch), alice.clone());
+        match rx_watch.recv().unwrap() {
+            Event::Error { channel, error: Error::AccessDenied(AccessDenied::Channel(Operation::Watch, _)) } =>
+                assert_eq!(channel, getter_id_1),
+            other => panic!("Unexpected event {:?}", other)
+        }
+
+        println!("* Once there are admins, only admins may change the rules.");
+        assert_eq!(manager.add_acl_role_members(AclRole {
+            role: ADMIN_ROLE.to_owned(),
+            users: vec![2]
+        }, bob.clone()).unwrap(), 1);
+        assert_matches!(manager.remove_acl_rules(vec![rule_door.clone()], alice.clone()),
+            Err(Error::AccessDenied(AccessDenied::Acl)));
+        assert_matches!(manager.remove_acl_role_members(AclRole {
+            role: "family".to_owned(),
+            users: vec![1]
+        }, User::None), Err(Error::AccessDenied(AccessDenied::Acl)));
+        assert_eq!(manager.get_acl_rules().len(), 1);
+    }
+
+    println!("* Start a new session, rules and roles should be restored.");
+    {
+        let manager = AdapterManager::new(Some(get_db_environment()));
+        manager.add_adapter(Arc::new(FakeAdapter::new(&id_1))).unwrap();
+        manager.add_service(Service::empty(&service_id_1, &id_1)).unwrap();
+        manager.add_channel(getter_1.clone()).unwrap();
+
+        assert_eq!(manager.get_acl_rules(), vec![rule_door.clone()]);
+        assert_eq!(manager.get_acl_roles().len(), 2);
+        assert!(manager.is_allowed(&alice, Operation::Fetch, &getter_id_1));
+        assert!(!manager.is_allowed(&bob, Operation::Fetch, &getter_id_1));
+
+        println!("* Removing rules also removes them from the database.");
+        assert_eq!(manager.remove_acl_rules(vec![rule_door.clone()], bob.clone()).unwrap(), 1);
+        assert!(manager.is_allowed(&bob, Operation::Fetch, &getter_id_1));
+    }
+    {
+        let manager = AdapterManager::new(Some(get_db_environment()));
+        assert_eq!(manager.get_acl_rules().len(), 0);
+    }
+}
+
+#[test]
+fn test_acl_ongoing_watch() {
+    println!("");
+    let manager = AdapterManager::new(None);
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let getter_id_1 = Id::<Channel>::new("getter id 1");
+
+    let adapter = FakeAdapter::new(&id_1);
+    let tweak = adapter.get_tweak();
+    manager.add_adapter(Arc::new(adapter)).unwrap();
+    manager.add_service(Service::empty(&service_id_1, &id_1)).unwrap();
+    manager.add_channel(Channel {
+        kind: ChannelKind::LightOn,
+        supports_fetch: true,
+        supports_watch: true,
+        .. Channel::empty(&getter_id_1, &service_id_1, &id_1)
+    }).unwrap();
+
+    let alice = User::Id(1);
+    let owner = User::Id(2);
+    manager.set_acl_owner_check(Box::new(|user| *user == User::Id(2)));
+    let rule_fetch_only = AclRule {
+        principal: Principal::Anyone,
+        target: AclTarget::Service(service_id_1.clone()),
+        operations: vec![Operation::Fetch],
+    };
+
+    let (tx_watch, rx_watch) = channel();
+    let _guard = manager.watch_values(target_map(vec![(
+        vec![ChannelSelector::new().with_id(getter_id_1.clone())],
+        Exactly::Always
+    )]), Box::new(tx_watch), alice.clone());
+
+    println!("* Without rules, the watcher receives the events of the channel.");
+    tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+    match rx_watch.recv().unwrap() {
+        Event::EnterRange { channel, .. } => assert_eq!(channel, getter_id_1),
+        other => panic!("Unexpected event {:?}", other)
+    }
+
+    println!("* Once a rule denies watching the channel, the watcher is informed and receives nothing else.");
+    assert_eq!(manager.add_acl_rules(vec![rule_fetch_only.clone()], owner.clone()).unwrap(), 1);
+    match rx_watch.recv().unwrap() {
+        Event::Error { channel, error: Error::AccessDenied(AccessDenied::Channel(Operation::Watch, _)) } =>
+            assert_eq!(channel, getter_id_1),
+        other => panic!("Unexpected event {:?}", other)
+    }
+    tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::Off)))));
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert_matches!(rx_watch.try_recv(), Err(_));
+
+    println!("* Once the rule is removed, the watcher receives the events again.");
+    assert_eq!(manager.remove_acl_rules(vec![rule_fetch_only], owner).unwrap(), 1);
+    tweak(Tweak::InjectGetterValue(getter_id_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+    match rx_watch.recv().unwrap() {
+        Event::EnterRange { channel, .. } => assert_eq!(channel, getter_id_1),
+        other => panic!("Unexpected event {:?}", other)
+    }
+}
+
+#[test]
+fn test_audit() {
+    let manager = AdapterManager::new(None);
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let setter_id_1 = Id::<Channel>::new("setter id 1");
+    let setter_id_2 = Id::<Channel>::new("setter id 2");
+    let tag_door = Id::<TagId>::new("door");
+
+    let adapter = FakeAdapter::new(&id_1);
+    let tweak = adapter.get_tweak();
+    manager.add_adapter(Arc::new(adapter)).unwrap();
+    manager.add_service(Service::empty(&service_id_1, &id_1)).unwrap();
+    for id in vec![&setter_id_1, &setter_id_2] {
+        manager.add_channel(Channel {
+            kind: ChannelKind::LightOn,
+            supports_send: true,
+            .. Channel::empty(id, &service_id_1, &id_1)
+        }).unwrap();
+    }
+    manager.add_channel_tags(vec![ChannelSelector::new().with_id(setter_id_1.clone())], vec![tag_door.clone()]);
+
+    let admin = User::Id(3);
+    manager.set_acl_owner_check(Box::new(|user| *user == User::Id(3)));
+    manager.add_acl_role_members(AclRole {
+        role: ADMIN_ROLE.to_owned(),
+        users: vec![3]
+    }, admin.clone()).unwrap();
+
+    let data_on = Payload::from_value(&Value::OnOff(OnOff::On), &Type::OnOff).unwrap();
+
+    println!("* Sending values records one entry per channel, with the result.");
+    tweak(Tweak::InjectSetterError(setter_id_2.clone(), Some(Error::InternalError(InternalError::GenericError("Oops".to_owned())))));
+    manager.send_values(target_map(vec![(vec![ChannelSelector::new()], data_on.clone())]), User::Id(1));
+    let entries = manager.get_audit_log(AuditQuery::default(), admin.clone()).unwrap();
+    assert_eq!(entries.len(), 2);
+    for entry in &entries {
+        assert_eq!(entry.user, User::Id(1));
+        match entry.event {
+            AuditEvent::Send { ref channel, ref value, ref result } => {
+                assert_eq!(*value, data_on);
+                if *channel == setter_id_1 {
+                    assert_matches!(*result, Ok(()));
+                } else {
+                    assert_eq!(*channel, setter_id_2);
+                    assert_matches!(*result, Err(Error::InternalError(_)));
+                }
+            }
+            ref other => panic!("Unexpected event {:?}", other)
+        }
+    }
+
+    println!("* Entries can be filtered by user, channel and tag.");
+    manager.send_values(target_map(vec![(vec![ChannelSelector::new().with_id(setter_id_2.clone())], data_on.clone())]), User::None);
+    manager.audit_script_execution("my script", 0, &User::Id(2));
+    assert_eq!(manager.get_audit_log(AuditQuery::default(), admin.clone()).unwrap().len(), 4);
+    assert_eq!(manager.get_audit_log(AuditQuery {
+        user: Some(1),
+        .. AuditQuery::default()
+    }, admin.clone()).unwrap().len(), 2);
+    assert_eq!(manager.get_audit_log(AuditQuery {
+        channel: Some(setter_id_2.clone()),
+        .. AuditQuery::default()
+    }, admin.clone()).unwrap().len(), 2);
+    let entries = manager.get_audit_log(AuditQuery {
+        tag: Some(tag_door.clone()),
+        .. AuditQuery::default()
+    }, admin.clone()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_matches!(entries[0].event, AuditEvent::Send { ref channel, .. } if *channel == setter_id_1);
+
+    println!("* Only admins may read the audit log.");
+    assert_matches!(manager.get_audit_log(AuditQuery::default(), User::Id(1)),
+        Err(Error::AccessDenied(AccessDenied::AuditLog)));
+    assert_matches!(manager.get_audit_log(AuditQuery::default(), User::None),
+        Err(Error::AccessDenied(AccessDenied::AuditLog)));
+
+    println!("* Most recent entries come first.");
+    let entries = manager.get_audit_log(AuditQuery {
+        limit: Some(1),
+        .. AuditQuery::default()
+    }, admin.clone()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].user, User::Id(2));
+    assert_matches!(entries[0].event, AuditEvent::ScriptExecution { ref script, rule_index: 0 } if script == "my script");
+}
+
+#[test]
+fn test_selectors() {
+    println!("");
+    let manager = AdapterManager::new(None);
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let id_2 = Id::<AdapterId>::new("adapter id 2");
+
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let service_id_2 = Id::<ServiceId>::new("service id 2");
+    let service_id_3 = Id::<ServiceId>::new("service id 3");
+
+    let getter_id_1 = Id::<Channel>::new("getter id 1");
+    let getter_id_2 = Id::<Channel>::new("getter id 2");
+    let getter_id_3 = Id::<Channel>::new("getter id 3");
+
+    let mut service_1 = Service::empty(&service_id_1, &id_1);
+    service_1.properties.insert("model".to_owned(), "Hue white lamp".to_owned());
+    service_1.properties.insert("room".to_owned(), "kitchen".to_owned());
+    let mut service_2 = Service::empty(&service_id_2, &id_1);
+    service_2.properties.insert("room".to_owned(), "bedroom".to_owned());
+    let service_3 = Service::empty(&service_id_3, &id_2);
+
+    let tag_1 = Id::<TagId>::new("tag_1");
+    let tag_2 = Id::<TagId>::new("tag_2");
+    let tag_3 = Id::<TagId>::new("tag_3");
+
+    manager.add_adapter(Arc::new(FakeAdapter::new(&id_1))).unwrap();
+    manager.add_adapter(Arc::new(FakeAdapter::new(&id_2))).unwrap();
+    for &(ref service, ref getter_id) in &[(&service_1, &getter_id_1), (&service_2, &getter_id_2), (&service_3, &getter_id_3)] {
+        manager.add_service((*service).clone()).unwrap();
+        manager.add_channel(Channel {
+            kind: ChannelKind::LightOn,
+            supports_fetch: true,
+            .. Channel::empty(getter_id, &service.id, &service.adapter)
+        }).unwrap();
+    }
+
+    manager.add_service_tags(vec![ServiceSelector::new().with_id(service_id_1.clone())], vec![tag_1.clone()]);
+    manager.add_service_tags(vec![ServiceSelector::new().with_id(service_id_2.clone())], vec![tag_2.clone()]);
+    manager.add_channel_tags(vec![ChannelSelector::new().with_id(getter_id_1.clone())], vec![tag_1.clone()]);
+    manager.add_channel_tags(vec![ChannelSelector::new().with_id(getter_id_2.clone())], vec![tag_1.clone(), tag_2.clone()]);
+
+    let service_ids = |selector: ServiceSelector| {
+        let mut ids : Vec<_> = manager.get_services(vec![selector]).drain(..).map(|service| service.id).collect();
+        ids.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        ids
+    };
+    let channel_ids = |selector: ChannelSelector| {
+        let mut ids : Vec<_> = manager.get_channels(vec![selector]).drain(..).map(|channel| channel.id).collect();
+        ids.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        ids
+    };
+
+    println!("* Selecting by adapter.");
+    assert_eq!(service_ids(ServiceSelector::new().with_adapter(id_1.clone())), vec![service_id_1.clone(), service_id_2.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().with_adapter(id_2.clone())), vec![service_id_3.clone()]);
+    assert_eq!(channel_ids(ChannelSelector::new().with_adapter(id_2.clone())), vec![getter_id_3.clone()]);
+    assert_eq!(channel_ids(ChannelSelector::new().with_adapter(id_1.clone()).with_adapter(id_2.clone())).len(), 0);
+
+    println!("* Selecting by excluded tags.");
+    assert_eq!(service_ids(ServiceSelector::new().without_tags(vec![tag_1.clone()])), vec![service_id_2.clone(), service_id_3.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().without_tags(vec![tag_1.clone(), tag_2.clone()])), vec![service_id_3.clone()]);
+    assert_eq!(channel_ids(ChannelSelector::new().without_tags(vec![tag_2.clone()])), vec![getter_id_1.clone(), getter_id_3.clone()]);
+    assert_eq!(channel_ids(ChannelSelector::new().with_tags(vec![tag_1.clone()]).without_tags(vec![tag_2.clone()])),
+               vec![getter_id_1.clone()]);
+
+    println!("* Selecting by any of several tags.");
+    assert_eq!(service_ids(ServiceSelector::new().with_any_of_tags(vec![tag_1.clone(), tag_2.clone()])),
+               vec![service_id_1.clone(), service_id_2.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().with_any_of_tags(vec![tag_3.clone()])).len(), 0);
+    assert_eq!(channel_ids(ChannelSelector::new().with_any_of_tags(vec![tag_2.clone(), tag_3.clone()])), vec![getter_id_2.clone()]);
+    assert_eq!(channel_ids(ChannelSelector::new()
+                .with_any_of_tags(vec![tag_1.clone(), tag_3.clone()])
+                .with_any_of_tags(vec![tag_2.clone()])),
+               vec![getter_id_2.clone()]);
+
+    println!("* Selecting by property matchers.");
+    assert_eq!(service_ids(ServiceSelector::new().with_property("room", "kitchen")), vec![service_id_1.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new()
+                .with_property_matcher("room", PropertyMatcher::AnyOf(vec!["kitchen".to_owned(), "bedroom".to_owned()]))),
+               vec![service_id_1.clone(), service_id_2.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().with_property_matcher("model", PropertyMatcher::Contains("Hue".to_owned()))),
+               vec![service_id_1.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().with_property_matcher("room", PropertyMatcher::Exists(true))),
+               vec![service_id_1.clone(), service_id_2.clone()]);
+    assert_eq!(service_ids(ServiceSelector::new().with_property_matcher("room", PropertyMatcher::Exists(false))),
+               vec![service_id_3.clone()]);
+
+    println!("* Combining selectors.");
+    let selector = ServiceSelector::new().with_adapter(id_1.clone())
+        .and(ServiceSelector::new().without_tags(vec![tag_1.clone()]));
+    assert_eq!(service_ids(selector), vec![service_id_2.clone()]);
+    let selector = ServiceSelector::new()
+        .with_property_matcher("room", PropertyMatcher::AnyOf(vec!["kitchen".to_owned(), "bedroom".to_owned()]))
+        .and(ServiceSelector::new().with_property("room", "bedroom"));
+    assert_eq!(service_ids(selector), vec![service_id_2.clone()]);
+    let selector = ServiceSelector::new().with_property("room", "kitchen")
+        .and(ServiceSelector::new().with_property("room", "bedroom"));
+    assert_eq!(service_ids(selector).len(), 0);
+
+    println!("* Parsing selectors from JSON.");
+    let selector = ServiceSelector::from_str(r#"{
+        "adapter": "adapter id 1",
+        "without_tags": ["tag_2"],
+        "any_of_tags": ["tag_1", "tag_3"],
+        "properties": {
+            "room": ["kitchen", "living room"],
+            "model": {"contains": "white"},
+            "color": {"exists": false}
+        }
+    }"#).unwrap();
+    assert_eq!(selector.properties.get("model"), Some(&PropertyMatcher::Contains("white".to_owned())));
+    assert_eq!(service_ids(selector), vec![service_id_1.clone()]);
+
+    assert_eq!(service_ids(ServiceSelector::from_str(r#"{"properties": {"room": "bedroom"}}"#).unwrap()),
+               vec![service_id_2.clone()]);
+    assert!(ServiceSelector::from_str(r#"{"properties": {"room": {"startswith": "k"}}}"#).is_err());
+    assert!(ServiceSelector::from_str(r#"{"without_tags": "tag_1"}"#).is_err());
+
+    let selector = ChannelSelector::from_str(r#"{
+        "adapter": "adapter id 1",
+        "any_of_tags": ["tag_2", "tag_3"]
+    }"#).unwrap();
+    assert_eq!(channel_ids(selector), vec![getter_id_2.clone()]);
+    let selector = ChannelSelector::from_str(r#"{"without_tags": ["tag_1"]}"#).unwrap();
+    assert_eq!(channel_ids(selector), vec![getter_id_3.clone()]);
+}
+
 #[test]
 fn test_add_remove_adapter() {
     for clear in vec![false, true] {
@@ -974,21 +1485,21 @@ fn test_fetch() {
         let adapter_2 = FakeAdapter::new(&id_2);
         let tweak_1 = adapter_1.get_tweak();
         println!("* Without adapters, fetching values from a selector that has no channels returns an empty vector.");
-        assert_eq!(manager.fetch_values(vec![ChannelSelector::new()], User::None).len(), 0);
+        assert_eq!(manager.fetch_values(vec![ChannelSelector::new()], None, User::None).len(), 0);
 
         println!("* With adapters, fetching values from a selector that has no channels returns an empty vector.");
         manager.add_adapter(Arc::new(adapter_1)).unwrap();
         manager.add_adapter(Arc::new(adapter_2)).unwrap();
         manager.add_service(service_1.clone()).unwrap();
         manager.add_service(service_2.clone()).unwrap();
-        assert_eq!(manager.fetch_values(vec![ChannelSelector::new()], User::None).len(), 0);
+        assert_eq!(manager.fetch_values(vec![ChannelSelector::new()], None, User::None).len(), 0);
 
         println!("* Fetching empty values from a selector that has channels returns a vector of empty values.");
         manager.add_channel(getter_1_1.clone()).unwrap();
         manager.add_channel(getter_1_2.clone()).unwrap();
         manager.add_channel(getter_1_3.clone()).unwrap();
         manager.add_channel(getter_2.clone()).unwrap();
-        let data = manager.fetch_values(vec![ChannelSelector::new()], User::None);
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, User::None);
         assert_eq!(data.len(), 4);
 
         for result in data.values() {
@@ -1002,7 +1513,7 @@ fn test_fetch() {
         println!("* Fetching values returns the right values.");
         tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
         tweak_1(Tweak::InjectGetterValue(getter_id_1_2.clone(), Ok(Some(Value::OnOff(OnOff::Off)))));
-        let data = manager.fetch_values(vec![ChannelSelector::new()], User::None);
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, User::None);
         assert_eq!(data.len(), 4);
         match data.get(&getter_id_1_1).transform() {
             Some(Ok(Some(Value::OnOff(OnOff::On)))) => {},
@@ -1021,9 +1532,41 @@ fn test_fetch() {
             other => panic!("Unexpected result, {:?}", other)
         }
 
+        println!("* Fetching values with a max age returns the cached values.");
+        tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Ok(Some(Value::OnOff(OnOff::Off)))));
+        let max_age = Duration::from(chrono::Duration::seconds(60));
+        let data = manager.fetch_values(vec![ChannelSelector::new()], Some(max_age), User::None);
+        assert_eq!(data.len(), 4);
+        match data.get(&getter_id_1_1).transform() {
+            Some(Ok(Some(Value::OnOff(OnOff::On)))) => {},
+            other => panic!("Unexpected result, {:?}", other)
+        }
+        match data.get(&getter_id_1_3).transform() {
+            Some(Ok(None)) => {},
+            other => panic!("Unexpected result, {:?}", other)
+        }
+
+        println!("* Fetching values with a max age of 0 does not return older values.");
+        thread::sleep(std::time::Duration::from_millis(10));
+        let max_age = Duration::from(chrono::Duration::zero());
+        let data = manager.fetch_values(vec![ChannelSelector::new()], Some(max_age), User::None);
+        match data.get(&getter_id_1_1).transform() {
+            Some(Ok(Some(Value::OnOff(OnOff::Off)))) => {},
+            other => panic!("Unexpected result, {:?}", other)
+        }
+
+        println!("* Fetched values are timestamped.");
+        let before = chrono::UTC::now();
+        let data = manager.fetch_values(vec![ChannelSelector::new().with_id(getter_id_1_1.clone())], None, User::None);
+        match data.get(&getter_id_1_1) {
+            Some(&Ok(Some(ref fetched))) => assert!(*fetched.timestamp.as_datetime() >= before),
+            other => panic!("Unexpected result, {:?}", other)
+        }
+        tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Ok(Some(Value::OnOff(OnOff::On)))));
+
         println!("* Fetching values returns the right errors.");
         tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(getter_id_1_1.clone())))));
-        let data = manager.fetch_values(vec![ChannelSelector::new()], User::None);
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, User::None);
         assert_eq!(data.len(), 4);
         match data.get(&getter_id_1_1).transform() {
             Some(Err(Error::InternalError(InternalError::NoSuchChannel(ref id)))) if *id == getter_id_1_1 => {},
@@ -1044,7 +1587,7 @@ fn test_fetch() {
 
         println!("* Fetching a value that causes an internal type error returns that error.");
         tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Ok(Some(Value::OpenClosed(OpenClosed::Open)))));
-        let data = manager.fetch_values(vec![ChannelSelector::new()], User::None);
+        let data = manager.fetch_values(vec![ChannelSelector::new()], None, User::None);
         assert_eq!(data.len(), 4);
         match data.get(&getter_id_1_1).transform() {
             Some(Err(Error::TypeError(TypeError {
@@ -1342,7 +1885,7 @@ fn test_watch() {
         guards.push(manager.watch_values(target_map(vec![(
             vec![ChannelSelector::new().with_id(Id::new("No such getter"))],
             Exactly::Always
-        )]), Box::new(tx_watch_1)));
+        )]), Box::new(tx_watch_1), User::None));
 
         println!("* With adapters, watching values from a selector that has no channels does nothing.");
         manager.add_adapter(Arc::new(adapter_1)).unwrap();
@@ -1358,14 +1901,14 @@ fn test_watch() {
         guards.push(manager.watch_values(target_map(vec![(
             vec![ChannelSelector::new().with_id(Id::new("No such getter"))],
             Exactly::Always
-        )]), Box::new(tx_watch)));
+        )]), Box::new(tx_watch), User::None));
 
         println!("* We can observe channels being added.");
         let (tx_watch, rx_watch) = channel();
         let guard = manager.watch_values(target_map(vec![(
             vec![ChannelSelector::new()],
             Exactly::Always
-        )]), Box::new(tx_watch)); // We keep `guard` out of `guards` to drop it manually later.
+        )]), Box::new(tx_watch), User::None); // We keep `guard` out of `guards` to drop it manually later.
 
         manager.add_channel(getter_1_1.clone()).unwrap();
         manager.add_channel(getter_1_2.clone()).unwrap();
@@ -1425,7 +1968,7 @@ fn test_watch() {
                     .with_tags(vec![tag_1.clone()])
             ],
             Exactly::Exactly(Value::Range(Box::new(Range::Eq(Value::OnOff(OnOff::On)))).transform())
-        )]), Box::new(tx_watch_2)));
+        )]), Box::new(tx_watch_2), User::None));
 
         println!("* Value changes are observed on both watchers");
         tweak_1(Tweak::InjectGetterValue(getter_id_1_1.clone(), Ok(Some(Value::OnOff(OnOff::Off)))));
@@ -1535,3 +2078,129 @@ fn test_watch() {
 
     println!("");
 }
+
+#[test]
+fn test_watch_topology() {
+    println!("");
+
+    let manager = AdapterManager::new(None);
+    let id_1 = Id::<AdapterId>::new("adapter id 1");
+    let service_id_1 = Id::<ServiceId>::new("service id 1");
+    let service_id_2 = Id::<ServiceId>::new("service id 2");
+    let getter_id_1 = Id::<Channel>::new("getter id 1");
+    let getter_id_2 = Id::<Channel>::new("getter id 2");
+    let tag_1 = Id::<TagId>::new("tag 1");
+
+    manager.add_adapter(Arc::new(FakeAdapter::new(&id_1))).unwrap();
+
+    let (tx_all, rx_all) = channel();
+    let guard_all = manager.watch_topology(vec![], Box::new(tx_all));
+
+    let (tx_1, rx_1) = channel();
+    let _guard_1 = manager.watch_topology(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+        Box::new(tx_1));
+
+    println!("* We are notified when services are added.");
+    manager.add_service(Service::empty(&service_id_1, &id_1)).unwrap();
+    manager.add_service(Service::empty(&service_id_2, &id_1)).unwrap();
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ServiceAdded(ref id) if *id == service_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ServiceAdded(ref id) if *id == service_id_2 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ServiceAdded(ref id) if *id == service_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert_matches!(rx_1.try_recv(), Err(_));
+
+    println!("* We are notified when channels are added, only if the service matches.");
+    manager.add_channel(Channel {
+        kind: ChannelKind::LightOn,
+        .. Channel::empty(&getter_id_1, &service_id_1, &id_1)
+    }).unwrap();
+    manager.add_channel(Channel {
+        kind: ChannelKind::LightOn,
+        .. Channel::empty(&getter_id_2, &service_id_2, &id_1)
+    }).unwrap();
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ChannelAdded { ref service, ref channel }
+            if *service == service_id_1 && *channel == getter_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ChannelAdded { ref service, ref channel }
+            if *service == service_id_2 && *channel == getter_id_2 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ChannelAdded { ref channel, .. } if *channel == getter_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert_matches!(rx_1.try_recv(), Err(_));
+
+    println!("* We are notified when tags change, but not when they are already present.");
+    assert_eq!(manager.add_service_tags(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+        vec![tag_1.clone()]), 1);
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ServiceTagsChanged { ref service, ref tags }
+            if *service == service_id_1 && *tags == vec![tag_1.clone()] => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert_eq!(manager.add_service_tags(vec![ServiceSelector::new().with_id(service_id_1.clone())],
+        vec![tag_1.clone()]), 1);
+    assert_matches!(rx_1.try_recv(), Err(_));
+
+    assert_eq!(manager.add_channel_tags(vec![ChannelSelector::new().with_id(getter_id_1.clone())],
+        vec![tag_1.clone()]), 1);
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ChannelTagsChanged { ref channel, ref tags, .. }
+            if *channel == getter_id_1 && *tags == vec![tag_1.clone()] => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert_eq!(manager.remove_channel_tags(vec![ChannelSelector::new().with_id(getter_id_1.clone())],
+        vec![tag_1.clone()]), 1);
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ChannelTagsChanged { ref channel, ref tags, .. }
+            if *channel == getter_id_1 && tags.is_empty() => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+
+    println!("* We are notified when channels and services are removed.");
+    while let Ok(_) = rx_all.try_recv() { }
+    manager.remove_channel(&getter_id_1).unwrap();
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ChannelRemoved { ref service, ref channel }
+            if *service == service_id_1 && *channel == getter_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    manager.remove_service(&service_id_1).unwrap();
+    match rx_1.recv().unwrap() {
+        TopologyEvent::ServiceRemoved(ref id) if *id == service_id_1 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert_matches!(rx_1.try_recv(), Err(_));
+
+    println!("* Removing a service also notifies the removal of its channels.");
+    while let Ok(_) = rx_all.try_recv() { }
+    manager.remove_service(&service_id_2).unwrap();
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ChannelRemoved { ref channel, .. } if *channel == getter_id_2 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+    match rx_all.recv().unwrap() {
+        TopologyEvent::ServiceRemoved(ref id) if *id == service_id_2 => {}
+        other => panic!("Unexpected event {:?}", other)
+    }
+
+    println!("* Once the guard is dropped, we are not notified anymore.");
+    drop(guard_all);
+    manager.add_service(Service::empty(&service_id_2, &id_1)).unwrap();
+    assert_matches!(rx_all.try_recv(), Err(_));
+
+    manager.stop();
+    println!("");
+}
@@ -9,7 +9,14 @@ use foxbox_taxonomy::selector::*;
 use foxbox_taxonomy::services::*;
 use foxbox_taxonomy::values::*;
 
+use chrono::Duration as ChronoDuration;
+use chrono::Weekday;
+use serde_json;
+
+use util::map;
+
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 /// A thinkerbell scrip"t.
 #[derive(Debug)]
@@ -44,17 +51,25 @@ impl Parser<Script<UncheckedCtx>> for Script<UncheckedCtx> {
 ///
 /// A single rule is represented as an object with the following fields:
 ///
-/// - conditions (array of Match): the conditions in which to execute
-///   the code – *all* conditions must be met;
+/// - conditions (Condition): the conditions in which to execute
+///   the code. For backwards compatibility, this may also be an
+///   array of Condition – *all* conditions must then be met;
 /// - execute (array of Statement): the code to execute once all conditions
-///   are met.
+///   are met;
+/// - otherwise (array of Statement, optional): the code to execute once the
+///   conditions were met and stop being met. Defaults to an empty array;
+/// - mode (Mode, optional): when to execute the code. Defaults to `OnEnter`;
+/// - cooldown (Duration, optional): if specified, the code is never executed
+///   twice within `cooldown`, which is useful e.g. with flapping sensors.
 ///
 /// ```
 /// extern crate foxbox_thinkerbell;
 /// extern crate foxbox_taxonomy;
+/// extern crate chrono;
 ///
 /// use foxbox_thinkerbell::ast::*;
 /// use foxbox_taxonomy::parse::*;
+/// use foxbox_taxonomy::values::*;
 ///
 /// # fn main() {
 /// let source = r#"{
@@ -68,22 +83,41 @@ impl Parser<Script<UncheckedCtx>> for Script<UncheckedCtx> {
 ///     "destination": [{"id": "my setter"}],
 ///     "value": {"OnOff": "Off"},
 ///     "kind": "LightOn"
-///   }]
+///   }],
+///   "otherwise": [{
+///     "destination": [{"id": "my setter"}],
+///     "value": {"OnOff": "On"},
+///     "kind": "LightOn"
+///   }],
+///   "mode": {"WhileMet": {"repeat": 600}},
+///   "cooldown": 60
 /// }"#;
 ///
-/// Rule::<UncheckedCtx>::from_str(&source).unwrap();
+/// let rule = Rule::<UncheckedCtx>::from_str(&source).unwrap();
+/// assert_eq!(rule.otherwise.len(), 1);
+/// assert_eq!(rule.mode, Mode::WhileMet { repeat: Duration::from(chrono::Duration::seconds(600)) });
 /// # }
 /// ```
 #[derive(Debug)]
 pub struct Rule<Ctx> where Ctx: Context {
-    /// The condition in which to execute the trigger. The condition
-    /// is matched once *all* the `Match` branches are true. Whenever
+    /// The condition in which to execute the trigger. Whenever
     /// `conditions` was false and becomes true, we execute `execute`.
-    pub conditions: Vec<Match<Ctx>>,
+    pub conditions: Condition<Ctx>,
 
     /// Stuff to do once `condition` is met.
     pub execute: Vec<Statement<Ctx>>,
 
+    /// Stuff to do once `condition` was met and stops being met.
+    /// Unlike `execute`, this is not affected by `mode` or `cooldown`.
+    pub otherwise: Vec<Statement<Ctx>>,
+
+    /// When to execute `execute`.
+    pub mode: Mode,
+
+    /// If specified, `execute` is not executed again until
+    /// `cooldown` has elapsed.
+    pub cooldown: Option<Duration>,
+
     pub phantom: PhantomData<Ctx>,
 }
 impl Parser<Rule<UncheckedCtx>> for Rule<UncheckedCtx> {
@@ -93,19 +127,399 @@ impl Parser<Rule<UncheckedCtx>> for Rule<UncheckedCtx> {
 
     fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
         let conditions = try!(path.push("conditions",
-            |path| Match::take_vec(path, source, "conditions"))
+            |path| Condition::take(path, source, "conditions"))
         );
         let execute = try!(path.push("execute",
             |path| Statement::take_vec(path, source, "execute"))
         );
+        let otherwise = match path.push("otherwise", |path| Statement::take_vec_opt(path, source, "otherwise")) {
+            Some(result) => try!(result),
+            None => vec![]
+        };
+        let mode = match path.push("mode", |path| Mode::take_opt(path, source, "mode")) {
+            Some(result) => try!(result),
+            None => Mode::OnEnter
+        };
+        let cooldown = match path.push("cooldown", |path| Duration::take_opt(path, source, "cooldown")) {
+            Some(result) => Some(try!(result)),
+            None => None
+        };
         Ok(Rule {
             conditions: conditions,
             execute: execute,
+            otherwise: otherwise,
+            mode: mode,
+            cooldown: cooldown,
             phantom: PhantomData,
         })
     }
 }
 
+/// When to execute the statements of a rule.
+///
+/// # JSON
+///
+/// A mode is represented as one of:
+///
+/// - string `"OnEnter"`;
+/// - string `"OnExit"`;
+/// - an object `{"WhileMet": {"repeat": Duration}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    /// Execute whenever the conditions were not met and become met.
+    OnEnter,
+
+    /// Execute whenever the conditions were met and stop being met.
+    OnExit,
+
+    /// Execute whenever the conditions become met, then every `repeat`
+    /// for as long as they remain met.
+    WhileMet { repeat: Duration },
+}
+
+impl Parser<Mode> for Mode {
+    fn description() -> String {
+        "Mode".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::String(ref str) if str == "OnEnter" => Ok(Mode::OnEnter),
+            JSON::String(ref str) if str == "OnExit" => Ok(Mode::OnExit),
+            JSON::String(ref str) => Err(ParseError::unknown_constant(str, &path)),
+            JSON::Object(ref obj) if obj.len() == 1 => {
+                if let Some(while_met) = obj.get("WhileMet") {
+                    let repeat = try!(path.push("WhileMet",
+                        |path| Duration::take(path, while_met, "repeat"))
+                    );
+                    return Ok(Mode::WhileMet { repeat: repeat });
+                }
+                Err(ParseError::type_error("Mode", &path, "a field WhileMet"))
+            }
+            _ => Err(ParseError::type_error("Mode", &path, "string or object"))
+        }
+    }
+}
+
+/// A boolean combination of matches.
+///
+/// # JSON
+///
+/// A condition is represented as one of:
+///
+/// - an object `{"All": array of Condition}`, met iff *all* the
+///   sub-conditions are met;
+/// - an object `{"Any": array of Condition}`, met iff *any* of the
+///   sub-conditions is met;
+/// - an object `{"Not": Condition}`, met iff the sub-condition is not met;
+/// - an object `{"Match": Match}` or, more simply, a `Match`;
+/// - an object `{"Schedule": Schedule}`, met while the schedule is active;
+/// - an array of Condition, which is a shorthand for `All`.
+///
+/// ```
+/// extern crate foxbox_thinkerbell;
+/// extern crate foxbox_taxonomy;
+///
+/// use foxbox_thinkerbell::ast::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// # fn main() {
+/// // The door is open and (it's after 22:00 or the porch light is off).
+/// let source = r#"{
+///   "All": [{
+///     "source": [{"id": "front door"}],
+///     "kind": "OpenClosed",
+///     "range": {"Eq": {"OpenClosed": "Open"}}
+///   }, {
+///     "Any": [{
+///       "source": [{"id": "clock"}],
+///       "kind": "CurrentTimeOfDay",
+///       "range": {"Geq": {"Duration": 79200}}
+///     }, {
+///       "Not": {
+///         "source": [{"id": "porch light"}],
+///         "kind": "LightOn",
+///         "range": {"Eq": {"OnOff": "On"}}
+///       }
+///     }]
+///   }]
+/// }"#;
+///
+/// let condition = Condition::<UncheckedCtx>::from_str(&source).unwrap();
+/// assert_eq!(condition.matches().len(), 3);
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum Condition<Ctx> where Ctx: Context {
+    /// Met iff all the sub-conditions are met.
+    All(Vec<Condition<Ctx>>),
+
+    /// Met iff at least one of the sub-conditions is met.
+    Any(Vec<Condition<Ctx>>),
+
+    /// Met iff the sub-condition is not met.
+    Not(Box<Condition<Ctx>>),
+
+    /// Met iff the match is met.
+    Match(Match<Ctx>),
+
+    /// Met iff the schedule is active.
+    Schedule(Schedule),
+}
+
+impl<Ctx> Condition<Ctx> where Ctx: Context {
+    /// All the matches of this condition, in depth-first order.
+    ///
+    /// During execution, matches are identified by their index in this vector.
+    pub fn matches(&self) -> Vec<&Match<Ctx>> {
+        let mut matches = vec![];
+        self.aux_matches(&mut matches);
+        matches
+    }
+
+    fn aux_matches<'a>(&'a self, matches: &mut Vec<&'a Match<Ctx>>) {
+        use self::Condition::*;
+        match *self {
+            All(ref conditions) | Any(ref conditions) => {
+                for condition in conditions {
+                    condition.aux_matches(matches);
+                }
+            }
+            Not(ref condition) => condition.aux_matches(matches),
+            Match(ref match_) => matches.push(match_),
+            Schedule(_) => {}
+        }
+    }
+
+    /// All the schedules of this condition, in depth-first order.
+    ///
+    /// During execution, schedules are identified by their index in this vector.
+    pub fn schedules(&self) -> Vec<&Schedule> {
+        let mut schedules = vec![];
+        self.aux_schedules(&mut schedules);
+        schedules
+    }
+
+    fn aux_schedules<'a>(&'a self, schedules: &mut Vec<&'a Schedule>) {
+        use self::Condition::*;
+        match *self {
+            All(ref conditions) | Any(ref conditions) => {
+                for condition in conditions {
+                    condition.aux_schedules(schedules);
+                }
+            }
+            Not(ref condition) => condition.aux_schedules(schedules),
+            Match(_) => {}
+            Schedule(ref schedule) => schedules.push(schedule),
+        }
+    }
+
+    /// Determine whether the condition is met, given whether each match is met.
+    /// Matches are identified by their index, as in `matches()`. Schedules are
+    /// considered inactive.
+    pub fn is_met<F>(&self, match_is_met: &F) -> bool where F: Fn(usize) -> bool {
+        self.is_met_with_schedules(match_is_met, &|_| false)
+    }
+
+    /// Determine whether the condition is met, given whether each match is met
+    /// and whether each schedule is active. Matches and schedules are identified
+    /// by their index, as in `matches()` and `schedules()` respectively.
+    pub fn is_met_with_schedules<F, G>(&self, match_is_met: &F, schedule_is_active: &G) -> bool
+        where F: Fn(usize) -> bool, G: Fn(usize) -> bool
+    {
+        let mut match_index = 0;
+        let mut schedule_index = 0;
+        self.aux_is_met(match_is_met, schedule_is_active, &mut match_index, &mut schedule_index)
+    }
+
+    fn aux_is_met<F, G>(&self, match_is_met: &F, schedule_is_active: &G,
+        match_index: &mut usize, schedule_index: &mut usize) -> bool
+        where F: Fn(usize) -> bool, G: Fn(usize) -> bool
+    {
+        use self::Condition::*;
+        // Note that we must visit every leaf, to keep indices in sync with `matches()`
+        // and `schedules()`.
+        match *self {
+            All(ref conditions) => conditions.iter()
+                .map(|condition| condition.aux_is_met(match_is_met, schedule_is_active, match_index, schedule_index))
+                .fold(true, |acc, is_met| acc && is_met),
+            Any(ref conditions) => conditions.iter()
+                .map(|condition| condition.aux_is_met(match_is_met, schedule_is_active, match_index, schedule_index))
+                .fold(false, |acc, is_met| acc || is_met),
+            Not(ref condition) => !condition.aux_is_met(match_is_met, schedule_is_active, match_index, schedule_index),
+            Match(_) => {
+                let is_met = match_is_met(*match_index);
+                *match_index += 1;
+                is_met
+            }
+            Schedule(_) => {
+                let is_active = schedule_is_active(*schedule_index);
+                *schedule_index += 1;
+                is_active
+            }
+        }
+    }
+}
+
+impl Parser<Condition<UncheckedCtx>> for Condition<UncheckedCtx> {
+    fn description() -> String {
+        "Condition".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        if let JSON::Array(_) = *source {
+            return Ok(Condition::All(try!(Vec::<Condition<UncheckedCtx>>::parse(path, source))));
+        }
+        if let Some(result) = path.push("All", |path| Condition::take_vec_opt(path, source, "All")) {
+            return Ok(Condition::All(try!(result)));
+        }
+        if let Some(result) = path.push("Any", |path| Condition::take_vec_opt(path, source, "Any")) {
+            return Ok(Condition::Any(try!(result)));
+        }
+        if let Some(result) = path.push("Not", |path| Condition::take_opt(path, source, "Not")) {
+            return Ok(Condition::Not(Box::new(try!(result))));
+        }
+        if let Some(result) = path.push("Match", |path| Match::take_opt(path, source, "Match")) {
+            return Ok(Condition::Match(try!(result)));
+        }
+        if let Some(result) = path.push("Schedule", |path| Schedule::take_opt(path, source, "Schedule")) {
+            return Ok(Condition::Schedule(try!(result)));
+        }
+        Ok(Condition::Match(try!(Match::parse(path, source))))
+    }
+}
+
+/// A schedule, active during a period of some days of the week.
+///
+/// Times of day are expressed in the local time of the box. Sunrise and
+/// sunset are computed from the location configured in the execution
+/// environment. If no location is configured, or if the sun doesn't rise
+/// or set on some day, the schedule is not active during that day.
+///
+/// # JSON
+///
+/// A schedule is represented as an object with the following fields:
+///
+/// - days (array of "Mon", "Tue", ..., "Sun", optional) - the days on which
+///   the schedule is active, by default every day;
+/// - start (TimeOfDay) - the time at which the schedule becomes active;
+/// - end (TimeOfDay, optional) - the time at which the schedule stops being
+///   active. If it is earlier than `start`, the schedule ends on the next day.
+///   By default, the schedule remains active for one minute, which makes it
+///   possible to use it as a cron-like trigger.
+///
+/// ```
+/// extern crate foxbox_thinkerbell;
+/// extern crate foxbox_taxonomy;
+/// extern crate chrono;
+///
+/// use foxbox_thinkerbell::ast::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// # fn main() {
+/// let source = r#"{
+///   "days": ["Mon", "Tue", "Wed", "Thu", "Fri"],
+///   "start": {"Sunset": -1800},
+///   "end": {"At": 82800}
+/// }"#;
+///
+/// let schedule = Schedule::from_str(&source).unwrap();
+/// assert_eq!(schedule.days.len(), 5);
+/// assert_eq!(schedule.days[0], chrono::Weekday::Mon);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// The days of the week on which the schedule starts. Never empty.
+    pub days: Vec<Weekday>,
+
+    pub start: TimeOfDay,
+
+    /// If `None`, the schedule remains active for one minute.
+    pub end: Option<TimeOfDay>,
+}
+impl Parser<Schedule> for Schedule {
+    fn description() -> String {
+        "Schedule".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let days = match path.push("days", |path| String::take_vec_opt(path, source, "days")) {
+            None => vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu,
+                         Weekday::Fri, Weekday::Sat, Weekday::Sun],
+            Some(Ok(days)) => try!(path.push("days", |path| {
+                map(days, |day| parse_weekday(&day, &path))
+            })),
+            Some(Err(err)) => return Err(err),
+        };
+        let start = try!(path.push("start", |path| TimeOfDay::take(path, source, "start")));
+        let end = match path.push("end", |path| TimeOfDay::take_opt(path, source, "end")) {
+            None => None,
+            Some(Ok(end)) => Some(end),
+            Some(Err(err)) => return Err(err),
+        };
+        Ok(Schedule {
+            days: days,
+            start: start,
+            end: end,
+        })
+    }
+}
+
+fn parse_weekday(day: &str, path: &Path) -> Result<Weekday, ParseError> {
+    match day {
+        "Mon" => Ok(Weekday::Mon),
+        "Tue" => Ok(Weekday::Tue),
+        "Wed" => Ok(Weekday::Wed),
+        "Thu" => Ok(Weekday::Thu),
+        "Fri" => Ok(Weekday::Fri),
+        "Sat" => Ok(Weekday::Sat),
+        "Sun" => Ok(Weekday::Sun),
+        _ => Err(ParseError::unknown_constant(day, path))
+    }
+}
+
+/// A time of day, used by schedules.
+///
+/// # JSON
+///
+/// A time of day is represented as one of:
+///
+/// - an object `{"At": Duration}`, a fixed time of day, in seconds since midnight;
+/// - an object `{"Sunrise": Duration}`, the time of sunrise, plus an offset in seconds
+///   (possibly negative);
+/// - an object `{"Sunset": Duration}`, the time of sunset, plus an offset in seconds
+///   (possibly negative).
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeOfDay {
+    At(Duration),
+    Sunrise(Duration),
+    Sunset(Duration),
+}
+impl Parser<TimeOfDay> for TimeOfDay {
+    fn description() -> String {
+        "TimeOfDay".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::Object(ref obj) if obj.len() == 1 => {
+                if let Some(result) = path.push("At", |path| Duration::take_opt(path, source, "At")) {
+                    return Ok(TimeOfDay::At(try!(result)));
+                }
+                if let Some(result) = path.push("Sunrise", |path| Duration::take_opt(path, source, "Sunrise")) {
+                    return Ok(TimeOfDay::Sunrise(try!(result)));
+                }
+                if let Some(result) = path.push("Sunset", |path| Duration::take_opt(path, source, "Sunset")) {
+                    return Ok(TimeOfDay::Sunset(try!(result)));
+                }
+                Err(ParseError::type_error("TimeOfDay", &path, "a field At, Sunrise or Sunset"))
+            }
+            _ => Err(ParseError::type_error("TimeOfDay", &path, "object"))
+        }
+    }
+}
+
 /// An individual match.
 ///
 /// Matchs always take the form: "data received from getter channel
@@ -211,7 +625,11 @@ impl Parser<Match<UncheckedCtx>> for Match<UncheckedCtx> {
 ///
 /// A statement is represented as an object with the following fields:
 /// - destination (array of ChannelSelector);
-/// - value (Value);
+/// - value (Value) - a constant value to send, *or*
+/// - value_from (StatementValue) - where to read the value to send at runtime.
+///   Exactly one of `value` and `value_from` is required;
+/// - transform (Transform, optional) - how to convert the value read at runtime
+///   before sending it. Only acceptable along with `value_from`;
 /// - kind (ChannelKind);
 ///
 /// ```
@@ -232,8 +650,27 @@ impl Parser<Match<UncheckedCtx>> for Match<UncheckedCtx> {
 /// }"#;
 ///
 /// let statement = Statement::<UncheckedCtx>::from_str(&source).unwrap();
-/// assert_eq!(statement.value, Payload::from_value_auto(&Value::OnOff(OnOff::Off)));
+/// match statement.value {
+///   StatementValue::Constant(ref payload) =>
+///     assert_eq!(*payload, Payload::from_value_auto(&Value::OnOff(OnOff::Off))),
+///   _ => panic!()
+/// }
 /// assert_eq!(statement.kind, ChannelKind::LightOn);
+///
+/// // Log the temperature of the oven that caused the rule to trigger.
+/// let source = r#"{
+///   "destination": [{"kind": "Log"}],
+///   "value_from": {"Trigger": {"match": 0}},
+///   "transform": {"Template": "The oven has reached {}"},
+///   "kind": "Log"
+/// }"#;
+///
+/// let statement = Statement::<UncheckedCtx>::from_str(&source).unwrap();
+/// match statement.value {
+///   StatementValue::Trigger { match_index } => assert_eq!(match_index, 0),
+///   _ => panic!()
+/// }
+/// assert_eq!(statement.transform, Some(Transform::Template("The oven has reached {}".to_owned())));
 /// # }
 /// ```
 #[derive(Debug)]
@@ -246,7 +683,11 @@ pub struct Statement<Ctx> where Ctx: Context {
     /// Data to send to the resource. During compilation, we check
     /// that the type of `value` is compatible with that of
     /// `destination`.
-    pub value: Payload,
+    pub value: StatementValue,
+
+    /// If specified, a conversion applied to values read at runtime
+    /// before they are sent to `destination`.
+    pub transform: Option<Transform>,
 
     /// The kind of channel expected from `destination`, e.g. "close
     /// the door", "set the temperature", etc. During compilation, we
@@ -268,12 +709,22 @@ impl Parser<Statement<UncheckedCtx>> for Statement<UncheckedCtx> {
         let kind = try!(path.push("kind",
             |path| ChannelKind::take(path, source, "kind"))
         );
-        let value = try!(path.push("value",
-            |path| Payload::take(path, source, "value"))
-        );
+        let value = match path.push("value", |path| Payload::take_opt(path, source, "value")) {
+            Some(_) if source.find("value_from").is_some() =>
+                return Err(ParseError::unknown_fields(vec!["value_from".to_owned()], &path)),
+            Some(result) => StatementValue::Constant(try!(result)),
+            None => try!(path.push("value_from",
+                |path| StatementValue::take(path, source, "value_from"))
+            )
+        };
+        let transform = match path.push("transform", |path| Transform::take_opt(path, source, "transform")) {
+            Some(result) => Some(try!(result)),
+            None => None
+        };
         Ok(Statement {
             destination: destination,
             value: value,
+            transform: transform,
             kind: kind,
             phantom: PhantomData,
         })
@@ -281,6 +732,214 @@ impl Parser<Statement<UncheckedCtx>> for Statement<UncheckedCtx> {
 }
 
 
+/// The value sent by a `Statement`.
+///
+/// # JSON
+///
+/// Constant values are represented by field `value` of the statement.
+/// Values read at runtime are represented by field `value_from`, as one of:
+///
+/// - an object `{"Trigger": {"match": integer}}` – the value with which a
+///   getter entered the range of a match of the rule. Matches are numbered
+///   from 0, in the order in which they appear in the conditions of the rule.
+///   If `match` is omitted, it defaults to 0;
+/// - an object `{"Fetch": {"source": array of ChannelSelector, "kind": ChannelKind}}` –
+///   the value fetched from a getter when the statement is executed.
+///
+/// ```
+/// extern crate foxbox_thinkerbell;
+/// extern crate foxbox_taxonomy;
+///
+/// use foxbox_thinkerbell::ast::*;
+/// use foxbox_taxonomy::services::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// # fn main() {
+/// let source = r#"{
+///   "Fetch": {
+///     "source": [{"id": "light A"}],
+///     "kind": {"Extension": {"vendor": "foxlink@mozilla.com", "adapter": "Philips Hue Adapter", "kind": "Color", "typ": "Color"}}
+///   }
+/// }"#;
+///
+/// match StatementValue::from_str(&source).unwrap() {
+///   StatementValue::Fetch { source, .. } => assert_eq!(source.len(), 1),
+///   _ => panic!()
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub enum StatementValue {
+    /// A value known when the script is written.
+    Constant(Payload),
+
+    /// The latest value with which a getter entered the range of the
+    /// match with index `match_index`, as in `Condition::matches()`.
+    Trigger { match_index: usize },
+
+    /// A value fetched from any of the `source` getters when the
+    /// statement is executed. During compilation, we make sure that
+    /// we restrict to the elements of `source` that offer `kind`.
+    Fetch { source: Vec<ChannelSelector>, kind: ChannelKind },
+}
+
+impl Parser<StatementValue> for StatementValue {
+    fn description() -> String {
+        "StatementValue".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::Object(ref obj) if obj.len() == 1 => {
+                if let Some(trigger) = obj.get("Trigger") {
+                    let match_index = match path.push("Trigger", |path| u32::take_opt(path, trigger, "match")) {
+                        Some(result) => try!(result) as usize,
+                        None => 0
+                    };
+                    return Ok(StatementValue::Trigger { match_index: match_index });
+                }
+                if let Some(fetch) = obj.get("Fetch") {
+                    return path.push("Fetch", |path| {
+                        let sources = try!(path.push("source",
+                            |path| ChannelSelector::take_vec(path, fetch, "source"))
+                        );
+                        let kind = try!(path.push("kind",
+                            |path| ChannelKind::take(path, fetch, "kind"))
+                        );
+                        Ok(StatementValue::Fetch {
+                            source: sources,
+                            kind: kind
+                        })
+                    });
+                }
+                Err(ParseError::type_error("StatementValue", &path, "a field Trigger or Fetch"))
+            }
+            _ => Err(ParseError::type_error("StatementValue", &path, "object"))
+        }
+    }
+}
+
+/// A conversion applied to a value read at runtime.
+///
+/// # JSON
+///
+/// A transform is represented as one of:
+///
+/// - an object `{"Scale": {"factor": number, "offset": number}}` – converts
+///   a numeric value (Temperature, Duration in seconds or ExtNumeric) `x`
+///   into `x * factor + offset`, preserving its type and unit. Fields `factor`
+///   and `offset` default to 1 and 0 respectively;
+/// - an object `{"Template": string}` – converts any value into a String,
+///   by replacing each occurrence of `{}` in the template with a textual
+///   representation of the value.
+///
+/// ```
+/// extern crate foxbox_thinkerbell;
+/// extern crate foxbox_taxonomy;
+///
+/// use foxbox_thinkerbell::ast::*;
+/// use foxbox_taxonomy::parse::*;
+///
+/// # fn main() {
+/// let source = r#"{"Scale": {"factor": 0.5}}"#;
+/// assert_eq!(Transform::from_str(&source).unwrap(), Transform::Scale { factor: 0.5, offset: 0. });
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    Scale { factor: f64, offset: f64 },
+    Template(String),
+}
+
+impl Parser<Transform> for Transform {
+    fn description() -> String {
+        "Transform".to_owned()
+    }
+
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match *source {
+            JSON::Object(ref obj) if obj.len() == 1 => {
+                if let Some(scale) = obj.get("Scale") {
+                    return path.push("Scale", |path| {
+                        let factor = match path.push("factor", |path| f64::take_opt(path, scale, "factor")) {
+                            Some(result) => try!(result),
+                            None => 1.
+                        };
+                        let offset = match path.push("offset", |path| f64::take_opt(path, scale, "offset")) {
+                            Some(result) => try!(result),
+                            None => 0.
+                        };
+                        Ok(Transform::Scale {
+                            factor: factor,
+                            offset: offset
+                        })
+                    });
+                }
+                if let Some(template) = obj.get("Template") {
+                    return Ok(Transform::Template(try!(path.push("Template", |path| String::parse(path, template)))));
+                }
+                Err(ParseError::type_error("Transform", &path, "a field Scale or Template"))
+            }
+            _ => Err(ParseError::type_error("Transform", &path, "object"))
+        }
+    }
+}
+
+impl Transform {
+    /// The type of the values produced by this transform from values of type `input`,
+    /// or `None` if the transform cannot be applied to such values.
+    pub fn output_type(&self, input: &Type) -> Option<Type> {
+        match *self {
+            Transform::Scale { .. } => match *input {
+                Type::Temperature | Type::Duration | Type::ExtNumeric => Some(input.clone()),
+                _ => None
+            },
+            Transform::Template(_) => Some(Type::String),
+        }
+    }
+
+    /// Apply the transform, or return `None` if it cannot be applied to `value`.
+    pub fn apply(&self, value: Value) -> Option<Value> {
+        match *self {
+            Transform::Scale { factor, offset } => {
+                let scale = |x: f64| x * factor + offset;
+                match value {
+                    Value::Temperature(Temperature::C(x)) => Some(Value::Temperature(Temperature::C(scale(x)))),
+                    Value::Temperature(Temperature::F(x)) => Some(Value::Temperature(Temperature::F(scale(x)))),
+                    Value::Duration(duration) => {
+                        let duration: ChronoDuration = duration.into();
+                        let ms = scale(duration.num_milliseconds() as f64 / 1000.) * 1000.;
+                        Some(Value::Duration(Duration::from(ChronoDuration::milliseconds(ms as i64))))
+                    }
+                    Value::ExtNumeric(mut ext) => {
+                        ext.value = scale(ext.value);
+                        Some(Value::ExtNumeric(ext))
+                    }
+                    _ => None
+                }
+            }
+            Transform::Template(ref template) => {
+                let text = match value {
+                    Value::String(ref s) => (**s).clone(),
+                    Value::Temperature(Temperature::C(x)) => format!("{}°C", x),
+                    Value::Temperature(Temperature::F(x)) => format!("{}°F", x),
+                    Value::Duration(duration) => {
+                        let duration: ChronoDuration = duration.into();
+                        format!("{}", duration.num_milliseconds() as f64 / 1000.)
+                    }
+                    Value::ExtNumeric(ref ext) => format!("{}", ext.value),
+                    ref other => match serde_json::to_string(&other.to_json()) {
+                        Ok(text) => text,
+                        Err(_) => return None
+                    }
+                };
+                Some(Value::String(Arc::new(template.replace("{}", &text))))
+            }
+        }
+    }
+}
+
+
 /// A manner of representing internal nodes.
 ///
 /// Two data structures implement `Context`:
@@ -4,25 +4,41 @@
 //! performs the following transformations and checks:
 //!
 //! - Ensure that the `Script` has at least one `Rule`.
-//! - Ensure that each `Rule` has at least one `Match`.
+//! - Ensure that each `Rule` has at least one `Match` or `Schedule`,
+//!   and that each `All` or `Any` condition has at least one sub-condition.
 //! - Ensure that each `Rule` has at least one `Statement`.
+//! - Ensure that each `Rule` in mode `WhileMet` repeats with a
+//!   positive interval.
 //! - Ensure that each `Match` has at least one `source`.
+//! - Ensure that each `Schedule` has at least one day and that its
+//!   times of day fall within the day.
 //! - Ensure that each `Statement` has at least one `destination`.
 //! - Ensure that in each `Match`, the type of `range` matches
 //!   the `kind`.
 //! - Ensure that in each `Statement`, the type of `value` matches
-//!   the `kind`.
+//!   the `kind`. For values read at runtime, this is the type of
+//!   the channel they are read from, after `transform`.
 //! - Transform each `Match` to make sure that the kind of the
 //!   `source` matches the `kind`, even if devices change.
 //! - Transform each `Statement` to make sure that the kind of the
 //!   `destination` matches the `kind`, even if devices change.
+//! - Transform each `Statement` that fetches its value to make sure
+//!   that the kind of the `source` matches its `kind`.
+//!
+//! Scripts may also be checked with `validate`, which reports
+//! all the errors at once, along with the channels each node resolves to.
 
-use ast::{ Script, Rule, Statement, Match, Context, UncheckedCtx };
+use ast::{ Script, Rule, Mode, Statement, StatementValue, Condition, Match, Schedule, TimeOfDay, Context, UncheckedCtx };
+use schedule::Location;
 use util::*;
 
 use foxbox_taxonomy::api::API;
-use foxbox_taxonomy::util::Exactly;
-use foxbox_taxonomy::values::Duration;
+use foxbox_taxonomy::parse::{ ParseError, Parser, Path };
+use foxbox_taxonomy::services::Channel;
+use foxbox_taxonomy::util::{ Exactly, Id };
+use foxbox_taxonomy::values::{ Duration, TimeStamp, Type };
+
+use chrono::Duration as ChronoDuration;
 
 use transformable_channels::mpsc::*;
 
@@ -42,6 +58,12 @@ pub trait ExecutableDevEnv: Send {
     /// A guard returned by `start_timer`. When the guard is dropped, the timer is cancelled.
     type TimerGuard;
     fn start_timer(&self, duration: Duration, timer: Box<ExtSender<()>>) -> Self::TimerGuard;
+
+    /// The current date, as seen by the timers of this environment.
+    fn now(&self) -> TimeStamp;
+
+    /// The location of the box, used to compute the time of sunrise and sunset, if known.
+    fn location(&self) -> Option<Location>;
 }
 impl<W, A, T> Debug for ExecutableDevEnv<WatchGuard=W, API=A, TimerGuard=T> {
     fn fmt(&self, _: &mut Formatter) -> Result<(), FmtError> {
@@ -70,7 +92,7 @@ pub enum SourceError {
     /// A rule doesn't have any statements.
     NoStatement,
 
-    /// A rule doesn't have any condition.
+    /// A rule, or one of its `All`/`Any` conditions, doesn't have any condition.
     NoMatch,
 
     /// A match doesn't have any source.
@@ -78,6 +100,24 @@ pub enum SourceError {
 
     /// A statement doesn't have any destination.
     NoStatementDestination,
+
+    /// A statement reads its value from a match that doesn't exist.
+    NoSuchMatch,
+
+    /// A statement fetches its value but doesn't have any source.
+    NoStatementSource,
+
+    /// A statement attempts to transform a constant value.
+    TransformOfConstant,
+
+    /// A rule is meant to repeat its statements but its interval is not positive.
+    InvalidRepeat,
+
+    /// A schedule doesn't have any day.
+    NoScheduleDay,
+
+    /// A schedule has a time of day that is negative or exceeds 24h.
+    InvalidTimeOfDay,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -92,6 +132,10 @@ pub enum TypeError {
     /// The value has one type but this type is incompatible with the
     /// kind of the `Statement`.
     KindAndValueDoNotAgree,
+
+    /// The transform of a `Statement` cannot be applied to values
+    /// of the type it reads.
+    InvalidTransform,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -100,6 +144,64 @@ pub enum Error {
     TypeError(TypeError),
 }
 
+/// A problem found while validating a script.
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    /// The JSON path of the faulty node, e.g. `.rules[0].execute[1]`.
+    pub at: String,
+
+    pub error: Error,
+}
+
+/// The channels to which a node of a script currently resolves.
+#[derive(Clone, Debug, Serialize)]
+pub struct Resolution {
+    /// The JSON path of the node, e.g. `.rules[0].execute[1]`.
+    pub at: String,
+
+    pub channels: Vec<Id<Channel>>,
+}
+
+/// The result of validating a script with `validate`.
+///
+/// JSON paths designate conditions in their canonical form, i.e. an array
+/// of conditions is reported as `{"All": [...]}`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Validation {
+    /// If the script could not be parsed, the reason. In this case,
+    /// `errors` and `channels` are empty.
+    pub parse_error: Option<ParseError>,
+
+    /// All the errors that prevent the script from compiling.
+    pub errors: Vec<Diagnostic>,
+
+    /// The channels to which each match, statement and fetched value
+    /// currently resolves. Devices that are added or removed later will
+    /// change this.
+    pub channels: Vec<Resolution>,
+}
+
+impl Validation {
+    /// `true` if the script can be compiled.
+    pub fn is_ok(&self) -> bool {
+        self.parse_error.is_none() && self.errors.is_empty()
+    }
+
+    fn add_error(&mut self, at: &Path, error: Error) {
+        self.errors.push(Diagnostic {
+            at: at.to_string(),
+            error: error
+        });
+    }
+
+    fn add_channels(&mut self, at: &Path, channels: Vec<Channel>) {
+        self.channels.push(Resolution {
+            at: at.to_string(),
+            channels: channels.into_iter().map(|channel| channel.id).collect()
+        });
+    }
+}
+
 pub struct Compiler<Env> where Env: ExecutableDevEnv {
     phantom: PhantomData<Env>,
 }
@@ -137,57 +239,267 @@ impl<Env> Compiler<Env> where Env: ExecutableDevEnv {
         if trigger.execute.len() == 0 {
             return Err(Error::SourceError(SourceError::NoStatement));
         }
-        if trigger.conditions.len() == 0 {
+        if trigger.conditions.matches().len() == 0 && trigger.conditions.schedules().len() == 0 {
             return Err(Error::SourceError(SourceError::NoMatch));
         }
-        let conditions = try!(map(trigger.conditions, |match_| {
-            self.compile_match(match_)
-        }));
+        try!(check_mode(&trigger.mode));
+        let match_types : Vec<_> = trigger.conditions.matches().iter()
+            .map(|match_| match_.kind.get_type())
+            .collect();
+        let conditions = try!(self.compile_condition(trigger.conditions));
         let execute = try!(map(trigger.execute, |statement| {
-            self.compile_statement(statement)
+            compile_statement(statement, &match_types)
+        }));
+        let otherwise = try!(map(trigger.otherwise, |statement| {
+            compile_statement(statement, &match_types)
         }));
         Ok(Rule {
             conditions: conditions,
             execute: execute,
+            otherwise: otherwise,
+            mode: trigger.mode,
+            cooldown: trigger.cooldown,
             phantom: PhantomData
         })
     }
 
-    fn compile_match(&self, match_: Match<UncheckedCtx>) -> Result<Match<CompiledCtx<Env>>, Error>
+    fn compile_condition(&self, condition: Condition<UncheckedCtx>) -> Result<Condition<CompiledCtx<Env>>, Error>
     {
-        if match_.source.len() == 0 {
-            return Err(Error::SourceError(SourceError::NoMatchSource));
-        }
-        let source = match_.source
-            .iter()
-            .map(|input| input.clone()
-                 .with_kind(match_.kind.clone())
-                 .with_supports_watch(Exactly::Exactly(true)))
-            .collect();
-        Ok(Match {
-            source: source,
-            kind: match_.kind,
-            range: match_.range,
-            duration: match_.duration,
-            phantom: PhantomData
-        })
+        match condition {
+            Condition::All(conditions) => {
+                if conditions.len() == 0 {
+                    return Err(Error::SourceError(SourceError::NoMatch));
+                }
+                Ok(Condition::All(try!(map(conditions, |condition| {
+                    self.compile_condition(condition)
+                }))))
+            }
+            Condition::Any(conditions) => {
+                if conditions.len() == 0 {
+                    return Err(Error::SourceError(SourceError::NoMatch));
+                }
+                Ok(Condition::Any(try!(map(conditions, |condition| {
+                    self.compile_condition(condition)
+                }))))
+            }
+            Condition::Not(condition) => Ok(Condition::Not(Box::new(try!(self.compile_condition(*condition))))),
+            Condition::Match(match_) => Ok(Condition::Match(try!(compile_match(match_)))),
+            Condition::Schedule(schedule) => {
+                try!(check_schedule(&schedule));
+                Ok(Condition::Schedule(schedule))
+            }
+        }
     }
+}
 
-    fn compile_statement(&self, statement: Statement<UncheckedCtx>) -> Result<Statement<CompiledCtx<Env>>, Error>
-    {
-        if statement.destination.len() == 0 {
-            return Err(Error::SourceError(SourceError::NoStatementDestination));
+/// Parse a script and check it against the current state of `api`, without executing it.
+///
+/// This is meant e.g. to let front-ends check a script before saving it.
+pub fn validate<A>(api: &A, source: &str) -> Validation where A: API {
+    let script = match Script::<UncheckedCtx>::from_str(source) {
+        Ok(script) => script,
+        Err(err) => return Validation {
+            parse_error: Some(err),
+            .. Validation::default()
         }
-        let destination = statement.destination
-            .iter()
-            .map(|output| output.clone()
-                 .with_kind(statement.kind.clone()))
-            .collect();
-        Ok(Statement {
-            destination: destination,
-            value: statement.value,
-            kind: statement.kind,
-            phantom: PhantomData
-        })
+    };
+    let mut validation = Validation::default();
+    Path::new().push("rules", |path| {
+        if script.rules.len() == 0 {
+            validation.add_error(&path, Error::SourceError(SourceError::NoRule));
+        }
+        for (rule, rule_index) in script.rules.into_iter().zip(0..) {
+            path.push_index(rule_index, |path| {
+                validate_rule(api, rule, &path, &mut validation)
+            });
+        }
+    });
+    validation
+}
+
+fn check_mode(mode: &Mode) -> Result<(), Error> {
+    if let Mode::WhileMet { ref repeat } = *mode {
+        let repeat : ChronoDuration = repeat.clone().into();
+        if repeat <= ChronoDuration::zero() {
+            return Err(Error::SourceError(SourceError::InvalidRepeat));
+        }
+    }
+    Ok(())
+}
+
+fn check_schedule(schedule: &Schedule) -> Result<(), Error> {
+    if schedule.days.len() == 0 {
+        return Err(Error::SourceError(SourceError::NoScheduleDay));
+    }
+    let times = Some(&schedule.start).into_iter().chain(schedule.end.iter());
+    for time in times {
+        if let TimeOfDay::At(ref duration) = *time {
+            let duration : ChronoDuration = duration.clone().into();
+            if duration < ChronoDuration::zero() || duration > ChronoDuration::days(1) {
+                return Err(Error::SourceError(SourceError::InvalidTimeOfDay));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile_match<Ctx>(match_: Match<UncheckedCtx>) -> Result<Match<Ctx>, Error> where Ctx: Context
+{
+    if match_.source.len() == 0 {
+        return Err(Error::SourceError(SourceError::NoMatchSource));
+    }
+    let source = match_.source
+        .iter()
+        .map(|input| input.clone()
+             .with_kind(match_.kind.clone())
+             .with_supports_watch(Exactly::Exactly(true)))
+        .collect();
+    Ok(Match {
+        source: source,
+        kind: match_.kind,
+        range: match_.range,
+        duration: match_.duration,
+        phantom: PhantomData
+    })
+}
+
+/// Compile a statement. `match_types` holds the type of each match of the
+/// rule, as in `Condition::matches()`.
+fn compile_statement<Ctx>(statement: Statement<UncheckedCtx>, match_types: &[Type]) -> Result<Statement<Ctx>, Error>
+    where Ctx: Context
+{
+    if statement.destination.len() == 0 {
+        return Err(Error::SourceError(SourceError::NoStatementDestination));
+    }
+    let destination = statement.destination
+        .iter()
+        .map(|output| output.clone()
+             .with_kind(statement.kind.clone()))
+        .collect();
+
+    // Determine the type of the values read at runtime.
+    let (value, input_type) = match statement.value {
+        StatementValue::Constant(payload) => {
+            if statement.transform.is_some() {
+                return Err(Error::SourceError(SourceError::TransformOfConstant));
+            }
+            (StatementValue::Constant(payload), None)
+        }
+        StatementValue::Trigger { match_index } => {
+            let typ = match match_types.get(match_index) {
+                None => return Err(Error::SourceError(SourceError::NoSuchMatch)),
+                Some(typ) => typ.clone()
+            };
+            (StatementValue::Trigger { match_index: match_index }, Some(typ))
+        }
+        StatementValue::Fetch { source, kind } => {
+            if source.len() == 0 {
+                return Err(Error::SourceError(SourceError::NoStatementSource));
+            }
+            let source = source
+                .iter()
+                .map(|input| input.clone()
+                     .with_kind(kind.clone())
+                     .with_supports_fetch(Exactly::Exactly(true)))
+                .collect();
+            let typ = kind.get_type();
+            (StatementValue::Fetch { source: source, kind: kind }, Some(typ))
+        }
+    };
+
+    // Make sure that the values we send have the type expected by `destination`.
+    if let Some(input_type) = input_type {
+        let output_type = match statement.transform {
+            None => input_type,
+            Some(ref transform) => match transform.output_type(&input_type) {
+                None => return Err(Error::TypeError(TypeError::InvalidTransform)),
+                Some(typ) => typ
+            }
+        };
+        if output_type != statement.kind.get_type() {
+            return Err(Error::TypeError(TypeError::KindAndValueDoNotAgree));
+        }
+    }
+
+    Ok(Statement {
+        destination: destination,
+        value: value,
+        transform: statement.transform,
+        kind: statement.kind,
+        phantom: PhantomData
+    })
+}
+
+fn validate_rule<A>(api: &A, rule: Rule<UncheckedCtx>, path: &Path, validation: &mut Validation) where A: API {
+    if rule.execute.len() == 0 {
+        path.push("execute", |path| validation.add_error(&path, Error::SourceError(SourceError::NoStatement)));
+    }
+    if let Err(err) = check_mode(&rule.mode) {
+        path.push("mode", |path| validation.add_error(&path, err));
+    }
+    let match_types : Vec<_> = rule.conditions.matches().iter()
+        .map(|match_| match_.kind.get_type())
+        .collect();
+    if match_types.len() == 0 && rule.conditions.schedules().len() == 0 {
+        path.push("conditions", |path| validation.add_error(&path, Error::SourceError(SourceError::NoMatch)));
+    } else {
+        path.push("conditions", |path| validate_condition(api, rule.conditions, &path, validation));
+    }
+    for (field, statements) in vec![("execute", rule.execute), ("otherwise", rule.otherwise)] {
+        path.push(field, |path| {
+            for (statement, statement_index) in statements.into_iter().zip(0..) {
+                path.push_index(statement_index, |path| {
+                    validate_statement(api, statement, &match_types, &path, validation)
+                });
+            }
+        });
+    }
+}
+
+fn validate_condition<A>(api: &A, condition: Condition<UncheckedCtx>, path: &Path, validation: &mut Validation) where A: API {
+    let (key, conditions) = match condition {
+        Condition::All(conditions) => ("All", conditions),
+        Condition::Any(conditions) => ("Any", conditions),
+        Condition::Not(condition) => {
+            path.push("Not", |path| validate_condition(api, *condition, &path, validation));
+            return;
+        }
+        Condition::Match(match_) => {
+            match compile_match::<UncheckedCtx>(match_) {
+                Ok(match_) => validation.add_channels(path, api.get_channels(match_.source)),
+                Err(err) => validation.add_error(path, err)
+            }
+            return;
+        }
+        Condition::Schedule(schedule) => {
+            if let Err(err) = check_schedule(&schedule) {
+                path.push("Schedule", |path| validation.add_error(&path, err));
+            }
+            return;
+        }
+    };
+    path.push(key, |path| {
+        if conditions.len() == 0 {
+            validation.add_error(&path, Error::SourceError(SourceError::NoMatch));
+        }
+        for (condition, condition_index) in conditions.into_iter().zip(0..) {
+            path.push_index(condition_index, |path| {
+                validate_condition(api, condition, &path, validation)
+            });
+        }
+    });
+}
+
+fn validate_statement<A>(api: &A, statement: Statement<UncheckedCtx>, match_types: &[Type],
+    path: &Path, validation: &mut Validation) where A: API
+{
+    match compile_statement::<UncheckedCtx>(statement, match_types) {
+        Ok(statement) => {
+            validation.add_channels(path, api.get_channels(statement.destination));
+            if let StatementValue::Fetch { source, .. } = statement.value {
+                path.push("value_from", |path| validation.add_channels(&path, api.get_channels(source)));
+            }
+        }
+        Err(err) => validation.add_error(path, err)
     }
 }
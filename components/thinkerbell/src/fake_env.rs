@@ -1,4 +1,5 @@
 use compile::ExecutableDevEnv;
+use schedule::Location;
 
 use foxbox_taxonomy::api::{ API, Error, User };
 use foxbox_taxonomy::manager::*;
@@ -36,13 +37,16 @@ struct TestSharedAdapterBackend {
     counter: usize,
 
     timers: BinaryHeap<Timer>,
-    trigger_timers_until: Option<DateTime<UTC>>,
+
+    /// The date until which timers have been fast-forwarded, if any. Shared with `FakeEnv`,
+    /// which uses it as the current date.
+    trigger_timers_until: Arc<Mutex<Option<DateTime<UTC>>>>,
 
     watchers: HashMap<usize, (Id<Channel>, Option<Box<Range>>, Box<ExtSender<WatchEvent<Value>>>)>,
 }
 
 impl TestSharedAdapterBackend {
-    fn new(on_event: Box<ExtSender<FakeEnvEvent>>) -> Self {
+    fn new(on_event: Box<ExtSender<FakeEnvEvent>>, trigger_timers_until: Arc<Mutex<Option<DateTime<UTC>>>>) -> Self {
         TestSharedAdapterBackend {
             getter_values: HashMap::new(),
             setter_errors: HashMap::new(),
@@ -50,7 +54,7 @@ impl TestSharedAdapterBackend {
             counter: 0,
             watchers: HashMap::new(),
             timers: BinaryHeap::new(),
-            trigger_timers_until: None,
+            trigger_timers_until: trigger_timers_until,
         }
     }
 
@@ -164,7 +168,7 @@ impl TestSharedAdapterBackend {
     }
 
     fn trigger_timers_until(&mut self, date: DateTime<UTC>) {
-        self.trigger_timers_until = Some(date);
+        *self.trigger_timers_until.lock().unwrap() = Some(date);
         loop {
             if let Some(ref timer) = self.timers.peek() {
                 if timer.date > date {
@@ -205,12 +209,21 @@ impl TestSharedAdapterBackend {
                 let _ = tx.send(FakeEnvEvent::Done);
             }
             ResetTimers(tx) => {
-                self.trigger_timers_until = None;
+                *self.trigger_timers_until.lock().unwrap() = None;
                 self.timers.clear();
                 let _ = tx.send(FakeEnvEvent::Done);
             }
-            AddTimer(timer) => {
-                match self.trigger_timers_until {
+            AddTimer(mut timer) => {
+                let trigger_timers_until = self.trigger_timers_until.lock().unwrap().clone();
+                // Once we have fast-forwarded, timers started afterwards are relative to the
+                // fast-forwarded date. Otherwise, a repeating timer would fire in a loop.
+                if let Some(ref date) = trigger_timers_until {
+                    if *date > timer.start {
+                        timer.date = date.clone() + (timer.date.clone() - timer.start.clone());
+                        timer.start = date.clone();
+                    }
+                }
+                match trigger_timers_until {
                     None => {
                         self.timers.push(timer);
                     }
@@ -362,6 +375,7 @@ impl Drop for TestAdapterWatchGuard {
 #[derive(Clone)]
 struct Timer {
     is_dropped: Arc<AtomicBool>,
+    start: DateTime<UTC>,
     date: DateTime<UTC>,
     on_triggered: Box<ExtSender<()>>,
 }
@@ -406,6 +420,11 @@ pub struct FakeEnv {
     ///
     on_event: Box<ExtSender<FakeEnvEvent>>,
     back_end: Box<ExtSender<AdapterOp>>,
+
+    /// The date until which timers have been fast-forwarded, if any.
+    trigger_timers_until: Arc<Mutex<Option<DateTime<UTC>>>>,
+
+    location: Arc<Mutex<Option<Location>>>,
 }
 impl fmt::Debug for FakeEnv {
     fn fmt(&self, _: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -424,21 +443,38 @@ impl ExecutableDevEnv for FakeEnv {
     type TimerGuard = TimerGuard;
     fn start_timer(&self, duration: Duration, timer: Box<ExtSender<()>>) -> Self::TimerGuard {
         let is_dropped = Arc::new(AtomicBool::new(false));
+        let now : DateTime<UTC> = self.now().into();
         let trigger = Timer {
-            date: UTC::now() + duration.into(),
+            start: now,
+            date: now + duration.into(),
             on_triggered: timer,
             is_dropped: is_dropped.clone()
         };
         let _ = self.back_end.send(AdapterOp::AddTimer(trigger));
         TimerGuard(is_dropped)
     }
+
+    /// The current date or, if timers have been fast-forwarded past it, the fast-forwarded date.
+    fn now(&self) -> TimeStamp {
+        let now = UTC::now();
+        match *self.trigger_timers_until.lock().unwrap() {
+            Some(ref date) if *date > now => TimeStamp::from_datetime(date.clone()),
+            _ => TimeStamp::from_datetime(now)
+        }
+    }
+
+    fn location(&self) -> Option<Location> {
+        self.location.lock().unwrap().clone()
+    }
 }
 impl FakeEnv {
     pub fn new(on_event: Box<ExtSender<FakeEnvEvent>>) -> Self {
         let (tx, rx) = channel();
         let on_event_clone = on_event.clone();
+        let trigger_timers_until = Arc::new(Mutex::new(None));
+        let trigger_timers_until_clone = trigger_timers_until.clone();
         thread::spawn(move || {
-            let mut back_end = TestSharedAdapterBackend::new(on_event_clone);
+            let mut back_end = TestSharedAdapterBackend::new(on_event_clone, trigger_timers_until_clone);
             for msg in rx {
                 back_end.execute(msg);
             }
@@ -448,6 +484,8 @@ impl FakeEnv {
             on_event: on_event,
             manager: Arc::new(AdapterManager::new(None)),
             back_end: Box::new(tx),
+            trigger_timers_until: trigger_timers_until,
+            location: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -506,6 +544,10 @@ impl FakeEnv {
             ResetTimers => {
                 self.back_end.send(AdapterOp::ResetTimers(self.on_event.clone())).unwrap();
             }
+            SetLocation(location) => {
+                *self.location.lock().unwrap() = location;
+                let _ = self.on_event.send(FakeEnvEvent::Done);
+            }
 //            _ => unimplemented!()
         }
     }
@@ -532,6 +574,9 @@ pub enum Instruction {
     InjectSetterErrors(Vec<(Id<Channel>, Option<Error>)>),
     TriggerTimersUntil(TimeStamp),
     ResetTimers,
+
+    /// Set the location used to compute sunrise and sunset.
+    SetLocation(Option<Location>),
 }
 
 /// Operations internal to a TestAdapter.
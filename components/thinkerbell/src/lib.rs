@@ -33,6 +33,9 @@ pub mod compile;
 /// Actually executing code.
 pub mod run;
 
+/// Determining when schedules are active, including sunrise and sunset.
+pub mod schedule;
+
 /// Miscellaneous internal utilities.
 pub mod util;
 
@@ -2,14 +2,17 @@ use ast::Script;
 use compile::ExecutableDevEnv;
 use run::{ Execution, ExecutionEvent, Error as RunError, StartStopError };
 
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
 use std::fmt::Debug;
 use std::path::{ Path as FilePath, PathBuf as FilePathBuf };
+use std::sync::{ Arc, Mutex };
 
 use foxbox_taxonomy::api::{ ResultMap, User };
 use foxbox_taxonomy::parse::*;
 use foxbox_taxonomy::util::{ Id };
+use foxbox_taxonomy::values::TimeStamp;
 
+use chrono::UTC;
 use rusqlite;
 use transformable_channels::mpsc::{ channel, ExtSender, TransformableSender };
 
@@ -33,6 +36,20 @@ pub enum Error {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
 pub struct ScriptId;
 
+/// The maximal number of events kept in the trace of each script.
+/// Older events are discarded first.
+pub const TRACE_CAPACITY: usize = 100;
+
+/// An event recorded in the execution trace of a script.
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceEntry {
+    /// The instant at which the event was received.
+    pub timestamp: TimeStamp,
+    pub event: ExecutionEvent,
+}
+
+type Traces = Arc<Mutex<HashMap<Id<ScriptId>, VecDeque<TraceEntry>>>>;
+
 /// ScriptManager stores a persistent database of scripts and executes them.
 /// Each script can be individually enabled or disabled.
 /// When a script is enabled, it is always running (unless an error occured during launch).
@@ -46,17 +63,21 @@ pub struct ScriptManager<Env, T> where Env: ExecutableDevEnv + Clone + Debug + '
     /// A map to track currently-executing scripts.
     runners: HashMap<Id<ScriptId>, Execution<Env>>,
 
-    /// The tx end of the channel passed to ScriptManager::new()
+    /// The tx end of the channel passed to ScriptManager::new(), receiving each new trace entry.
     tx: Box<T>,
+
+    /// The most recent execution events of each script, at most `TRACE_CAPACITY` per script.
+    traces: Traces,
 }
 
 impl<Env, T> ScriptManager<Env, T>
     where Env: ExecutableDevEnv + Clone + Debug + 'static,
-          T: ExtSender<(Id<ScriptId>, ExecutionEvent)> + TransformableSender<(Id<ScriptId>, ExecutionEvent)> {
+          T: ExtSender<(Id<ScriptId>, TraceEntry)> + TransformableSender<(Id<ScriptId>, TraceEntry)> {
 
     /// Create a ScriptManager using a SQLite database file with the given path, i.e. filename.
     /// If the database file does not exist, it will be created.
     ///
+    /// Each event recorded in the trace of a script is also sent to `tx`.
     /// NOTE: You MUST consume the contents of `tx` to prevent memory leaks.
     ///
     /// The database stores records with this schema:
@@ -83,7 +104,8 @@ impl<Env, T> ScriptManager<Env, T>
             path: path.to_owned(),
             env: env,
             runners: HashMap::new(),
-            tx: tx
+            tx: tx,
+            traces: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
@@ -175,6 +197,7 @@ impl<Env, T> ScriptManager<Env, T>
     /// If the script cannot be stopped (due to an error), it will not be removed.
     pub fn remove(&mut self, id: &Id<ScriptId>) -> Result<(), Error> {
         try!(self.set_enabled(id, false));
+        self.traces.lock().unwrap().remove(id);
         let connection = try!(rusqlite::Connection::open(&self.path));
         connection.execute("DELETE FROM scripts WHERE id = $1", &[&id.to_string()])
             .map(|_| ())
@@ -228,6 +251,17 @@ impl<Env, T> ScriptManager<Env, T>
         self.runners.contains_key(id)
     }
 
+    /// Get the most recent execution events of a script, oldest first.
+    ///
+    /// Events are recorded while the script is running and kept while it is disabled,
+    /// so that clients can find out why a script did or did not act.
+    pub fn get_trace(&self, id: &Id<ScriptId>) -> Vec<TraceEntry> {
+        match self.traces.lock().unwrap().get(id) {
+            Some(trace) => trace.iter().cloned().collect(),
+            None => vec![]
+        }
+    }
+
     /// Execute a script. If the script is already running, stop the existing script.
     fn start_script(&mut self, id: &Id<ScriptId>, source: &String, owner: &User) -> Result<(), Error> {
         // Stop the script is necessary.
@@ -242,8 +276,19 @@ impl<Env, T> ScriptManager<Env, T>
         // Now start it.
         let mut runner = Execution::<Env>::new();
         let tx_id = id.clone();
-        let tx = self.tx.map(move |event| {
-            (tx_id.clone(), event)
+        let traces = self.traces.clone();
+        let tx = self.tx.map(move |event: ExecutionEvent| {
+            let mut traces = traces.lock().unwrap();
+            let trace = traces.entry(tx_id.clone()).or_insert_with(VecDeque::new);
+            if trace.len() >= TRACE_CAPACITY {
+                trace.pop_front();
+            }
+            let entry = TraceEntry {
+                timestamp: TimeStamp::from_datetime(UTC::now()),
+                event: event
+            };
+            trace.push_back(entry.clone());
+            (tx_id.clone(), entry)
         });
         let parsed_source = try!(Path::new().push_str("recipe", |path| Script::from_str_at(path, source)));
         try!(runner.start(self.env.clone(), parsed_source, owner.clone(), tx));
@@ -1,6 +1,6 @@
 //! Launching and running the script
 
-use ast::{ Script, Statement, UncheckedCtx } ;
+use ast::{ Script, Mode, Statement, StatementValue, UncheckedCtx } ;
 use compile::{ Compiler, CompiledCtx, ExecutableDevEnv } ;
 pub use compile::{ Error as CompileError, SourceError, TypeError };
 use compile;
@@ -12,6 +12,8 @@ use foxbox_taxonomy::services::Channel;
 use foxbox_taxonomy::util::{ Exactly, Id };
 use foxbox_taxonomy::values::{ Duration, Type, Value };
 
+use chrono::{ DateTime, UTC };
+
 use transformable_channels::mpsc::*;
 
 use std::collections::HashSet;
@@ -131,7 +133,7 @@ pub struct ExecutionTask<Env> where Env: ExecutableDevEnv {
     rx: Receiver<ExecutionOp>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum ExecutionEvent {
     Starting {
         result: Result<(), Error>,
@@ -141,9 +143,30 @@ pub enum ExecutionEvent {
     },
     Sent {
         rule_index: usize,
+
+        /// The list of statements to which the statement belongs.
+        branch: Branch,
         statement_index: usize,
         result: Vec<(Id<Channel>, Result<(), Error>)>
     },
+    /// A getter has started or stopped meeting a condition of a rule.
+    GetterUpdate {
+        rule_index: usize,
+        condition_index: usize,
+        id: Id<Channel>,
+        is_met: bool,
+    },
+    /// A schedule of a rule has become active or inactive.
+    ScheduleUpdate {
+        rule_index: usize,
+        schedule_index: usize,
+        is_active: bool,
+    },
+    /// The conditions of a rule have started or stopped being met.
+    RuleUpdate {
+        rule_index: usize,
+        is_met: bool,
+    },
     TimerStart {
         rule_index: usize,
         condition_index: usize,
@@ -158,6 +181,16 @@ pub enum ExecutionEvent {
     }
 }
 
+/// A list of statements of a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Branch {
+    /// The statements of `Rule::execute`.
+    Execute,
+
+    /// The statements of `Rule::otherwise`.
+    Otherwise,
+}
+
 enum ExecutionOp {
     /// We have received an update from the AdapterManager.
     Update {
@@ -187,6 +220,30 @@ enum ExecutionOp {
         condition_index: usize,
     },
 
+    /// Time to check whether a schedule has become active or inactive.
+    ScheduleUpdate {
+        rule_index: usize,
+
+        /// The index of the schedule, as in `Condition::schedules()`.
+        schedule_index: usize,
+    },
+
+    /// Time to evaluate the conditions of a rule for the first time, once the initial
+    /// state of its matches and schedules is known.
+    Evaluate {
+        rule_index: usize,
+    },
+
+    /// Time to execute once again the statements of a rule in mode `WhileMet`.
+    Repeat {
+        rule_index: usize,
+    },
+
+    /// The cooldown of a rule has elapsed, its statements may be executed again.
+    CooldownComplete {
+        rule_index: usize,
+    },
+
     /// Time to stop executing the script.
     Stop(Mutex<Box<Fn(Result<(), Error>) + Send>>)
 }
@@ -197,6 +254,10 @@ impl Debug for ExecutionOp {
         match *self {
             Update {..} => formatter.write_str("Update"),
             UpdateCondition { .. } => formatter.write_str("UpdateCondition"),
+            ScheduleUpdate { .. } => formatter.write_str("ScheduleUpdate"),
+            Evaluate { .. } => formatter.write_str("Evaluate"),
+            Repeat { .. } => formatter.write_str("Repeat"),
+            CooldownComplete { .. } => formatter.write_str("CooldownComplete"),
             Stop (_) => formatter.write_str("Stop")
         }
     }
@@ -211,12 +272,30 @@ struct ConditionState {
     /// If `None`, a duration is attached to this condition and we need to make sure that the
     /// condition remains true for at least `duration` before we decide whether to proceed with
     /// statements.
-    duration: Option<Duration>
+    duration: Option<Duration>,
+
+    /// The latest value with which a getter entered the range, if any.
+    last_value: Option<(Payload, Type)>,
+}
+struct ScheduleRunState<Env> where Env: ExecutableDevEnv {
+    is_active: bool,
+
+    /// The timer until the next time we need to check the schedule.
+    timer: Option<Env::TimerGuard>,
 }
 struct RuleState<Env> where Env: ExecutableDevEnv {
     rule_is_met: bool,
     per_condition: Vec<ConditionState>,
+    per_schedule: Vec<ScheduleRunState<Env>>,
     ongoing_timer: Option<Env::TimerGuard>, // FIXME: It's actually a guard.
+
+    /// If the rule is in mode `WhileMet` and its conditions are met, the timer
+    /// until the next execution of its statements.
+    repeat_timer: Option<Env::TimerGuard>,
+
+    /// If the rule has a cooldown that hasn't elapsed yet, the timer until it
+    /// elapses. Statements are not executed while this is `Some`.
+    cooldown_timer: Option<Env::TimerGuard>,
 }
 
 impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
@@ -251,7 +330,7 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
 
         // FIXME: We could optimize requests by detecting if several share a `TargetMap<ChannelSelector, Exactly<Range>>`
         let mut per_rule : Vec<_> = self.script.rules.iter().zip(0 as usize..).map(|(rule, rule_index)| {
-            let per_condition = rule.conditions.iter().zip(0 as usize..).map(|(condition, condition_index)| {
+            let per_condition = rule.conditions.matches().iter().zip(0 as usize..).map(|(condition, condition_index)| {
                 // We will often end up watching several times the
                 // same channel. For the moment, we do not attempt to
                 // optimize either I/O (which we expect will be
@@ -278,21 +357,47 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
                                 rule_index: rule_index,
                                 condition_index: condition_index
                             }
-                        }))));
+                        })),
+                        self.owner.clone()));
                 ConditionState {
                     match_is_met: false,
                     per_getter: HashSet::new(),
                     duration: condition.duration.clone(),
+                    last_value: None,
+                }
+            }).collect();
+
+            // Schedules are checked once we start processing messages.
+            let per_schedule = rule.conditions.schedules().iter().zip(0 as usize..).map(|(_, schedule_index)| {
+                let _ = self.tx.send(ExecutionOp::ScheduleUpdate {
+                    rule_index: rule_index,
+                    schedule_index: schedule_index,
+                });
+                ScheduleRunState {
+                    is_active: false,
+                    timer: None,
                 }
             }).collect();
 
             RuleState {
                 rule_is_met: false,
                 per_condition: per_condition,
+                per_schedule: per_schedule,
                 ongoing_timer: None,
+                repeat_timer: None,
+                cooldown_timer: None,
             }
         }).collect();
 
+        // Conditions such as `Not` may be met before any getter enters or leaves its range
+        // and before any schedule changes, so evaluate each rule once everything above has
+        // been processed.
+        for rule_index in 0..per_rule.len() {
+            let _ = self.tx.send(ExecutionOp::Evaluate {
+                rule_index: rule_index,
+            });
+        }
+
         for msg in self.rx.iter() {
             match msg {
                 ExecutionOp::Stop(cb) => {
@@ -306,7 +411,50 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
                 ExecutionOp::UpdateCondition { id, is_met, rule_index, condition_index } => {
                     debug!("[Recipe '{}'] Updating the state of rule {}, condition {} => {}", self.script.name, rule_index, condition_index, is_met);
                     self.update_conditions(&self.script.name, id, is_met, &mut per_rule,
-                        rule_index, condition_index, &api, &on_event);
+                        rule_index, condition_index, &env, &on_event);
+                }
+                ExecutionOp::ScheduleUpdate { rule_index, schedule_index } => {
+                    let now : DateTime<UTC> = env.now().into();
+                    let state = self.script.rules[rule_index].conditions.schedules()[schedule_index]
+                        .state_at(&now, env.location().as_ref());
+                    debug!("[Recipe '{}'] Schedule {} of rule {} is active: {}, next check at {}", self.script.name, schedule_index, rule_index, state.is_active, state.next_check);
+
+                    // Check again whenever the schedule may change.
+                    let tx = self.tx.map(move |()| {
+                        ExecutionOp::ScheduleUpdate {
+                            rule_index: rule_index,
+                            schedule_index: schedule_index,
+                        }
+                    });
+                    per_rule[rule_index].per_schedule[schedule_index].timer =
+                        Some(env.start_timer(Duration::from(state.next_check - now), Box::new(tx)));
+
+                    if per_rule[rule_index].per_schedule[schedule_index].is_active != state.is_active {
+                        per_rule[rule_index].per_schedule[schedule_index].is_active = state.is_active;
+                        let _ = on_event.send(ExecutionEvent::ScheduleUpdate {
+                            rule_index: rule_index,
+                            schedule_index: schedule_index,
+                            is_active: state.is_active,
+                        });
+                        self.update_rule(&self.script.name, &mut per_rule, rule_index, &env, &on_event);
+                    }
+                }
+                ExecutionOp::Evaluate { rule_index } => {
+                    debug!("[Recipe '{}'] Evaluating rule {} for the first time", self.script.name, rule_index);
+                    self.update_rule(&self.script.name, &mut per_rule, rule_index, &env, &on_event);
+                }
+                ExecutionOp::Repeat { rule_index } => {
+                    if !per_rule[rule_index].rule_is_met || per_rule[rule_index].repeat_timer.is_none() {
+                        // The conditions have stopped being met since the timer fired.
+                        continue;
+                    }
+                    debug!("[Recipe '{}'] Repeating the statements of rule {}", self.script.name, rule_index);
+                    self.start_repeat_timer(&mut per_rule, rule_index, &env);
+                    self.execute_rule(&self.script.name, &mut per_rule, rule_index, &env, &on_event);
+                }
+                ExecutionOp::CooldownComplete { rule_index } => {
+                    debug!("[Recipe '{}'] Cooldown of rule {} has elapsed", self.script.name, rule_index);
+                    per_rule[rule_index].cooldown_timer = None;
                 }
                 ExecutionOp::Update { event, rule_index, condition_index } => {
                     match event {
@@ -336,32 +484,38 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
                             debug!("[Recipe '{}'] Added getter {}.", self.script.name, id);
                             // An getter was added. Nothing to do.
                         }
-                        WatchEvent::EnterRange { channel: id, value, .. } => {
+                        WatchEvent::EnterRange { channel: id, value, type_ } => {
                             debug!("[Recipe '{}'] Getter {} has entered the range for rule {}, condition {}: {:?}", self.script.name, id, rule_index, condition_index, value);
+                            // Remember the value, in case a statement needs it.
+                            per_rule[rule_index].per_condition[condition_index].last_value =
+                                Some((value, type_));
                             // We have entered a range. If there is a
                             // timer, start it, otherwise update conditions.
-                            let msg = move || {
-                                ExecutionOp::UpdateCondition {
-                                    id: id.clone(),
-                                    is_met: true,
-                                    rule_index: rule_index,
-                                    condition_index: condition_index
-                                }
-                            };
-                            let duration = match per_rule[rule_index].
-                                per_condition[condition_index].
-                                duration {
+                            // Conditions are updated immediately rather than through
+                            // `self.tx`, so that initial values are taken into account
+                            // before the rule is first evaluated.
+                            let duration = per_rule[rule_index].per_condition[condition_index].duration.clone();
+                            let duration = match duration {
                                 None => {
                                     debug!("[Recipe '{}'] No timer for rule {}, condition {}, we should trigger the execution immediately.", self.script.name, rule_index, condition_index);
-                                    let _ = self.tx.send(msg());
+                                    self.update_conditions(&self.script.name, id, true, &mut per_rule,
+                                        rule_index, condition_index, &env, &on_event);
                                     continue
                                 }
-                                Some(ref duration) => {
+                                Some(duration) => {
                                     debug!("[Recipe '{}'] There is a timer for rule {}, condition {}, we should trigger the execution in {:?}s.", self.script.name, rule_index, condition_index, duration);
-                                    duration.clone()
+                                    duration
                                 }
                             };
 
+                            let msg = move || {
+                                ExecutionOp::UpdateCondition {
+                                    id: id.clone(),
+                                    is_met: true,
+                                    rule_index: rule_index,
+                                    condition_index: condition_index
+                                }
+                            };
                             let tx = self.tx.map(move |()| {
                                 msg()
                             });
@@ -384,13 +538,8 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
                                 });
                             }
                             // Regardless, update the condition.
-                            let msg = ExecutionOp::UpdateCondition {
-                                id: id,
-                                is_met: false,
-                                rule_index: rule_index,
-                                condition_index: condition_index
-                            };
-                            let _ = self.tx.send(msg);
+                            self.update_conditions(&self.script.name, id, false, &mut per_rule,
+                                rule_index, condition_index, &env, &on_event);
                         }
                     }
                 }
@@ -402,16 +551,14 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
     /// we now need to fire the statements.
     fn update_conditions<S>(&self, name: &str, id: Id<Channel>, getter_is_met: bool,
             per_rule: &mut Vec<RuleState<Env>>, rule_index: usize, condition_index: usize,
-            api: &Env::API, on_event: &S)
+            env: &Env, on_event: &S)
             where S: ExtSender<ExecutionEvent> + Clone
     {
-        use std::mem::replace;
-
         let was_met = if getter_is_met {
             !per_rule[rule_index]
                 .per_condition[condition_index]
                 .per_getter
-                .insert(id)
+                .insert(id.clone())
         } else {
              per_rule[rule_index]
                 .per_condition[condition_index]
@@ -425,6 +572,12 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
             // Nothing has changed, no need to update any further.
             return;
         }
+        let _ = on_event.send(ExecutionEvent::GetterUpdate {
+            rule_index: rule_index,
+            condition_index: condition_index,
+            id: id,
+            is_met: getter_is_met,
+        });
 
         // 1. Is the match met?
         //
@@ -440,61 +593,193 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv + Debug {
             .per_condition[condition_index]
             .match_is_met = some_getter_is_met;
 
+        self.update_rule(name, per_rule, rule_index, env, on_event);
+        debug!("[Thinkerbell update_condition {}] done.", name);
+    }
+
+    /// A match or a schedule of a rule has changed. Determine whether the rule is now met
+    /// and act according to its mode.
+    fn update_rule<S>(&self, name: &str, per_rule: &mut Vec<RuleState<Env>>, rule_index: usize,
+            env: &Env, on_event: &S)
+            where S: ExtSender<ExecutionEvent> + Clone
+    {
+        use std::mem::replace;
+
         // 2. Is the condition met?
         //
-        // The condition is met iff all of the
-        // matches are met.
-        let condition_is_met =
-            per_rule[rule_index]
-            .per_condition
-            .iter()
-            .find(|condition_state| condition_state.match_is_met)
-            .is_some();
+        // Evaluate the condition tree of the rule,
+        // given the state of each of its matches and schedules.
+        let condition_is_met = {
+            let per_condition = &per_rule[rule_index].per_condition;
+            let per_schedule = &per_rule[rule_index].per_schedule;
+            self.script.rules[rule_index].conditions.is_met_with_schedules(&|match_index| {
+                per_condition[match_index].match_is_met
+            }, &|schedule_index| {
+                per_schedule[schedule_index].is_active
+            })
+        };
 
-        // 3. Are we in a case in which the
-        // condition was not met and is now met?
+        // 3. Has the rule just become met or stopped being met?
+        // If so, act according to its mode.
         let condition_was_met =
             replace(&mut per_rule[rule_index].rule_is_met, condition_is_met);
 
         debug!("[Thinkerbell update_condition {}] Updating condition for rule: {} => {}", name, condition_was_met, condition_is_met);
 
-        if !condition_was_met && condition_is_met {
-            // Ahah, we have just triggered the statements!
-            debug!("[Thinkerbell update_condition {}] Triggering {} statements.", name, self.script.rules[rule_index].execute.len());
-            for (statement, statement_index) in self.script.rules[rule_index].execute.iter().zip(0..) {
-                debug!("[Thinkerbell update_condition {}] Triggering statement {}/{}.", name, statement_index, self.script.rules[rule_index].execute.len());
-                let result = statement.eval(&api, &self.owner);
-                debug!("[Thinkerbell update_condition {}] Statement result {}/{}: {:?}.", name, statement_index, self.script.rules[rule_index].execute.len(), result);
-                if result.is_empty() {
-                    warn!("[Recipe '{}'] In rule {}, attempting to trigger statement {}, couldn't find any receiver channel.", name,
-                            rule_index, condition_index);
+        if condition_was_met != condition_is_met {
+            let _ = on_event.send(ExecutionEvent::RuleUpdate {
+                rule_index: rule_index,
+                is_met: condition_is_met,
+            });
+        }
+
+        let mode = &self.script.rules[rule_index].mode;
+        match (condition_was_met, condition_is_met) {
+            (false, true) => {
+                self.start_repeat_timer(per_rule, rule_index, env);
+                if *mode != Mode::OnExit {
+                    // Ahah, we have just triggered the statements!
+                    self.execute_rule(name, per_rule, rule_index, env, on_event);
+                }
+            }
+            (true, false) => {
+                // Stop repeating, if we were.
+                per_rule[rule_index].repeat_timer = None;
+                if *mode == Mode::OnExit {
+                    self.execute_rule(name, per_rule, rule_index, env, on_event);
                 }
+                self.eval_statements(name, per_rule, rule_index, Branch::Otherwise, env, on_event);
+            }
+            _ => {}
+        }
+    }
 
-                let _ = on_event.send(ExecutionEvent::Sent {
-                    rule_index: rule_index,
-                    statement_index: statement_index,
-                    result: result,
-                });
+    /// (Re)start the timer that repeats the statements of a rule, if it is in mode `WhileMet`.
+    fn start_repeat_timer(&self, per_rule: &mut Vec<RuleState<Env>>, rule_index: usize, env: &Env) {
+        if let Mode::WhileMet { ref repeat } = self.script.rules[rule_index].mode {
+            let tx = self.tx.map(move |()| {
+                ExecutionOp::Repeat {
+                    rule_index: rule_index
+                }
+            });
+            per_rule[rule_index].repeat_timer = Some(env.start_timer(repeat.clone(), Box::new(tx)));
+        }
+    }
+
+    /// Execute the statements of a rule, unless it is cooling down.
+    fn execute_rule<S>(&self, name: &str, per_rule: &mut Vec<RuleState<Env>>, rule_index: usize,
+            env: &Env, on_event: &S)
+            where S: ExtSender<ExecutionEvent> + Clone
+    {
+        if per_rule[rule_index].cooldown_timer.is_some() {
+            debug!("[Thinkerbell execute_rule {}] Rule {} is cooling down, not triggering its statements.", name, rule_index);
+            return;
+        }
+        self.eval_statements(name, per_rule, rule_index, Branch::Execute, env, on_event);
+
+        if let Some(ref cooldown) = self.script.rules[rule_index].cooldown {
+            let tx = self.tx.map(move |()| {
+                ExecutionOp::CooldownComplete {
+                    rule_index: rule_index
+                }
+            });
+            per_rule[rule_index].cooldown_timer = Some(env.start_timer(cooldown.clone(), Box::new(tx)));
+        }
+    }
+
+    /// Evaluate each statement of a branch of a rule.
+    fn eval_statements<S>(&self, name: &str, per_rule: &[RuleState<Env>], rule_index: usize,
+            branch: Branch, env: &Env, on_event: &S)
+            where S: ExtSender<ExecutionEvent> + Clone
+    {
+        let api = env.api();
+        let statements = match branch {
+            Branch::Execute => &self.script.rules[rule_index].execute,
+            Branch::Otherwise => &self.script.rules[rule_index].otherwise,
+        };
+        debug!("[Thinkerbell eval_statements {}] Triggering {} statements ({:?}).", name, statements.len(), branch);
+        if !statements.is_empty() {
+            api.audit_script_execution(name, rule_index, &self.owner);
+        }
+        for (statement, statement_index) in statements.iter().zip(0..) {
+            debug!("[Thinkerbell eval_statements {}] Triggering statement {}/{}.", name, statement_index, statements.len());
+            let result = statement.eval(api, &self.owner, &per_rule[rule_index].per_condition);
+            debug!("[Thinkerbell eval_statements {}] Statement result {}/{}: {:?}.", name, statement_index, statements.len(), result);
+            if result.is_empty() {
+                warn!("[Recipe '{}'] In rule {}, attempting to trigger statement {} ({:?}), couldn't find any receiver channel.", name,
+                        rule_index, statement_index, branch);
             }
+
+            let _ = on_event.send(ExecutionEvent::Sent {
+                rule_index: rule_index,
+                branch: branch,
+                statement_index: statement_index,
+                result: result,
+            });
         }
-        debug!("[Thinkerbell update_condition {}] done.", name);
     }
 }
 
 
 impl<Env> Statement<CompiledCtx<Env>> where Env: ExecutableDevEnv {
-    fn eval(&self, api: &Env::API, owner: &User) ->  Vec<(Id<Channel>, Result<(), Error>)> {
+    fn eval(&self, api: &Env::API, owner: &User, per_condition: &[ConditionState]) ->  Vec<(Id<Channel>, Result<(), Error>)> {
+        let payload = match self.get_value(api, owner, per_condition) {
+            Ok(payload) => payload,
+            Err(err) => {
+                // We don't know what to send, so report the error for each destination.
+                return api.get_channels(self.destination.clone())
+                    .into_iter()
+                    .map(|channel| (channel.id, Err(err.clone())))
+                    .collect();
+            }
+        };
         api.send_values(vec![Targetted {
             select: self.destination.clone(),
-            payload: self.value.clone()
+            payload: payload
         }], owner.clone())
             .into_iter()
             .map(|(id, result)|
                  (id, result.map_err(|err| Error::APIError(err))))
             .collect()
     }
-}
 
+    /// Determine the value to send, reading it from the getters if necessary.
+    fn get_value(&self, api: &Env::API, owner: &User, per_condition: &[ConditionState]) -> Result<Payload, Error> {
+        let (payload, type_) = match self.value {
+            StatementValue::Constant(ref payload) => return Ok(payload.clone()),
+            StatementValue::Trigger { match_index } => {
+                match per_condition[match_index].last_value {
+                    Some(ref value) => value.clone(),
+                    None => return Err(Error::ValueError(ValueError::NoValue))
+                }
+            }
+            StatementValue::Fetch { ref source, .. } => {
+                // Any of the sources will do.
+                let fetched = api.fetch_values(source.clone(), None, owner.clone())
+                    .into_iter()
+                    .filter_map(|(_, result)| match result {
+                        Ok(Some(fetched)) => Some((fetched.value, fetched.type_)),
+                        _ => None
+                    })
+                    .next();
+                match fetched {
+                    Some(value) => value,
+                    None => return Err(Error::ValueError(ValueError::NoValue))
+                }
+            }
+        };
+        match self.transform {
+            None => Ok(payload),
+            Some(ref transform) => {
+                let value = try!(payload.to_value(&type_).map_err(Error::APIError));
+                match transform.apply(value) {
+                    Some(value) => Payload::from_value(&value, &self.kind.get_type()).map_err(Error::APIError),
+                    None => Err(Error::ValueError(ValueError::InvalidTransform))
+                }
+            }
+        }
+    }
+}
 
 
 #[derive(Clone, Debug, Serialize)]
@@ -504,9 +789,20 @@ pub enum StartStopError {
     ThreadError,
 }
 
+/// An error while determining the value sent by a statement.
+#[derive(Clone, Debug, Serialize)]
+pub enum ValueError {
+    /// The getters haven't provided any value yet.
+    NoValue,
+
+    /// The value could not be transformed.
+    InvalidTransform,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub enum Error {
     CompileError(compile::Error),
     StartStopError(StartStopError),
     APIError(api::Error),
+    ValueError(ValueError),
 }
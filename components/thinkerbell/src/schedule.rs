@@ -0,0 +1,154 @@
+//! Determining when schedules are active.
+//!
+//! Schedules are expressed in the local time of the box, while the
+//! execution environment measures time in UTC. Sunrise and sunset are
+//! computed with the sunrise equation, which is precise to within a
+//! few minutes.
+
+use ast::{ Schedule, TimeOfDay };
+
+use chrono::{ Date, DateTime, Datelike, Local, NaiveDate, TimeZone, UTC };
+use chrono::Duration as ChronoDuration;
+
+use std::f64::consts::PI;
+
+/// The location of the box, used to determine the time of sunrise and sunset.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// In degrees, positive in the northern hemisphere.
+    pub latitude: f64,
+
+    /// In degrees, positive east of Greenwich.
+    pub longitude: f64,
+}
+
+/// The current state of a schedule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleState {
+    /// `true` if the schedule is currently active.
+    pub is_active: bool,
+
+    /// The date at which the state of the schedule should be checked again.
+    pub next_check: DateTime<UTC>,
+}
+
+impl Schedule {
+    /// Determine whether the schedule is active at `now` and when it may next change.
+    pub fn state_at(&self, now: &DateTime<UTC>, location: Option<&Location>) -> ScheduleState {
+        // If nothing happens within a week (e.g. during the polar night),
+        // check again the next day.
+        let mut next_check = now.clone() + ChronoDuration::days(1);
+        let mut is_active = false;
+
+        // A period may have started on the previous day and end today.
+        let today = now.with_timezone(&Local).date();
+        let mut day = today.pred();
+        for _ in 0..9 {
+            if let Some((start, end)) = self.period_on(&day, location) {
+                if start <= *now && *now < end {
+                    is_active = true;
+                }
+                for date in &[start, end] {
+                    if date > now && *date < next_check {
+                        next_check = date.clone();
+                    }
+                }
+            }
+            day = day.succ();
+        }
+        ScheduleState {
+            is_active: is_active,
+            next_check: next_check,
+        }
+    }
+
+    /// The period during which the schedule is active if it starts on `day`, if any.
+    fn period_on(&self, day: &Date<Local>, location: Option<&Location>) -> Option<(DateTime<UTC>, DateTime<UTC>)> {
+        if self.days.iter().find(|weekday| **weekday == day.weekday()).is_none() {
+            return None;
+        }
+        let start = match self.start.on(day, location) {
+            None => return None,
+            Some(start) => start
+        };
+        let end = match self.end {
+            None => start + ChronoDuration::minutes(1),
+            Some(ref end) => match end.on(day, location) {
+                None => return None,
+                Some(end) if end <= start => end + ChronoDuration::days(1),
+                Some(end) => end,
+            }
+        };
+        Some((start, end))
+    }
+}
+
+impl TimeOfDay {
+    /// The date corresponding to this time of day on `day`, if any.
+    fn on(&self, day: &Date<Local>, location: Option<&Location>) -> Option<DateTime<UTC>> {
+        match *self {
+            TimeOfDay::At(ref duration) => {
+                let midnight = match day.and_hms_opt(0, 0, 0) {
+                    None => return None,
+                    Some(midnight) => midnight
+                };
+                let duration : ChronoDuration = duration.clone().into();
+                Some((midnight + duration).with_timezone(&UTC))
+            }
+            TimeOfDay::Sunrise(ref offset) => {
+                let offset : ChronoDuration = offset.clone().into();
+                location.and_then(|location| sun_times(&day.naive_local(), location))
+                    .map(|(sunrise, _)| sunrise + offset)
+            }
+            TimeOfDay::Sunset(ref offset) => {
+                let offset : ChronoDuration = offset.clone().into();
+                location.and_then(|location| sun_times(&day.naive_local(), location))
+                    .map(|(_, sunset)| sunset + offset)
+            }
+        }
+    }
+}
+
+/// Compute the times of sunrise and sunset on a given day, using the sunrise equation.
+///
+/// Returns `None` if the sun doesn't rise or doesn't set on that day.
+pub fn sun_times(day: &NaiveDate, location: &Location) -> Option<(DateTime<UTC>, DateTime<UTC>)> {
+    fn sin(degrees: f64) -> f64 {
+        (degrees * PI / 180.).sin()
+    }
+    fn cos(degrees: f64) -> f64 {
+        (degrees * PI / 180.).cos()
+    }
+
+    // Days since 2000-01-01 12:00 UTC, a.k.a. J2000.
+    let n = (*day - NaiveDate::from_ymd(2000, 1, 1)).num_days() as f64 + 0.0008;
+
+    // Mean solar noon.
+    let mean_noon = n - location.longitude / 360.;
+
+    // Solar mean anomaly, equation of the center and ecliptic longitude.
+    let anomaly = (357.5291 + 0.98560028 * mean_noon) % 360.;
+    let center = 1.9148 * sin(anomaly) + 0.0200 * sin(2. * anomaly) + 0.0003 * sin(3. * anomaly);
+    let ecliptic = (anomaly + center + 180. + 102.9372) % 360.;
+
+    // Solar transit, in days since J2000.
+    let transit = mean_noon + 0.0053 * sin(anomaly) - 0.0069 * sin(2. * ecliptic);
+
+    // Declination of the sun and hour angle.
+    let sin_declination = sin(ecliptic) * sin(23.44);
+    let cos_declination = (1. - sin_declination * sin_declination).sqrt();
+    let cos_hour_angle = (sin(-0.83) - sin(location.latitude) * sin_declination) /
+        (cos(location.latitude) * cos_declination);
+    if cos_hour_angle < -1. || cos_hour_angle > 1. {
+        // Midnight sun or polar night.
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos() * 180. / PI;
+
+    let to_date = |days: f64| {
+        // J2000 is 946728000 seconds after the Unix epoch.
+        let seconds = 946728000. + days * 86400.;
+        UTC.timestamp(seconds.floor() as i64, 0)
+    };
+    Some((to_date(transit - hour_angle / 360.), to_date(transit + hour_angle / 360.)))
+}
@@ -72,3 +72,43 @@ fn test_database_add_remove_script() {
     db.put(&name, &load_json("./examples/ruleset.json"), &User::Id(1)).unwrap();
     assert_eq!(db.get_running_count(), 1);
 }
+
+#[test]
+fn test_trace() {
+    use foxbox_thinkerbell::run::ExecutionEvent;
+
+    let (tx_env, _) = channel();
+    let env = FakeEnv::new(Box::new(tx_env));
+
+    let (tx, rx) = channel();
+    let mut db = ScriptManager::new(env, Path::new("./test_trace_database.sqlite"), Box::new(tx)).unwrap();
+    db.remove_all().unwrap();
+
+    let name = Id::<ScriptId>::new("Sample Ruleset");
+    println!("* Unknown recipes have an empty trace.");
+    assert_eq!(db.get_trace(&name).len(), 0);
+
+    println!("* Starting a recipe is recorded in its trace.");
+    db.put(&name, &load_json("./examples/ruleset.json"), &User::Id(1)).unwrap();
+    let trace = db.get_trace(&name);
+    match trace[0].event {
+        ExecutionEvent::Starting { result: Ok(()) } => {},
+        ref other => panic!("Unexpected event {:?}", other)
+    }
+
+    println!("* New trace entries are also sent to the manager's channel.");
+    let (id, entry) = rx.recv().unwrap();
+    assert_eq!(id, name);
+    match entry.event {
+        ExecutionEvent::Starting { result: Ok(()) } => {},
+        ref other => panic!("Unexpected event {:?}", other)
+    }
+
+    println!("* The trace is kept while the recipe is disabled.");
+    db.set_enabled(&name, false).unwrap();
+    assert!(db.get_trace(&name).len() >= 1);
+
+    println!("* The trace is dropped once the recipe is removed.");
+    db.remove(&name).unwrap();
+    assert_eq!(db.get_trace(&name).len(), 0);
+}
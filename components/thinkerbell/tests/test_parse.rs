@@ -1,10 +1,15 @@
 extern crate foxbox_thinkerbell;
 extern crate foxbox_taxonomy;
 extern crate serde_json;
+extern crate chrono;
 
 use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::*;
 use foxbox_thinkerbell::ast::*;
 
+use std::sync::Arc;
+
 #[test]
 fn test_parse_bad_field() {
     let src = "{
@@ -40,3 +45,159 @@ fn test_parse_simple_rule() {
     Script::from_str(src).unwrap();
 }
 
+
+#[test]
+fn test_parse_condition_tree() {
+    let src = r#"{
+  "All": [
+    {
+      "source": [{"id": "door"}],
+      "kind": "OpenClosed",
+      "range": {"Eq": {"OpenClosed": "Open"}}
+    },
+    {
+      "Any": [
+        {
+          "Match": {
+            "source": [{"id": "clock"}],
+            "kind": "CurrentTimeOfDay",
+            "range": {"Geq": {"Duration": 79200}}
+          }
+        },
+        {
+          "Not": {
+            "source": [{"id": "porch light"}],
+            "kind": "LightOn",
+            "range": {"Eq": {"OnOff": "On"}}
+          }
+        }
+      ]
+    }
+  ]
+}"#;
+    let condition = Condition::<UncheckedCtx>::from_str(src).unwrap();
+    assert_eq!(condition.matches().len(), 3);
+
+    // Matches are numbered depth-first: door, clock, porch light.
+    let is_met = |door: bool, clock: bool, light: bool| {
+        condition.is_met(&|index| [door, clock, light][index])
+    };
+    assert!(is_met(true, true, true));
+    assert!(is_met(true, false, false));
+    assert!(is_met(true, true, false));
+    assert!(!is_met(true, false, true));
+    assert!(!is_met(false, true, false));
+    assert!(!is_met(false, false, false));
+}
+
+#[test]
+fn test_parse_conditions_array() {
+    // An array of conditions is a shorthand for `All`.
+    let src = r#"[{
+      "source": [{"id": "door"}],
+      "kind": "OpenClosed",
+      "range": {"Eq": {"OpenClosed": "Open"}}
+    }, {
+      "source": [{"id": "window"}],
+      "kind": "OpenClosed",
+      "range": {"Eq": {"OpenClosed": "Open"}}
+    }]"#;
+    let condition = Condition::<UncheckedCtx>::from_str(src).unwrap();
+    assert_eq!(condition.matches().len(), 2);
+    assert!(condition.is_met(&|_| true));
+    assert!(!condition.is_met(&|index| index == 0));
+}
+
+#[test]
+fn test_parse_statement_value_from() {
+    let src = r#"{
+      "destination": [{"id": "light B"}],
+      "value_from": {"Fetch": {"source": [{"id": "light A"}], "kind": "LightOn"}},
+      "kind": "LightOn"
+    }"#;
+    let statement = Statement::<UncheckedCtx>::from_str(src).unwrap();
+    match statement.value {
+        StatementValue::Fetch { ref source, ref kind } => {
+            assert_eq!(source.len(), 1);
+            assert_eq!(*kind, ChannelKind::LightOn);
+        }
+        ref other => panic!("Unexpected value {:?}", other)
+    }
+    assert!(statement.transform.is_none());
+
+    // The index of the match defaults to 0.
+    let src = r#"{
+      "destination": [{"id": "oven"}],
+      "value_from": {"Trigger": {}},
+      "transform": {"Scale": {"factor": 2, "offset": -10}},
+      "kind": "OvenTemperature"
+    }"#;
+    let statement = Statement::<UncheckedCtx>::from_str(src).unwrap();
+    match statement.value {
+        StatementValue::Trigger { match_index: 0 } => {},
+        ref other => panic!("Unexpected value {:?}", other)
+    }
+    assert_eq!(statement.transform, Some(Transform::Scale { factor: 2., offset: -10. }));
+
+    // A statement needs a value.
+    let src = r#"{
+      "destination": [{"id": "oven"}],
+      "kind": "OvenTemperature"
+    }"#;
+    assert!(Statement::<UncheckedCtx>::from_str(src).is_err());
+
+    // A statement cannot have both a constant value and a value read at runtime.
+    let src = r#"{
+      "destination": [{"id": "light B"}],
+      "value": {"OnOff": "On"},
+      "value_from": {"Trigger": {}},
+      "kind": "LightOn"
+    }"#;
+    match Statement::<UncheckedCtx>::from_str(src) {
+        Err(ParseError::UnknownFields { ref names, .. }) if *names == vec!["value_from".to_owned()] => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+}
+
+#[test]
+fn test_transform() {
+    let scale = Transform::Scale { factor: 2., offset: 1. };
+    assert_eq!(scale.output_type(&Type::Temperature), Some(Type::Temperature));
+    assert_eq!(scale.output_type(&Type::OnOff), None);
+    assert_eq!(scale.apply(Value::Temperature(Temperature::C(20.))),
+        Some(Value::Temperature(Temperature::C(41.))));
+    assert_eq!(scale.apply(Value::OnOff(OnOff::On)), None);
+
+    let template = Transform::Template("The oven is at {}".to_owned());
+    assert_eq!(template.output_type(&Type::Temperature), Some(Type::String));
+    assert_eq!(template.apply(Value::Temperature(Temperature::C(200.))),
+        Some(Value::String(Arc::new("The oven is at 200°C".to_owned()))));
+    assert_eq!(template.apply(Value::String(Arc::new("hot".to_owned()))),
+        Some(Value::String(Arc::new("The oven is at hot".to_owned()))));
+}
+
+#[test]
+fn test_parse_schedule() {
+    let src = r#"{
+      "Any": [{
+        "Schedule": {"days": ["Sat", "Sun"], "start": {"Sunrise": 1800}}
+      }, {
+        "source": [{"id": "door"}],
+        "kind": "OpenClosed",
+        "range": {"Eq": {"OpenClosed": "Open"}}
+      }]
+    }"#;
+    let condition = Condition::<UncheckedCtx>::from_str(src).unwrap();
+    assert_eq!(condition.matches().len(), 1);
+    let schedules = condition.schedules();
+    assert_eq!(schedules.len(), 1);
+    assert_eq!(schedules[0].start, TimeOfDay::Sunrise(Duration::from(chrono::Duration::seconds(1800))));
+    assert_eq!(schedules[0].end, None);
+
+    assert!(condition.is_met_with_schedules(&|_| false, &|_| true));
+    assert!(!condition.is_met_with_schedules(&|_| false, &|_| false));
+
+    println!("* Days must be valid.");
+    let src = r#"{"days": ["Someday"], "start": {"At": 0}}"#;
+    assert!(Schedule::from_str(src).is_err());
+}
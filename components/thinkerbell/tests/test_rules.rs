@@ -5,6 +5,7 @@ extern crate transformable_channels;
 
 extern crate chrono;
 
+use foxbox_thinkerbell::compile::{ validate, ExecutableDevEnv };
 use foxbox_thinkerbell::fake_env::*;
 use foxbox_thinkerbell::run::*;
 use foxbox_thinkerbell::ast::*;
@@ -13,7 +14,7 @@ use foxbox_taxonomy::api::{ Error as APIError, User };
 use foxbox_taxonomy::io::*;
 use foxbox_taxonomy::selector::*;
 use foxbox_taxonomy::services::*;
-use foxbox_taxonomy::values::{ Duration, OnOff, Range, TimeStamp, Type, TypeError as APITypeError , Value };
+use foxbox_taxonomy::values::{ Duration, OnOff, Range, Temperature, TimeStamp, Type, TypeError as APITypeError , Value };
 
 use std::fmt::Debug;
 use std::marker::PhantomData;
@@ -22,7 +23,7 @@ use std::collections::HashMap;
 
 use transformable_channels::mpsc::*;
 
-use chrono::{ UTC, Duration as ChronoDuration };
+use chrono::{ Local, Timelike, UTC, Duration as ChronoDuration };
 
 #[derive(Debug)]
 enum Event {
@@ -49,11 +50,101 @@ fn test_compile() {
 
     println!("* Attempting to parse an run an empty script will raise an error.");
     let script = Script::from_str(r#"{"name": "foo", "rules": []}"#).unwrap();
-    match exec.start(env, script, User::None, tx_run) {
+    match exec.start(env.clone(), script, User::None, tx_run.clone()) {
         Err(Error::CompileError(CompileError::SourceError(SourceError::NoRule))) => {},
         other => panic!("Unexpected result {:?}", other)
     }
 
+    println!("* Attempting to parse and run a script with an empty `Any` condition will raise an error.");
+    let script = Script::from_str(r#"{"name": "foo", "rules": [{
+        "conditions": {"Any": []},
+        "execute": [{
+            "destination": [{"id": "my setter"}],
+            "value": {"OnOff": "Off"},
+            "kind": "LightOn"
+        }]
+    }]}"#).unwrap();
+    let mut exec = Execution::<FakeEnv>::new();
+    match exec.start(env.clone(), script, User::None, tx_run.clone()) {
+        Err(Error::CompileError(CompileError::SourceError(SourceError::NoMatch))) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    println!("* Attempting to run a script that sends the value of a match with an incompatible kind will raise an error.");
+    let script = Script::from_str(r#"{"name": "foo", "rules": [{
+        "conditions": [{
+            "source": [{"id": "my getter"}],
+            "kind": "OvenTemperature",
+            "range": {"Geq": {"Temperature": {"C": 300}}}
+        }],
+        "execute": [{
+            "destination": [{"id": "my setter"}],
+            "value_from": {"Trigger": {"match": 0}},
+            "kind": "LightOn"
+        }]
+    }]}"#).unwrap();
+    let mut exec = Execution::<FakeEnv>::new();
+    match exec.start(env.clone(), script, User::None, tx_run.clone()) {
+        Err(Error::CompileError(CompileError::TypeError(TypeError::KindAndValueDoNotAgree))) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    println!("* Attempting to run a script that sends the value of a nonexistent match will raise an error.");
+    let script = Script::from_str(r#"{"name": "foo", "rules": [{
+        "conditions": [{
+            "source": [{"id": "my getter"}],
+            "kind": "OvenTemperature",
+            "range": {"Geq": {"Temperature": {"C": 300}}}
+        }],
+        "execute": [{
+            "destination": [{"id": "my setter"}],
+            "value_from": {"Trigger": {"match": 1}},
+            "kind": "OvenTemperature"
+        }]
+    }]}"#).unwrap();
+    let mut exec = Execution::<FakeEnv>::new();
+    match exec.start(env.clone(), script, User::None, tx_run.clone()) {
+        Err(Error::CompileError(CompileError::SourceError(SourceError::NoSuchMatch))) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    println!("* Attempting to run a script that scales a non-numeric value will raise an error.");
+    let script = Script::from_str(r#"{"name": "foo", "rules": [{
+        "conditions": [{
+            "source": [{"id": "my getter"}],
+            "kind": "OvenTemperature",
+            "range": {"Geq": {"Temperature": {"C": 300}}}
+        }],
+        "execute": [{
+            "destination": [{"id": "my setter"}],
+            "value_from": {"Fetch": {"source": [{"id": "my light"}], "kind": "LightOn"}},
+            "transform": {"Scale": {"factor": 2}},
+            "kind": "LightOn"
+        }]
+    }]}"#).unwrap();
+    let mut exec = Execution::<FakeEnv>::new();
+    match exec.start(env.clone(), script, User::None, tx_run.clone()) {
+        Err(Error::CompileError(CompileError::TypeError(TypeError::InvalidTransform))) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    println!("* Formatting the value of a match as a string is accepted.");
+    let script = Script::from_str(r#"{"name": "foo", "rules": [{
+        "conditions": [{
+            "source": [{"id": "my getter"}],
+            "kind": "OvenTemperature",
+            "range": {"Geq": {"Temperature": {"C": 300}}}
+        }],
+        "execute": [{
+            "destination": [{"id": "my setter"}],
+            "value_from": {"Trigger": {}},
+            "transform": {"Template": "The oven is at {}"},
+            "kind": "Log"
+        }]
+    }]}"#).unwrap();
+    let mut exec = Execution::<FakeEnv>::new();
+    exec.start(env.clone(), script, User::None, tx_run.clone()).unwrap();
+
     println!("//FIXME: Attempting to parse a script with an empty condition will raise an error.");
     println!("//FIXME: Attempting to parse a script with an empty statement will raise an error.");
     println!("//FIXME: Attempting to parse a script with an empty source will raise an error.");
@@ -100,8 +191,8 @@ fn test_run() {
         name: "Test script".to_owned(),
         rules: vec![
             Rule {
-                conditions: vec![
-                    Match {
+                conditions: Condition::All(vec![
+                    Condition::Match(Match {
                         source: vec![
                             ChannelSelector::new()
                         ],
@@ -109,18 +200,22 @@ fn test_run() {
                         range: Range::Eq(Value::OnOff(OnOff::On)),
                         duration: None,
                         phantom: PhantomData
-                    }
-                ],
+                    })
+                ]),
                 execute: vec![
                     Statement {
                         destination: vec![
                             ChannelSelector::new()
                         ],
-                        value: data_off,
+                        value: StatementValue::Constant(data_off),
+                        transform: None,
                         kind: ChannelKind::LightOn,
                         phantom: PhantomData,
                     }
                 ],
+                otherwise: vec![],
+                mode: Mode::OnEnter,
+                cooldown: None,
                 phantom: PhantomData
             }
         ],
@@ -486,8 +581,8 @@ fn test_run_with_delay() {
         name: "Test script".to_owned(),
         rules: vec![
             Rule {
-                conditions: vec![
-                    Match {
+                conditions: Condition::All(vec![
+                    Condition::Match(Match {
                         source: vec![
                             ChannelSelector::new()
                         ],
@@ -495,18 +590,22 @@ fn test_run_with_delay() {
                         range: Range::Eq(Value::OnOff(OnOff::On)),
                         duration: Some(Duration::from(chrono::Duration::seconds(10))),
                         phantom: PhantomData
-                    }
-                ],
+                    })
+                ]),
                 execute: vec![
                     Statement {
                         destination: vec![
                             ChannelSelector::new()
                         ],
-                        value: data_off,
+                        value: StatementValue::Constant(data_off),
+                        transform: None,
                         kind: ChannelKind::LightOn,
                         phantom: PhantomData,
                     }
                 ],
+                otherwise: vec![],
+                mode: Mode::OnEnter,
+                cooldown: None,
                 phantom: PhantomData
             }
         ],
@@ -718,3 +817,446 @@ fn test_run_with_delay() {
 
     println!("* Drop complete.");
 }
+
+/// Start executing a script in a new environment with a single getter "Getter 1"
+/// and a single setter "Setter 1", both of kind `LightOn`.
+fn start_simple_env(source: &str) -> (FakeEnv, Execution<FakeEnv>, Receiver<()>, Receiver<(Id<Channel>, Value)>) {
+    let (tx, rx) : (_, Receiver<Event>) = channel();
+
+    let tx_env = Box::new(tx.map(|event| Event::Env(event)));
+    let tx_run = tx.map(|event| Event::Run(event));
+    let (tx_done, rx_done) = channel();
+    let (tx_send, rx_send) = channel();
+
+    let env = FakeEnv::new(tx_env);
+    let mut exec = Execution::<FakeEnv>::new();
+
+    thread::spawn(move || {
+        for msg in rx {
+            if let Event::Env(FakeEnvEvent::Done) = msg {
+                tx_done.send(()).unwrap();
+            } else if let Event::Env(FakeEnvEvent::Send { id, value }) = msg {
+                tx_send.send((id, value)).unwrap();
+            }
+        }
+    });
+
+    let adapter_id = Id::<AdapterId>::new("Adapter 1");
+    let service_id = Id::<ServiceId>::new("Service 1");
+
+    env.execute(Instruction::AddAdapters(vec![adapter_id.to_string()]));
+    rx_done.recv().unwrap();
+
+    env.execute(Instruction::AddServices(vec![
+        Service::empty(&service_id, &adapter_id)
+    ]));
+    rx_done.recv().unwrap();
+
+    env.execute(Instruction::AddChannels(vec![
+        Channel {
+            kind: ChannelKind::LightOn,
+            supports_fetch: true,
+            supports_watch: true,
+            .. Channel::empty(&Id::new("Getter 1"), &service_id, &adapter_id)
+        },
+        Channel {
+            kind: ChannelKind::LightOn,
+            supports_send: true,
+            .. Channel::empty(&Id::new("Setter 1"), &service_id, &adapter_id)
+        }
+    ]));
+    rx_done.recv().unwrap();
+
+    let script = Script::from_str(source).unwrap();
+    exec.start(env.clone(), script, User::None, tx_run).unwrap();
+
+    (env, exec, rx_done, rx_send)
+}
+
+/// A rule turning off "Setter 1" once "Getter 1" is on, with some additional options.
+fn simple_script(options: &str) -> String {
+    format!(r#"{{"name": "Test script", "rules": [{{
+        "conditions": [{{
+            "source": [{{"id": "Getter 1"}}],
+            "kind": "LightOn",
+            "range": {{"Eq": {{"OnOff": "On"}}}}
+        }}],
+        "execute": [{{
+            "destination": [{{"id": "Setter 1"}}],
+            "value": {{"OnOff": "Off"}},
+            "kind": "LightOn"
+        }}]
+        {}
+    }}]}}"#, options)
+}
+
+fn inject(env: &FakeEnv, rx_done: &Receiver<()>, value: OnOff) {
+    env.execute(Instruction::InjectGetterValues(vec![
+        (Id::new("Getter 1"), Ok(Value::OnOff(value)))
+    ]));
+    rx_done.recv().unwrap();
+}
+
+fn trigger_timers(env: &FakeEnv, rx_done: &Receiver<()>, seconds: i64) {
+    env.execute(Instruction::TriggerTimersUntil(TimeStamp::from(UTC::now() + ChronoDuration::seconds(seconds))));
+    rx_done.recv().unwrap();
+}
+
+fn assert_sent(rx_send: &Receiver<(Id<Channel>, Value)>) {
+    assert_sent_value(rx_send, OnOff::Off)
+}
+
+fn assert_sent_value(rx_send: &Receiver<(Id<Channel>, Value)>, expected: OnOff) {
+    let (id, value) = rx_send.recv().unwrap();
+    assert_eq!(id, Id::new("Setter 1"));
+    assert_eq!(value, Value::OnOff(expected));
+}
+
+fn assert_not_sent(rx_send: &Receiver<(Id<Channel>, Value)>) {
+    thread::sleep(std::time::Duration::from_millis(100));
+    rx_send.try_recv().unwrap_err();
+}
+
+#[test]
+fn test_run_on_exit() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&simple_script(r#", "mode": "OnExit""#));
+
+    println!("* Entering the range doesn't trigger the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_not_sent(&rx_send);
+
+    println!("* Leaving the range triggers the send.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Entering the range again doesn't trigger the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_while_met() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&simple_script(r#", "mode": {"WhileMet": {"repeat": 60}}"#));
+
+    println!("* Entering the range triggers the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Waiting while in the range triggers the send again, once per interval.");
+    trigger_timers(&env, &rx_done, 90);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    trigger_timers(&env, &rx_done, 200);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Leaving the range stops the repetition.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_not_sent(&rx_send);
+
+    trigger_timers(&env, &rx_done, 400);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_cooldown() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&simple_script(r#", "cooldown": 60"#));
+
+    println!("* Entering the range triggers the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent(&rx_send);
+
+    println!("* Entering the range again during the cooldown doesn't trigger the send.");
+    inject(&env, &rx_done, OnOff::Off);
+    inject(&env, &rx_done, OnOff::On);
+    assert_not_sent(&rx_send);
+
+    println!("* Once the cooldown has elapsed, entering the range triggers the send.");
+    trigger_timers(&env, &rx_done, 90);
+    assert_not_sent(&rx_send);
+
+    inject(&env, &rx_done, OnOff::Off);
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_otherwise() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&simple_script(r#", "otherwise": [{
+            "destination": [{"id": "Setter 1"}],
+            "value": {"OnOff": "On"},
+            "kind": "LightOn"
+        }]"#));
+
+    println!("* Entering the range triggers `execute`.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent_value(&rx_send, OnOff::Off);
+    assert_not_sent(&rx_send);
+
+    println!("* Leaving the range triggers `otherwise`.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_sent_value(&rx_send, OnOff::On);
+    assert_not_sent(&rx_send);
+
+    println!("* Remaining out of the range doesn't trigger anything.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_not_sent(&rx_send);
+
+    println!("* Entering the range again triggers `execute` again.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent_value(&rx_send, OnOff::Off);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_validate() {
+    let (env, _exec, _rx_done, _rx_send) = start_simple_env(&simple_script(""));
+
+    println!("* A correct script resolves to the current channels.");
+    let validation = validate(env.api(), &simple_script(""));
+    assert!(validation.is_ok());
+    assert_eq!(validation.channels.len(), 2);
+    assert_eq!(validation.channels[0].at, ".rules[0].conditions.All[0]");
+    assert_eq!(validation.channels[0].channels, vec![Id::new("Getter 1")]);
+    assert_eq!(validation.channels[1].at, ".rules[0].execute[0]");
+    assert_eq!(validation.channels[1].channels, vec![Id::new("Setter 1")]);
+
+    println!("* Parse errors are reported.");
+    let validation = validate(env.api(), r#"{"name": "foo"}"#);
+    assert!(!validation.is_ok());
+    assert!(validation.parse_error.is_some());
+
+    println!("* All compilation errors are reported, with their path.");
+    let validation = validate(env.api(), r#"{"name": "Test script", "rules": [{
+        "conditions": {"Any": []},
+        "execute": [{
+            "destination": [{"id": "Setter 1"}],
+            "value_from": {"Trigger": {"match": 3}},
+            "kind": "LightOn"
+        }],
+        "mode": {"WhileMet": {"repeat": 0}}
+    }]}"#);
+    assert!(!validation.is_ok());
+    let paths : Vec<_> = validation.errors.iter().map(|diagnostic| diagnostic.at.clone()).collect();
+    assert_eq!(paths, vec![".rules[0].mode", ".rules[0].conditions", ".rules[0].execute[0]"]);
+}
+
+#[test]
+fn test_run_schedule() {
+    // A schedule active between one and two hours from now, local time.
+    let seconds = Local::now().num_seconds_from_midnight() as i64;
+    let start = (seconds + 3600) % 86400;
+    let end = (seconds + 7200) % 86400;
+    let source = format!(r#"{{"name": "Test script", "rules": [{{
+        "conditions": {{"Schedule": {{"start": {{"At": {}}}, "end": {{"At": {}}}}}}},
+        "execute": [{{
+            "destination": [{{"id": "Setter 1"}}],
+            "value": {{"OnOff": "Off"}},
+            "kind": "LightOn"
+        }}]
+    }}]}}"#, start, end);
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&source);
+
+    println!("* The statements are not executed before the schedule starts.");
+    assert_not_sent(&rx_send);
+    trigger_timers(&env, &rx_done, 1800);
+    assert_not_sent(&rx_send);
+
+    println!("* The statements are executed once the schedule starts.");
+    trigger_timers(&env, &rx_done, 5400);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Nothing happens when the schedule ends.");
+    trigger_timers(&env, &rx_done, 9000);
+    assert_not_sent(&rx_send);
+
+    println!("* The statements are executed again the next day.");
+    trigger_timers(&env, &rx_done, 86400 + 5400);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_transform() {
+    let (tx, rx) : (_, Receiver<Event>) = channel();
+
+    let tx_env = Box::new(tx.map(|event| Event::Env(event)));
+    let tx_run = tx.map(|event| Event::Run(event));
+    let (tx_done, rx_done) = channel();
+    let (tx_send, rx_send) = channel();
+
+    let env = FakeEnv::new(tx_env);
+    let mut exec = Execution::<FakeEnv>::new();
+
+    thread::spawn(move || {
+        for msg in rx {
+            if let Event::Env(FakeEnvEvent::Done) = msg {
+                tx_done.send(()).unwrap();
+            } else if let Event::Env(FakeEnvEvent::Send { id, value }) = msg {
+                tx_send.send((id, value)).unwrap();
+            }
+        }
+    });
+
+    let adapter_id = Id::<AdapterId>::new("Adapter 1");
+    let service_id = Id::<ServiceId>::new("Service 1");
+
+    env.execute(Instruction::AddAdapters(vec![adapter_id.to_string()]));
+    rx_done.recv().unwrap();
+
+    env.execute(Instruction::AddServices(vec![
+        Service::empty(&service_id, &adapter_id)
+    ]));
+    rx_done.recv().unwrap();
+
+    env.execute(Instruction::AddChannels(vec![
+        Channel {
+            kind: ChannelKind::OvenTemperature,
+            supports_fetch: true,
+            supports_watch: true,
+            .. Channel::empty(&Id::new("Oven getter"), &service_id, &adapter_id)
+        },
+        Channel {
+            kind: ChannelKind::OvenTemperature,
+            supports_send: true,
+            .. Channel::empty(&Id::new("Oven setter"), &service_id, &adapter_id)
+        },
+        Channel {
+            kind: ChannelKind::Log,
+            supports_send: true,
+            .. Channel::empty(&Id::new("Log setter"), &service_id, &adapter_id)
+        }
+    ]));
+    rx_done.recv().unwrap();
+
+    let script = Script::from_str(r#"{"name": "Test script", "rules": [{
+        "conditions": [{
+            "source": [{"id": "Oven getter"}],
+            "kind": "OvenTemperature",
+            "range": {"Geq": {"Temperature": {"C": 300}}}
+        }],
+        "execute": [{
+            "destination": [{"id": "Oven setter"}],
+            "value_from": {"Trigger": {"match": 0}},
+            "transform": {"Scale": {"factor": 0.5, "offset": 10}},
+            "kind": "OvenTemperature"
+        }, {
+            "destination": [{"id": "Log setter"}],
+            "value_from": {"Trigger": {"match": 0}},
+            "transform": {"Template": "The oven is at {}"},
+            "kind": "Log"
+        }]
+    }]}"#).unwrap();
+    exec.start(env.clone(), script, User::None, tx_run).unwrap();
+
+    println!("* Entering the range sends the transformed values of the trigger.");
+    env.execute(Instruction::InjectGetterValues(vec![
+        (Id::new("Oven getter"), Ok(Value::Temperature(Temperature::C(300.))))
+    ]));
+    rx_done.recv().unwrap();
+
+    let mut sent = HashMap::new();
+    for _ in 0..2 {
+        let (id, value) = rx_send.recv().unwrap();
+        sent.insert(id, value);
+    }
+    assert_eq!(sent.get(&Id::new("Oven setter")), Some(&Value::Temperature(Temperature::C(160.))));
+    assert_eq!(sent.get(&Id::new("Log setter")), Some(&Value::String(std::sync::Arc::new("The oven is at 300°C".to_owned()))));
+    assert_not_sent(&rx_send);
+}
+
+/// A schedule active between one and two hours from now, local time.
+fn schedule_in_one_hour() -> String {
+    let seconds = Local::now().num_seconds_from_midnight() as i64;
+    let start = (seconds + 3600) % 86400;
+    let end = (seconds + 7200) % 86400;
+    format!(r#"{{"Schedule": {{"start": {{"At": {}}}, "end": {{"At": {}}}}}}}"#, start, end)
+}
+
+/// A rule turning off "Setter 1" once `conditions` are met.
+fn script_with_conditions(conditions: &str) -> String {
+    format!(r#"{{"name": "Test script", "rules": [{{
+        "conditions": {},
+        "execute": [{{
+            "destination": [{{"id": "Setter 1"}}],
+            "value": {{"OnOff": "Off"}},
+            "kind": "LightOn"
+        }}]
+    }}]}}"#, conditions)
+}
+
+#[test]
+fn test_run_not() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&script_with_conditions(r#"{"Not": {
+        "source": [{"id": "Getter 1"}],
+        "kind": "LightOn",
+        "range": {"Eq": {"OnOff": "On"}}
+    }}"#));
+
+    println!("* A condition that is met from the start triggers the send immediately.");
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Entering the range of the negated match doesn't trigger the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_not_sent(&rx_send);
+
+    println!("* Leaving the range of the negated match triggers the send.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_not_schedule() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&script_with_conditions(
+        &format!(r#"{{"Not": {}}}"#, schedule_in_one_hour())));
+
+    println!("* A negated schedule that starts inactive triggers the send immediately.");
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Nothing happens while the schedule is active.");
+    trigger_timers(&env, &rx_done, 5400);
+    assert_not_sent(&rx_send);
+
+    println!("* The send is triggered again once the schedule ends.");
+    trigger_timers(&env, &rx_done, 9000);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+}
+
+#[test]
+fn test_run_any() {
+    let (env, _exec, rx_done, rx_send) = start_simple_env(&script_with_conditions(&format!(r#"{{"Any": [{{
+        "source": [{{"id": "Getter 1"}}],
+        "kind": "LightOn",
+        "range": {{"Eq": {{"OnOff": "On"}}}}
+    }}, {}]}}"#, schedule_in_one_hour())));
+
+    println!("* Nothing happens while neither condition is met.");
+    assert_not_sent(&rx_send);
+
+    println!("* Meeting the match triggers the send.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Leaving the range doesn't trigger anything.");
+    inject(&env, &rx_done, OnOff::Off);
+    assert_not_sent(&rx_send);
+
+    println!("* Once the schedule starts, the send is triggered again.");
+    trigger_timers(&env, &rx_done, 5400);
+    assert_sent(&rx_send);
+    assert_not_sent(&rx_send);
+
+    println!("* Meeting the match while the schedule is active doesn't trigger anything.");
+    inject(&env, &rx_done, OnOff::On);
+    assert_not_sent(&rx_send);
+}
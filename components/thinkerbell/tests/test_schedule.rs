@@ -0,0 +1,83 @@
+extern crate foxbox_taxonomy;
+extern crate foxbox_thinkerbell;
+
+extern crate chrono;
+
+use foxbox_thinkerbell::ast::*;
+use foxbox_thinkerbell::schedule::*;
+
+use foxbox_taxonomy::parse::*;
+
+use chrono::{ DateTime, Local, NaiveDate, TimeZone, UTC };
+
+fn local(day: u32, h: u32, m: u32) -> DateTime<UTC> {
+    // 2016-06-06 is a Monday.
+    Local.ymd(2016, 6, day).and_hms(h, m, 0).with_timezone(&UTC)
+}
+
+#[test]
+fn test_schedule_state() {
+    let schedule = Schedule::from_str(r#"{
+      "days": ["Mon"],
+      "start": {"At": 28800},
+      "end": {"At": 32400}
+    }"#).unwrap();
+
+    println!("* Before the start, the schedule is inactive until the start.");
+    let state = schedule.state_at(&local(6, 7, 0), None);
+    assert!(!state.is_active);
+    assert_eq!(state.next_check, local(6, 8, 0));
+
+    println!("* During the period, the schedule is active until the end.");
+    let state = schedule.state_at(&local(6, 8, 30), None);
+    assert!(state.is_active);
+    assert_eq!(state.next_check, local(6, 9, 0));
+
+    println!("* On other days, the schedule is inactive.");
+    let state = schedule.state_at(&local(5, 8, 30), None);
+    assert!(!state.is_active);
+    assert_eq!(state.next_check, local(6, 8, 0));
+
+    println!("* A period may end on the next day.");
+    let schedule = Schedule::from_str(r#"{
+      "days": ["Mon"],
+      "start": {"At": 79200},
+      "end": {"At": 21600}
+    }"#).unwrap();
+    let state = schedule.state_at(&local(7, 2, 0), None);
+    assert!(state.is_active);
+    assert_eq!(state.next_check, local(7, 6, 0));
+
+    println!("* By default, a schedule is active for one minute.");
+    let schedule = Schedule::from_str(r#"{"start": {"At": 28800}}"#).unwrap();
+    assert_eq!(schedule.days.len(), 7);
+    let state = schedule.state_at(&local(8, 8, 0), None);
+    assert!(state.is_active);
+    assert_eq!(state.next_check, local(8, 8, 1));
+
+    println!("* Without a location, schedules relative to the sun are never active.");
+    let schedule = Schedule::from_str(r#"{"start": {"Sunset": 0}}"#).unwrap();
+    let state = schedule.state_at(&local(8, 8, 0), None);
+    assert!(!state.is_active);
+}
+
+#[test]
+fn test_sun_times() {
+    let paris = Location {
+        latitude: 48.85,
+        longitude: 2.35,
+    };
+    let (sunrise, sunset) = sun_times(&NaiveDate::from_ymd(2016, 6, 21), &paris).unwrap();
+    // Sunrise is at 03:47 UTC and sunset at 19:58 UTC.
+    let expected_sunrise = UTC.ymd(2016, 6, 21).and_hms(3, 47, 0);
+    let expected_sunset = UTC.ymd(2016, 6, 21).and_hms(19, 58, 0);
+    assert!((sunrise - expected_sunrise).num_minutes().abs() <= 5);
+    assert!((sunset - expected_sunset).num_minutes().abs() <= 5);
+
+    println!("* The sun doesn't set during the polar summer.");
+    let north_cape = Location {
+        latitude: 71.17,
+        longitude: 25.78,
+    };
+    assert!(sun_times(&NaiveDate::from_ymd(2016, 6, 21), &north_cape).is_none());
+}
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Stores group definitions for the group adapter.
+//!
+//! # The groups database
+//!
+//! The "groups" table stores one row per group, indexed by the name of
+//! the group. The definition is stored as the JSON source sent by the
+//! user, and parsed again whenever the groups are restored.
+//!
+
+use rusqlite::{ self, Connection };
+
+pub struct GroupsDb {
+    db: Connection,
+}
+
+impl GroupsDb {
+    /// Opens the database at `path` and creates it if not available yet.
+    pub fn new(path: &str) -> rusqlite::Result<Self> {
+        let db = try!(Connection::open(path));
+        try!(db.execute("CREATE TABLE IF NOT EXISTS groups (
+                    name        TEXT NOT NULL PRIMARY KEY,
+                    definition  TEXT NOT NULL
+            )", &[]));
+
+        Ok(GroupsDb {
+            db: db
+        })
+    }
+
+    /// Adds the group `name`, replacing any previous definition with the same name.
+    pub fn put(&self, name: &str, definition: &str) -> rusqlite::Result<()> {
+        try!(self.db.execute("INSERT OR REPLACE INTO groups VALUES ($1, $2)",
+                             &[&name, &definition]
+        ));
+        Ok(())
+    }
+
+    /// Removes the group `name`.
+    pub fn remove(&self, name: &str) -> rusqlite::Result<()> {
+        try!(self.db.execute("DELETE FROM groups WHERE name=$1", &[&name]));
+        Ok(())
+    }
+
+    /// Gets the definitions of all the groups.
+    pub fn get_definitions(&self) -> rusqlite::Result<Vec<String>> {
+        let mut definitions = Vec::new();
+        let mut stmt = try!(self.db.prepare("SELECT definition FROM groups"));
+        let rows = try!(stmt.query(&[]));
+        for result_row in rows {
+            let row = try!(result_row);
+            definitions.push(row.get(0));
+        }
+        Ok(definitions)
+    }
+}
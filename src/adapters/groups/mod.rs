@@ -0,0 +1,610 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An adapter grouping channels offered by other adapters into virtual services.
+//!
+//! A group is defined by a name, a `ChannelKind` and a `ChannelSelector`, e.g.
+//!
+//! ```json
+//! {
+//!   "name": "Downstairs lights",
+//!   "kind": "LightOn",
+//!   "members": {"tags": ["downstairs"]}
+//! }
+//! ```
+//!
+//! Each group is exposed as its own service, with a getter and a setter of kind `kind`.
+//! Values sent to the setter are sent to all the channels of kind `kind` matched by
+//! `members`. Values fetched from the getter are aggregated from these channels:
+//!
+//! - `OnOff` values are `On` if any member is `On`. The service also offers an
+//!   `AllOn` getter, which is `On` only if all members are `On`;
+//! - `ExtNumeric` values (e.g. brightness) are the average of the members;
+//! - `Color` values are the average color of the members;
+//! - other values are returned only if all members agree.
+//!
+//! Members that cannot be reached are ignored by the getters.
+//!
+//! The definitions of groups are stored in the profile, so groups survive restarts.
+
+mod db;
+
+use adapters::utils::{ self, db_error, find_item, first_error };
+
+use foxbox_taxonomy::api::{ API, Error, InternalError, Targetted, User };
+use foxbox_taxonomy::io::Payload;
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::selector::*;
+use foxbox_taxonomy::services::{ AdapterId, ServiceId, Service, Channel, ChannelKind };
+use foxbox_taxonomy::util::{ Exactly, Id };
+use foxbox_taxonomy::values::{ Color, ExtValue, Json, OnOff, Type, TypeError, Value };
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+
+static ADAPTER_NAME: &'static str = "Group adapter (built-in)";
+static ADAPTER_VENDOR: &'static str = "team@link.mozilla.org";
+static ADAPTER_VERSION: [u32;4] = [0, 0, 0, 0];
+
+/// The definition of a group, as provided by the user.
+#[derive(Clone, Debug)]
+pub struct GroupDefinition {
+    /// The name of the group. Also used to derive the ids of the service and its channels.
+    pub name: String,
+
+    /// The kind of the channels of the group.
+    pub kind: ChannelKind,
+
+    /// The channels of other adapters controlled by the group. Only channels with kind
+    /// `kind` are used.
+    pub members: ChannelSelector,
+}
+
+impl Parser<GroupDefinition> for GroupDefinition {
+    fn description() -> String {
+        "GroupDefinition".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let name = try!(path.push("name", |path| String::take(path, source, "name")));
+        let kind = try!(path.push("kind", |path| ChannelKind::take(path, source, "kind")));
+        let members = try!(path.push("members", |path| ChannelSelector::take(path, source, "members")));
+        Ok(GroupDefinition {
+            name: name,
+            kind: kind,
+            members: members,
+        })
+    }
+}
+
+/// An internal data structure to track the service and channels of a group.
+#[derive(Clone)]
+struct Group {
+    definition: GroupDefinition,
+
+    /// The JSON source of the definition, as stored in the database.
+    source: JSON,
+
+    service_id: Id<ServiceId>,
+    getter_id: Id<Channel>,
+    setter_id: Id<Channel>,
+    setter_remove_id: Id<Channel>,
+
+    /// Only for groups of `OnOff` channels.
+    getter_all_on_id: Option<Id<Channel>>,
+}
+
+/// `GroupAdapter` exposes groups of channels as virtual services.
+///
+/// This adapter exposes a root service, with an `AddGroup` setter (to add or replace a group,
+/// using a `Json` definition) and a `Groups` getter (to list the definitions of all groups).
+/// Each group is exposed as its own service, with a getter and a setter mirroring the kind
+/// of the group, and a `RemoveGroup` setter.
+#[derive(Clone)]
+pub struct GroupAdapter {
+    /// A reference to the AdapterManager.
+    adapter_manager: Arc<AdapterManager>,
+
+    /// The groups, indexed by name.
+    groups: Arc<Mutex<HashMap<String, Group>>>,
+
+    /// The path to the database storing the definitions.
+    db_path: String,
+
+    /// The ID of this adapter (permanently fixed)
+    adapter_id: Id<AdapterId>,
+
+    /// The ID of the root service's "Add Group" setter.
+    setter_add_group_id: Id<Channel>,
+
+    /// The ID of the root service's "Groups" getter.
+    getter_groups_id: Id<Channel>,
+}
+
+/// A `ChannelKind` specific to this adapter.
+fn extension_kind(adapter_id: &Id<AdapterId>, kind: &str, typ: Type) -> ChannelKind {
+    utils::extension_kind(ADAPTER_VENDOR, adapter_id, kind, typ)
+}
+
+/// Combine the values of the members of a group into a single value.
+///
+/// If `all_on` is `true`, `OnOff` values are `On` only if all values are `On`.
+/// Otherwise, they are `On` if any value is `On`.
+fn aggregate(values: &[Value], all_on: bool) -> Option<Value> {
+    let first = match values.first() {
+        None => return None,
+        Some(first) => first
+    };
+    match *first {
+        Value::OnOff(_) => {
+            let is_on = |value: &Value| *value == Value::OnOff(OnOff::On);
+            let on = if all_on {
+                values.iter().all(is_on)
+            } else {
+                values.iter().any(is_on)
+            };
+            Some(Value::OnOff(if on { OnOff::On } else { OnOff::Off }))
+        },
+        Value::ExtNumeric(ref ext) => {
+            let numbers : Vec<f64> = values.iter().filter_map(|value| match *value {
+                Value::ExtNumeric(ref ext) => Some(ext.value),
+                _ => None
+            }).collect();
+            let sum = numbers.iter().fold(0., |sum, number| sum + number);
+            Some(Value::ExtNumeric(ExtValue {
+                value: sum / numbers.len() as f64,
+                .. ext.clone()
+            }))
+        },
+        Value::Color(_) => {
+            // Hue is an angle, so we average it on the unit circle.
+            let (mut sin, mut cos, mut saturation, mut brightness, mut count) = (0., 0., 0., 0., 0.);
+            for value in values {
+                if let Value::Color(Color::HSV(h, s, v)) = *value {
+                    sin += h.to_radians().sin();
+                    cos += h.to_radians().cos();
+                    saturation += s;
+                    brightness += v;
+                    count += 1.;
+                }
+            }
+            let mut hue = sin.atan2(cos).to_degrees();
+            if hue < 0. {
+                hue += 360.;
+            }
+            Some(Value::Color(Color::HSV(hue, saturation / count, brightness / count)))
+        },
+        _ => {
+            if values.iter().all(|value| value == first) {
+                Some(first.clone())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl Adapter for GroupAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        self.adapter_id.clone()
+    }
+
+    fn name(&self) -> &str {
+        ADAPTER_NAME
+    }
+
+    fn vendor(&self) -> &str {
+        ADAPTER_VENDOR
+    }
+
+    fn version(&self) -> &[u32;4] {
+        &ADAPTER_VERSION
+    }
+
+    fn fetch_values(&self, mut set: Vec<Id<Channel>>, user: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+        set.drain(..).map(|id| {
+            if id == self.getter_groups_id {
+                let groups = self.groups.lock().unwrap();
+                let sources = groups.values().map(|group| group.source.clone()).collect();
+                return (id, Ok(Some(Value::Json(Arc::new(Json(JSON::Array(sources)))))));
+            }
+            let group = self.find_group(|group| group.getter_id == id || group.getter_all_on_id.as_ref() == Some(&id));
+            let result = match group {
+                None => Err(Error::InternalError(InternalError::NoSuchChannel(id.clone()))),
+                Some(group) => {
+                    let all_on = group.getter_all_on_id.as_ref() == Some(&id);
+                    Ok(aggregate(&self.fetch_members(&group, &user), all_on))
+                }
+            };
+            (id, result)
+        }).collect()
+    }
+
+    fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, user: User) -> ResultMap<Id<Channel>, (), Error> {
+        values.drain().map(|(id, value)| {
+            if id == self.setter_add_group_id {
+                let result = match value {
+                    Value::Json(ref json) => self.add_group(&json.0),
+                    _ => Err(Error::TypeError(TypeError {
+                        expected: Type::Json,
+                        got: value.get_type()
+                    }))
+                };
+                return (id, result);
+            }
+            if let Some(group) = self.find_group(|group| group.setter_remove_id == id) {
+                return (id, self.remove_group(&group.definition.name));
+            }
+            let result = match self.find_group(|group| group.setter_id == id) {
+                None => Err(Error::InternalError(InternalError::NoSuchChannel(id.clone()))),
+                Some(group) => self.send_members(&group, &value, &user)
+            };
+            (id, result)
+        }).collect()
+    }
+}
+
+impl GroupAdapter {
+    fn get_db(&self) -> Result<db::GroupsDb, Error> {
+        db::GroupsDb::new(&self.db_path).map_err(db_error)
+    }
+
+    fn find_group<F>(&self, predicate: F) -> Option<Group> where F: Fn(&Group) -> bool {
+        find_item(&self.groups, predicate)
+    }
+
+    /// The selectors for the channels of other adapters that are members of a group.
+    fn members(&self, group: &Group, selector: ChannelSelector) -> Vec<ChannelSelector> {
+        let selector = group.definition.members.clone()
+            .with_kind(group.definition.kind.clone())
+            .and(selector);
+        self.adapter_manager.get_channels(vec![selector]).drain(..)
+            // Groups cannot contain other groups, to avoid cycles.
+            .filter(|channel| channel.adapter != self.adapter_id)
+            .map(|channel| ChannelSelector::new().with_id(channel.id))
+            .collect()
+    }
+
+    /// Fetch the values of the members of a group, ignoring members that do not respond.
+    fn fetch_members(&self, group: &Group, user: &User) -> Vec<Value> {
+        let members = self.members(group, ChannelSelector::new().with_supports_fetch(Exactly::Exactly(true)));
+        if members.is_empty() {
+            return vec![];
+        }
+        self.adapter_manager.fetch_values(members, None, user.clone())
+            .drain()
+            .filter_map(|(id, result)| match result {
+                Ok(Some(fetched)) => fetched.value.to_value(&fetched.type_).ok(),
+                Ok(None) => None,
+                Err(err) => {
+                    warn!("[groups@link.mozilla.org] Could not fetch member {} of group {}: {:?}",
+                        id, group.definition.name, err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Send a value to all the members of a group.
+    fn send_members(&self, group: &Group, value: &Value, user: &User) -> Result<(), Error> {
+        let payload = try!(Payload::from_value(value, &group.definition.kind.get_type()));
+        let members = self.members(group, ChannelSelector::new().with_supports_send(Exactly::Exactly(true)));
+        if members.is_empty() {
+            return Ok(());
+        }
+        let results = self.adapter_manager.send_values(vec![Targetted::new(members, payload)], user.clone());
+        first_error(results, |id, err| {
+            warn!("[groups@link.mozilla.org] Could not send value to member {} of group {}: {:?}",
+                id, group.definition.name, err);
+        })
+    }
+
+    /// Add a group or replace the group with the same name, and store its definition.
+    ///
+    /// If the definition is invalid or cannot be stored, any previous group is kept.
+    fn add_group(&self, source: &JSON) -> Result<(), Error> {
+        let definition = try!(GroupDefinition::parse(Path::new(), source).map_err(Error::ParseError));
+        let name = definition.name.clone();
+
+        let stored = serde_json::to_string(source).unwrap();
+        try!(try!(self.get_db()).put(&name, &stored).map_err(db_error));
+
+        try!(self.remove_group_service(&name));
+        self.add_group_service(definition, source.clone())
+    }
+
+    /// Remove a group and its definition.
+    fn remove_group(&self, name: &str) -> Result<(), Error> {
+        try!(self.remove_group_service(name));
+        try!(self.get_db()).remove(name).map_err(db_error)
+    }
+
+    /// Add the service and channels of a group. (This does not store the definition.)
+    fn add_group_service(&self, definition: GroupDefinition, source: JSON) -> Result<(), Error> {
+        let service_id = Id::new(&format!("group/{}", definition.name));
+        let is_on_off = definition.kind.get_type() == Type::OnOff;
+        let group = Group {
+            getter_id: Id::new(&format!("{}/get", service_id.as_atom())),
+            setter_id: Id::new(&format!("{}/set", service_id.as_atom())),
+            setter_remove_id: Id::new(&format!("{}/remove", service_id.as_atom())),
+            getter_all_on_id: if is_on_off {
+                Some(Id::new(&format!("{}/all_on", service_id.as_atom())))
+            } else {
+                None
+            },
+            service_id: service_id,
+            definition: definition,
+            source: source,
+        };
+
+        let mut service = Service::empty(&group.service_id, &self.adapter_id);
+        service.properties.insert("name".to_owned(), group.definition.name.clone());
+        service.tags.insert(Id::new("type:Group"));
+        try!(self.adapter_manager.add_service(service));
+
+        try!(self.adapter_manager.add_channel(Channel {
+            supports_fetch: true,
+            kind: group.definition.kind.clone(),
+            ..Channel::empty(&group.getter_id, &group.service_id, &self.adapter_id)
+        }));
+
+        try!(self.adapter_manager.add_channel(Channel {
+            supports_send: true,
+            kind: group.definition.kind.clone(),
+            ..Channel::empty(&group.setter_id, &group.service_id, &self.adapter_id)
+        }));
+
+        try!(self.adapter_manager.add_channel(Channel {
+            supports_send: true,
+            kind: extension_kind(&self.adapter_id, "RemoveGroup", Type::Unit),
+            ..Channel::empty(&group.setter_remove_id, &group.service_id, &self.adapter_id)
+        }));
+
+        if let Some(ref getter_all_on_id) = group.getter_all_on_id {
+            try!(self.adapter_manager.add_channel(Channel {
+                supports_fetch: true,
+                kind: extension_kind(&self.adapter_id, "AllOn", Type::OnOff),
+                ..Channel::empty(getter_all_on_id, &group.service_id, &self.adapter_id)
+            }));
+        }
+
+        info!("[groups@link.mozilla.org] Added group '{}'", group.definition.name);
+        self.groups.lock().unwrap().insert(group.definition.name.clone(), group);
+        Ok(())
+    }
+
+    /// Remove the service and channels of a group. (This does not remove the definition.)
+    fn remove_group_service(&self, name: &str) -> Result<(), Error> {
+        let group = match self.groups.lock().unwrap().remove(name) {
+            None => return Ok(()),
+            Some(group) => group
+        };
+        info!("[groups@link.mozilla.org] Removed group '{}'", name);
+        self.adapter_manager.remove_service(&group.service_id)
+    }
+
+    /// Register the adapter and restore the groups stored in the database at `db_path`.
+    pub fn init(manager: &Arc<AdapterManager>, db_path: &str) -> Result<(), Error> {
+        let adapter_id = Id::new("groups@link.mozilla.org");
+        let setter_add_group_id = Id::new("groups-add-group");
+        let getter_groups_id = Id::new("groups-get-groups");
+        let root_service_id = Id::new("groups-root-service");
+
+        let adapter = GroupAdapter {
+            adapter_manager: manager.clone(),
+            groups: Arc::new(Mutex::new(HashMap::new())),
+            db_path: db_path.to_owned(),
+            adapter_id: adapter_id.clone(),
+            setter_add_group_id: setter_add_group_id.clone(),
+            getter_groups_id: getter_groups_id.clone(),
+        };
+
+        // Add the adapter and the root service (the one that exposes `AddGroup` for adding new groups).
+        try!(manager.add_adapter(Arc::new(adapter.clone())));
+        try!(manager.add_service(Service::empty(&root_service_id, &adapter_id)));
+        try!(manager.add_channel(Channel {
+            kind: extension_kind(&adapter_id, "AddGroup", Type::Json),
+            supports_send: true,
+            ..Channel::empty(&setter_add_group_id, &root_service_id, &adapter_id)
+        }));
+        try!(manager.add_channel(Channel {
+            kind: extension_kind(&adapter_id, "Groups", Type::Json),
+            supports_fetch: true,
+            ..Channel::empty(&getter_groups_id, &root_service_id, &adapter_id)
+        }));
+
+        let definitions = try!(try!(adapter.get_db()).get_definitions().map_err(db_error));
+        for source in definitions {
+            let result = serde_json::from_str::<JSON>(&source)
+                .map_err(|err| Error::InternalError(InternalError::GenericError(format!("{:?}", err))))
+                .and_then(|json| {
+                    let definition = try!(GroupDefinition::parse(Path::new(), &json).map_err(Error::ParseError));
+                    adapter.add_group_service(definition, json)
+                });
+            if let Err(err) = result {
+                error!("[groups@link.mozilla.org] Unable to restore group {}: {:?}", source, err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+describe! groups {
+    before_each {
+        use foxbox_taxonomy::api::FetchedValue;
+        use tempdir::TempDir;
+
+        /// Lights whose power can be fetched and sent, counting the values they receive.
+        struct StubLights {
+            power: Arc<Mutex<HashMap<Id<Channel>, OnOff>>>,
+            sent: Arc<Mutex<usize>>,
+        }
+        impl Adapter for StubLights {
+            fn id(&self) -> Id<AdapterId> {
+                Id::new("stub-lights")
+            }
+            fn name(&self) -> &str {
+                "Stub lights"
+            }
+            fn vendor(&self) -> &str {
+                ADAPTER_VENDOR
+            }
+            fn version(&self) -> &[u32;4] {
+                &ADAPTER_VERSION
+            }
+            fn fetch_values(&self, mut set: Vec<Id<Channel>>, _: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+                let power = self.power.lock().unwrap();
+                set.drain(..).map(|id| {
+                    let value = power.get(&id).map(|on_off| Value::OnOff(on_off.clone()));
+                    (id, Ok(value))
+                }).collect()
+            }
+            fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, _: User) -> ResultMap<Id<Channel>, (), Error> {
+                values.drain().map(|(id, value)| {
+                    *self.sent.lock().unwrap() += 1;
+                    match value {
+                        Value::OnOff(on_off) => {
+                            self.power.lock().unwrap().insert(id.clone(), on_off);
+                            (id, Ok(()))
+                        }
+                        other => (id, Err(Error::InvalidValue(other)))
+                    }
+                }).collect()
+            }
+        }
+
+        let lights_id = Id::<AdapterId>::new("stub-lights");
+        let lights_service_id = Id::<ServiceId>::new("lights");
+        let light_1 = Id::<Channel>::new("light-1");
+        let light_2 = Id::<Channel>::new("light-2");
+        let power = Arc::new(Mutex::new(HashMap::new()));
+        let sent = Arc::new(Mutex::new(0));
+
+        let manager = Arc::new(AdapterManager::new(None));
+        manager.add_adapter(Arc::new(StubLights { power: power.clone(), sent: sent.clone() })).unwrap();
+        manager.add_service(Service::empty(&lights_service_id, &lights_id)).unwrap();
+        for id in vec![&light_1, &light_2] {
+            power.lock().unwrap().insert(id.clone(), OnOff::Off);
+            manager.add_channel(Channel {
+                kind: ChannelKind::LightOn,
+                supports_fetch: true,
+                supports_send: true,
+                tags: vec![Id::new("downstairs")].into_iter().collect(),
+                ..Channel::empty(id, &lights_service_id, &lights_id)
+            }).unwrap();
+        }
+
+        let profile_dir = TempDir::new_in("/tmp", "foxbox").unwrap();
+        let db_path = profile_dir.path().join("groups.sqlite");
+        GroupAdapter::init(&manager, db_path.to_str().unwrap()).unwrap();
+
+        let send = |id: &str, value: Value, typ: Type| {
+            let payload = Payload::from_value(&value, &typ).unwrap();
+            let selector = vec![ChannelSelector::new().with_id(Id::new(id))];
+            let results = manager.send_values(vec![Targetted::new(selector, payload)], User::None);
+            match results.get(&Id::new(id)) {
+                Some(&Ok(())) => {},
+                other => panic!("Unexpected send result {:?}", other)
+            }
+        };
+        let fetch = |id: &str| {
+            let results = manager.fetch_values(vec![ChannelSelector::new().with_id(Id::new(id))], None, User::None);
+            match results.get(&Id::new(id)) {
+                Some(&Ok(Some(FetchedValue { ref value, ref type_, .. }))) => value.to_value(type_).unwrap(),
+                other => panic!("Unexpected fetch result {:?}", other)
+            }
+        };
+        let add_group = |source: &str| {
+            let json : JSON = serde_json::from_str(source).unwrap();
+            send("groups-add-group", Value::Json(Arc::new(Json(json))), Type::Json);
+        };
+
+        add_group(r#"{"name": "Downstairs", "kind": "LightOn", "members": {"tags": ["downstairs"]}}"#);
+    }
+
+    it "should send values to all the members of a group" {
+        send("group/Downstairs/set", Value::OnOff(OnOff::On), Type::OnOff);
+        assert_eq!(power.lock().unwrap().get(&light_1), Some(&OnOff::On));
+        assert_eq!(power.lock().unwrap().get(&light_2), Some(&OnOff::On));
+        assert_eq!(*sent.lock().unwrap(), 2);
+    }
+
+    it "should aggregate the values of the members of a group" {
+        assert_eq!(fetch("group/Downstairs/get"), Value::OnOff(OnOff::Off));
+        power.lock().unwrap().insert(light_1.clone(), OnOff::On);
+        assert_eq!(fetch("group/Downstairs/get"), Value::OnOff(OnOff::On));
+        assert_eq!(fetch("group/Downstairs/all_on"), Value::OnOff(OnOff::Off));
+        power.lock().unwrap().insert(light_2.clone(), OnOff::On);
+        assert_eq!(fetch("group/Downstairs/all_on"), Value::OnOff(OnOff::On));
+    }
+
+    it "should not include other groups in a group" {
+        // Tag the channels of the first group, so that they match the second group.
+        let selector = ChannelSelector::new().with_parent(Id::new("group/Downstairs"));
+        assert!(manager.add_channel_tags(vec![selector], vec![Id::new("downstairs")]) > 0);
+        add_group(r#"{"name": "Everything", "kind": "LightOn", "members": {"tags": ["downstairs"]}}"#);
+
+        send("group/Everything/set", Value::OnOff(OnOff::On), Type::OnOff);
+        // Each light received the value once, rather than once more through the first group.
+        assert_eq!(*sent.lock().unwrap(), 2);
+    }
+
+    it "should restore groups from the database" {
+        let manager = Arc::new(AdapterManager::new(None));
+        GroupAdapter::init(&manager, db_path.to_str().unwrap()).unwrap();
+        let channels = manager.get_channels(vec![ChannelSelector::new().with_parent(Id::new("group/Downstairs"))]);
+        assert_eq!(channels.len(), 4);
+    }
+
+    it "should keep a group if its replacement is invalid" {
+        let json : JSON = serde_json::from_str(r#"{"name": "Downstairs", "kind": "LightOn"}"#).unwrap();
+        let payload = Payload::from_value(&Value::Json(Arc::new(Json(json))), &Type::Json).unwrap();
+        let selector = vec![ChannelSelector::new().with_id(Id::new("groups-add-group"))];
+        let results = manager.send_values(vec![Targetted::new(selector, payload)], User::None);
+        assert!(results.get(&Id::new("groups-add-group")).unwrap().is_err());
+        assert_eq!(fetch("group/Downstairs/get"), Value::OnOff(OnOff::Off));
+    }
+
+    it "should aggregate OnOff values as any-on or all-on" {
+        let values = vec![Value::OnOff(OnOff::On), Value::OnOff(OnOff::Off)];
+        assert_eq!(aggregate(&values, false), Some(Value::OnOff(OnOff::On)));
+        assert_eq!(aggregate(&values, true), Some(Value::OnOff(OnOff::Off)));
+        assert_eq!(aggregate(&[], false), None);
+    }
+
+    it "should average colors" {
+        let values = vec![Value::Color(Color::HSV(350., 0.2, 0.4)), Value::Color(Color::HSV(10., 0.4, 0.6))];
+        match aggregate(&values, false) {
+            Some(Value::Color(Color::HSV(h, s, v))) => {
+                assert!(h < 0.001 || h > 359.999);
+                assert!((s - 0.3).abs() < 0.001);
+                assert!((v - 0.5).abs() < 0.001);
+            }
+            other => panic!("Unexpected aggregate {:?}", other)
+        }
+    }
+
+    it "should only return other values if all members agree" {
+        use foxbox_taxonomy::values::OpenClosed;
+        let values = vec![Value::OpenClosed(OpenClosed::Open), Value::OpenClosed(OpenClosed::Open)];
+        assert_eq!(aggregate(&values, false), Some(Value::OpenClosed(OpenClosed::Open)));
+        let values = vec![Value::OpenClosed(OpenClosed::Open), Value::OpenClosed(OpenClosed::Closed)];
+        assert_eq!(aggregate(&values, false), None);
+    }
+
+    it "should parse group definitions" {
+        let definition = GroupDefinition::from_str(r#"{
+            "name": "Downstairs lights",
+            "kind": "LightOn",
+            "members": {"tags": ["downstairs"]}
+        }"#).unwrap();
+        assert_eq!(definition.name, "Downstairs lights");
+        assert_eq!(definition.kind, ChannelKind::LightOn);
+        assert!(GroupDefinition::from_str(r#"{"name": "No members", "kind": "LightOn"}"#).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The configuration of the generic HTTP adapter, as read from the profile.
+
+use super::json_path::JsonPath;
+
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::services::ChannelKind;
+
+use std::collections::HashMap;
+
+/// The placeholder replaced by the value in the body of `send` requests.
+pub static VALUE_PLACEHOLDER: &'static str = "{{value}}";
+
+/// An HTTP request to perform when fetching or sending a value.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `url` (string, required);
+/// - `method` (string, optional): defaults to `GET` for `fetch` and to `POST` for `send`;
+/// - `headers` (object of strings, optional): additional headers;
+/// - `path` (`JSONPath`, optional, only for `fetch`): where to find the value in the response.
+///   Defaults to the entire response;
+/// - `body` (string, optional, only for `send`): a template for the body of the request,
+///   in which `{{value}}` is replaced by the JSON representation of the value. Defaults to
+///   `{{value}}`.
+#[derive(Clone, Debug)]
+pub struct RequestConfig {
+    pub url: String,
+    pub method: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub path: Option<JsonPath>,
+    pub body: Option<String>,
+}
+
+impl Parser<RequestConfig> for RequestConfig {
+    fn description() -> String {
+        "RequestConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let url = try!(path.push("url", |path| String::take(path, source, "url")));
+        let method = match path.push("method", |path| String::take_opt(path, source, "method")) {
+            Some(Ok(method)) => Some(method.to_uppercase()),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let headers = match path.push("headers", |path| HashMap::<String, String>::take_opt(path, source, "headers")) {
+            Some(Ok(headers)) => headers,
+            Some(Err(err)) => return Err(err),
+            None => HashMap::new()
+        };
+        let json_path = match path.push("path", |path| JsonPath::take_opt(path, source, "path")) {
+            Some(Ok(json_path)) => Some(json_path),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let body = match path.push("body", |path| String::take_opt(path, source, "body")) {
+            Some(Ok(body)) => Some(body),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        Ok(RequestConfig {
+            url: url,
+            method: method,
+            headers: headers,
+            path: json_path,
+            body: body,
+        })
+    }
+}
+
+/// A channel of a device.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `id` (string, required): unique within the service;
+/// - `kind` (`ChannelKind`, required): determines the type of values;
+/// - `fetch` (`RequestConfig`, optional): how to read the value;
+/// - `send` (`RequestConfig`, optional): how to change the value;
+/// - `poll` (number of seconds, optional): if specified, the channel can be watched by
+///   fetching the value at this interval. Requires `fetch`;
+/// - `values` (object, optional): how the device represents values, e.g.
+///   `{"On": true, "Off": false}`. Values that do not appear in this table are
+///   represented as in the taxonomy, e.g. `"On"`.
+#[derive(Clone, Debug)]
+pub struct ChannelConfig {
+    pub id: String,
+    pub kind: ChannelKind,
+    pub fetch: Option<RequestConfig>,
+    pub send: Option<RequestConfig>,
+    pub poll: Option<f64>,
+    pub values: HashMap<String, JSON>,
+}
+
+impl Parser<ChannelConfig> for ChannelConfig {
+    fn description() -> String {
+        "ChannelConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let id = try!(path.push("id", |path| String::take(path, source, "id")));
+        let kind = try!(path.push("kind", |path| ChannelKind::take(path, source, "kind")));
+        let fetch = match path.push("fetch", |path| RequestConfig::take_opt(path, source, "fetch")) {
+            Some(Ok(fetch)) => Some(fetch),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let send = match path.push("send", |path| RequestConfig::take_opt(path, source, "send")) {
+            Some(Ok(send)) => Some(send),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let poll = match path.push("poll", |path| f64::take_opt(path, source, "poll")) {
+            Some(Ok(poll)) if poll > 0. && fetch.is_some() => Some(poll),
+            Some(Ok(_)) => return Err(ParseError::type_error("poll", &path, "positive number, with fetch")),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let values = match source.find("values") {
+            None => HashMap::new(),
+            Some(&JSON::Object(ref values)) => values.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            Some(_) => return Err(ParseError::type_error("values", &path, "object"))
+        };
+        Ok(ChannelConfig {
+            id: id,
+            kind: kind,
+            fetch: fetch,
+            send: send,
+            poll: poll,
+            values: values,
+        })
+    }
+}
+
+/// A device, exposed as a service.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `id` (string, required): unique among the devices of the adapter;
+/// - `properties` (object of strings, optional): the properties of the service,
+///   e.g. `{"name": "Garage door"}`;
+/// - `channels` (array of `ChannelConfig`, required).
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    pub id: String,
+    pub properties: HashMap<String, String>,
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl Parser<ServiceConfig> for ServiceConfig {
+    fn description() -> String {
+        "ServiceConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let id = try!(path.push("id", |path| String::take(path, source, "id")));
+        let properties = match path.push("properties", |path| HashMap::<String, String>::take_opt(path, source, "properties")) {
+            Some(Ok(properties)) => properties,
+            Some(Err(err)) => return Err(err),
+            None => HashMap::new()
+        };
+        let channels = try!(path.push("channels", |path| ChannelConfig::take_vec(path, source, "channels")));
+        Ok(ServiceConfig {
+            id: id,
+            properties: properties,
+            channels: channels,
+        })
+    }
+}
+
+/// The configuration of the adapter.
+///
+/// # JSON
+///
+/// An object with a field `services` (array of `ServiceConfig`).
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub services: Vec<ServiceConfig>,
+}
+
+impl Parser<Config> for Config {
+    fn description() -> String {
+        "HttpJsonConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let services = try!(path.push("services", |path| ServiceConfig::take_vec(path, source, "services")));
+        Ok(Config {
+            services: services,
+        })
+    }
+}
@@ -0,0 +1,154 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A small subset of `JSONPath`, sufficient to extract a value from the responses of
+//! simple devices.
+//!
+//! Supported paths start with `$` and are followed by any number of `.field`,
+//! `["field"]` and `[index]` steps, e.g. `$.relays[0].state`.
+
+use foxbox_taxonomy::parse::*;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonPath {
+    source: String,
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    /// Parse a path, returning a human-readable message in case of error.
+    pub fn new(source: &str) -> Result<Self, String> {
+        let mut chars = source.chars().peekable();
+        if chars.next() != Some('$') {
+            return Err(format!("JSONPath {} should start with '$'", source));
+        }
+        let mut steps = vec![];
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    let mut field = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        field.push(c);
+                        chars.next();
+                    }
+                    if field.is_empty() {
+                        return Err(format!("Empty field name in JSONPath {}", source));
+                    }
+                    steps.push(Step::Field(field));
+                }
+                '[' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(format!("Unterminated '[' in JSONPath {}", source))
+                        }
+                    }
+                    let quoted = inner.len() >= 2 &&
+                        ((inner.starts_with('"') && inner.ends_with('"')) ||
+                         (inner.starts_with('\'') && inner.ends_with('\'')));
+                    if quoted {
+                        steps.push(Step::Field(inner[1..inner.len() - 1].to_owned()));
+                    } else {
+                        match inner.parse() {
+                            Ok(index) => steps.push(Step::Index(index)),
+                            Err(_) => return Err(format!("Invalid index {} in JSONPath {}", inner, source))
+                        }
+                    }
+                }
+                _ => return Err(format!("Unexpected character '{}' in JSONPath {}", c, source))
+            }
+        }
+        Ok(JsonPath {
+            source: source.to_owned(),
+            steps: steps,
+        })
+    }
+
+    /// Extract the value designated by this path, if it exists.
+    pub fn extract<'a>(&self, json: &'a JSON) -> Option<&'a JSON> {
+        let mut current = json;
+        for step in &self.steps {
+            current = match (step, current) {
+                (&Step::Field(ref field), &JSON::Object(ref object)) => match object.get(field) {
+                    Some(value) => value,
+                    None => return None
+                },
+                (&Step::Index(index), &JSON::Array(ref array)) => match array.get(index) {
+                    Some(value) => value,
+                    None => return None
+                },
+                _ => return None
+            }
+        }
+        Some(current)
+    }
+}
+
+impl Parser<JsonPath> for JsonPath {
+    fn description() -> String {
+        "JSONPath".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        match source.as_string() {
+            Some(string) => JsonPath::new(string).map_err(|_| ParseError::type_error("JSONPath", &path, "$.field[index]...")),
+            None => Err(ParseError::type_error("JSONPath", &path, "string"))
+        }
+    }
+}
+
+impl ToJSON for JsonPath {
+    fn to_json(&self) -> JSON {
+        JSON::String(self.source.clone())
+    }
+}
+
+#[cfg(test)]
+describe! json_path {
+    before_each {
+        extern crate serde_json;
+
+        let json: JSON = serde_json::from_str(r#"{
+            "relays": [{"state": "On"}, {"state": "Off"}],
+            "door": {"open": true},
+            "odd.name": 3
+        }"#).unwrap();
+    }
+
+    it "should extract fields and array items" {
+        let path = JsonPath::new("$.relays[1].state").unwrap();
+        assert_eq!(path.extract(&json), Some(&JSON::String("Off".to_owned())));
+
+        let path = JsonPath::new("$.door.open").unwrap();
+        assert_eq!(path.extract(&json), Some(&JSON::Bool(true)));
+
+        let path = JsonPath::new("$['odd.name']").unwrap();
+        assert_eq!(path.extract(&json), Some(&JSON::U64(3)));
+
+        assert_eq!(JsonPath::new("$").unwrap().extract(&json), Some(&json));
+    }
+
+    it "should return None for missing values" {
+        assert_eq!(JsonPath::new("$.relays[2].state").unwrap().extract(&json), None);
+        assert_eq!(JsonPath::new("$.door.closed").unwrap().extract(&json), None);
+        assert_eq!(JsonPath::new("$.door[0]").unwrap().extract(&json), None);
+    }
+
+    it "should reject invalid paths" {
+        assert!(JsonPath::new("relays").is_err());
+        assert!(JsonPath::new("$.").is_err());
+        assert!(JsonPath::new("$.relays[one]").is_err());
+        assert!(JsonPath::new("$.relays[0").is_err());
+    }
+}
@@ -0,0 +1,443 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A generic adapter for devices exposing a simple HTTP/JSON API.
+//!
+//! Rather than requiring one adapter per device, this adapter reads the description of
+//! devices from `http_devices.json` in the profile, e.g.
+//!
+//! ```json
+//! {
+//!   "services": [{
+//!     "id": "garage",
+//!     "properties": {"name": "Garage door"},
+//!     "channels": [{
+//!       "id": "door",
+//!       "kind": "OpenClosed",
+//!       "fetch": {"url": "http://192.168.1.20/status", "path": "$.door.open"},
+//!       "send": {"url": "http://192.168.1.20/door", "method": "PUT", "body": "{\"open\": {{value}}}"},
+//!       "poll": 30,
+//!       "values": {"Open": true, "Closed": false}
+//!     }]
+//!   }]
+//! }
+//! ```
+//!
+//! Each device is exposed as a service `http-json/<service id>`, with one channel
+//! `http-json/<service id>/<channel id>` per channel. See module `config` for the details
+//! of the format.
+//!
+//! Devices rarely notify anybody of changes, so channels with a `poll` interval are
+//! watched by fetching their value at this interval.
+
+mod config;
+pub mod json_path;
+
+use self::config::{ ChannelConfig, Config, RequestConfig, VALUE_PLACEHOLDER };
+
+use adapters::utils::read_config;
+
+use foxbox_taxonomy::api::{ Error, InternalError, Operation, User };
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::{ Range, Type, TypeError, Value };
+
+use transformable_channels::mpsc::*;
+
+use chrono;
+use hyper;
+use serde_json;
+use timer;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+
+static ADAPTER_NAME: &'static str = "Generic HTTP/JSON adapter (built-in)";
+static ADAPTER_VENDOR: &'static str = "team@link.mozilla.org";
+static ADAPTER_VERSION: [u32;4] = [0, 0, 0, 0];
+
+/// How long we wait for a device before giving up on a request. Requests of polled channels
+/// run on the shared timer thread, so a device that never answers must not block it forever.
+const HTTP_TIMEOUT_SECS: u64 = 10;
+
+fn http_error<E>(err: E) -> Error where E: ::std::fmt::Display {
+    Error::InternalError(InternalError::GenericError(format!("HTTP error: {}", err)))
+}
+
+/// Perform an HTTP request, returning the body of the response.
+fn http_request(request: &RequestConfig, default_method: &str, body: Option<&str>) -> Result<String, Error> {
+    use hyper::header::{ Connection, Headers };
+    use hyper::method::Method;
+
+    let method = request.method.as_ref().map_or(default_method, |method| method as &str);
+    let method : Method = try!(method.parse().map_err(http_error));
+    let mut headers = Headers::new();
+    headers.set(Connection::close());
+    for (name, value) in &request.headers {
+        headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+    }
+
+    let mut client = hyper::Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_SECS)));
+    client.set_write_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_SECS)));
+    let mut builder = client.request(method, &request.url as &str).headers(headers);
+    if let Some(body) = body {
+        builder = builder.body(body);
+    }
+    let mut response = try!(builder.send().map_err(http_error));
+    let mut content = String::new();
+    try!(response.read_to_string(&mut content).map_err(http_error));
+    if !response.status.is_success() {
+        return Err(http_error(format!("{} returned {}", request.url, response.status)));
+    }
+    Ok(content)
+}
+
+/// A channel, along with everything needed to talk to the device.
+struct HttpChannel {
+    id: Id<Channel>,
+    config: ChannelConfig,
+    type_: Type,
+}
+
+impl HttpChannel {
+    fn fetch(&self) -> Result<Value, Error> {
+        let request = match self.config.fetch {
+            None => return Err(Error::OperationNotSupported(Operation::Fetch, self.id.clone())),
+            Some(ref request) => request
+        };
+        let response = try!(http_request(request, "GET", None));
+        let json : JSON = try!(serde_json::from_str(&response).map_err(|err| Error::ParseError(ParseError::json(err))));
+        let raw = match request.path {
+            None => &json,
+            Some(ref path) => match path.extract(&json) {
+                Some(raw) => raw,
+                None => return Err(Error::InternalError(InternalError::GenericError(
+                    format!("No value at {} in the response of {}", path.to_json().as_string().unwrap_or(""), request.url))))
+            }
+        };
+        self.to_value(raw)
+    }
+
+    fn send(&self, value: &Value) -> Result<(), Error> {
+        let request = match self.config.send {
+            None => return Err(Error::OperationNotSupported(Operation::Send, self.id.clone())),
+            Some(ref request) => request
+        };
+        let raw = try!(serde_json::to_string(&try!(self.to_raw(value))).map_err(http_error));
+        let body = request.body.as_ref()
+            .map_or(VALUE_PLACEHOLDER, |body| body as &str)
+            .replace(VALUE_PLACEHOLDER, &raw);
+        try!(http_request(request, "POST", Some(&body)));
+        Ok(())
+    }
+
+    /// Convert the representation used by the device into a `Value`.
+    fn to_value(&self, raw: &JSON) -> Result<Value, Error> {
+        let inner = match self.config.values.iter().find(|&(_, value)| value == raw) {
+            Some((name, _)) => JSON::String(name.clone()),
+            None => raw.clone()
+        };
+        let key = self.type_.to_json().as_string().unwrap_or("").to_owned();
+        let wrapped = JSON::Object(vec![(key, inner)].into_iter().collect());
+        let value = try!(Value::parse(Path::new(), &wrapped).map_err(Error::ParseError));
+        if value.get_type() != self.type_ {
+            return Err(Error::TypeError(TypeError { expected: self.type_.clone(), got: value.get_type() }));
+        }
+        Ok(value)
+    }
+
+    /// Convert a `Value` into the representation used by the device.
+    fn to_raw(&self, value: &Value) -> Result<JSON, Error> {
+        if value.get_type() != self.type_ {
+            return Err(Error::TypeError(TypeError { expected: self.type_.clone(), got: value.get_type() }));
+        }
+        let inner = match value.to_json() {
+            JSON::Object(object) => object.into_iter().next().map_or(JSON::Null, |(_, inner)| inner),
+            other => other
+        };
+        if let JSON::String(ref name) = inner {
+            if let Some(raw) = self.config.values.get(name) {
+                return Ok(raw.clone());
+            }
+        }
+        Ok(inner)
+    }
+
+    /// Fetch the value and notify the watcher if needed.
+    fn poll(&self, range: &Option<Range>, last: &mut Option<Value>, tx: &ExtSender<WatchEvent<Value>>) {
+        let value = match self.fetch() {
+            Ok(value) => value,
+            Err(err) => {
+                debug!("[http-json] Could not poll {}: {}", self.id, err);
+                return;
+            }
+        };
+        let event = watch_event(&self.id, range, last.as_ref(), &value);
+        *last = Some(value);
+        if let Some(event) = event {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+/// A guard used to stop polling a channel.
+struct Guard(timer::Guard);
+impl AdapterWatchGuard for Guard {
+}
+
+/// `HttpJsonAdapter` exposes devices described in a configuration file.
+pub struct HttpJsonAdapter {
+    channels: HashMap<Id<Channel>, Arc<HttpChannel>>,
+
+    /// Timer used to schedule the polls of the channels being watched. The polls themselves
+    /// run on their own threads, so that a slow device doesn't delay the other channels.
+    timer: Mutex<timer::Timer>,
+}
+
+impl HttpJsonAdapter {
+    pub fn id() -> Id<AdapterId> {
+        Id::new("http-json@link.mozilla.org")
+    }
+
+    /// Register the devices described in the configuration file at `config_path`.
+    ///
+    /// If the file does not exist, the adapter is not registered.
+    pub fn init(adapt: &Arc<AdapterManager>, config_path: &str) -> Result<(), Error> {
+        match try!(read_config::<Config>(config_path)) {
+            None => {
+                debug!("[http-json] No configuration at {}, not starting", config_path);
+                Ok(())
+            }
+            Some(config) => Self::init_with_config(adapt, config)
+        }
+    }
+
+    /// Register the devices described in `config`.
+    pub fn init_with_config(adapt: &Arc<AdapterManager>, config: Config) -> Result<(), Error> {
+        let adapter_id = Self::id();
+        let mut services = vec![];
+        let mut channels = HashMap::new();
+        for service_config in config.services {
+            let service_id = Id::<ServiceId>::new(&format!("http-json/{}", service_config.id));
+            let mut service = Service::empty(&service_id, &adapter_id);
+            service.properties = service_config.properties;
+            let mut service_channels = vec![];
+            for channel_config in service_config.channels {
+                let channel = Channel {
+                    kind: channel_config.kind.clone(),
+                    supports_fetch: channel_config.fetch.is_some(),
+                    supports_send: channel_config.send.is_some(),
+                    supports_watch: channel_config.poll.is_some(),
+                    .. Channel::empty(&Id::new(&format!("http-json/{}/{}", service_config.id, channel_config.id)),
+                                      &service_id, &adapter_id)
+                };
+                channels.insert(channel.id.clone(), Arc::new(HttpChannel {
+                    id: channel.id.clone(),
+                    type_: channel_config.kind.get_type(),
+                    config: channel_config,
+                }));
+                service_channels.push(channel);
+            }
+            services.push((service, service_channels));
+        }
+
+        try!(adapt.add_adapter(Arc::new(HttpJsonAdapter {
+            channels: channels,
+            timer: Mutex::new(timer::Timer::new()),
+        })));
+        for (service, mut service_channels) in services {
+            try!(adapt.add_service(service));
+            for channel in service_channels.drain(..) {
+                try!(adapt.add_channel(channel));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_channel(&self, id: &Id<Channel>, operation: Operation) -> Result<&Arc<HttpChannel>, Error> {
+        self.channels.get(id).ok_or_else(|| Error::OperationNotSupported(operation, id.clone()))
+    }
+}
+
+impl Adapter for HttpJsonAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        Self::id()
+    }
+
+    fn name(&self) -> &str {
+        ADAPTER_NAME
+    }
+
+    fn vendor(&self) -> &str {
+        ADAPTER_VENDOR
+    }
+
+    fn version(&self) -> &[u32;4] {
+        &ADAPTER_VERSION
+    }
+
+    fn fetch_values(&self, mut set: Vec<Id<Channel>>, _: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+        set.drain(..).map(|id| {
+            let result = self.get_channel(&id, Operation::Fetch)
+                .and_then(|channel| channel.fetch())
+                .map(Some);
+            (id, result)
+        }).collect()
+    }
+
+    fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, _: User) -> ResultMap<Id<Channel>, (), Error> {
+        values.drain().map(|(id, value)| {
+            let result = self.get_channel(&id, Operation::Send)
+                .and_then(|channel| channel.send(&value));
+            (id, result)
+        }).collect()
+    }
+
+    fn register_watch(&self, mut watch: Vec<WatchTarget>) -> WatchResult {
+        watch.drain(..).map(|(id, filter, tx)| {
+            let channel = match self.channels.get(&id) {
+                Some(channel) if channel.config.poll.is_some() => channel.clone(),
+                _ => return (id.clone(), Err(Error::OperationNotSupported(Operation::Watch, id)))
+            };
+            let range = match filter {
+                None => None,
+                Some(Value::Range(range)) => Some(*range),
+                Some(other) => return (id, Err(Error::InvalidValue(other)))
+            };
+            let interval = chrono::Duration::milliseconds((channel.config.poll.unwrap_or(0.) * 1000.) as i64);
+            // The latest value and the watcher, locked while a poll is in progress.
+            let state = Arc::new(Mutex::new((None, tx)));
+            let guard = self.timer.lock().unwrap().schedule_repeating(interval, move || {
+                let channel = channel.clone();
+                let range = range.clone();
+                let state = state.clone();
+                thread::spawn(move || {
+                    // If the previous poll is still waiting for the device, skip this one.
+                    if let Ok(mut state) = state.try_lock() {
+                        let state = &mut *state;
+                        channel.poll(&range, &mut state.0, &*state.1);
+                    }
+                });
+            });
+            (id, Ok(Box::new(Guard(guard)) as Box<AdapterWatchGuard>))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+describe! http_json {
+    before_each {
+        use foxbox_taxonomy::api::{ API, Targetted, WatchEvent as APIWatchEvent };
+        use foxbox_taxonomy::io::Payload;
+        use foxbox_taxonomy::selector::*;
+        use foxbox_taxonomy::values::OpenClosed;
+        use hyper::server::{ Handler, Request, Response, Server };
+
+        /// A device storing whether a door is open, and reporting it as `{"door": {"open": bool}}`.
+        struct MockDevice(Arc<Mutex<bool>>);
+        impl Handler for MockDevice {
+            fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, res: Response<'a>) {
+                let mut body = String::new();
+                req.read_to_string(&mut body).unwrap();
+                let mut open = self.0.lock().unwrap();
+                if req.method == hyper::method::Method::Post {
+                    let json : JSON = serde_json::from_str(&body).unwrap();
+                    *open = json.find("open").unwrap().as_boolean().unwrap();
+                }
+                res.send(format!(r#"{{"door": {{"open": {}}}}}"#, *open).as_bytes()).unwrap();
+            }
+        }
+
+        let open = Arc::new(Mutex::new(false));
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle(MockDevice(open.clone())).unwrap();
+        let url = format!("http://{}/door", listening.socket);
+        let config = Config::from_str(&r#"{
+            "services": [{
+                "id": "garage",
+                "properties": {"name": "Garage door"},
+                "channels": [{
+                    "id": "door",
+                    "kind": "OpenClosed",
+                    "fetch": {"url": "URL", "path": "$.door.open"},
+                    "send": {"url": "URL", "body": "{\"open\": {{value}}}"},
+                    "poll": 0.05,
+                    "values": {"Open": true, "Closed": false}
+                }]
+            }]
+        }"#.replace("URL", &url)).unwrap();
+
+        let manager = Arc::new(AdapterManager::new(None));
+        HttpJsonAdapter::init_with_config(&manager, config).unwrap();
+        let id = Id::<Channel>::new("http-json/garage/door");
+        let selector = vec![ChannelSelector::new().with_id(id.clone())];
+    }
+
+    after_each {
+        listening.close().unwrap();
+    }
+
+    it "should register the services and channels of the configuration" {
+        let services = manager.get_services(vec![ServiceSelector::new().with_id(Id::new("http-json/garage"))]);
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].properties.get("name").unwrap(), "Garage door");
+        let channel = services[0].channels.get(&id).unwrap();
+        assert_eq!(channel.kind, ChannelKind::OpenClosed);
+        assert!(channel.supports_fetch && channel.supports_send && channel.supports_watch);
+    }
+
+    it "should fetch and send values through HTTP" {
+        let fetched = manager.fetch_values(selector.clone(), None, User::None);
+        match fetched.get(&id) {
+            Some(&Ok(Some(ref fetched))) =>
+                assert_eq!(fetched.value.to_value(&Type::OpenClosed).unwrap(), Value::OpenClosed(OpenClosed::Closed)),
+            other => panic!("Unexpected fetch result {:?}", other)
+        }
+
+        let payload = Payload::from_value(&Value::OpenClosed(OpenClosed::Open), &Type::OpenClosed).unwrap();
+        let sent = manager.send_values(vec![Targetted::new(selector.clone(), payload)], User::None);
+        match sent.get(&id) {
+            Some(&Ok(())) => {},
+            other => panic!("Unexpected send result {:?}", other)
+        }
+        assert!(*open.lock().unwrap());
+    }
+
+    it "should watch values by polling the device" {
+        let (tx, rx) = channel();
+        let _guard = manager.watch_values(vec![Targetted::new(selector.clone(), Exactly::Always)], Box::new(tx), User::None);
+        *open.lock().unwrap() = true;
+        loop {
+            match rx.recv().unwrap() {
+                APIWatchEvent::EnterRange { channel, value, .. } => {
+                    assert_eq!(channel, id);
+                    assert_eq!(value.to_value(&Type::OpenClosed).unwrap(), Value::OpenClosed(OpenClosed::Open));
+                    break;
+                }
+                APIWatchEvent::ChannelAdded(_) => continue,
+                other => panic!("Unexpected event {:?}", other)
+            }
+        }
+    }
+
+    it "should convert between device and taxonomy values" {
+        let channel = HttpChannel {
+            id: id.clone(),
+            config: Config::from_str(r#"{"services": [{"id": "s", "channels": [{
+                "id": "c", "kind": "LightOn", "values": {"On": 1, "Off": 0}
+            }]}]}"#).unwrap().services[0].channels[0].clone(),
+            type_: Type::OnOff,
+        };
+        use foxbox_taxonomy::values::OnOff;
+        assert_eq!(channel.to_value(&JSON::U64(1)).unwrap(), Value::OnOff(OnOff::On));
+        assert_eq!(channel.to_value(&JSON::String("Off".to_owned())).unwrap(), Value::OnOff(OnOff::Off));
+        assert!(channel.to_value(&JSON::U64(2)).is_err());
+        assert_eq!(channel.to_raw(&Value::OnOff(OnOff::Off)).unwrap(), JSON::U64(0));
+        assert!(channel.to_raw(&Value::OpenClosed(OpenClosed::Open)).is_err());
+    }
+}
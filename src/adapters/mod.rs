@@ -8,6 +8,12 @@ pub mod clock;
 /// An adapter displaying messages on the console.
 pub mod console;
 
+/// An adapter grouping channels of other adapters into virtual services.
+mod groups;
+
+/// A generic adapter for devices exposing a simple HTTP/JSON API.
+mod http_json;
+
 /// A Text To Speak adapter
 #[cfg(target_os = "linux")]
 pub mod tts;
@@ -15,16 +21,26 @@ pub mod tts;
 /// An adapter providing access to IP cameras.
 mod ip_camera;
 
+/// An adapter bridging devices connected to a MQTT broker.
+mod mqtt;
+
 /// An adapter dedicated to the Philips Hue
 mod philips_hue;
 
+/// An adapter capturing and restoring the state of channels as scenes.
+mod scenes;
+
 /// An adapter providing access to Thinkerbell.
 mod thinkerbell;
 
+/// Helpers shared by several adapters.
+mod utils;
+
 /// An adapter providing `WebPush` services.
 pub mod webpush;
 
 use foxbox_taxonomy::manager::AdapterManager as TaxoManager;
+use foxbox_thinkerbell::schedule::Location;
 
 use self::thinkerbell::ThinkerbellAdapter;
 use traits::Controller;
@@ -64,7 +80,23 @@ impl<T: Controller> AdapterManager<T> {
         webpush::WebPush::init(c, manager).unwrap();
         ip_camera::IPCameraAdapter::init(manager, self.controller.clone()).unwrap();
         let scripts_path = &self.controller.get_profile().path_for("thinkerbell_scripts.sqlite");
-        ThinkerbellAdapter::init(manager, scripts_path).unwrap(); // FIXME: no unwrap!
+        ThinkerbellAdapter::init(manager, scripts_path, self.get_location()).unwrap(); // FIXME: no unwrap!
+        let groups_path = &self.controller.get_profile().path_for("groups.sqlite");
+        if let Err(err) = groups::GroupAdapter::init(manager, groups_path) {
+            error!("Could not start the groups adapter: {}", err);
+        }
+        let scenes_path = &self.controller.get_profile().path_for("scenes.sqlite");
+        if let Err(err) = scenes::SceneAdapter::init(manager, scenes_path) {
+            error!("Could not start the scenes adapter: {}", err);
+        }
+        let http_devices_path = &self.controller.get_profile().path_for("http_devices.json");
+        if let Err(err) = http_json::HttpJsonAdapter::init(manager, http_devices_path) {
+            error!("Could not start the generic HTTP adapter: {}", err);
+        }
+        let mqtt_path = &self.controller.get_profile().path_for("mqtt.json");
+        if let Err(err) = mqtt::MqttAdapter::init(manager, mqtt_path) {
+            error!("Could not start the MQTT adapter: {}", err);
+        }
         let profile_openzwave = &self.controller.get_profile().path_for("openzwave");
         let openzwave_device = self.controller.clone().get_config().get("openzwave", "device");
         OpenzwaveAdapter::init(manager, profile_openzwave, openzwave_device).unwrap();
@@ -72,6 +104,21 @@ impl<T: Controller> AdapterManager<T> {
         self.start_tts(manager);
     }
 
+    /// The location of the box, as configured in `thinkerbell/latitude` and `thinkerbell/longitude`.
+    fn get_location(&self) -> Option<Location> {
+        let config = self.controller.get_config();
+        let coordinate = |property: &str| {
+            config.get("thinkerbell", property).and_then(|value| value.parse::<f64>().ok())
+        };
+        match (coordinate("latitude"), coordinate("longitude")) {
+            (Some(latitude), Some(longitude)) => Some(Location {
+                latitude: latitude,
+                longitude: longitude,
+            }),
+            _ => None
+        }
+    }
+
     /// Stop all the adapters.
     pub fn stop(&self) {
     }
@@ -0,0 +1,496 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A minimal MQTT 3.1.1 client, sufficient to talk to a broker on the local network.
+//!
+//! Only QoS 0 is supported: messages are published at most once and subscriptions are
+//! requested with QoS 0, so the client never needs to store messages for retransmission.
+//!
+//! The client connects from a dedicated thread, and reconnects whenever the connection is
+//! lost, so a broker that is down or restarting never blocks the box.
+
+use std::cmp;
+use std::io::{ self, Read, Write };
+use std::net::{ Shutdown, TcpStream };
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use std::time::Duration;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// The largest length that can be encoded in the header of a packet.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// How long we wait before connecting again, after the first failure. The delay doubles
+/// with each consecutive failure, up to `MAX_RECONNECT_DELAY_SECS`.
+const MIN_RECONNECT_DELAY_SECS: u64 = 1;
+const MAX_RECONNECT_DELAY_SECS: u64 = 60;
+
+fn invalid_data<E>(err: E) -> io::Error where E: Into<Box<::std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Connect { client_id: String, keep_alive: u16 },
+    ConnAck { return_code: u8 },
+    Publish { topic: String, payload: Vec<u8>, retain: bool },
+    Subscribe { packet_id: u16, topics: Vec<String> },
+    SubAck { packet_id: u16 },
+    Unsubscribe { packet_id: u16, topics: Vec<String> },
+    UnsubAck { packet_id: u16 },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+fn push_string(buf: &mut Vec<u8>, value: &str) {
+    push_u16(buf, value.len() as u16);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the variable header and payload of a packet.
+struct Body<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn u8(&mut self) -> io::Result<u8> {
+        match self.bytes.get(self.pos) {
+            Some(&byte) => {
+                self.pos += 1;
+                Ok(byte)
+            }
+            None => Err(invalid_data("Truncated packet"))
+        }
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let high = try!(self.u8()) as u16;
+        let low = try!(self.u8()) as u16;
+        Ok(high << 8 | low)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = try!(self.u16()) as usize;
+        if self.pos + len > self.bytes.len() {
+            return Err(invalid_data("Truncated packet"));
+        }
+        let string = try!(String::from_utf8(self.bytes[self.pos .. self.pos + len].to_vec()).map_err(invalid_data));
+        self.pos += len;
+        Ok(string)
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        let rest = self.bytes[self.pos..].to_vec();
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        use self::Packet::*;
+        let mut body = vec![];
+        let header = match *self {
+            Connect { ref client_id, keep_alive } => {
+                push_string(&mut body, "MQTT");
+                body.push(4); // Protocol level 3.1.1.
+                body.push(0x02); // Clean session, no will, no credentials.
+                push_u16(&mut body, keep_alive);
+                push_string(&mut body, client_id);
+                CONNECT << 4
+            }
+            ConnAck { return_code } => {
+                body.push(0);
+                body.push(return_code);
+                CONNACK << 4
+            }
+            Publish { ref topic, ref payload, retain } => {
+                push_string(&mut body, topic);
+                body.extend_from_slice(payload);
+                let flags = if retain { 0x01 } else { 0 };
+                PUBLISH << 4 | flags
+            }
+            Subscribe { packet_id, ref topics } => {
+                push_u16(&mut body, packet_id);
+                for topic in topics {
+                    push_string(&mut body, topic);
+                    body.push(0); // QoS 0.
+                }
+                SUBSCRIBE << 4 | 0x02
+            }
+            SubAck { packet_id } => {
+                push_u16(&mut body, packet_id);
+                body.push(0);
+                SUBACK << 4
+            }
+            Unsubscribe { packet_id, ref topics } => {
+                push_u16(&mut body, packet_id);
+                for topic in topics {
+                    push_string(&mut body, topic);
+                }
+                UNSUBSCRIBE << 4 | 0x02
+            }
+            UnsubAck { packet_id } => {
+                push_u16(&mut body, packet_id);
+                UNSUBACK << 4
+            }
+            PingReq => PINGREQ << 4,
+            PingResp => PINGRESP << 4,
+            Disconnect => DISCONNECT << 4,
+        };
+
+        let mut packet = vec![header];
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                packet.push(byte | 0x80);
+            } else {
+                packet.push(byte);
+                break;
+            }
+        }
+        packet.extend(body);
+        packet
+    }
+
+    /// Read a single packet.
+    pub fn read<R>(reader: &mut R) -> io::Result<Packet> where R: Read {
+        let mut byte = [0; 1];
+        try!(reader.read_exact(&mut byte));
+        let header = byte[0];
+
+        let mut len = 0;
+        let mut multiplier = 1;
+        loop {
+            try!(reader.read_exact(&mut byte));
+            len += (byte[0] & 0x7f) as usize * multiplier;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            multiplier *= 128;
+            if len > MAX_REMAINING_LENGTH || multiplier > 128 * 128 * 128 {
+                return Err(invalid_data("Invalid packet length"));
+            }
+        }
+
+        let mut bytes = vec![0; len];
+        try!(reader.read_exact(&mut bytes));
+        Self::decode(header, &bytes)
+    }
+
+    fn decode(header: u8, bytes: &[u8]) -> io::Result<Packet> {
+        use self::Packet::*;
+        let mut body = Body {
+            bytes: bytes,
+            pos: 0,
+        };
+        let packet = match header >> 4 {
+            CONNECT => {
+                if try!(body.string()) != "MQTT" {
+                    return Err(invalid_data("Unsupported protocol"));
+                }
+                let _level = try!(body.u8());
+                let _flags = try!(body.u8());
+                let keep_alive = try!(body.u16());
+                Connect {
+                    keep_alive: keep_alive,
+                    client_id: try!(body.string()),
+                }
+            }
+            CONNACK => {
+                let _flags = try!(body.u8());
+                ConnAck {
+                    return_code: try!(body.u8()),
+                }
+            }
+            PUBLISH => {
+                let topic = try!(body.string());
+                if (header >> 1) & 0x03 != 0 {
+                    // We only subscribe with QoS 0, but brokers may still send the
+                    // packet identifier of messages published with a higher QoS.
+                    let _packet_id = try!(body.u16());
+                }
+                Publish {
+                    topic: topic,
+                    payload: body.rest(),
+                    retain: header & 0x01 != 0,
+                }
+            }
+            SUBSCRIBE | UNSUBSCRIBE => {
+                let packet_id = try!(body.u16());
+                let mut topics = vec![];
+                while !body.is_empty() {
+                    topics.push(try!(body.string()));
+                    if header >> 4 == SUBSCRIBE {
+                        let _qos = try!(body.u8());
+                    }
+                }
+                if header >> 4 == SUBSCRIBE {
+                    Subscribe { packet_id: packet_id, topics: topics }
+                } else {
+                    Unsubscribe { packet_id: packet_id, topics: topics }
+                }
+            }
+            SUBACK => SubAck { packet_id: try!(body.u16()) },
+            UNSUBACK => UnsubAck { packet_id: try!(body.u16()) },
+            PINGREQ => PingReq,
+            PINGRESP => PingResp,
+            DISCONNECT => Disconnect,
+            kind => return Err(invalid_data(format!("Unsupported packet type {}", kind)))
+        };
+        Ok(packet)
+    }
+}
+
+/// A connection to a MQTT broker.
+pub struct Client {
+    /// The stream to the broker, if we are currently connected.
+    stream: Mutex<Option<TcpStream>>,
+    next_packet_id: AtomicUsize,
+}
+
+impl Client {
+    /// Start connecting to `broker` (`host:port`) and subscribing to `topics`, from a
+    /// dedicated thread. This never blocks.
+    ///
+    /// Whenever the connection fails or is lost, the client connects and subscribes again,
+    /// waiting longer after each consecutive failure. Messages received on `topics` are passed
+    /// to `on_message`, from the same thread. The thread stops once the client is dropped.
+    pub fn start<F>(broker: String, client_id: String, keep_alive: u16, topics: Vec<String>, on_message: F) -> Arc<Client>
+        where F: Fn(&str, &[u8]) + Send + 'static
+    {
+        let client = Arc::new(Client {
+            stream: Mutex::new(None),
+            next_packet_id: AtomicUsize::new(0),
+        });
+        let weak = Arc::downgrade(&client);
+        thread::spawn(move || {
+            let mut delay = MIN_RECONNECT_DELAY_SECS;
+            loop {
+                // Only hold the client while connecting, so that it may be dropped while
+                // we are listening.
+                let connection = match weak.upgrade() {
+                    Some(client) => client.connect(&broker, &client_id, keep_alive, &topics),
+                    None => break
+                };
+                match connection {
+                    Ok(mut reader) => {
+                        info!("[mqtt] Connected to broker {}", broker);
+                        delay = MIN_RECONNECT_DELAY_SECS;
+                        let err = Self::listen(&mut reader, &on_message);
+                        error!("[mqtt] Lost connection to broker {}: {}", broker, err);
+                    }
+                    Err(err) => {
+                        warn!("[mqtt] Could not connect to broker {}: {}", broker, err);
+                    }
+                }
+                match weak.upgrade() {
+                    Some(client) => *client.stream.lock().unwrap() = None,
+                    None => break
+                }
+
+                debug!("[mqtt] Reconnecting to broker {} in {} seconds", broker, delay);
+                thread::sleep(Duration::from_secs(delay));
+                delay = cmp::min(delay * 2, MAX_RECONNECT_DELAY_SECS);
+            }
+        });
+        client
+    }
+
+    /// Connect to the broker and subscribe to `topics`, returning the stream from which to
+    /// read incoming packets.
+    fn connect(&self, broker: &str, client_id: &str, keep_alive: u16, topics: &[String]) -> io::Result<TcpStream> {
+        let mut stream = try!(TcpStream::connect(broker));
+        if keep_alive > 0 {
+            // We ping the broker twice per `keep_alive` interval and it answers each ping, so
+            // hearing nothing for a whole interval means that the connection is dead.
+            try!(stream.set_read_timeout(Some(Duration::from_secs(keep_alive as u64))));
+        }
+        try!(stream.write_all(&Packet::Connect {
+            client_id: client_id.to_owned(),
+            keep_alive: keep_alive,
+        }.encode()));
+        match try!(Packet::read(&mut stream)) {
+            Packet::ConnAck { return_code: 0 } => {},
+            Packet::ConnAck { return_code } =>
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+                    format!("The broker refused the connection (return code {})", return_code))),
+            other => return Err(invalid_data(format!("Expected CONNACK, got {:?}", other)))
+        }
+
+        let reader = try!(stream.try_clone());
+        *self.stream.lock().unwrap() = Some(stream);
+        if !topics.is_empty() {
+            try!(self.subscribe(topics.to_vec()));
+        }
+        Ok(reader)
+    }
+
+    /// Dispatch incoming messages until the connection is lost.
+    fn listen<F>(reader: &mut TcpStream, on_message: &F) -> io::Error where F: Fn(&str, &[u8]) {
+        loop {
+            match Packet::read(reader) {
+                Ok(Packet::Publish { topic, payload, .. }) => on_message(&topic, &payload),
+                Ok(_) => {}, // Acknowledgements.
+                Err(err) => return err
+            }
+        }
+    }
+
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        self.send(Packet::Publish {
+            topic: topic.to_owned(),
+            payload: payload.to_vec(),
+            retain: retain,
+        })
+    }
+
+    fn subscribe(&self, topics: Vec<String>) -> io::Result<()> {
+        let packet_id = self.packet_id();
+        self.send(Packet::Subscribe {
+            packet_id: packet_id,
+            topics: topics,
+        })
+    }
+
+    /// Let the broker know that the client is still alive. Must be called at least once
+    /// per `keep_alive` interval. Does nothing while disconnected.
+    pub fn ping(&self) -> io::Result<()> {
+        if self.stream.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        self.send(Packet::PingReq)
+    }
+
+    fn send(&self, packet: Packet) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap();
+        match *stream {
+            Some(ref mut stream) => stream.write_all(&packet.encode()),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected to the broker"))
+        }
+    }
+
+    /// A non-zero packet identifier.
+    fn packet_id(&self) -> u16 {
+        (self.next_packet_id.fetch_add(1, Ordering::SeqCst) % 0xffff + 1) as u16
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Closing the socket also stops the thread listening on it.
+        if let Some(ref stream) = *self.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+describe! packets {
+    it "should encode and decode packets" {
+        use std::io::Cursor;
+
+        let packets = vec![
+            Packet::Connect { client_id: "foxbox".to_owned(), keep_alive: 60 },
+            Packet::ConnAck { return_code: 0 },
+            Packet::Publish { topic: "kitchen/light".to_owned(), payload: b"ON".to_vec(), retain: true },
+            Packet::Publish { topic: "big".to_owned(), payload: vec![7; 1000], retain: false },
+            Packet::Subscribe { packet_id: 1, topics: vec!["a/b".to_owned(), "c".to_owned()] },
+            Packet::SubAck { packet_id: 1 },
+            Packet::Unsubscribe { packet_id: 2, topics: vec!["a/b".to_owned()] },
+            Packet::UnsubAck { packet_id: 2 },
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect,
+        ];
+        for packet in packets {
+            let decoded = Packet::read(&mut Cursor::new(packet.encode())).unwrap();
+            assert_eq!(decoded, packet);
+        }
+    }
+
+    it "should match the wire format" {
+        let publish = Packet::Publish { topic: "a/b".to_owned(), payload: b"1".to_vec(), retain: true };
+        assert_eq!(publish.encode(), vec![0x31, 6, 0, 3, b'a', b'/', b'b', b'1']);
+        assert_eq!(Packet::PingReq.encode(), vec![0xc0, 0]);
+    }
+
+    it "should reject truncated packets" {
+        use std::io::Cursor;
+
+        assert!(Packet::read(&mut Cursor::new(vec![0x30, 4, 0, 3, b'a'])).is_err());
+        assert!(Packet::read(&mut Cursor::new(vec![0x30, 2, 0, 3])).is_err());
+    }
+}
+
+#[cfg(test)]
+describe! client {
+    it "should reconnect and subscribe again after losing the connection" {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker = listener.local_addr().unwrap().to_string();
+        let _client = Client::start(broker, "foxbox".to_owned(), 60, vec!["a/b".to_owned()], |_, _| {});
+
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            match Packet::read(&mut stream).unwrap() {
+                Packet::Connect { ref client_id, .. } if client_id == "foxbox" => {},
+                other => panic!("Unexpected packet {:?}", other)
+            }
+            stream.write_all(&Packet::ConnAck { return_code: 0 }.encode()).unwrap();
+            match Packet::read(&mut stream).unwrap() {
+                Packet::Subscribe { ref topics, .. } if *topics == vec!["a/b".to_owned()] => {},
+                other => panic!("Unexpected packet {:?}", other)
+            }
+            // Dropping the stream closes the connection.
+        }
+    }
+
+    it "should disconnect once dropped" {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker = listener.local_addr().unwrap().to_string();
+        let client = Client::start(broker, "foxbox".to_owned(), 60, vec![], |_, _| {});
+
+        let (mut stream, _) = listener.accept().unwrap();
+        match Packet::read(&mut stream).unwrap() {
+            Packet::Connect { .. } => {},
+            other => panic!("Unexpected packet {:?}", other)
+        }
+        stream.write_all(&Packet::ConnAck { return_code: 0 }.encode()).unwrap();
+
+        // Wait until the client is listening.
+        while client.stream.lock().unwrap().is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(client);
+        assert!(Packet::read(&mut stream).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The configuration of the MQTT adapter, as read from the profile.
+
+use adapters::http_json::json_path::JsonPath;
+
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::services::ChannelKind;
+
+use std::collections::HashMap;
+
+/// A channel mapped to MQTT topics.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `id` (string, required): unique within the service;
+/// - `kind` (`ChannelKind`, required): determines the type of values;
+/// - `topic` (string, optional): the topic on which the device publishes its state.
+///   If specified, the channel supports fetch (returning the latest message) and watch;
+/// - `command_topic` (string, optional): the topic on which values are published when
+///   they are sent to the channel;
+/// - `retain` (bool, optional): whether values sent to the channel should be retained by
+///   the broker. Defaults to `false`;
+/// - `path` (`JSONPath`, optional): where to find the value in JSON messages, e.g.
+///   `$.temperature`. Defaults to the entire message;
+/// - `values` (object, optional): how the device represents values, e.g.
+///   `{"On": "ON", "Off": "OFF"}`.
+///
+/// At least one of `topic` and `command_topic` is required. Topics are matched exactly, so
+/// they may not contain the wildcards `+` and `#`.
+#[derive(Clone, Debug)]
+pub struct ChannelConfig {
+    pub id: String,
+    pub kind: ChannelKind,
+    pub topic: Option<String>,
+    pub command_topic: Option<String>,
+    pub retain: bool,
+    pub path: Option<JsonPath>,
+    pub values: HashMap<String, JSON>,
+}
+
+impl Parser<ChannelConfig> for ChannelConfig {
+    fn description() -> String {
+        "ChannelConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let id = try!(path.push("id", |path| String::take(path, source, "id")));
+        let kind = try!(path.push("kind", |path| ChannelKind::take(path, source, "kind")));
+        let topic = match path.push("topic", |path| String::take_opt(path, source, "topic")) {
+            Some(Ok(topic)) => Some(topic),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let command_topic = match path.push("command_topic", |path| String::take_opt(path, source, "command_topic")) {
+            Some(Ok(command_topic)) => Some(command_topic),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        if topic.is_none() && command_topic.is_none() {
+            return Err(ParseError::missing_field("topic", &path));
+        }
+        for (name, topic) in vec![("topic", &topic), ("command_topic", &command_topic)] {
+            if let Some(ref topic) = *topic {
+                if topic.contains('+') || topic.contains('#') {
+                    return Err(ParseError::type_error(name, &path, "topic without wildcards"));
+                }
+            }
+        }
+        let retain = match path.push("retain", |path| bool::take_opt(path, source, "retain")) {
+            Some(Ok(retain)) => retain,
+            Some(Err(err)) => return Err(err),
+            None => false
+        };
+        let json_path = match path.push("path", |path| JsonPath::take_opt(path, source, "path")) {
+            Some(Ok(json_path)) => Some(json_path),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let values = match source.find("values") {
+            None => HashMap::new(),
+            Some(&JSON::Object(ref values)) => values.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            Some(_) => return Err(ParseError::type_error("values", &path, "object"))
+        };
+        Ok(ChannelConfig {
+            id: id,
+            kind: kind,
+            topic: topic,
+            command_topic: command_topic,
+            retain: retain,
+            path: json_path,
+            values: values,
+        })
+    }
+}
+
+/// A device, exposed as a service.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `id` (string, required): unique among the devices of the adapter;
+/// - `properties` (object of strings, optional): the properties of the service;
+/// - `channels` (array of `ChannelConfig`, required).
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    pub id: String,
+    pub properties: HashMap<String, String>,
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl Parser<ServiceConfig> for ServiceConfig {
+    fn description() -> String {
+        "ServiceConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let id = try!(path.push("id", |path| String::take(path, source, "id")));
+        let properties = match path.push("properties", |path| HashMap::<String, String>::take_opt(path, source, "properties")) {
+            Some(Ok(properties)) => properties,
+            Some(Err(err)) => return Err(err),
+            None => HashMap::new()
+        };
+        let channels = try!(path.push("channels", |path| ChannelConfig::take_vec(path, source, "channels")));
+        Ok(ServiceConfig {
+            id: id,
+            properties: properties,
+            channels: channels,
+        })
+    }
+}
+
+/// The configuration of the adapter.
+///
+/// # JSON
+///
+/// An object with fields:
+///
+/// - `broker` (string, required): the address of the broker, as `host:port`;
+/// - `client_id` (string, optional): defaults to `foxbox`;
+/// - `keep_alive` (number of seconds, optional): defaults to 60;
+/// - `publish_prefix` (string, optional): if specified, the values of all the channels of
+///   the box are published under this topic, e.g. `foxbox`;
+/// - `services` (array of `ServiceConfig`, optional).
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub broker: String,
+    pub client_id: String,
+    pub keep_alive: u16,
+    pub publish_prefix: Option<String>,
+    pub services: Vec<ServiceConfig>,
+}
+
+impl Parser<Config> for Config {
+    fn description() -> String {
+        "MqttConfig".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let broker = try!(path.push("broker", |path| String::take(path, source, "broker")));
+        let client_id = match path.push("client_id", |path| String::take_opt(path, source, "client_id")) {
+            Some(Ok(client_id)) => client_id,
+            Some(Err(err)) => return Err(err),
+            None => "foxbox".to_owned()
+        };
+        let keep_alive = match path.push("keep_alive", |path| u32::take_opt(path, source, "keep_alive")) {
+            Some(Ok(keep_alive)) if keep_alive > 0 && keep_alive <= 0xffff => keep_alive as u16,
+            Some(Ok(_)) => return Err(ParseError::type_error("keep_alive", &path, "number between 1 and 65535")),
+            Some(Err(err)) => return Err(err),
+            None => 60
+        };
+        let publish_prefix = match path.push("publish_prefix", |path| String::take_opt(path, source, "publish_prefix")) {
+            Some(Ok(prefix)) => Some(prefix),
+            Some(Err(err)) => return Err(err),
+            None => None
+        };
+        let services = match path.push("services", |path| ServiceConfig::take_vec_opt(path, source, "services")) {
+            Some(Ok(services)) => services,
+            Some(Err(err)) => return Err(err),
+            None => vec![]
+        };
+        Ok(Config {
+            broker: broker,
+            client_id: client_id,
+            keep_alive: keep_alive,
+            publish_prefix: publish_prefix,
+            services: services,
+        })
+    }
+}
+
+#[cfg(test)]
+describe! config {
+    it "should reject topics with wildcards" {
+        for topic in vec!["bedroom/+", "bedroom/#", "+/light"] {
+            for field in vec!["topic", "command_topic"] {
+                let source = format!(r#"{{"id": "light", "kind": "LightOn", "{}": "{}"}}"#, field, topic);
+                assert!(ChannelConfig::from_str(&source).is_err(), "{} {}", field, topic);
+            }
+        }
+        assert!(ChannelConfig::from_str(r#"{"id": "light", "kind": "LightOn", "topic": "bedroom/light"}"#).is_ok());
+    }
+}
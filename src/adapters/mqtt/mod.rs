@@ -0,0 +1,616 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An adapter bridging FoxBox and a MQTT broker.
+//!
+//! The adapter reads `mqtt.json` in the profile, e.g.
+//!
+//! ```json
+//! {
+//!   "broker": "192.168.1.2:1883",
+//!   "publish_prefix": "foxbox",
+//!   "services": [{
+//!     "id": "bedroom",
+//!     "properties": {"name": "Bedroom sensor"},
+//!     "channels": [{
+//!       "id": "light",
+//!       "kind": "LightOn",
+//!       "topic": "bedroom/light",
+//!       "command_topic": "bedroom/light/set",
+//!       "values": {"On": "ON", "Off": "OFF"}
+//!     }]
+//!   }]
+//! }
+//! ```
+//!
+//! Each device is exposed as a service `mqtt/<service id>`, with one channel
+//! `mqtt/<service id>/<channel id>` per channel. The adapter subscribes to the `topic` of
+//! each channel: fetching returns the latest message received on this topic, and watchers
+//! are notified of each new message. Sending a value publishes it on the `command_topic`.
+//!
+//! The adapter connects to the broker in the background and reconnects whenever the
+//! connection is lost. Sending values fails while disconnected.
+//!
+//! If `publish_prefix` is specified, the values of all the other channels of the box that
+//! anonymous users may watch are published as they change, as retained messages on `<prefix>/<channel id>`, so that other
+//! home automation tools can use the devices known to FoxBox.
+
+mod client;
+mod config;
+
+use self::client::Client;
+use self::config::{ ChannelConfig, Config };
+
+use adapters::utils::read_config;
+
+use foxbox_taxonomy::api::{ API, Error, InternalError, Operation, Targetted, User, WatchEvent as APIWatchEvent };
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::selector::ChannelSelector;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::{ ExtValue, Json, OnOff, OpenClosed, Range, Temperature, Type, TypeError, Value };
+
+use transformable_channels::mpsc::*;
+
+use chrono;
+use serde_json;
+use timer;
+
+use std::collections::{ HashMap, HashSet };
+use std::str;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+static ADAPTER_NAME: &'static str = "MQTT adapter (built-in)";
+static ADAPTER_VENDOR: &'static str = "team@link.mozilla.org";
+static ADAPTER_VERSION: [u32;4] = [0, 0, 0, 0];
+
+fn mqtt_error<E>(err: E) -> Error where E: ::std::fmt::Display {
+    Error::InternalError(InternalError::GenericError(format!("MQTT error: {}", err)))
+}
+
+/// Turn e.g. `ON` or `on` into `On`.
+fn capitalize(source: &str) -> String {
+    let lower = source.to_lowercase();
+    let mut chars = lower.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+/// The topic on which the values of a channel of the box are published.
+fn taxonomy_topic(prefix: &str, id: &Id<Channel>) -> String {
+    // Wildcards may not appear in the topics of published messages.
+    format!("{}/{}", prefix, id.to_string().replace('+', "_").replace('#', "_"))
+}
+
+/// A channel, along with the topics used to talk to the device.
+struct MqttChannel {
+    id: Id<Channel>,
+    config: ChannelConfig,
+    type_: Type,
+}
+
+impl MqttChannel {
+    /// Convert a message published by the device into a `Value`.
+    fn decode(&self, payload: &[u8]) -> Result<Value, Error> {
+        let text = try!(str::from_utf8(payload).map_err(mqtt_error)).trim();
+        // Devices publish JSON as well as plain text such as `ON` or `21.5`.
+        let message = serde_json::from_str::<JSON>(text).unwrap_or_else(|_| JSON::String(text.to_owned()));
+        let raw = match self.config.path {
+            None => message,
+            Some(ref path) => match path.extract(&message) {
+                Some(raw) => raw.clone(),
+                None => return Err(mqtt_error(format!("No value at {} in message {}",
+                    path.to_json().as_string().unwrap_or(""), text)))
+            }
+        };
+        if let Some((name, _)) = self.config.values.iter().find(|&(_, value)| *value == raw) {
+            return self.parse_value(JSON::String(name.clone()));
+        }
+        match (self.type_.clone(), raw) {
+            (Type::Json, raw) => Ok(Value::Json(Arc::new(Json(raw)))),
+            (Type::String, JSON::String(string)) => Ok(Value::String(Arc::new(string))),
+            (Type::String, raw) => Ok(Value::String(Arc::new(try!(serde_json::to_string(&raw).map_err(mqtt_error))))),
+            (Type::Temperature, ref raw) if raw.as_f64().is_some() =>
+                Ok(Value::Temperature(Temperature::C(raw.as_f64().unwrap_or(0.)))),
+            (Type::ExtNumeric, ref raw) if raw.as_f64().is_some() => match self.config.kind {
+                ChannelKind::Extension { ref vendor, ref adapter, ref kind, .. } => Ok(Value::ExtNumeric(ExtValue {
+                    value: raw.as_f64().unwrap_or(0.),
+                    vendor: vendor.clone(),
+                    adapter: adapter.clone(),
+                    kind: kind.clone(),
+                })),
+                _ => self.parse_value(raw.clone())
+            },
+            (Type::OnOff, JSON::Bool(on)) => Ok(Value::OnOff(if on { OnOff::On } else { OnOff::Off })),
+            (Type::OpenClosed, JSON::Bool(open)) =>
+                Ok(Value::OpenClosed(if open { OpenClosed::Open } else { OpenClosed::Closed })),
+            (_, JSON::String(string)) => self.parse_value(JSON::String(string.clone()))
+                .or_else(|_| self.parse_value(JSON::String(capitalize(&string)))),
+            (_, raw) => self.parse_value(raw)
+        }
+    }
+
+    /// Parse the JSON representation of a value of the type of this channel, e.g. `"On"`.
+    fn parse_value(&self, inner: JSON) -> Result<Value, Error> {
+        let key = self.type_.to_json().as_string().unwrap_or("").to_owned();
+        let wrapped = JSON::Object(vec![(key, inner)].into_iter().collect());
+        let value = try!(Value::parse(Path::new(), &wrapped).map_err(Error::ParseError));
+        if value.get_type() != self.type_ {
+            return Err(Error::TypeError(TypeError { expected: self.type_.clone(), got: value.get_type() }));
+        }
+        Ok(value)
+    }
+
+    /// Convert a `Value` into a message for the device.
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, Error> {
+        if value.get_type() != self.type_ {
+            return Err(Error::TypeError(TypeError { expected: self.type_.clone(), got: value.get_type() }));
+        }
+        let raw = match *value {
+            Value::Json(ref json) => json.0.clone(),
+            Value::Temperature(Temperature::C(celsius)) => JSON::F64(celsius),
+            Value::Temperature(Temperature::F(fahrenheit)) => JSON::F64((fahrenheit - 32.) * 5. / 9.),
+            Value::ExtNumeric(ref ext) => JSON::F64(ext.value),
+            _ => match value.to_json() {
+                JSON::Object(object) => object.into_iter().next().map_or(JSON::Null, |(_, inner)| inner),
+                other => other
+            }
+        };
+        let raw = match raw {
+            JSON::String(name) => self.config.values.get(&name).cloned().unwrap_or(JSON::String(name)),
+            other => other
+        };
+        match raw {
+            JSON::String(string) => Ok(string.into_bytes()),
+            other => serde_json::to_string(&other).map(String::into_bytes).map_err(mqtt_error)
+        }
+    }
+
+    fn publish(&self, client: &Client, value: &Value) -> Result<(), Error> {
+        let topic = match self.config.command_topic {
+            None => return Err(Error::OperationNotSupported(Operation::Send, self.id.clone())),
+            Some(ref topic) => topic
+        };
+        let payload = try!(self.encode(value));
+        client.publish(topic, &payload, self.config.retain).map_err(mqtt_error)
+    }
+}
+
+struct Watcher {
+    channel: Id<Channel>,
+    range: Option<Range>,
+    tx: Box<ExtSender<WatchEvent<Value>>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The latest value received for each channel.
+    values: HashMap<Id<Channel>, Value>,
+    watchers: HashMap<usize, Watcher>,
+    next_watcher: usize,
+}
+
+/// Dispatch a message received from the broker to the channels subscribed to `topic`.
+fn on_message(channels: &HashMap<String, Vec<Arc<MqttChannel>>>, state: &Mutex<State>, topic: &str, payload: &[u8]) {
+    let channels = match channels.get(topic) {
+        Some(channels) => channels,
+        None => return
+    };
+    for channel in channels {
+        let value = match channel.decode(payload) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("[mqtt] Ignoring message on {} for {}: {}", topic, channel.id, err);
+                continue;
+            }
+        };
+        let mut state = state.lock().unwrap();
+        let previous = state.values.insert(channel.id.clone(), value.clone());
+        for watcher in state.watchers.values() {
+            if watcher.channel != channel.id {
+                continue;
+            }
+            if let Some(event) = watch_event(&channel.id, &watcher.range, previous.as_ref(), &value) {
+                let _ = watcher.tx.send(event);
+            }
+        }
+    }
+}
+
+/// A guard used to stop watching a channel.
+struct Guard {
+    key: usize,
+    state: Arc<Mutex<State>>,
+}
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().watchers.remove(&self.key);
+    }
+}
+impl AdapterWatchGuard for Guard {
+}
+
+pub struct MqttAdapter {
+    client: Arc<Client>,
+    channels: HashMap<Id<Channel>, Arc<MqttChannel>>,
+    state: Arc<Mutex<State>>,
+
+    /// Pings the broker to keep the connection alive.
+    _keep_alive: Mutex<(timer::Timer, timer::Guard)>,
+}
+
+impl MqttAdapter {
+    pub fn id() -> Id<AdapterId> {
+        Id::new("mqtt@link.mozilla.org")
+    }
+
+    /// Connect to the broker configured in the file at `config_path`.
+    ///
+    /// If the file does not exist, the adapter is not registered.
+    pub fn init(adapt: &Arc<AdapterManager>, config_path: &str) -> Result<(), Error> {
+        match try!(read_config::<Config>(config_path)) {
+            None => {
+                debug!("[mqtt] No configuration at {}, not starting", config_path);
+                Ok(())
+            }
+            Some(config) => Self::init_with_config(adapt, config)
+        }
+    }
+
+    /// Register the devices described in `config` and start connecting to the broker.
+    pub fn init_with_config(adapt: &Arc<AdapterManager>, config: Config) -> Result<(), Error> {
+        let adapter_id = Self::id();
+        let mut services = vec![];
+        let mut channels = HashMap::new();
+        let mut subscriptions : HashMap<String, Vec<Arc<MqttChannel>>> = HashMap::new();
+        for service_config in config.services {
+            let service_id = Id::<ServiceId>::new(&format!("mqtt/{}", service_config.id));
+            let mut service = Service::empty(&service_id, &adapter_id);
+            service.properties = service_config.properties;
+            let mut service_channels = vec![];
+            for channel_config in service_config.channels {
+                let channel = Channel {
+                    kind: channel_config.kind.clone(),
+                    supports_fetch: channel_config.topic.is_some(),
+                    supports_send: channel_config.command_topic.is_some(),
+                    supports_watch: channel_config.topic.is_some(),
+                    .. Channel::empty(&Id::new(&format!("mqtt/{}/{}", service_config.id, channel_config.id)),
+                                      &service_id, &adapter_id)
+                };
+                let mqtt_channel = Arc::new(MqttChannel {
+                    id: channel.id.clone(),
+                    type_: channel_config.kind.get_type(),
+                    config: channel_config,
+                });
+                if let Some(ref topic) = mqtt_channel.config.topic {
+                    subscriptions.entry(topic.clone()).or_insert_with(Vec::new).push(mqtt_channel.clone());
+                }
+                channels.insert(channel.id.clone(), mqtt_channel);
+                service_channels.push(channel);
+            }
+            services.push((service, service_channels));
+        }
+
+        let topics : Vec<String> = subscriptions.keys().cloned().collect();
+        let state = Arc::new(Mutex::new(State::default()));
+        let client = {
+            let state = state.clone();
+            Client::start(config.broker, config.client_id, config.keep_alive, topics, move |topic, payload| {
+                on_message(&subscriptions, &state, topic, payload)
+            })
+        };
+
+        let timer = timer::Timer::new();
+        let ping = {
+            let client = client.clone();
+            timer.schedule_repeating(chrono::Duration::milliseconds(config.keep_alive as i64 * 500), move || {
+                if let Err(err) = client.ping() {
+                    warn!("[mqtt] Could not ping the broker: {}", err);
+                }
+            })
+        };
+
+        let own_channels : HashSet<Id<Channel>> = channels.keys().cloned().collect();
+        try!(adapt.add_adapter(Arc::new(MqttAdapter {
+            client: client.clone(),
+            channels: channels,
+            state: state,
+            _keep_alive: Mutex::new((timer, ping)),
+        })));
+        for (service, mut service_channels) in services {
+            try!(adapt.add_service(service));
+            for channel in service_channels.drain(..) {
+                try!(adapt.add_channel(channel));
+            }
+        }
+
+        if let Some(prefix) = config.publish_prefix {
+            Self::publish_taxonomy(adapt, client, prefix, own_channels);
+        }
+        Ok(())
+    }
+
+    /// Publish the values of all the channels of the box that anonymous users may watch,
+    /// except those of this adapter, under topic `prefix`.
+    fn publish_taxonomy(adapt: &Arc<AdapterManager>, client: Arc<Client>, prefix: String, own_channels: HashSet<Id<Channel>>) {
+        let (tx, rx) = channel();
+        let guard = adapt.watch_values(vec![Targetted::new(vec![ChannelSelector::new()], Exactly::Always)], Box::new(tx), User::None);
+        thread::spawn(move || {
+            let _guard = guard;
+            for event in rx {
+                if let APIWatchEvent::EnterRange { channel, value, .. } = event {
+                    if own_channels.contains(&channel) {
+                        continue;
+                    }
+                    let result = serde_json::to_string(&value.to_json()).map_err(mqtt_error).and_then(|payload|
+                        client.publish(&taxonomy_topic(&prefix, &channel), payload.as_bytes(), true).map_err(mqtt_error));
+                    if let Err(err) = result {
+                        warn!("[mqtt] Could not publish the value of {}: {}", channel, err);
+                    }
+                }
+            }
+        });
+    }
+
+    fn get_channel(&self, id: &Id<Channel>, operation: Operation) -> Result<&Arc<MqttChannel>, Error> {
+        self.channels.get(id).ok_or_else(|| Error::OperationNotSupported(operation, id.clone()))
+    }
+}
+
+impl Adapter for MqttAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        Self::id()
+    }
+
+    fn name(&self) -> &str {
+        ADAPTER_NAME
+    }
+
+    fn vendor(&self) -> &str {
+        ADAPTER_VENDOR
+    }
+
+    fn version(&self) -> &[u32;4] {
+        &ADAPTER_VERSION
+    }
+
+    fn fetch_values(&self, mut set: Vec<Id<Channel>>, _: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+        set.drain(..).map(|id| {
+            let result = match self.channels.get(&id) {
+                Some(channel) if channel.config.topic.is_some() =>
+                    Ok(self.state.lock().unwrap().values.get(&id).cloned()),
+                _ => Err(Error::OperationNotSupported(Operation::Fetch, id.clone()))
+            };
+            (id, result)
+        }).collect()
+    }
+
+    fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, _: User) -> ResultMap<Id<Channel>, (), Error> {
+        values.drain().map(|(id, value)| {
+            let result = self.get_channel(&id, Operation::Send)
+                .and_then(|channel| channel.publish(&self.client, &value));
+            (id, result)
+        }).collect()
+    }
+
+    fn register_watch(&self, mut watch: Vec<WatchTarget>) -> WatchResult {
+        watch.drain(..).map(|(id, filter, tx)| {
+            match self.channels.get(&id) {
+                Some(channel) if channel.config.topic.is_some() => {},
+                _ => return (id.clone(), Err(Error::OperationNotSupported(Operation::Watch, id)))
+            }
+            let range = match filter {
+                None => None,
+                Some(Value::Range(range)) => Some(*range),
+                Some(other) => return (id, Err(Error::InvalidValue(other)))
+            };
+            let mut state = self.state.lock().unwrap();
+            // Let the watcher know about the latest value, typically a retained message.
+            if let Some(value) = state.values.get(&id) {
+                if let Some(event) = watch_event(&id, &range, None, value) {
+                    let _ = tx.send(event);
+                }
+            }
+            let key = state.next_watcher;
+            state.next_watcher += 1;
+            state.watchers.insert(key, Watcher {
+                channel: id.clone(),
+                range: range,
+                tx: tx,
+            });
+            (id, Ok(Box::new(Guard {
+                key: key,
+                state: self.state.clone(),
+            }) as Box<AdapterWatchGuard>))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+describe! mqtt {
+    before_each {
+        use super::client::Packet;
+        use foxbox_taxonomy::io::Payload;
+        use std::io::Write;
+        use std::net::TcpListener;
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        fn wait_for<F>(condition: F) where F: Fn() -> bool {
+            for _ in 0..100 {
+                if condition() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("Timeout");
+        }
+
+        // A broker accepting a single client, recording the packets it receives. Packets
+        // sent on `to_client` are forwarded to the client.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(vec![]));
+        let (to_client, from_broker) = mpsc::channel::<Packet>();
+        {
+            let received = received.clone();
+            let to_client = to_client.clone();
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                thread::spawn(move || {
+                    for packet in from_broker {
+                        if writer.write_all(&packet.encode()).is_err() {
+                            break;
+                        }
+                    }
+                });
+                while let Ok(packet) = Packet::read(&mut stream) {
+                    let ack = match packet {
+                        Packet::Connect { .. } => Some(Packet::ConnAck { return_code: 0 }),
+                        Packet::Subscribe { packet_id, .. } => Some(Packet::SubAck { packet_id: packet_id }),
+                        Packet::PingReq => Some(Packet::PingResp),
+                        _ => None
+                    };
+                    if let Some(ack) = ack {
+                        to_client.send(ack).unwrap();
+                    }
+                    received.lock().unwrap().push(packet);
+                }
+            });
+        }
+
+        let config = Config::from_str(&r#"{
+            "broker": "BROKER",
+            "services": [{
+                "id": "bedroom",
+                "properties": {"name": "Bedroom"},
+                "channels": [{
+                    "id": "light",
+                    "kind": "LightOn",
+                    "topic": "bedroom/light",
+                    "command_topic": "bedroom/light/set",
+                    "values": {"On": "ON", "Off": "OFF"}
+                }, {
+                    "id": "temperature",
+                    "kind": {"vendor": "team@link.mozilla.org", "adapter": "mqtt@link.mozilla.org",
+                             "kind": "Temperature", "type": "Temperature"},
+                    "topic": "bedroom/sensor",
+                    "path": "$.temperature"
+                }]
+            }]
+        }"#.replace("BROKER", &broker)).unwrap();
+
+        let manager = Arc::new(AdapterManager::new(None));
+        MqttAdapter::init_with_config(&manager, config).unwrap();
+        // The client connects in the background.
+        wait_for(|| received.lock().unwrap().iter().any(|packet| match *packet {
+            Packet::Subscribe { .. } => true,
+            _ => false
+        }));
+        let light = Id::<Channel>::new("mqtt/bedroom/light");
+        let temperature = Id::<Channel>::new("mqtt/bedroom/temperature");
+    }
+
+    it "should register the services and channels of the configuration" {
+        use foxbox_taxonomy::selector::ServiceSelector;
+
+        let services = manager.get_services(vec![ServiceSelector::new().with_id(Id::new("mqtt/bedroom"))]);
+        assert_eq!(services.len(), 1);
+        let channel = services[0].channels.get(&light).unwrap();
+        assert!(channel.supports_fetch && channel.supports_send && channel.supports_watch);
+        let channel = services[0].channels.get(&temperature).unwrap();
+        assert!(channel.supports_fetch && !channel.supports_send && channel.supports_watch);
+
+        wait_for(|| received.lock().unwrap().iter().any(|packet| match *packet {
+            Packet::Subscribe { ref topics, .. } => topics.len() == 2,
+            _ => false
+        }));
+    }
+
+    it "should fetch the latest message received on a topic" {
+        let selector = vec![ChannelSelector::new().with_id(temperature.clone())];
+        match manager.fetch_values(selector.clone(), None, User::None).get(&temperature) {
+            Some(&Ok(None)) => {},
+            other => panic!("Unexpected fetch result {:?}", other)
+        }
+
+        to_client.send(Packet::Publish {
+            topic: "bedroom/sensor".to_owned(),
+            payload: br#"{"temperature": 21.5, "humidity": 40}"#.to_vec(),
+            retain: true
+        }).unwrap();
+        wait_for(|| match manager.fetch_values(selector.clone(), None, User::None).get(&temperature) {
+            Some(&Ok(Some(ref fetched))) =>
+                fetched.value.to_value(&Type::Temperature).unwrap() == Value::Temperature(Temperature::C(21.5)),
+            _ => false
+        });
+    }
+
+    it "should publish the values sent to a channel" {
+        let payload = Payload::from_value(&Value::OnOff(OnOff::On), &Type::OnOff).unwrap();
+        let sent = manager.send_values(vec![Targetted::new(vec![ChannelSelector::new().with_id(light.clone())], payload)], User::None);
+        match sent.get(&light) {
+            Some(&Ok(())) => {},
+            other => panic!("Unexpected send result {:?}", other)
+        }
+        wait_for(|| received.lock().unwrap().iter().any(|packet| *packet == Packet::Publish {
+            topic: "bedroom/light/set".to_owned(),
+            payload: b"ON".to_vec(),
+            retain: false
+        }));
+    }
+
+    it "should notify watchers of new messages" {
+        let (tx, rx) = channel();
+        let _guard = manager.watch_values(vec![Targetted::new(vec![ChannelSelector::new().with_id(light.clone())], Exactly::Always)], Box::new(tx), User::None);
+        to_client.send(Packet::Publish {
+            topic: "bedroom/light".to_owned(),
+            payload: b"OFF".to_vec(),
+            retain: false
+        }).unwrap();
+        loop {
+            match rx.recv().unwrap() {
+                APIWatchEvent::EnterRange { channel, value, .. } => {
+                    assert_eq!(channel, light);
+                    assert_eq!(value.to_value(&Type::OnOff).unwrap(), Value::OnOff(OnOff::Off));
+                    break;
+                }
+                APIWatchEvent::ChannelAdded(_) => continue,
+                other => panic!("Unexpected event {:?}", other)
+            }
+        }
+    }
+
+    it "should decode the usual payloads" {
+        let channel = |kind: &str| {
+            let config = ChannelConfig::from_str(&format!(r#"{{"id": "c", "kind": {}, "topic": "t"}}"#, kind)).unwrap();
+            MqttChannel {
+                id: Id::new("c"),
+                type_: config.kind.get_type(),
+                config: config,
+            }
+        };
+        let switch = channel(r#""LightOn""#);
+        assert_eq!(switch.decode(b"on").unwrap(), Value::OnOff(OnOff::On));
+        assert_eq!(switch.decode(b"OFF").unwrap(), Value::OnOff(OnOff::Off));
+        assert_eq!(switch.decode(b"true").unwrap(), Value::OnOff(OnOff::On));
+        assert!(switch.decode(b"dimmed").is_err());
+        assert_eq!(switch.encode(&Value::OnOff(OnOff::On)).unwrap(), b"On".to_vec());
+
+        let door = channel(r#""OpenClosed""#);
+        assert_eq!(door.decode(b"CLOSED").unwrap(), Value::OpenClosed(OpenClosed::Closed));
+
+        let humidity = channel(r#"{"vendor": "v", "adapter": "a", "kind": "Humidity", "type": "ExtNumeric"}"#);
+        match humidity.decode(b" 40.5\n").unwrap() {
+            Value::ExtNumeric(ext) => assert_eq!(ext.value, 40.5),
+            other => panic!("Unexpected value {:?}", other)
+        }
+
+        assert_eq!(taxonomy_topic("foxbox", &Id::new("ozw/#1")), "foxbox/ozw/_1");
+    }
+}
@@ -0,0 +1,160 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module that implements groups and scenes for `PhilipsHueAdapter`
+//!
+//! Every group of lights of a bridge is registered as a service with a
+//! setter to switch all the lights of the group on or off. Rooms are
+//! additionally tagged with the name of the room, as are the services of
+//! the lights they contain, so that e.g. all the lights of the kitchen
+//! can be selected with tag `Kitchen`.
+//!
+//! The scenes stored in a bridge are registered as send-only channels of a
+//! single service per bridge. Sending `Unit` to such a channel activates the
+//! scene. Each channel is tagged with the name of the scene.
+
+use foxbox_taxonomy::api::{ API, Error };
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::selector::ServiceSelector;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::Type;
+use super::*;
+use super::hub_api::HubApi;
+use super::structs::{ GroupEntry, SceneEntry };
+use std::sync::{ Arc, Mutex };
+
+const CUSTOM_PROPERTY_NAME: &'static str = "name";
+const CUSTOM_PROPERTY_TYPE: &'static str = "type";
+const CUSTOM_PROPERTY_CLASS: &'static str = "class";
+
+#[derive(Clone)]
+pub struct Group {
+    api: Arc<Mutex<HubApi>>,
+    hub_id: String,
+    group_id: String,
+    pub service_id: Id<ServiceId>,
+    pub set_power_id: Id<Channel>,
+}
+
+impl Group {
+    pub fn new(api: Arc<Mutex<HubApi>>, hub_id: &str, group_id: &str) -> Self {
+        let id = format!("group-{}", group_id);
+        Group {
+            api: api,
+            hub_id: hub_id.to_owned(),
+            group_id: group_id.to_owned(),
+            service_id: create_light_id(hub_id, &id),
+            set_power_id: create_setter_id("power", hub_id, &id),
+        }
+    }
+
+    pub fn init_service(&self, manager: Arc<AdapterManager>, services: LightServiceMap,
+        entry: &GroupEntry) -> Result<(), Error>
+    {
+        info!("New Philips Hue `{}` service for group {} on bridge {}",
+            entry.grouptype, self.group_id, self.hub_id);
+        let adapter_id = create_adapter_id();
+
+        let mut service = Service::empty(&self.service_id, &adapter_id);
+        service.properties.insert(CUSTOM_PROPERTY_NAME.to_owned(),
+            entry.name.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(),
+            "Light/Group".to_owned());
+        if let Some(ref class) = entry.class {
+            service.properties.insert(CUSTOM_PROPERTY_CLASS.to_owned(), class.to_owned());
+        }
+        service.tags.insert(tag_id!("type:Light/Group"));
+        let is_room = entry.grouptype == "Room";
+        if is_room {
+            service.tags.insert(tag_id!(&entry.name));
+        }
+
+        try!(manager.add_service(service));
+
+        try!(manager.add_channel(Channel {
+            supports_send: true,
+            kind: ChannelKind::LightOn,
+            ..Channel::empty(&self.set_power_id, &self.service_id, &adapter_id)
+        }));
+
+        if is_room {
+            let selectors = entry.lights.iter().map(|light_id| {
+                ServiceSelector::new().with_id(create_light_id(&self.hub_id, light_id))
+            }).collect();
+            manager.add_service_tags(selectors, vec![tag_id!(&entry.name)]);
+        }
+
+        services.lock().unwrap().groups.insert(self.set_power_id.clone(), self.clone());
+        Ok(())
+    }
+
+    pub fn set_power(&self, on: bool) {
+        self.api.lock().unwrap().set_group_power(&self.group_id, on);
+    }
+}
+
+#[derive(Clone)]
+pub struct Scene {
+    api: Arc<Mutex<HubApi>>,
+    scene_id: String,
+    pub activate_id: Id<Channel>,
+}
+
+impl Scene {
+    pub fn new(api: Arc<Mutex<HubApi>>, hub_id: &str, scene_id: &str) -> Self {
+        Scene {
+            api: api,
+            scene_id: scene_id.to_owned(),
+            activate_id: create_setter_id("activate", hub_id, &format!("scene-{}", scene_id)),
+        }
+    }
+
+    /// Register the scenes stored in the bridge `hub_id` as channels of a
+    /// single service.
+    pub fn init_services(api: Arc<Mutex<HubApi>>, hub_id: &str, manager: Arc<AdapterManager>,
+        services: LightServiceMap) -> Result<(), Error>
+    {
+        let scenes = match api.lock().unwrap().get_scenes() {
+            Some(scenes) => scenes,
+            None => return Ok(())
+        };
+        let scenes: Vec<(String, SceneEntry)> = scenes.into_iter()
+            // Recycled scenes are created by apps for their own use.
+            .filter(|&(_, ref scene)| !scene.recycle.unwrap_or(false))
+            .collect();
+        if scenes.is_empty() {
+            return Ok(());
+        }
+
+        info!("New Philips Hue scenes service with {} scenes on bridge {}", scenes.len(), hub_id);
+        let adapter_id = create_adapter_id();
+        let service_id = create_light_id(hub_id, "scenes");
+        let mut service = Service::empty(&service_id, &adapter_id);
+        service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(), "Light/Scenes".to_owned());
+        service.tags.insert(tag_id!("type:Light/Scenes"));
+        try!(manager.add_service(service));
+
+        for (scene_id, entry) in scenes {
+            let scene = Scene::new(api.clone(), hub_id, &scene_id);
+            let mut channel = Channel {
+                supports_send: true,
+                kind: ChannelKind::Extension {
+                    vendor: Id::new("foxlink@mozilla.com"),
+                    adapter: Id::new("Philips Hue Adapter"),
+                    kind: Id::new("ActivateScene"),
+                    typ: Type::Unit,
+                },
+                ..Channel::empty(&scene.activate_id, &service_id, &adapter_id)
+            };
+            channel.tags.insert(tag_id!(&entry.name));
+            try!(manager.add_channel(channel));
+            services.lock().unwrap().scenes.insert(scene.activate_id.clone(), scene);
+        }
+        Ok(())
+    }
+
+    pub fn activate(&self) {
+        self.api.lock().unwrap().activate_scene(&self.scene_id);
+    }
+}
@@ -5,10 +5,13 @@
 //! Module to handle Philips Hue bridges
 //!
 //! This module implements various aspects Philips Hue bridges (short: hubs).
-//! It handles pairing and light enumeration. Detected lights are
-//! reported to the adapter's main loop via IPC.
+//! It handles pairing and light enumeration. Detected lights, groups, scenes
+//! and sensors are reported to the adapter's main loop via IPC.
 //!
-//! The module spawns a management thread for every hub.
+//! The module spawns a management thread for every hub. Once paired, this
+//! thread polls the state of all the lights of the hub in a single request,
+//! and likewise for sensors, every `philips_hue/poll_interval` seconds
+//! (default: 2), and reports changes to the adapter.
 
 use serde_json;
 use std::sync::{ Arc, Mutex };
@@ -121,10 +124,32 @@ impl<C: Controller> Hub<C> {
                     debug!("Found light {} on hub {}", light_id, id);
                     adapter.send(HueAction::AddLight(id.to_owned(), light_id.to_owned()));
                 }
+                // Groups come after lights, so that rooms can tag the services of their lights.
+                adapter.send(HueAction::AddGroups(id.to_owned()));
+                adapter.send(HueAction::AddScenes(id.to_owned()));
+                adapter.send(HueAction::AddSensors(id.to_owned()));
+
+                let poll_interval = adapter.controller.get_config().get_or_set_default(
+                    "philips_hue", "poll_interval", "2");
+                // A poll interval of 0 would hammer the hub, so it is as invalid as garbage.
+                let poll_interval = match poll_interval.parse::<u64>() {
+                    Ok(interval) if interval > 0 => interval,
+                    _ => {
+                        warn!("Invalid Philips Hue poll interval {}, using 2 seconds", poll_interval);
+                        2
+                    }
+                };
 
                 loop { // Forever
-                    // TODO: add hub monitoring (polling) here
-                    thread::sleep(Duration::from_millis(60*1000));
+                    let lights = api.lock().unwrap().get_all_lights();
+                    if let Some(lights) = lights {
+                        adapter.update_lights(&id, lights);
+                    }
+                    let sensors = api.lock().unwrap().get_sensors();
+                    if let Some(sensors) = sensors {
+                        adapter.update_sensors(&id, sensors);
+                    }
+                    thread::sleep(Duration::from_millis(poll_interval * 1000));
                 }
             }
         });
@@ -7,6 +7,8 @@
 //! This module is used in various places, for example in the Hub
 //! objects and in the Light objects.
 
+use core::fmt::Debug;
+use serde::de::Deserialize;
 use serde_json;
 use std;
 use std::collections::BTreeMap;
@@ -149,17 +151,15 @@ impl HubApi {
     }
 
     pub fn get_lights(&self) -> Vec<String> {
-        let mut lights: Vec<String> = Vec::new();
-        let url = "lights";
-        let res = self.get(url).unwrap(); // TODO: remove unwrap
-        let json: BTreeMap<String, structs::SettingsLightEntry> =
-            structs::parse_json(&res).unwrap(); // TODO: no unwrap
-
-        for key in json.keys() {
-            lights.push(key.to_owned());
+        match self.get_all_lights() {
+            Some(lights) => lights.keys().cloned().collect(),
+            None => Vec::new()
         }
+    }
 
-        lights
+    /// Get the description and state of all the lights of the bridge in a single request.
+    pub fn get_all_lights(&self) -> Option<BTreeMap<String, structs::SettingsLightEntry>> {
+        self.get_map("lights")
     }
 
     pub fn get_light_status(&self, id: &str) -> structs::SettingsLightEntry {
@@ -168,22 +168,91 @@ impl HubApi {
         structs::parse_json(&res).unwrap() // TODO no unwrap
     }
 
-    pub fn set_light_power(&self, light_id: &str, on: bool) {
-        let url = format!("lights/{}/state", light_id);
+    /// Get the groups of lights of the bridge, including rooms.
+    pub fn get_groups(&self) -> Option<BTreeMap<String, structs::GroupEntry>> {
+        self.get_map("groups")
+    }
+
+    /// Get the scenes stored in the bridge.
+    pub fn get_scenes(&self) -> Option<BTreeMap<String, structs::SceneEntry>> {
+        self.get_map("scenes")
+    }
+
+    /// Get the description and state of all the sensors of the bridge, including
+    /// switches.
+    pub fn get_sensors(&self) -> Option<BTreeMap<String, structs::SensorEntry>> {
+        self.get_map("sensors")
+    }
+
+    pub fn get_sensor_status(&self, id: &str) -> Option<structs::SensorEntry> {
+        self.get(&format!("sensors/{}", id)).ok().and_then(|res| structs::parse_json(&res))
+    }
+
+    fn get_map<T: Deserialize + Debug>(&self, cmd: &str) -> Option<BTreeMap<String, T>> {
+        match self.get(cmd) {
+            Ok(res) => structs::parse_json(&res),
+            Err(err) => {
+                warn!("Could not get {} from Philips Hue bridge {}: {}", cmd, self.id, err);
+                None
+            }
+        }
+    }
+
+    pub fn set_group_power(&self, group_id: &str, on: bool) {
+        let url = format!("groups/{}/action", group_id);
         let cmd = json!({ on: on });
         let _ = self.put(&url, &cmd);
     }
 
-    pub fn set_light_color(&self, light_id: &str, hsv: (u32, u32, u32)) {
+    pub fn activate_scene(&self, scene_id: &str) {
+        // Group 0 contains all the lights, so that the scene
+        // applies to all the lights it was stored with.
+        let cmd = json!({ scene: scene_id });
+        let _ = self.put("groups/0/action", &cmd);
+    }
+
+    pub fn set_light_power(&self, light_id: &str, on: bool, transition: Option<u32>) {
+        self.set_light_state(light_id, json_value!({ on: on }), transition);
+    }
+
+    pub fn set_light_color(&self, light_id: &str, hsv: (u32, u32, u32), transition: Option<u32>) {
         let (hue, sat, val) = hsv;
-        let url = format!("lights/{}/state", light_id);
-        let cmd = json!({ hue: hue, sat: sat, bri: val });
-        let _ = self.put(&url, &cmd);
+        self.set_light_state(light_id, json_value!({ hue: hue, sat: sat, bri: val }), transition);
+    }
+
+    pub fn set_light_brightness(&self, light_id: &str, bri: u32, transition: Option<u32>) {
+        self.set_light_state(light_id, json_value!({ bri: bri }), transition);
     }
 
-    pub fn set_light_brightness(&self, light_id: &str, bri: u32) {
+    /// Set the color temperature of a light, in mireds.
+    pub fn set_light_color_temperature(&self, light_id: &str, ct: u32, transition: Option<u32>) {
+        self.set_light_state(light_id, json_value!({ ct: ct }), transition);
+    }
+
+    /// Set the alert of a light: `select` blinks once, `lselect` blinks for
+    /// 15 seconds and `none` stops blinking.
+    pub fn set_light_alert(&self, light_id: &str, alert: &str) {
+        self.set_light_state(light_id, json_value!({ alert: alert }), None);
+    }
+
+    /// Set the effect of a light: `colorloop` cycles through all hues,
+    /// `none` stops the effect.
+    pub fn set_light_effect(&self, light_id: &str, effect: &str) {
+        self.set_light_state(light_id, json_value!({ effect: effect }), None);
+    }
+
+    /// `transition` is the duration of the change, in multiples of 100ms.
+    /// The bridge defaults to 4, i.e. 400ms.
+    fn set_light_state(&self, light_id: &str, state: serde_json::Value, transition: Option<u32>) {
+        let mut state = match state {
+            serde_json::Value::Object(state) => state,
+            _ => return
+        };
+        if let Some(transition) = transition {
+            state.insert("transitiontime".to_owned(), serde_json::to_value(&transition));
+        }
         let url = format!("lights/{}/state", light_id);
-        let cmd = json!({ bri: bri });
+        let cmd = serde_json::to_string(&state).unwrap_or("{}".to_owned());
         let _ = self.put(&url, &cmd);
     }
 }
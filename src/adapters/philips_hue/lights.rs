@@ -6,14 +6,24 @@
 //!
 //! This module implements AdapterManager-facing functionality.
 //! It registers a service for every light and adds setters and
-//! getters according to the light type.
+//! getters according to the light type:
+//!
+//! - all lights have power, brightness, alert and transition channels;
+//! - color lights also have color and effect channels;
+//! - white ambiance lights have a color temperature channel, in kelvin.
+//!
+//! Sending a duration to the transition channel of a light sets the
+//! duration of the subsequent changes of power, brightness, color and
+//! color temperature of the light.
 
+use chrono::Duration as ChronoDuration;
 use foxbox_taxonomy::api::Error;
 use foxbox_taxonomy::manager::*;
 use foxbox_taxonomy::services::*;
-use foxbox_taxonomy::values::{ Type };
+use foxbox_taxonomy::values::{ Color, Duration, ExtValue, OnOff, Type, Value };
 use super::*;
 use super::hub_api::HubApi;
+use super::structs::SettingsLightState;
 use std::sync::{ Arc, Mutex };
 
 const CUSTOM_PROPERTY_MANUFACTURER: &'static str = "manufacturer";
@@ -21,17 +31,54 @@ const CUSTOM_PROPERTY_MODEL: &'static str = "model";
 const CUSTOM_PROPERTY_NAME: &'static str = "name";
 const CUSTOM_PROPERTY_TYPE: &'static str = "type";
 
+// Hue API takes color temperatures in mireds, in [153, 500], i.e. [2000K, 6500K].
+const MIN_MIREDS: f64 = 153f64;
+const MAX_MIREDS: f64 = 500f64;
+
+/// What a type of light supports, besides power and brightness.
+struct LightCapabilities {
+    service_type: &'static str,
+    color: bool,
+    color_temperature: bool,
+}
+
+impl LightCapabilities {
+    fn from_type(lighttype: &str) -> Option<Self> {
+        let (service_type, color, color_temperature) = match lighttype {
+            "Extended color light" => ("Light/ColorLight", true, true),
+            "Color light" => ("Light/ColorLight", true, false),
+            "Color temperature light" => ("Light/WhiteAmbianceLight", false, true),
+            "Dimmable light" => ("Light/DimmerLight", false, false),
+            _ => return None
+        };
+        Some(LightCapabilities {
+            service_type: service_type,
+            color: color,
+            color_temperature: color_temperature,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Light {
     api: Arc<Mutex<HubApi>>,
     hub_id: String,
     light_id: String,
-    service_id: Id<ServiceId>,
+    /// The duration of transitions, in multiples of 100ms, if set.
+    transition: Arc<Mutex<Option<u32>>>,
+    pub service_id: Id<ServiceId>,
     pub get_available_id: Id<Channel>,
     pub get_power_id: Id<Channel>,
     pub set_power_id: Id<Channel>,
+    pub get_brightness_id: Id<Channel>,
+    pub set_brightness_id: Id<Channel>,
     pub get_color_id: Id<Channel>,
     pub set_color_id: Id<Channel>,
+    pub get_color_temperature_id: Id<Channel>,
+    pub set_color_temperature_id: Id<Channel>,
+    pub set_alert_id: Id<Channel>,
+    pub set_effect_id: Id<Channel>,
+    pub set_transition_id: Id<Channel>,
 }
 
 impl Light {
@@ -42,12 +89,20 @@ impl Light {
             api: api,
             hub_id: hub_id.to_owned(),
             light_id: light_id.to_owned(),
+            transition: Arc::new(Mutex::new(None)),
             service_id: create_light_id(&hub_id, &light_id),
             get_available_id: create_getter_id("available", &hub_id, &light_id),
             get_power_id: create_getter_id("power", &hub_id, &light_id),
             set_power_id: create_setter_id("power", &hub_id, &light_id),
+            get_brightness_id: create_getter_id("brightness", &hub_id, &light_id),
+            set_brightness_id: create_setter_id("brightness", &hub_id, &light_id),
             get_color_id: create_getter_id("color", &hub_id, &light_id),
             set_color_id: create_setter_id("color", &hub_id, &light_id),
+            get_color_temperature_id: create_getter_id("color_temperature", &hub_id, &light_id),
+            set_color_temperature_id: create_setter_id("color_temperature", &hub_id, &light_id),
+            set_alert_id: create_setter_id("alert", &hub_id, &light_id),
+            set_effect_id: create_setter_id("effect", &hub_id, &light_id),
+            set_transition_id: create_setter_id("transition", &hub_id, &light_id),
         }
     }
     pub fn start(&self) {
@@ -62,156 +117,203 @@ impl Light {
         let adapter_id = create_adapter_id();
         let status = self.api.lock().unwrap().get_light_status(&self.light_id);
 
-        if status.lighttype == "Extended color light" {
-
-            info!("New Philips Hue `Extended Color Light` service for light {} on bridge {}",
-                self.light_id, self.hub_id);
-
-            let mut service = Service::empty(&self.service_id, &adapter_id);
-            service.properties.insert(CUSTOM_PROPERTY_MANUFACTURER.to_owned(),
-                status.manufacturername.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_MODEL.to_owned(),
-                status.modelid.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_NAME.to_owned(),
-                status.name.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(),
-                "Light/ColorLight".to_owned());
-            service.tags.insert(tag_id!("type:Light/ColorLight"));
-
-            try!(manager.add_service(service));
-
-            // The `available` getter yields `On` when the light
-            // is plugged in and `Off` when it is not. Availability
-            // Has no effect on the API other than that you won't
-            // see the light change because it lacks external power.
+        let capabilities = match LightCapabilities::from_type(&status.lighttype) {
+            Some(capabilities) => capabilities,
+            None => {
+                warn!("Ignoring unsupported Hue light type {}, ID {} on bridge {}",
+                    status.lighttype, self.light_id, self.hub_id);
+                return Ok(());
+            }
+        };
+
+        info!("New Philips Hue `{}` service for light {} on bridge {}",
+            status.lighttype, self.light_id, self.hub_id);
+
+        let mut service = Service::empty(&self.service_id, &adapter_id);
+        service.properties.insert(CUSTOM_PROPERTY_MANUFACTURER.to_owned(),
+            status.manufacturername.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_MODEL.to_owned(),
+            status.modelid.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_NAME.to_owned(),
+            status.name.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(),
+            capabilities.service_type.to_owned());
+        service.tags.insert(tag_id!(&format!("type:{}", capabilities.service_type)));
+
+        try!(manager.add_service(service));
+
+        // The `available` getter yields `On` when the light
+        // is plugged in and `Off` when it is not. Availability
+        // Has no effect on the API other than that you won't
+        // see the light change because it lacks external power.
+        // This is the `reachable` field of the light state.
+        try!(manager.add_channel(Channel {
+             supports_fetch: true,
+             supports_watch: true,
+             kind: ChannelKind::Extension {
+                 vendor: Id::new("foxlink@mozilla.com"),
+                 adapter: Id::new("Philips Hue Adapter"),
+                 kind: Id::new("available"),
+                 typ: Type::OnOff,
+             },
+             ..Channel::empty(&self.get_available_id, &self.service_id, &adapter_id)
+        }));
+
+        try!(manager.add_channel(Channel {
+             supports_fetch: true,
+             supports_watch: true,
+             kind: ChannelKind::LightOn,
+             ..Channel::empty(&self.get_power_id, &self.service_id, &adapter_id)
+        }));
+
+        try!(manager.add_channel(Channel {
+            supports_send: true,
+            kind: ChannelKind::LightOn,
+            ..Channel::empty(&self.set_power_id, &self.service_id, &adapter_id)
+        }));
+
+        try!(self.add_brightness_channels(&manager));
+
+        // Sending `Unit` makes the light blink once.
+        try!(manager.add_channel(Channel {
+            supports_send: true,
+            kind: extension_kind("Alert", Type::Unit),
+            ..Channel::empty(&self.set_alert_id, &self.service_id, &adapter_id)
+        }));
+
+        try!(manager.add_channel(Channel {
+            supports_send: true,
+            kind: extension_kind("Transition", Type::Duration),
+            ..Channel::empty(&self.set_transition_id, &self.service_id, &adapter_id)
+        }));
+
+        if capabilities.color {
             try!(manager.add_channel(Channel {
                  supports_fetch: true,
-                 kind: ChannelKind::Extension {
-                     vendor: Id::new("foxlink@mozilla.com"),
-                     adapter: Id::new("Philips Hue Adapter"),
-                     kind: Id::new("available"),
-                     typ: Type::OnOff,
-                 },
-                 ..Channel::empty(&self.get_available_id, &self.service_id, &adapter_id)
+                 supports_watch: true,
+                 kind: extension_kind("Color", Type::Color),
+                 ..Channel::empty(&self.get_color_id, &self.service_id, &adapter_id)
             }));
 
-
             try!(manager.add_channel(Channel {
-                 supports_fetch: true,
-                 kind: ChannelKind::LightOn,
-                 ..Channel::empty(&self.get_power_id, &self.service_id, &adapter_id)
+                supports_send: true,
+                kind: extension_kind("Color", Type::Color),
+                ..Channel::empty(&self.set_color_id, &self.service_id, &adapter_id)
             }));
 
+            // Sending `On` starts cycling through all hues, `Off` stops.
             try!(manager.add_channel(Channel {
                 supports_send: true,
-                kind: ChannelKind::LightOn,
-                ..Channel::empty(&self.set_power_id, &self.service_id, &adapter_id)
+                kind: extension_kind("ColorLoop", Type::OnOff),
+                ..Channel::empty(&self.set_effect_id, &self.service_id, &adapter_id)
             }));
+        }
 
+        if capabilities.color_temperature {
             try!(manager.add_channel(Channel {
                  supports_fetch: true,
-                 kind: ChannelKind::Extension {
-                     vendor: Id::new("foxlink@mozilla.com"),
-                     adapter: Id::new("Philips Hue Adapter"),
-                     kind: Id::new("Color"),
-                     typ: Type::Color,
-                 },
-                 ..Channel::empty(&self.get_color_id, &self.service_id, &adapter_id)
+                 supports_watch: true,
+                 kind: extension_kind("ColorTemperature", Type::ExtNumeric),
+                 ..Channel::empty(&self.get_color_temperature_id, &self.service_id, &adapter_id)
             }));
 
             try!(manager.add_channel(Channel {
                 supports_send: true,
-                kind: ChannelKind::Extension {
-                    vendor: Id::new("foxlink@mozilla.com"),
-                    adapter: Id::new("Philips Hue Adapter"),
-                    kind: Id::new("Color"),
-                    typ: Type::Color,
-                },
-                ..Channel::empty(&self.set_color_id, &self.service_id, &adapter_id)
+                kind: extension_kind("ColorTemperature", Type::ExtNumeric),
+                ..Channel::empty(&self.set_color_temperature_id, &self.service_id, &adapter_id)
             }));
+        }
 
-
-            let mut services_lock = services.lock().unwrap();
-            services_lock.getters.insert(self.get_available_id.clone(), self.clone());
-            services_lock.getters.insert(self.get_power_id.clone(), self.clone());
-            services_lock.setters.insert(self.set_power_id.clone(), self.clone());
+        let mut services_lock = services.lock().unwrap();
+        services_lock.getters.insert(self.get_available_id.clone(), self.clone());
+        services_lock.getters.insert(self.get_power_id.clone(), self.clone());
+        services_lock.setters.insert(self.set_power_id.clone(), self.clone());
+        services_lock.getters.insert(self.get_brightness_id.clone(), self.clone());
+        services_lock.setters.insert(self.set_brightness_id.clone(), self.clone());
+        services_lock.setters.insert(self.set_alert_id.clone(), self.clone());
+        services_lock.setters.insert(self.set_transition_id.clone(), self.clone());
+        if capabilities.color {
             services_lock.getters.insert(self.get_color_id.clone(), self.clone());
             services_lock.setters.insert(self.set_color_id.clone(), self.clone());
+            services_lock.setters.insert(self.set_effect_id.clone(), self.clone());
+        }
+        if capabilities.color_temperature {
+            services_lock.getters.insert(self.get_color_temperature_id.clone(), self.clone());
+            services_lock.setters.insert(self.set_color_temperature_id.clone(), self.clone());
+        }
+        Ok(())
+    }
 
-        } else if status.lighttype == "Dimmable light" {
-            info!("New Philips Hue `Dimmable Light` service for light {} on bridge {}",
-                self.light_id, self.hub_id);
-            let mut service = Service::empty(&self.service_id, &adapter_id);
-            service.properties.insert(CUSTOM_PROPERTY_MANUFACTURER.to_owned(),
-                status.manufacturername.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_MODEL.to_owned(),
-                status.modelid.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_NAME.to_owned(),
-                status.name.to_owned());
-            service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(),
-                "Light/DimmerLight".to_owned());
-            service.tags.insert(tag_id!("type:Light/DimmerLight"));
-
-            try!(manager.add_service(service));
-
-            try!(manager.add_channel(Channel {
-                 supports_fetch: true,
-                 kind: ChannelKind::Extension {
-                     vendor: Id::new("foxlink@mozilla.com"),
-                     adapter: Id::new("Philips Hue Adapter"),
-                     kind: Id::new("available"),
-                     typ: Type::OnOff,
-                 },
-                 ..Channel::empty(&self.get_available_id, &self.service_id, &adapter_id)
-            }));
-
+    fn add_brightness_channels(&self, manager: &Arc<AdapterManager>) -> Result<(), Error> {
+        let adapter_id = create_adapter_id();
+        try!(manager.add_channel(Channel {
+            supports_fetch: true,
+            supports_watch: true,
+            kind: brightness_kind(),
+            ..Channel::empty(&self.get_brightness_id, &self.service_id, &adapter_id)
+        }));
+        manager.add_channel(Channel {
+            supports_send: true,
+            kind: brightness_kind(),
+            ..Channel::empty(&self.set_brightness_id, &self.service_id, &adapter_id)
+        })
+    }
 
-            try!(manager.add_channel(Channel {
-                 supports_fetch: true,
-                 kind: ChannelKind::LightOn,
-                 ..Channel::empty(&self.get_power_id, &self.service_id, &adapter_id)
-            }));
+    pub fn get_state(&self) -> SettingsLightState {
+        self.api.lock().unwrap().get_light_status(&self.light_id).state
+    }
 
-            try!(manager.add_channel(Channel {
-                 supports_send: true,
-                 kind: ChannelKind::LightOn,
-                 ..Channel::empty(&self.set_power_id, &self.service_id, &adapter_id)
-            }));
+    /// The values of the getter channels of this light, given its state.
+    pub fn get_values(&self, state: &SettingsLightState) -> Vec<(Id<Channel>, Value)> {
+        let on_off = |on| Value::OnOff(if on { OnOff::On } else { OnOff::Off });
 
-            let mut services_lock = services.lock().unwrap();
-            services_lock.getters.insert(self.get_available_id.clone(), self.clone());
-            services_lock.getters.insert(self.get_power_id.clone(), self.clone());
-            services_lock.setters.insert(self.set_power_id.clone(), self.clone());
+        // Hue API gives brightness value in [0, 254]
+        let brightness = Value::ExtNumeric(ExtValue {
+            value: state.bri as f64 / 254f64,
+            vendor: Id::new("foxlink@mozilla.com"),
+            adapter: Id::new("Philips Hue Adapter"),
+            kind: Id::new("Brightness"),
+        });
 
-        } else {
-            warn!("Ignoring unsupported Hue light type {}, ID {} on bridge {}",
-                status.lighttype, self.light_id, self.hub_id);
+        // Hue API gives hue angle in [0, 65535], and sat and val in [0, 254]
+        let hue: f64 = state.hue.unwrap_or(0) as f64 / 65536f64 * 360f64;
+        let sat: f64 = state.sat.unwrap_or(0) as f64 / 254f64;
+        let val: f64 = state.bri as f64 / 254f64;
+
+        let mut values = vec![
+            (self.get_available_id.clone(), on_off(state.reachable)),
+            (self.get_power_id.clone(), on_off(state.on)),
+            (self.get_brightness_id.clone(), brightness),
+            (self.get_color_id.clone(), Value::Color(Color::HSV(hue, sat, val))),
+        ];
+
+        // Hue API gives color temperature in mireds, absent for lights that don't support it.
+        if let Some(ct) = state.ct {
+            if ct > 0 {
+                values.push((self.get_color_temperature_id.clone(), Value::ExtNumeric(ExtValue {
+                    value: (1_000_000f64 / ct as f64).round(),
+                    vendor: Id::new("foxlink@mozilla.com"),
+                    adapter: Id::new("Philips Hue Adapter"),
+                    kind: Id::new("ColorTemperature"),
+                })));
+            }
         }
-        Ok(())
+        values
     }
 
-    pub fn get_available(&self) -> bool {
-        let status = self.api.lock().unwrap().get_light_status(&self.light_id);
-        status.state.reachable
-    }
-
-    pub fn get_power(&self) -> bool {
-        let status = self.api.lock().unwrap().get_light_status(&self.light_id);
-        status.state.on
+    /// Fetch the value of getter channel `id` from the bridge.
+    pub fn get_value(&self, id: &Id<Channel>) -> Option<Value> {
+        let state = self.get_state();
+        self.get_values(&state).into_iter()
+            .find(|&(ref channel, _)| channel == id)
+            .map(|(_, value)| value)
     }
 
     pub fn set_power(&self, on: bool) {
-        self.api.lock().unwrap().set_light_power(&self.light_id, on);
+        let transition = self.get_transition();
+        self.api.lock().unwrap().set_light_power(&self.light_id, on, transition);
     }
 
-    #[allow(dead_code)]
-    pub fn get_brightness(&self) -> f64 {
-        // Hue API gives brightness value in [0, 254]
-        let ls = self.api.lock().unwrap().get_light_status(&self.light_id);
-        ls.state.bri as f64 / 254f64
-    }
-
-    #[allow(dead_code)]
     pub fn set_brightness(&self, bri: f64) {
         // Hue API takes brightness value in [0, 254]
         let bri = bri.max(0f64).min(1f64); // [0,1]
@@ -219,16 +321,8 @@ impl Light {
         // convert to value space used by Hue
         let bri: u32 = (bri * 254f64) as u32;
 
-        self.api.lock().unwrap().set_light_brightness(&self.light_id, bri);
-    }
-
-    pub fn get_color(&self) -> (f64, f64, f64) {
-        // Hue API gives hue angle in [0, 65535], and sat and val in [0, 254]
-        let ls = self.api.lock().unwrap().get_light_status(&self.light_id);
-        let hue: f64 = ls.state.hue.unwrap_or(0) as f64 / 65536f64 * 360f64;
-        let sat: f64 = ls.state.sat.unwrap_or(0) as f64 / 254f64;
-        let val: f64 = ls.state.bri as f64 / 254f64;
-        (hue, sat, val)
+        let transition = self.get_transition();
+        self.api.lock().unwrap().set_light_brightness(&self.light_id, bri, transition);
     }
 
     pub fn set_color(&self, hsv: (f64, f64, f64)) {
@@ -243,6 +337,54 @@ impl Light {
         let sat: u32 = (sat * 254f64) as u32;
         let val: u32 = (val * 254f64) as u32;
 
-        self.api.lock().unwrap().set_light_color(&self.light_id, (hue, sat, val));
+        let transition = self.get_transition();
+        self.api.lock().unwrap().set_light_color(&self.light_id, (hue, sat, val), transition);
+    }
+
+    /// Set the color temperature of the light, in kelvin.
+    pub fn set_color_temperature(&self, kelvin: f64) {
+        if kelvin <= 0f64 {
+            return;
+        }
+        let mireds = (1_000_000f64 / kelvin).max(MIN_MIREDS).min(MAX_MIREDS);
+        let transition = self.get_transition();
+        self.api.lock().unwrap().set_light_color_temperature(&self.light_id, mireds as u32,
+            transition);
+    }
+
+    /// Make the light blink once.
+    pub fn alert(&self) {
+        self.api.lock().unwrap().set_light_alert(&self.light_id, "select");
+    }
+
+    /// Start or stop cycling through all hues.
+    pub fn set_color_loop(&self, on: bool) {
+        let effect = if on { "colorloop" } else { "none" };
+        self.api.lock().unwrap().set_light_effect(&self.light_id, effect);
+    }
+
+    /// Set the duration of the subsequent changes of the light.
+    pub fn set_transition(&self, duration: Duration) {
+        let duration: ChronoDuration = duration.into();
+        // Hue API takes transition time in multiples of 100ms.
+        let transition = (duration.num_milliseconds().max(0) + 50) / 100;
+        *self.transition.lock().unwrap() = Some(transition as u32);
+    }
+
+    fn get_transition(&self) -> Option<u32> {
+        *self.transition.lock().unwrap()
+    }
+}
+
+fn brightness_kind() -> ChannelKind {
+    extension_kind("Brightness", Type::ExtNumeric)
+}
+
+fn extension_kind(kind: &str, typ: Type) -> ChannelKind {
+    ChannelKind::Extension {
+        vendor: Id::new("foxlink@mozilla.com"),
+        adapter: Id::new("Philips Hue Adapter"),
+        kind: Id::new(kind),
+        typ: typ,
     }
 }
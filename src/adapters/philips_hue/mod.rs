@@ -5,28 +5,37 @@
 //! The `PhilipsHueAdapter`
 //!
 //! This adapter implements support for Philips Hue bridges.
+//!
+//! The state of the lights is polled from each bridge (see module `hub`) and
+//! compared to the previous state, so that watchers of the channels of the
+//! lights are notified of changes. The same goes for sensors.
 
 // Clippy complains about `hub_id` and `hub_ip` being too similar,
 // suggests renaming to `hub_i_p`.
 #![allow(clippy)]
 
 pub mod discovery;
+pub mod groups;
 pub mod http;
 pub mod hub;
 pub mod hub_api;
 pub mod lights;
+pub mod sensors;
 pub mod structs;
 
 use foxbox_taxonomy::api::{ Error, InternalError, User };
 use foxbox_taxonomy::manager::*;
 use foxbox_taxonomy::services::*;
-use foxbox_taxonomy::values::{ Color, OnOff, Type, TypeError, Value };
+use foxbox_taxonomy::values::{ Color, OnOff, Range, Type, TypeError, Value };
 
-use std::collections::HashMap;
+use std::collections::{ BTreeMap, HashMap };
 use std::sync::{ Arc, Mutex };
 use std::thread;
 use self::hub::Hub;
+use self::groups::{ Group, Scene };
 use self::lights::Light;
+use self::sensors::{ Sensor, create_sensor_service_id };
+use self::structs::{ SensorEntry, SensorState, SettingsLightEntry, SettingsLightState };
 use traits::Controller;
 use transformable_channels::mpsc::*;
 
@@ -40,6 +49,9 @@ pub enum HueAction {
     TriggerDiscovery,
     AddHub(String, String),         // Hub id, hub ip
     AddLight(String, String),       // Hub id, light id
+    AddGroups(String),              // Hub id
+    AddScenes(String),              // Hub id
+    AddSensors(String),             // Hub id
     RemoveHub(String),              // Hub id
     RemoveLight(String, String),    // Hub id, light id
     StopAdapter,
@@ -50,6 +62,91 @@ pub type LightServiceMap = Arc<Mutex<LightServiceMapInternal>>;
 pub struct LightServiceMapInternal {
     getters: HashMap<Id<Channel>, Light>,
     setters: HashMap<Id<Channel>, Light>,
+
+    groups: HashMap<Id<Channel>, Group>,
+    scenes: HashMap<Id<Channel>, Scene>,
+    sensors: HashMap<Id<Channel>, Sensor>,
+
+    /// The state of each light, as of the latest poll of its hub.
+    states: HashMap<Id<ServiceId>, SettingsLightState>,
+
+    /// The state of each sensor, as of the latest poll of its hub.
+    sensor_states: HashMap<Id<ServiceId>, SensorState>,
+
+    watchers: HashMap<usize, Watcher>,
+    next_watcher: usize,
+}
+
+impl LightServiceMapInternal {
+    fn new() -> Self {
+        LightServiceMapInternal {
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            groups: HashMap::new(),
+            scenes: HashMap::new(),
+            sensors: HashMap::new(),
+            states: HashMap::new(),
+            sensor_states: HashMap::new(),
+            watchers: HashMap::new(),
+            next_watcher: 0,
+        }
+    }
+
+    /// The value of getter channel `id`, as of the latest poll of its hub.
+    fn get_cached_value(&self, id: &Id<Channel>) -> Option<Value> {
+        let values = if let Some(light) = self.getters.get(id) {
+            self.states.get(&light.service_id).map(|state| light.get_values(state))
+        } else if let Some(sensor) = self.sensors.get(id) {
+            self.sensor_states.get(&sensor.service_id).and_then(|state| sensor.get_value(state))
+                .map(|value| vec![(sensor.get_value_id.clone(), value)])
+        } else {
+            None
+        };
+        values.and_then(|values| {
+            values.into_iter()
+                .find(|&(ref channel, _)| channel == id)
+                .map(|(_, value)| value)
+        })
+    }
+
+    /// Notify the watchers of the channels whose value changed from
+    /// `previous` to `current`.
+    fn notify_watchers(&self, previous: Vec<(Id<Channel>, Value)>, current: Vec<(Id<Channel>, Value)>) {
+        let previous: HashMap<Id<Channel>, Value> = previous.into_iter().collect();
+        for (channel, value) in current {
+            let previous_value = previous.get(&channel);
+            if previous_value == Some(&value) {
+                continue;
+            }
+            for watcher in self.watchers.values() {
+                if watcher.channel != channel {
+                    continue;
+                }
+                if let Some(event) = watch_event(&channel, &watcher.range, previous_value, &value) {
+                    let _ = watcher.tx.send(event);
+                }
+            }
+        }
+    }
+}
+
+struct Watcher {
+    channel: Id<Channel>,
+    range: Option<Range>,
+    tx: Box<ExtSender<WatchEvent<Value>>>,
+}
+
+/// A guard used to stop watching a channel.
+struct WatcherGuard {
+    key: usize,
+    services: LightServiceMap,
+}
+impl Drop for WatcherGuard {
+    fn drop(&mut self) {
+        self.services.lock().unwrap().watchers.remove(&self.key);
+    }
+}
+impl AdapterWatchGuard for WatcherGuard {
 }
 
 #[derive(Clone)]
@@ -71,10 +168,7 @@ pub struct PhilipsHueAdapter<C> {
 impl<C: Controller> PhilipsHueAdapter<C> {
     #[allow(dead_code)]
     pub fn init(manager: &Arc<AdapterManager>, controller: C) -> Result<(), Error> {
-        let services = Arc::new(Mutex::new(LightServiceMapInternal {
-            getters: HashMap::new(),
-            setters: HashMap::new(),
-        }));
+        let services = Arc::new(Mutex::new(LightServiceMapInternal::new()));
 
         let (tx, rx) = channel();
 
@@ -136,6 +230,60 @@ impl<C: Controller> PhilipsHueAdapter<C> {
                             lights.insert(id, Arc::new(Mutex::new(new_light)));
                         }
                     },
+                    HueAction::AddGroups(hub_id) => {
+                        debug!("HueAction::AddGroups({}) received", hub_id);
+                        let api = match hubs.get(&hub_id) {
+                            Some(hub) => hub.lock().unwrap().api.clone(),
+                            None => {
+                                warn!("Ignoring request to add groups of unknown Hue hub");
+                                continue 'recv;
+                            }
+                        };
+                        let groups = api.lock().unwrap().get_groups().unwrap_or(BTreeMap::new());
+                        for (group_id, entry) in groups {
+                            let group = Group::new(api.clone(), &hub_id, &group_id);
+                            if let Err(err) = group.init_service(manager.clone(), services.clone(), &entry) {
+                                error!("Could not add Hue group {}: {:?}", group_id, err);
+                            }
+                        }
+                    },
+                    HueAction::AddScenes(hub_id) => {
+                        debug!("HueAction::AddScenes({}) received", hub_id);
+                        let api = match hubs.get(&hub_id) {
+                            Some(hub) => hub.lock().unwrap().api.clone(),
+                            None => {
+                                warn!("Ignoring request to add scenes of unknown Hue hub");
+                                continue 'recv;
+                            }
+                        };
+                        if let Err(err) = Scene::init_services(api, &hub_id, manager.clone(), services.clone()) {
+                            error!("Could not add Hue scenes: {:?}", err);
+                        }
+                    },
+                    HueAction::AddSensors(hub_id) => {
+                        debug!("HueAction::AddSensors({}) received", hub_id);
+                        let api = match hubs.get(&hub_id) {
+                            Some(hub) => hub.lock().unwrap().api.clone(),
+                            None => {
+                                warn!("Ignoring request to add sensors of unknown Hue hub");
+                                continue 'recv;
+                            }
+                        };
+                        let sensors = api.lock().unwrap().get_sensors().unwrap_or(BTreeMap::new());
+                        for (sensor_id, entry) in sensors {
+                            let sensor = match Sensor::new(api.clone(), &hub_id, &sensor_id, &entry) {
+                                Some(sensor) => sensor,
+                                None => {
+                                    debug!("Ignoring Hue sensor {} of unsupported type {}",
+                                        sensor_id, entry.sensortype);
+                                    continue;
+                                }
+                            };
+                            if let Err(err) = sensor.init_service(manager.clone(), services.clone(), &entry) {
+                                error!("Could not add Hue sensor {}: {:?}", sensor_id, err);
+                            }
+                        }
+                    },
                     // Currently unused
                     HueAction::RemoveHub(hub_id) => {
                         debug!("HueAction::RemoveHub({}) received", hub_id);
@@ -192,6 +340,55 @@ impl<C: Controller> PhilipsHueAdapter<C> {
     pub fn send(&self, action: HueAction) {
         let _ = self.tx.lock().unwrap().send(action);
     }
+
+    /// Record the state of the lights of hub `hub_id`, as polled from the hub,
+    /// and notify the watchers of the channels whose value changed.
+    pub fn update_lights(&self, hub_id: &str, lights: BTreeMap<String, SettingsLightEntry>) {
+        let mut services = self.services.lock().unwrap();
+        for (light_id, entry) in lights {
+            let previous = services.states.insert(create_light_id(hub_id, &light_id),
+                entry.state.clone());
+            if previous.as_ref() == Some(&entry.state) {
+                continue;
+            }
+            let light = match services.getters.get(&create_getter_id("power", hub_id, &light_id)) {
+                Some(light) => light.clone(),
+                None => continue // Not registered yet, or unsupported.
+            };
+            let previous_values = match previous {
+                Some(ref previous) => light.get_values(previous),
+                None => vec![]
+            };
+            services.notify_watchers(previous_values, light.get_values(&entry.state));
+        }
+    }
+
+    /// Record the state of the sensors of hub `hub_id`, as polled from the hub,
+    /// and notify the watchers of the sensors whose value changed.
+    pub fn update_sensors(&self, hub_id: &str, sensors: BTreeMap<String, SensorEntry>) {
+        let mut services = self.services.lock().unwrap();
+        for (sensor_id, entry) in sensors {
+            let service_id = create_sensor_service_id(hub_id, &sensor_id);
+            let previous = services.sensor_states.insert(service_id.clone(), entry.state.clone());
+            if previous.as_ref() == Some(&entry.state) {
+                continue;
+            }
+            let sensor = match services.sensors.values().find(|sensor| sensor.service_id == service_id) {
+                Some(sensor) => sensor.clone(),
+                None => continue // Not registered yet, or unsupported.
+            };
+            let get_values = |state: &SensorState| -> Vec<(Id<Channel>, Value)> {
+                sensor.get_value(state)
+                    .map(|value| (sensor.get_value_id.clone(), value))
+                    .into_iter().collect()
+            };
+            let previous_values = match previous {
+                Some(ref previous) => get_values(previous),
+                None => vec![]
+            };
+            services.notify_watchers(previous_values, get_values(&entry.state));
+        }
+    }
 }
 
 pub fn create_adapter_id() -> Id<AdapterId> {
@@ -231,31 +428,20 @@ impl<C: Controller> Adapter for PhilipsHueAdapter<C> {
         -> ResultMap<Id<Channel>, Option<Value>, Error>
     {
         set.drain(..).map(|id| {
-            let light = match self.services.lock().unwrap().getters.get(&id) {
-                Some(light) => light.clone(),
-                None => return (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
+            let (light, sensor) = {
+                let services = self.services.lock().unwrap();
+                (services.getters.get(&id).cloned(), services.sensors.get(&id).cloned())
+            };
+            let value = match (light, sensor) {
+                (Some(light), _) => light.get_value(&id),
+                (None, Some(sensor)) => sensor.fetch_value(),
+                (None, None) => return (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
             };
 
-            if id == light.get_available_id {
-                if light.get_available() {
-                    return (id, Ok(Some(Value::OnOff(OnOff::On))));
-                } else {
-                    return (id, Ok(Some(Value::OnOff(OnOff::Off))));
-                }
-            }
-            if id == light.get_power_id {
-                if light.get_power() {
-                    return (id, Ok(Some(Value::OnOff(OnOff::On))));
-                } else {
-                    return (id, Ok(Some(Value::OnOff(OnOff::Off))));
-                }
+            match value {
+                Some(value) => (id, Ok(Some(value))),
+                None => (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
             }
-            if id == light.get_color_id {
-                let (h, s, v) = light.get_color();
-                return (id, Ok(Some(Value::Color(Color::HSV(h, s, v)))));
-            }
-
-            (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
         }).collect()
     }
 
@@ -263,8 +449,38 @@ impl<C: Controller> Adapter for PhilipsHueAdapter<C> {
         -> ResultMap<Id<Channel>, (), Error>
     {
         values.drain().map(|(id, value)| {
-            let light = match self.services.lock().unwrap().setters.get(&id) {
-                Some(light) => light.clone(),
+            let (light, group, scene) = {
+                let services = self.services.lock().unwrap();
+                (services.setters.get(&id).cloned(), services.groups.get(&id).cloned(),
+                    services.scenes.get(&id).cloned())
+            };
+            if let Some(group) = group {
+                match value {
+                    Value::OnOff(OnOff::On)  => { group.set_power(true); },
+                    Value::OnOff(OnOff::Off) => { group.set_power(false); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::OnOff
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
+            if let Some(scene) = scene {
+                match value {
+                    Value::Unit => { scene.activate(); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::Unit
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
+            let light = match light {
+                Some(light) => light,
                 None => return (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
             };
 
@@ -281,6 +497,18 @@ impl<C: Controller> Adapter for PhilipsHueAdapter<C> {
                 }
                 return (id, Ok(()));
             }
+            if id == light.set_brightness_id {
+                match value {
+                    Value::ExtNumeric(ref brightness) => { light.set_brightness(brightness.value); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::ExtNumeric
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
             if id == light.set_color_id {
                 match value {
                     Value::Color(Color::HSV(h, s, v)) => { light.set_color((h, s, v)); },
@@ -293,8 +521,302 @@ impl<C: Controller> Adapter for PhilipsHueAdapter<C> {
                 }
                 return (id, Ok(()));
             }
+            if id == light.set_color_temperature_id {
+                match value {
+                    Value::ExtNumeric(ref kelvin) => { light.set_color_temperature(kelvin.value); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::ExtNumeric
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
+            if id == light.set_alert_id {
+                match value {
+                    Value::Unit => { light.alert(); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::Unit
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
+            if id == light.set_effect_id {
+                match value {
+                    Value::OnOff(OnOff::On)  => { light.set_color_loop(true); },
+                    Value::OnOff(OnOff::Off) => { light.set_color_loop(false); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::OnOff
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
+            if id == light.set_transition_id {
+                match value {
+                    Value::Duration(ref duration) => { light.set_transition(duration.clone()); },
+                    _ => {
+                        return (id, Err(Error::TypeError(TypeError {
+                                        got: value.get_type(),
+                                        expected: Type::Duration
+                                    })));
+                    }
+                }
+                return (id, Ok(()));
+            }
 
             (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
         }).collect()
     }
+    fn register_watch(&self, mut watch: Vec<WatchTarget>) -> WatchResult {
+        watch.drain(..).map(|(id, filter, tx)| {
+            let mut services = self.services.lock().unwrap();
+            if !services.getters.contains_key(&id) && !services.sensors.contains_key(&id) {
+                return (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))));
+            }
+            let range = match filter {
+                None => None,
+                Some(Value::Range(range)) => Some(*range),
+                Some(other) => return (id, Err(Error::InvalidValue(other)))
+            };
+
+            // Let the watcher know about the current value, if the hub has
+            // already been polled.
+            if let Some(value) = services.get_cached_value(&id) {
+                if let Some(event) = watch_event(&id, &range, None, &value) {
+                    let _ = tx.send(event);
+                }
+            }
+
+            let key = services.next_watcher;
+            services.next_watcher += 1;
+            services.watchers.insert(key, Watcher {
+                channel: id.clone(),
+                range: range,
+                tx: tx,
+            });
+            (id, Ok(Box::new(WatcherGuard {
+                key: key,
+                services: self.services.clone(),
+            }) as Box<AdapterWatchGuard>))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+describe! philips_hue {
+    before_each {
+        use foxbox_taxonomy::api::{ API, Targetted, WatchEvent as APIWatchEvent };
+        use foxbox_taxonomy::io::Payload;
+        use foxbox_taxonomy::selector::*;
+        use chrono::Duration as ChronoDuration;
+        use foxbox_taxonomy::values::{ Duration, ExtValue, OpenClosed };
+        use hyper::method::Method;
+        use hyper::server::{ Handler, Request, Response, Server };
+        use hyper::uri::RequestUri;
+        use serde_json;
+        use std::io::Read;
+        use stubs::controller::ControllerStub;
+        use super::hub_api::HubApi;
+
+        /// A bridge with a single light, whose `on` and `reachable` fields are shared with the test,
+        /// and a motion sensor, whose `presence` field is shared with the test. The body of the
+        /// latest PUT request is also shared with the test.
+        struct MockBridge(Arc<Mutex<(bool, bool)>>, Arc<Mutex<bool>>, Arc<Mutex<String>>);
+        impl Handler for MockBridge {
+            fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, res: Response<'a>) {
+                let mut body = String::new();
+                req.read_to_string(&mut body).unwrap();
+                let mut state = self.0.lock().unwrap();
+                if req.method == Method::Put {
+                    *self.2.lock().unwrap() = body.clone();
+                    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    if let Some(on) = json.find("on").and_then(|on| on.as_boolean()) {
+                        state.0 = on;
+                    }
+                }
+                let light = format!(r#"{{"state": {{"on": {}, "bri": 127, "hue": 0, "sat": 254,
+                    "effect": "none", "xy": [0.6, 0.3], "ct": 153, "alert": "none",
+                    "colormode": "hs", "reachable": {}}}, "type": "Extended color light",
+                    "name": "Lamp", "modelid": "LCT007", "manufacturername": "Philips",
+                    "uniqueid": "00:17:88:01:10:34:f0:0a-0b", "swversion": "66014919"}}"#,
+                    state.0, state.1);
+                let sensor = format!(r#"{{"state": {{"presence": {}, "lastupdated": "2016-06-01T10:00:00"}},
+                    "name": "Hall sensor", "type": "ZLLPresence", "modelid": "SML001",
+                    "manufacturername": "Philips"}}"#, *self.1.lock().unwrap());
+                let response = match req.uri {
+                    RequestUri::AbsolutePath(ref path) if path == "/api/token/lights" =>
+                        format!(r#"{{"1": {}}}"#, light),
+                    RequestUri::AbsolutePath(ref path) if path == "/api/token/lights/1" => light,
+                    RequestUri::AbsolutePath(ref path) if path == "/api/token/sensors" =>
+                        format!(r#"{{"1": {{"state": {{"daylight": true}}, "name": "Daylight",
+                            "type": "Daylight", "modelid": "PHDL00", "manufacturername": "Philips"}},
+                            "2": {}}}"#, sensor),
+                    RequestUri::AbsolutePath(ref path) if path == "/api/token/sensors/2" => sensor,
+                    _ => r#"[{"success": {}}]"#.to_owned()
+                };
+                res.send(response.as_bytes()).unwrap();
+            }
+        }
+
+        let state = Arc::new(Mutex::new((true, true)));
+        let presence = Arc::new(Mutex::new(false));
+        let last_put = Arc::new(Mutex::new(String::new()));
+        let mut listening = Server::http("127.0.0.1:0").unwrap()
+            .handle(MockBridge(state.clone(), presence.clone(), last_put.clone())).unwrap();
+
+        let manager = Arc::new(AdapterManager::new(None));
+        let (tx, _rx) = channel();
+        let adapter = PhilipsHueAdapter {
+            manager: manager.clone(),
+            controller: ControllerStub::new(),
+            services: Arc::new(Mutex::new(LightServiceMapInternal::new())),
+            tx: Arc::new(Mutex::new(tx)),
+            adapter_id: create_adapter_id(),
+        };
+        manager.add_adapter(Arc::new(adapter.clone())).unwrap();
+
+        let api = Arc::new(Mutex::new(HubApi::new("hub", &listening.socket.to_string(), "token")));
+        let mut light = Light::new(api.clone(), "hub", "1");
+        light.init_service(manager.clone(), adapter.services.clone()).unwrap();
+
+        let poll = || {
+            let lights = api.lock().unwrap().get_all_lights().unwrap();
+            adapter.update_lights("hub", lights);
+            let sensors = api.lock().unwrap().get_sensors().unwrap();
+            adapter.update_sensors("hub", sensors);
+        };
+    }
+
+    after_each {
+        listening.close().unwrap();
+    }
+
+    it "should fetch and send values through the bridge" {
+        let power = vec![ChannelSelector::new().with_id(light.get_power_id.clone())];
+        let brightness = vec![ChannelSelector::new().with_id(light.get_brightness_id.clone())];
+        let fetched = manager.fetch_values(power.clone(), None, User::None);
+        match fetched.get(&light.get_power_id) {
+            Some(&Ok(Some(ref fetched))) =>
+                assert_eq!(fetched.value.to_value(&Type::OnOff).unwrap(), Value::OnOff(OnOff::On)),
+            other => panic!("Unexpected fetch result {:?}", other)
+        }
+        let fetched = manager.fetch_values(brightness, None, User::None);
+        match fetched.get(&light.get_brightness_id) {
+            Some(&Ok(Some(ref fetched))) => match fetched.value.to_value(&Type::ExtNumeric).unwrap() {
+                Value::ExtNumeric(ref ext) => assert_eq!(ext.value, 0.5),
+                other => panic!("Unexpected value {:?}", other)
+            },
+            other => panic!("Unexpected fetch result {:?}", other)
+        }
+
+        let payload = Payload::from_value(&Value::OnOff(OnOff::Off), &Type::OnOff).unwrap();
+        let setter = vec![ChannelSelector::new().with_id(light.set_power_id.clone())];
+        manager.send_values(vec![Targetted::new(setter, payload)], User::None);
+        assert_eq!(state.lock().unwrap().0, false);
+    }
+
+    it "should apply the transition time to subsequent changes" {
+        let send = |id: &Id<Channel>, value: Value, type_: Type| {
+            let payload = Payload::from_value(&value, &type_).unwrap();
+            let setter = vec![ChannelSelector::new().with_id(id.clone())];
+            manager.send_values(vec![Targetted::new(setter, payload)], User::None);
+        };
+        send(&light.set_power_id, Value::OnOff(OnOff::Off), Type::OnOff);
+        assert_eq!(*last_put.lock().unwrap(), r#"{"on":false}"#);
+
+        send(&light.set_transition_id, Value::Duration(Duration::from(ChronoDuration::milliseconds(1500))),
+            Type::Duration);
+        send(&light.set_power_id, Value::OnOff(OnOff::On), Type::OnOff);
+        assert_eq!(*last_put.lock().unwrap(), r#"{"on":true,"transitiontime":15}"#);
+
+        // 2700K is 370 mireds.
+        send(&light.set_color_temperature_id, Value::ExtNumeric(ExtValue {
+            value: 2700.,
+            vendor: Id::new("foxlink@mozilla.com"),
+            adapter: Id::new("Philips Hue Adapter"),
+            kind: Id::new("ColorTemperature"),
+        }), Type::ExtNumeric);
+        assert_eq!(*last_put.lock().unwrap(), r#"{"ct":370,"transitiontime":15}"#);
+
+        // Alerts are not affected by the transition time.
+        send(&light.set_alert_id, Value::Unit, Type::Unit);
+        assert_eq!(*last_put.lock().unwrap(), r#"{"alert":"select"}"#);
+    }
+
+    it "should notify watchers of the changes found by polling the bridge" {
+        let (tx, rx) = channel();
+        let _guard = manager.watch_values(vec![
+            Targetted::new(vec![ChannelSelector::new().with_id(light.get_power_id.clone())], Exactly::Always),
+            Targetted::new(vec![ChannelSelector::new().with_id(light.get_available_id.clone())], Exactly::Always),
+        ], Box::new(tx), User::None);
+        let next_event = || {
+            loop {
+                match rx.recv().unwrap() {
+                    APIWatchEvent::EnterRange { channel, value, type_ } => return (channel, value.to_value(&type_).unwrap()),
+                    APIWatchEvent::ChannelAdded(_) => continue,
+                    other => panic!("Unexpected event {:?}", other)
+                }
+            }
+        };
+
+        poll();
+        let mut events = vec![next_event(), next_event()];
+        events.sort_by(|a, b| a.0.to_string().cmp(&b.0.to_string()));
+        assert_eq!(events, vec![
+            (light.get_available_id.clone(), Value::OnOff(OnOff::On)),
+            (light.get_power_id.clone(), Value::OnOff(OnOff::On)),
+        ]);
+
+        // Nothing changed, so only the light going out and becoming unreachable is reported.
+        poll();
+        *state.lock().unwrap() = (false, false);
+        poll();
+        let mut events = vec![next_event(), next_event()];
+        events.sort_by(|a, b| a.0.to_string().cmp(&b.0.to_string()));
+        assert_eq!(events, vec![
+            (light.get_available_id.clone(), Value::OnOff(OnOff::Off)),
+            (light.get_power_id.clone(), Value::OnOff(OnOff::Off)),
+        ]);
+    }
+
+    it "should fetch and watch the sensors of the bridge" {
+        let sensors = api.lock().unwrap().get_sensors().unwrap();
+        assert!(Sensor::new(api.clone(), "hub", "1", &sensors["1"]).is_none());
+        let sensor = Sensor::new(api.clone(), "hub", "2", &sensors["2"]).unwrap();
+        sensor.init_service(manager.clone(), adapter.services.clone(), &sensors["2"]).unwrap();
+
+        let selector = vec![ChannelSelector::new().with_id(sensor.get_value_id.clone())];
+        let fetched = manager.fetch_values(selector.clone(), None, User::None);
+        match fetched.get(&sensor.get_value_id) {
+            Some(&Ok(Some(ref fetched))) =>
+                assert_eq!(fetched.value.to_value(&Type::OpenClosed).unwrap(),
+                    Value::OpenClosed(OpenClosed::Closed)),
+            other => panic!("Unexpected fetch result {:?}", other)
+        }
+
+        let (tx, rx) = channel();
+        let _guard = manager.watch_values(vec![Targetted::new(selector, Exactly::Always)], Box::new(tx), User::None);
+        let next_event = || {
+            loop {
+                match rx.recv().unwrap() {
+                    APIWatchEvent::EnterRange { channel, value, type_ } => return (channel, value.to_value(&type_).unwrap()),
+                    APIWatchEvent::ChannelAdded(_) => continue,
+                    other => panic!("Unexpected event {:?}", other)
+                }
+            }
+        };
+
+        poll();
+        assert_eq!(next_event(), (sensor.get_value_id.clone(), Value::OpenClosed(OpenClosed::Closed)));
+        *presence.lock().unwrap() = true;
+        poll();
+        assert_eq!(next_event(), (sensor.get_value_id.clone(), Value::OpenClosed(OpenClosed::Open)));
+    }
 }
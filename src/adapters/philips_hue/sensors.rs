@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module that implements sensors for `PhilipsHueAdapter`
+//!
+//! Every supported sensor of a bridge is registered as a service with a
+//! single getter, which supports watching:
+//!
+//! - motion sensors (`ZLLPresence`) are `OpenClosed`, `Open` while motion
+//!   is detected;
+//! - dimmer switches (`ZLLSwitch`) are `ExtNumeric`, the code of the last
+//!   button event, e.g. 1002 when the "on" button is released;
+//! - temperature sensors (`ZLLTemperature`) are `Temperature`;
+//! - light level sensors (`ZLLLightLevel`) are `ExtNumeric`, in the unit of
+//!   the bridge, i.e. `10000 * log10(lux) + 1`.
+
+use foxbox_taxonomy::api::Error;
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::services::*;
+use foxbox_taxonomy::values::{ ExtValue, OpenClosed, Temperature, Type, Value };
+use super::*;
+use super::hub_api::HubApi;
+use super::structs::{ SensorEntry, SensorState };
+use std::sync::{ Arc, Mutex };
+
+const CUSTOM_PROPERTY_MANUFACTURER: &'static str = "manufacturer";
+const CUSTOM_PROPERTY_MODEL: &'static str = "model";
+const CUSTOM_PROPERTY_NAME: &'static str = "name";
+const CUSTOM_PROPERTY_TYPE: &'static str = "type";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SensorKind {
+    Motion,
+    Switch,
+    Temperature,
+    LightLevel,
+}
+
+impl SensorKind {
+    fn from_type(sensortype: &str) -> Option<Self> {
+        match sensortype {
+            "ZLLPresence" | "CLIPPresence" => Some(SensorKind::Motion),
+            "ZLLSwitch" | "ZGPSwitch" => Some(SensorKind::Switch),
+            "ZLLTemperature" | "CLIPTemperature" => Some(SensorKind::Temperature),
+            "ZLLLightLevel" | "CLIPLightLevel" => Some(SensorKind::LightLevel),
+            _ => None
+        }
+    }
+
+    /// The name of the channel, and of the field of the sensor state.
+    fn field(&self) -> &'static str {
+        match *self {
+            SensorKind::Motion => "presence",
+            SensorKind::Switch => "buttonevent",
+            SensorKind::Temperature => "temperature",
+            SensorKind::LightLevel => "lightlevel",
+        }
+    }
+
+    fn service_type(&self) -> &'static str {
+        match *self {
+            SensorKind::Motion => "Sensor/Motion",
+            SensorKind::Switch => "Sensor/Switch",
+            SensorKind::Temperature => "Sensor/Temperature",
+            SensorKind::LightLevel => "Sensor/LightLevel",
+        }
+    }
+
+    fn channel_kind(&self) -> ChannelKind {
+        let (kind, typ) = match *self {
+            SensorKind::Motion => ("Motion", Type::OpenClosed),
+            SensorKind::Switch => ("ButtonEvent", Type::ExtNumeric),
+            SensorKind::Temperature => ("Temperature", Type::Temperature),
+            SensorKind::LightLevel => ("LightLevel", Type::ExtNumeric),
+        };
+        ChannelKind::Extension {
+            vendor: Id::new("foxlink@mozilla.com"),
+            adapter: Id::new("Philips Hue Adapter"),
+            kind: Id::new(kind),
+            typ: typ,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sensor {
+    api: Arc<Mutex<HubApi>>,
+    hub_id: String,
+    sensor_id: String,
+    kind: SensorKind,
+    pub service_id: Id<ServiceId>,
+    pub get_value_id: Id<Channel>,
+}
+
+impl Sensor {
+    /// Create the sensor described by `entry`, if its type is supported.
+    pub fn new(api: Arc<Mutex<HubApi>>, hub_id: &str, sensor_id: &str, entry: &SensorEntry)
+        -> Option<Self>
+    {
+        SensorKind::from_type(&entry.sensortype).map(|kind| {
+            Sensor {
+                api: api,
+                hub_id: hub_id.to_owned(),
+                sensor_id: sensor_id.to_owned(),
+                kind: kind,
+                service_id: create_sensor_service_id(hub_id, sensor_id),
+                get_value_id: create_getter_id(kind.field(), hub_id, &format!("sensor-{}", sensor_id)),
+            }
+        })
+    }
+
+    pub fn init_service(&self, manager: Arc<AdapterManager>, services: LightServiceMap,
+        entry: &SensorEntry) -> Result<(), Error>
+    {
+        info!("New Philips Hue `{}` service for sensor {} on bridge {}",
+            entry.sensortype, self.sensor_id, self.hub_id);
+        let adapter_id = create_adapter_id();
+
+        let mut service = Service::empty(&self.service_id, &adapter_id);
+        service.properties.insert(CUSTOM_PROPERTY_MANUFACTURER.to_owned(),
+            entry.manufacturername.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_MODEL.to_owned(),
+            entry.modelid.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_NAME.to_owned(),
+            entry.name.to_owned());
+        service.properties.insert(CUSTOM_PROPERTY_TYPE.to_owned(),
+            self.kind.service_type().to_owned());
+        service.tags.insert(tag_id!(&format!("type:{}", self.kind.service_type())));
+
+        try!(manager.add_service(service));
+
+        try!(manager.add_channel(Channel {
+            supports_fetch: true,
+            supports_watch: true,
+            kind: self.kind.channel_kind(),
+            ..Channel::empty(&self.get_value_id, &self.service_id, &adapter_id)
+        }));
+
+        services.lock().unwrap().sensors.insert(self.get_value_id.clone(), self.clone());
+        Ok(())
+    }
+
+    /// The value of the sensor, given its state.
+    pub fn get_value(&self, state: &SensorState) -> Option<Value> {
+        let field = match state.get(self.kind.field()) {
+            Some(field) => field,
+            None => return None
+        };
+        let ext = |kind: &str, value: f64| Value::ExtNumeric(ExtValue {
+            value: value,
+            vendor: Id::new("foxlink@mozilla.com"),
+            adapter: Id::new("Philips Hue Adapter"),
+            kind: Id::new(kind),
+        });
+        match self.kind {
+            SensorKind::Motion => field.as_boolean().map(|presence|
+                Value::OpenClosed(if presence { OpenClosed::Open } else { OpenClosed::Closed })),
+            SensorKind::Switch => field.as_f64().map(|event| ext("ButtonEvent", event)),
+            // Hue API gives temperatures in hundredths of degrees Celsius.
+            SensorKind::Temperature => field.as_f64().map(|temperature|
+                Value::Temperature(Temperature::C(temperature / 100f64))),
+            SensorKind::LightLevel => field.as_f64().map(|level| ext("LightLevel", level)),
+        }
+    }
+
+    /// Fetch the value of the sensor from the bridge.
+    pub fn fetch_value(&self) -> Option<Value> {
+        let entry = self.api.lock().unwrap().get_sensor_status(&self.sensor_id);
+        entry.and_then(|entry| self.get_value(&entry.state))
+    }
+}
+
+/// Sensors and lights are numbered independently by the bridge, so sensor
+/// identifiers are prefixed to keep their services distinct.
+pub fn create_sensor_service_id(hub_id: &str, sensor_id: &str) -> Id<ServiceId> {
+    create_light_id(hub_id, &format!("sensor-{}", sensor_id))
+}
@@ -77,7 +77,7 @@ pub struct SettingsLightEntry {
     pub state: SettingsLightState,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct SettingsLightState {
     pub on: bool,
     pub ct: Option<u32>,
@@ -91,6 +91,36 @@ pub struct SettingsLightState {
     pub alert: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct GroupEntry {
+    pub name: String,
+    pub lights: Vec<String>,
+    #[serde(rename="type")]
+    pub grouptype: String,
+    pub class: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SceneEntry {
+    pub name: String,
+    pub lights: Vec<String>,
+    pub recycle: Option<bool>,
+}
+
+/// The state of a sensor depends on its type, e.g. `{"presence": true, ...}` for
+/// motion sensors or `{"temperature": 2150, ...}` for temperature sensors.
+pub type SensorState = BTreeMap<String, serde_json::Value>;
+
+#[derive(Deserialize, Debug)]
+pub struct SensorEntry {
+    pub name: String,
+    #[serde(rename="type")]
+    pub sensortype: String,
+    pub modelid: String,
+    pub manufacturername: String,
+    pub state: SensorState,
+}
+
 impl Settings {
     pub fn new(json: &str) -> Option<Settings> {
         parse_json(json)
@@ -0,0 +1,71 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Stores scenes for the scenes adapter.
+//!
+//! # The scenes database
+//!
+//! The "scenes" table stores one row per channel of each scene, with the
+//! name of the scene, the id of the channel and the JSON-serialized payload
+//! captured for this channel.
+//!
+
+use rusqlite::{ self, Connection };
+
+use std::collections::HashMap;
+
+pub struct ScenesDb {
+    db: Connection,
+}
+
+impl ScenesDb {
+    /// Opens the database at `path` and creates it if not available yet.
+    pub fn new(path: &str) -> rusqlite::Result<Self> {
+        let db = try!(Connection::open(path));
+        try!(db.execute("CREATE TABLE IF NOT EXISTS scenes (
+                    name        TEXT NOT NULL,
+                    channel     TEXT NOT NULL,
+                    payload     TEXT NOT NULL,
+                    PRIMARY KEY (name, channel)
+            )", &[]));
+
+        Ok(ScenesDb {
+            db: db
+        })
+    }
+
+    /// Stores the scene `name`, replacing any previous scene with the same name.
+    ///
+    /// `payloads` is a list of `(channel id, JSON-serialized payload)`. The scene is stored
+    /// atomically: if any of the payloads cannot be stored, the previous scene is kept.
+    pub fn put(&self, name: &str, payloads: &[(String, String)]) -> rusqlite::Result<()> {
+        let transaction = try!(self.db.transaction());
+        try!(self.remove(name));
+        for &(ref channel, ref payload) in payloads {
+            try!(self.db.execute("INSERT INTO scenes VALUES ($1, $2, $3)",
+                                 &[&name, channel, payload]
+            ));
+        }
+        transaction.commit()
+    }
+
+    /// Removes the scene `name`.
+    pub fn remove(&self, name: &str) -> rusqlite::Result<()> {
+        try!(self.db.execute("DELETE FROM scenes WHERE name=$1", &[&name]));
+        Ok(())
+    }
+
+    /// Gets all the scenes, indexed by name.
+    pub fn get_scenes(&self) -> rusqlite::Result<HashMap<String, Vec<(String, String)>>> {
+        let mut scenes = HashMap::new();
+        let mut stmt = try!(self.db.prepare("SELECT name, channel, payload FROM scenes"));
+        let rows = try!(stmt.query(&[]));
+        for result_row in rows {
+            let row = try!(result_row);
+            let name : String = row.get(0);
+            scenes.entry(name).or_insert_with(Vec::new).push((row.get(1), row.get(2)));
+        }
+        Ok(scenes)
+    }
+}
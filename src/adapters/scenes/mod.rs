@@ -0,0 +1,428 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An adapter capturing and restoring the state of a set of channels.
+//!
+//! A scene is captured by sending a `Json` definition to the `AddScene` setter, e.g.
+//!
+//! ```json
+//! {
+//!   "name": "Movie mode",
+//!   "channels": [{"tags": ["living room"]}, {"id": "tv-power"}]
+//! }
+//! ```
+//!
+//! The adapter fetches the current value of every channel matched by `channels` that supports
+//! both fetch and send, and stores these values under the name of the scene. Each scene is
+//! exposed as its own service, with an `ActivateScene` setter that sends the stored values back
+//! to their channels, and a `RemoveScene` setter. Capturing a scene that matches no such
+//! channel fails with `InvalidValue`.
+//!
+//! Scenes are stored in the profile, so they survive restarts.
+
+mod db;
+
+use adapters::utils::{ self, db_error, find_item, first_error };
+
+use foxbox_taxonomy::api::{ API, Error, InternalError, TargetMap, Targetted, User };
+use foxbox_taxonomy::io::Payload;
+use foxbox_taxonomy::manager::*;
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::selector::*;
+use foxbox_taxonomy::services::{ AdapterId, ServiceId, Service, Channel, ChannelKind };
+use foxbox_taxonomy::util::{ Exactly, Id };
+use foxbox_taxonomy::values::{ Json, Type, TypeError, Value };
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+
+static ADAPTER_NAME: &'static str = "Scenes adapter (built-in)";
+static ADAPTER_VENDOR: &'static str = "team@link.mozilla.org";
+static ADAPTER_VERSION: [u32;4] = [0, 0, 0, 0];
+
+/// The request to capture a scene, as provided by the user.
+#[derive(Clone, Debug)]
+pub struct SceneRequest {
+    /// The name of the scene. Also used to derive the ids of the service and its channels.
+    pub name: String,
+
+    /// The channels to capture. Only channels that support both fetch and send are captured.
+    pub channels: Vec<ChannelSelector>,
+}
+
+impl Parser<SceneRequest> for SceneRequest {
+    fn description() -> String {
+        "SceneRequest".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        let name = try!(path.push("name", |path| String::take(path, source, "name")));
+        let channels = try!(path.push("channels", |path| Vec::<ChannelSelector>::take(path, source, "channels")));
+        Ok(SceneRequest {
+            name: name,
+            channels: channels,
+        })
+    }
+}
+
+/// An internal data structure to track the service, channels and values of a scene.
+#[derive(Clone)]
+struct Scene {
+    name: String,
+
+    /// The values captured for each channel.
+    payloads: HashMap<Id<Channel>, Payload>,
+
+    service_id: Id<ServiceId>,
+    setter_activate_id: Id<Channel>,
+    setter_remove_id: Id<Channel>,
+}
+
+impl ToJSON for Scene {
+    fn to_json(&self) -> JSON {
+        vec![
+            ("name", self.name.to_json()),
+            ("channels", self.payloads.to_json()),
+        ].to_json()
+    }
+}
+
+/// `SceneAdapter` exposes scenes as services.
+///
+/// This adapter exposes a root service, with an `AddScene` setter (to capture a scene, replacing
+/// any scene with the same name) and a `Scenes` getter (to list the scenes and their values).
+/// Each scene is exposed as its own service, with `ActivateScene` and `RemoveScene` setters.
+#[derive(Clone)]
+pub struct SceneAdapter {
+    /// A reference to the AdapterManager.
+    adapter_manager: Arc<AdapterManager>,
+
+    /// The scenes, indexed by name.
+    scenes: Arc<Mutex<HashMap<String, Scene>>>,
+
+    /// The path to the database storing the scenes.
+    db_path: String,
+
+    /// The ID of this adapter (permanently fixed)
+    adapter_id: Id<AdapterId>,
+
+    /// The ID of the root service's "Add Scene" setter.
+    setter_add_scene_id: Id<Channel>,
+
+    /// The ID of the root service's "Scenes" getter.
+    getter_scenes_id: Id<Channel>,
+}
+
+/// A `ChannelKind` specific to this adapter.
+fn extension_kind(adapter_id: &Id<AdapterId>, kind: &str, typ: Type) -> ChannelKind {
+    utils::extension_kind(ADAPTER_VENDOR, adapter_id, kind, typ)
+}
+
+impl Adapter for SceneAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        self.adapter_id.clone()
+    }
+
+    fn name(&self) -> &str {
+        ADAPTER_NAME
+    }
+
+    fn vendor(&self) -> &str {
+        ADAPTER_VENDOR
+    }
+
+    fn version(&self) -> &[u32;4] {
+        &ADAPTER_VERSION
+    }
+
+    fn fetch_values(&self, mut set: Vec<Id<Channel>>, _: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+        set.drain(..).map(|id| {
+            if id == self.getter_scenes_id {
+                let scenes = self.scenes.lock().unwrap();
+                let json = JSON::Array(scenes.values().map(|scene| scene.to_json()).collect());
+                return (id, Ok(Some(Value::Json(Arc::new(Json(json))))));
+            }
+            (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
+        }).collect()
+    }
+
+    fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, user: User) -> ResultMap<Id<Channel>, (), Error> {
+        values.drain().map(|(id, value)| {
+            if id == self.setter_add_scene_id {
+                let result = match value {
+                    Value::Json(ref json) => self.add_scene(&json.0, &user),
+                    _ => Err(Error::TypeError(TypeError {
+                        expected: Type::Json,
+                        got: value.get_type()
+                    }))
+                };
+                return (id, result);
+            }
+            if let Some(scene) = self.find_scene(|scene| scene.setter_activate_id == id) {
+                return (id, self.activate_scene(&scene, &user));
+            }
+            if let Some(scene) = self.find_scene(|scene| scene.setter_remove_id == id) {
+                return (id, self.remove_scene(&scene.name));
+            }
+            (id.clone(), Err(Error::InternalError(InternalError::NoSuchChannel(id))))
+        }).collect()
+    }
+}
+
+impl SceneAdapter {
+    fn get_db(&self) -> Result<db::ScenesDb, Error> {
+        db::ScenesDb::new(&self.db_path).map_err(db_error)
+    }
+
+    fn find_scene<F>(&self, predicate: F) -> Option<Scene> where F: Fn(&Scene) -> bool {
+        find_item(&self.scenes, predicate)
+    }
+
+    /// Capture the values of the channels of a scene, replacing any scene with the same name.
+    fn add_scene(&self, source: &JSON, user: &User) -> Result<(), Error> {
+        let request = try!(SceneRequest::parse(Path::new(), source).map_err(Error::ParseError));
+
+        let capturable = ChannelSelector::new()
+            .with_supports_fetch(Exactly::Exactly(true))
+            .with_supports_send(Exactly::Exactly(true));
+        let selectors = request.channels.iter()
+            .map(|selector| selector.clone().and(capturable.clone()))
+            .collect();
+        let channels : Vec<_> = self.adapter_manager.get_channels(selectors).drain(..)
+            // Scenes cannot contain the channels of other scenes.
+            .filter(|channel| channel.adapter != self.adapter_id)
+            .map(|channel| ChannelSelector::new().with_id(channel.id))
+            .collect();
+        if channels.is_empty() {
+            return Err(Error::InvalidValue(Value::Json(Arc::new(Json(source.clone())))));
+        }
+
+        let mut payloads = HashMap::new();
+        for (id, result) in self.adapter_manager.fetch_values(channels, None, user.clone()) {
+            match result {
+                Ok(Some(fetched)) => {
+                    payloads.insert(id, fetched.value);
+                },
+                Ok(None) => {},
+                Err(err) => {
+                    warn!("[scenes@link.mozilla.org] Could not capture channel {} for scene {}: {:?}",
+                        id, request.name, err);
+                }
+            }
+        }
+
+        let stored : Vec<_> = payloads.iter()
+            .map(|(id, payload)| (id.to_string(), serde_json::to_string(&payload.to_json()).unwrap()))
+            .collect();
+        try!(try!(self.get_db()).put(&request.name, &stored).map_err(db_error));
+
+        try!(self.remove_scene_service(&request.name));
+        self.add_scene_service(&request.name, payloads)
+    }
+
+    /// Send the values of a scene to their channels.
+    fn activate_scene(&self, scene: &Scene, user: &User) -> Result<(), Error> {
+        let targets : TargetMap<ChannelSelector, Payload> = scene.payloads.iter()
+            .map(|(id, payload)| Targetted::new(vec![ChannelSelector::new().with_id(id.clone())], payload.clone()))
+            .collect();
+        let results = self.adapter_manager.send_values(targets, user.clone());
+        info!("[scenes@link.mozilla.org] Activated scene '{}'", scene.name);
+        first_error(results, |id, err| {
+            warn!("[scenes@link.mozilla.org] Could not restore channel {} for scene {}: {:?}",
+                id, scene.name, err);
+        })
+    }
+
+    /// Remove a scene and its stored values.
+    fn remove_scene(&self, name: &str) -> Result<(), Error> {
+        try!(self.remove_scene_service(name));
+        try!(self.get_db()).remove(name).map_err(db_error)
+    }
+
+    /// Add the service and channels of a scene. (This does not store the values.)
+    fn add_scene_service(&self, name: &str, payloads: HashMap<Id<Channel>, Payload>) -> Result<(), Error> {
+        let service_id = Id::new(&format!("scene/{}", name));
+        let scene = Scene {
+            name: name.to_owned(),
+            payloads: payloads,
+            setter_activate_id: Id::new(&format!("{}/activate", service_id.as_atom())),
+            setter_remove_id: Id::new(&format!("{}/remove", service_id.as_atom())),
+            service_id: service_id,
+        };
+
+        let mut service = Service::empty(&scene.service_id, &self.adapter_id);
+        service.properties.insert("name".to_owned(), name.to_owned());
+        service.tags.insert(Id::new("type:Scene"));
+        try!(self.adapter_manager.add_service(service));
+
+        try!(self.adapter_manager.add_channel(Channel {
+            supports_send: true,
+            kind: extension_kind(&self.adapter_id, "ActivateScene", Type::Unit),
+            ..Channel::empty(&scene.setter_activate_id, &scene.service_id, &self.adapter_id)
+        }));
+
+        try!(self.adapter_manager.add_channel(Channel {
+            supports_send: true,
+            kind: extension_kind(&self.adapter_id, "RemoveScene", Type::Unit),
+            ..Channel::empty(&scene.setter_remove_id, &scene.service_id, &self.adapter_id)
+        }));
+
+        info!("[scenes@link.mozilla.org] Added scene '{}'", name);
+        self.scenes.lock().unwrap().insert(name.to_owned(), scene);
+        Ok(())
+    }
+
+    /// Remove the service and channels of a scene. (This does not remove the stored values.)
+    fn remove_scene_service(&self, name: &str) -> Result<(), Error> {
+        let scene = match self.scenes.lock().unwrap().remove(name) {
+            None => return Ok(()),
+            Some(scene) => scene
+        };
+        info!("[scenes@link.mozilla.org] Removed scene '{}'", name);
+        self.adapter_manager.remove_service(&scene.service_id)
+    }
+
+    /// Register the adapter and restore the scenes stored in the database at `db_path`.
+    pub fn init(manager: &Arc<AdapterManager>, db_path: &str) -> Result<(), Error> {
+        let adapter_id = Id::new("scenes@link.mozilla.org");
+        let setter_add_scene_id = Id::new("scenes-add-scene");
+        let getter_scenes_id = Id::new("scenes-get-scenes");
+        let root_service_id = Id::new("scenes-root-service");
+
+        let adapter = SceneAdapter {
+            adapter_manager: manager.clone(),
+            scenes: Arc::new(Mutex::new(HashMap::new())),
+            db_path: db_path.to_owned(),
+            adapter_id: adapter_id.clone(),
+            setter_add_scene_id: setter_add_scene_id.clone(),
+            getter_scenes_id: getter_scenes_id.clone(),
+        };
+
+        // Add the adapter and the root service (the one that exposes `AddScene` for capturing scenes).
+        try!(manager.add_adapter(Arc::new(adapter.clone())));
+        try!(manager.add_service(Service::empty(&root_service_id, &adapter_id)));
+        try!(manager.add_channel(Channel {
+            kind: extension_kind(&adapter_id, "AddScene", Type::Json),
+            supports_send: true,
+            ..Channel::empty(&setter_add_scene_id, &root_service_id, &adapter_id)
+        }));
+        try!(manager.add_channel(Channel {
+            kind: extension_kind(&adapter_id, "Scenes", Type::Json),
+            supports_fetch: true,
+            ..Channel::empty(&getter_scenes_id, &root_service_id, &adapter_id)
+        }));
+
+        let scenes = try!(try!(adapter.get_db()).get_scenes().map_err(db_error));
+        for (name, mut stored) in scenes {
+            let mut payloads = HashMap::new();
+            for (channel, payload) in stored.drain(..) {
+                let parsed = match serde_json::from_str::<JSON>(&payload) {
+                    Ok(json) => Payload::parse(Path::new(), &json).map_err(|err| format!("{:?}", err)),
+                    Err(err) => Err(format!("{:?}", err))
+                };
+                match parsed {
+                    Ok(payload) => {
+                        payloads.insert(Id::new(&channel), payload);
+                    },
+                    Err(err) => {
+                        error!("[scenes@link.mozilla.org] Unable to restore channel {} of scene {}: {}",
+                            channel, name, err);
+                    }
+                }
+            }
+            if let Err(err) = adapter.add_scene_service(&name, payloads) {
+                error!("[scenes@link.mozilla.org] Unable to restore scene {}: {:?}", name, err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+describe! scenes {
+    before_each {
+        use foxbox_taxonomy::values::OnOff;
+        use tempdir::TempDir;
+
+        /// A TV whose power can be fetched and sent.
+        struct StubTv(Arc<Mutex<OnOff>>);
+        impl Adapter for StubTv {
+            fn id(&self) -> Id<AdapterId> {
+                Id::new("stub-tv")
+            }
+            fn name(&self) -> &str {
+                "Stub TV"
+            }
+            fn vendor(&self) -> &str {
+                ADAPTER_VENDOR
+            }
+            fn version(&self) -> &[u32;4] {
+                &ADAPTER_VERSION
+            }
+            fn fetch_values(&self, mut set: Vec<Id<Channel>>, _: User) -> ResultMap<Id<Channel>, Option<Value>, Error> {
+                set.drain(..).map(|id| (id, Ok(Some(Value::OnOff(self.0.lock().unwrap().clone()))))).collect()
+            }
+            fn send_values(&self, mut values: HashMap<Id<Channel>, Value>, _: User) -> ResultMap<Id<Channel>, (), Error> {
+                values.drain().map(|(id, value)| {
+                    match value {
+                        Value::OnOff(on_off) => {
+                            *self.0.lock().unwrap() = on_off;
+                            (id, Ok(()))
+                        }
+                        other => (id, Err(Error::InvalidValue(other)))
+                    }
+                }).collect()
+            }
+        }
+
+        let power = Arc::new(Mutex::new(OnOff::On));
+        let manager = Arc::new(AdapterManager::new(None));
+        let tv_id = Id::<AdapterId>::new("stub-tv");
+        let tv_service_id = Id::<ServiceId>::new("tv");
+        manager.add_adapter(Arc::new(StubTv(power.clone()))).unwrap();
+        manager.add_service(Service::empty(&tv_service_id, &tv_id)).unwrap();
+        manager.add_channel(Channel {
+            kind: ChannelKind::LightOn,
+            supports_fetch: true,
+            supports_send: true,
+            ..Channel::empty(&Id::new("tv-power"), &tv_service_id, &tv_id)
+        }).unwrap();
+
+        let profile_dir = TempDir::new_in("/tmp", "foxbox").unwrap();
+        let db_path = profile_dir.path().join("scenes.sqlite");
+        SceneAdapter::init(&manager, db_path.to_str().unwrap()).unwrap();
+    }
+
+    it "should parse scene requests" {
+        let request = SceneRequest::from_str(r#"{
+            "name": "Movie mode",
+            "channels": [{"tags": ["living room"]}, {"id": "tv-power"}]
+        }"#).unwrap();
+        assert_eq!(request.name, "Movie mode");
+        assert_eq!(request.channels.len(), 2);
+        let request = SceneRequest::from_str(r#"{"name": "Movie mode", "channels": {"id": "tv-power"}}"#).unwrap();
+        assert_eq!(request.channels.len(), 1);
+    }
+
+    it "should capture and activate scenes" {
+        let send = |id: &str, value: Value, typ: Type| {
+            let payload = Payload::from_value(&value, &typ).unwrap();
+            let selector = vec![ChannelSelector::new().with_id(Id::new(id))];
+            let results = manager.send_values(vec![Targetted::new(selector, payload)], User::None);
+            match results.get(&Id::new(id)) {
+                Some(&Ok(())) => {},
+                other => panic!("Unexpected send result {:?}", other)
+            }
+        };
+
+        let request : JSON = serde_json::from_str(r#"{"name": "Movie mode", "channels": [{"id": "tv-power"}]}"#).unwrap();
+        send("scenes-add-scene", Value::Json(Arc::new(Json(request))), Type::Json);
+
+        *power.lock().unwrap() = OnOff::Off;
+        send("scene/Movie mode/activate", Value::Unit, Type::Unit);
+        assert_eq!(*power.lock().unwrap(), OnOff::On);
+    }
+}
@@ -4,12 +4,15 @@ use foxbox_taxonomy::api::{ Error, InternalError, User };
 use foxbox_taxonomy::manager::*;
 use foxbox_taxonomy::services::{ AdapterId, ServiceId, Service, Channel, ChannelKind };
 use foxbox_taxonomy::util::Id;
-use foxbox_taxonomy::values::{ Duration, Type, Value, TypeError, OnOff };
+use foxbox_taxonomy::values::{ Duration, Json, TimeStamp, Type, Value, TypeError, OnOff };
 
 use foxbox_thinkerbell::compile::ExecutableDevEnv;
-use foxbox_thinkerbell::manager::{ ScriptManager, ScriptId, Error as ScriptManagerError };
-use foxbox_thinkerbell::run::ExecutionEvent;
+use foxbox_thinkerbell::manager::{ ScriptManager, ScriptId, TraceEntry, Error as ScriptManagerError };
+use foxbox_thinkerbell::schedule::Location;
 
+use chrono::UTC;
+
+use serde_json;
 use timer;
 use transformable_channels::mpsc::*;
 
@@ -32,6 +35,9 @@ static ADAPTER_VERSION: [u32;4] = [0, 0, 0, 0];
 /// - Set Enabled (setter) -- toggles whether or not the script is enabled
 /// - Get Enabled (getter) -- returns whether or not the script is enabled
 /// - Remove (setter) -- removes the script
+/// - Get Trace (getter) -- returns the most recent execution events of the script, e.g. conditions
+///   becoming met or unmet, timers and the result of sending values. Watching it yields each new
+///   event as it is recorded.
 ///
 /// This adapter performs most actions by delegating channel messages to its main thread.
 #[derive(Clone)]
@@ -48,6 +54,59 @@ pub struct ThinkerbellAdapter {
 
     /// The ID of the root service's "Add Rule" setter.
     setter_add_rule_id: Id<Channel>,
+
+    /// The watchers of the trace getters.
+    trace_watchers: Arc<Mutex<TraceWatchers>>,
+}
+
+struct TraceWatcher {
+    channel: Id<Channel>,
+    tx: Box<ExtSender<WatchEvent<Value>>>,
+}
+
+#[derive(Default)]
+struct TraceWatchers {
+    watchers: HashMap<usize, TraceWatcher>,
+    next_watcher: usize,
+}
+
+impl TraceWatchers {
+    /// Send a new trace entry to the watchers of the trace getter of its script.
+    fn notify(&self, script_id: &Id<ScriptId>, entry: &TraceEntry) {
+        let getter_id = trace_getter_id(&rule_service_id(script_id));
+        let value = Value::Json(Arc::new(Json(serde_json::to_value(entry))));
+        for watcher in self.watchers.values() {
+            if watcher.channel == getter_id {
+                let _ = watcher.tx.send(WatchEvent::Enter {
+                    id: getter_id.clone(),
+                    value: value.clone()
+                });
+            }
+        }
+    }
+}
+
+/// A guard used to stop watching a trace getter.
+struct Guard {
+    key: usize,
+    trace_watchers: Arc<Mutex<TraceWatchers>>,
+}
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.trace_watchers.lock().unwrap().watchers.remove(&self.key);
+    }
+}
+impl AdapterWatchGuard for Guard {
+}
+
+/// The ID of the service exposing a script.
+fn rule_service_id(script_id: &Id<ScriptId>) -> Id<ServiceId> {
+    Id::new(&format!("thinkerbell/{}", script_id.as_atom()))
+}
+
+/// The ID of the getter exposing the execution trace of a script.
+fn trace_getter_id(service_id: &Id<ServiceId>) -> Id<Channel> {
+    Id::new(&format!("{}/trace", service_id.as_atom()))
 }
 
 /// Thinkerbell requires an execution environment following this API.
@@ -56,7 +115,10 @@ struct ThinkerbellExecutionEnv {
     adapter_manager: Arc<AdapterManager>,
 
     // FIXME: Timer's not clonable, so we should only use one, right? Does this have to be mutexed?
-    timer: Arc<Mutex<timer::Timer>>
+    timer: Arc<Mutex<timer::Timer>>,
+
+    /// The location of the box, if it has been configured.
+    location: Option<Location>,
 }
 impl fmt::Debug for ThinkerbellExecutionEnv {
     fn fmt(&self, _: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -81,6 +143,14 @@ impl ExecutableDevEnv for ThinkerbellExecutionEnv {
             let _ = sender.send(());
         })
     }
+
+    fn now(&self) -> TimeStamp {
+        TimeStamp::from_datetime(UTC::now())
+    }
+
+    fn location(&self) -> Option<Location> {
+        self.location.clone()
+    }
 }
 
 /// Convert a `ScriptManagerError` into an API Error.
@@ -133,6 +203,27 @@ impl Adapter for ThinkerbellAdapter {
             })
             .collect()
     }
+
+    fn register_watch(&self, mut watch: Vec<WatchTarget>) -> WatchResult {
+        // Only the trace getters support watching, and their values cannot be compared
+        // against a range, so watchers receive every new entry.
+        watch.drain(..).map(|(id, filter, tx)| {
+            if let Some(value) = filter {
+                return (id, Err(Error::InvalidValue(value)));
+            }
+            let mut trace_watchers = self.trace_watchers.lock().unwrap();
+            let key = trace_watchers.next_watcher;
+            trace_watchers.next_watcher += 1;
+            trace_watchers.watchers.insert(key, TraceWatcher {
+                channel: id.clone(),
+                tx: tx,
+            });
+            (id, Ok(Box::new(Guard {
+                key: key,
+                trace_watchers: self.trace_watchers.clone(),
+            }) as Box<AdapterWatchGuard>))
+        }).collect()
+    }
 }
 
 /// `ThinkerbellAdapter`'s main loop handles messages of these types.
@@ -151,6 +242,7 @@ struct ThinkerbellRule {
     getter_is_enabled_id: Id<Channel>,
     setter_is_enabled_id: Id<Channel>,
     setter_remove_id: Id<Channel>,
+    getter_trace_id: Id<Channel>,
 }
 
 impl ThinkerbellAdapter {
@@ -159,7 +251,7 @@ impl ThinkerbellAdapter {
     fn main(
         &self,
         rx: Receiver<ThinkAction>,
-        mut script_manager: ScriptManager<ThinkerbellExecutionEnv, RawSender<(Id<ScriptId>, ExecutionEvent)>>
+        mut script_manager: ScriptManager<ThinkerbellExecutionEnv, RawSender<(Id<ScriptId>, TraceEntry)>>
     ) {
         // Store an in-memory list of all of the rules (their getters, setters, etc.).
         // We need to track these to respond to getter/setter requests.
@@ -220,6 +312,11 @@ impl ThinkerbellAdapter {
                                 }
                             };
                             continue 'recv;
+                        } else if getter_id == rule.getter_trace_id {
+                            let trace = script_manager.get_trace(&rule.script_id);
+                            let json = serde_json::to_value(&trace);
+                            let _ = tx.send(Ok(Some(Value::Json(Arc::new(Json(json))))));
+                            continue 'recv;
                         }
                     }
                     let _ = tx.send(Err(Error::InternalError(InternalError::NoSuchChannel(getter_id.clone()))));
@@ -284,7 +381,7 @@ impl ThinkerbellAdapter {
 
     /// Add a new service for a script. (This does not start this script, this just adds a Service.)
     fn add_rule_service(&self, script_id: Id<ScriptId>) -> Result<ThinkerbellRule, Error> {
-        let service_id = Id::new(&format!("thinkerbell/{}", script_id.as_atom()));
+        let service_id = rule_service_id(&script_id);
 
         let rule = ThinkerbellRule {
             script_id: script_id.clone(),
@@ -293,6 +390,7 @@ impl ThinkerbellAdapter {
             getter_is_enabled_id: Id::new(&format!("{}/get_enabled", service_id.as_atom())),
             setter_is_enabled_id: Id::new(&format!("{}/set_enabled", service_id.as_atom())),
             setter_remove_id: Id::new(&format!("{}/remove", service_id.as_atom())),
+            getter_trace_id: trace_getter_id(&service_id),
         };
 
         try!(self.adapter_manager.add_service(Service::empty(&service_id, &self.adapter_id)));
@@ -323,6 +421,14 @@ impl ThinkerbellAdapter {
              kind:  ChannelKind::RemoveThinkerbellRule,
              ..Channel::empty(&rule.setter_remove_id, &service_id, &self.adapter_id)
         }));
+
+        // Add getter for the execution trace.
+        try!(self.adapter_manager.add_channel(Channel {
+             supports_fetch: true,
+             supports_watch: true,
+             kind:  ChannelKind::ThinkerbellRuleTrace,
+             ..Channel::empty(&rule.getter_trace_id, &service_id, &self.adapter_id)
+        }));
         info!("[thinkerbell@link.mozilla.org] Added Thinkerbell Rule for '{}'", &script_id.to_string());
 
         Ok(rule)
@@ -335,7 +441,9 @@ impl ThinkerbellAdapter {
     }
 
     /// Everything is initialized here, but the real work happens in the main() loop.
-    pub fn init(manager: &Arc<AdapterManager>, scripts_path: &str) -> Result<(), Error> {
+    ///
+    /// `location` is used by scripts that depend on the time of sunrise or sunset.
+    pub fn init(manager: &Arc<AdapterManager>, scripts_path: &str, location: Option<Location>) -> Result<(), Error> {
         let adapter_id = Id::new("thinkerbell@link.mozilla.org");
         let setter_add_rule_id = Id::new("thinkerbell-add-rule");
         let root_service_id = Id::new("thinkerbell-root-service");
@@ -344,7 +452,8 @@ impl ThinkerbellAdapter {
         let (tx_env, rx_env) = channel();
         let env = ThinkerbellExecutionEnv {
             adapter_manager: manager.clone(),
-            timer: Arc::new(Mutex::new(timer::Timer::new()))
+            timer: Arc::new(Mutex::new(timer::Timer::new())),
+            location: location,
         };
 
         let mut script_manager = try!(
@@ -363,6 +472,7 @@ impl ThinkerbellAdapter {
             adapter_manager: manager.clone(),
             adapter_id: adapter_id.clone(),
             setter_add_rule_id: setter_add_rule_id.clone(),
+            trace_watchers: Arc::new(Mutex::new(TraceWatchers::default())),
         };
 
         // Add the adapter and the root service (the one that exposes `AddThinkerbellRule` for adding new rules).
@@ -374,18 +484,19 @@ impl ThinkerbellAdapter {
             ..Channel::empty(&setter_add_rule_id, &root_service_id, &adapter_id)
         }));
 
+        let trace_watchers = adapter.trace_watchers.clone();
         thread::spawn(move || {
             info!("[thinkerbell@link.mozilla.org] Started Thinkerbell main thread.");
             adapter.main(rx, script_manager)
         });
 
-        // FIXME: We need to consume the events from the execution environment to prevent the
-        // queue from growing unboundedly, but right now we don't use these events.
+        // Consume the trace entries recorded by the `ScriptManager`, both to prevent the queue
+        // from growing unboundedly and to forward them to the watchers of the trace getters.
         // FIXME: When a script stops due to an error, we should update our state accordingly.
         // (Right now we only update the state when the script is explicitly started/stopped.)
         thread::spawn(move || {
-            loop {
-                let _ = rx_env.recv();
+            for (script_id, entry) in rx_env {
+                trace_watchers.lock().unwrap().notify(&script_id, &entry);
             }
         });
 
@@ -0,0 +1,74 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Helpers shared by the built-in adapters.
+
+use foxbox_taxonomy::api::{ Error, InternalError };
+use foxbox_taxonomy::manager::ResultMap;
+use foxbox_taxonomy::parse::Parser;
+use foxbox_taxonomy::services::{ AdapterId, Channel, ChannelKind };
+use foxbox_taxonomy::util::Id;
+use foxbox_taxonomy::values::Type;
+
+use rusqlite;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+
+pub fn db_error(err: rusqlite::Error) -> Error {
+    Error::InternalError(InternalError::GenericError(format!("{:?}", err)))
+}
+
+/// A `ChannelKind` specific to an adapter.
+pub fn extension_kind(vendor: &str, adapter_id: &Id<AdapterId>, kind: &str, typ: Type) -> ChannelKind {
+    ChannelKind::Extension {
+        vendor: Id::new(vendor),
+        adapter: adapter_id.clone(),
+        kind: Id::new(kind),
+        typ: typ,
+    }
+}
+
+/// The first item of `items` that matches `predicate`.
+pub fn find_item<T, F>(items: &Mutex<HashMap<String, T>>, predicate: F) -> Option<T>
+    where T: Clone, F: Fn(&T) -> bool
+{
+    let items = items.lock().unwrap();
+    items.values().find(|item| predicate(item)).cloned()
+}
+
+/// Reduce the results of sending values to several channels to the first error, if any.
+/// `on_error` is called for each error, e.g. to log it.
+pub fn first_error<F>(mut results: ResultMap<Id<Channel>, (), Error>, on_error: F) -> Result<(), Error>
+    where F: Fn(&Id<Channel>, &Error)
+{
+    let mut first_error = None;
+    for (id, result) in results.drain() {
+        if let Err(err) = result {
+            on_error(&id, &err);
+            if first_error.is_none() {
+                first_error = Some(err);
+            }
+        }
+    }
+    match first_error {
+        None => Ok(()),
+        Some(err) => Err(err)
+    }
+}
+
+/// Read and parse the configuration file at `config_path`, or `None` if there is no such file.
+pub fn read_config<T>(config_path: &str) -> Result<Option<T>, Error> where T: Parser<T> {
+    let mut source = String::new();
+    match File::open(config_path) {
+        Err(_) => return Ok(None),
+        Ok(mut file) => {
+            try!(file.read_to_string(&mut source).map_err(|err|
+                Error::InternalError(InternalError::GenericError(format!("Could not read {}: {}", config_path, err)))));
+        }
+    }
+    T::from_str(&source).map(Some).map_err(Error::ParseError)
+}
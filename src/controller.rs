@@ -7,8 +7,12 @@ extern crate mio;
 
 use adapters::AdapterManager;
 use config_store::ConfigService;
+use foxbox_taxonomy::api::{ API, User };
+use foxbox_taxonomy::history::HistoryRecorder;
 use foxbox_taxonomy::manager::AdapterManager as TaxoManager;
-use foxbox_users::UsersManager;
+use foxbox_taxonomy::parse::Parser;
+use foxbox_taxonomy::selector::ChannelSelector;
+use foxbox_users::{ ReadFilter, UsersManager };
 use http_server::HttpServer;
 use profile_service::{ ProfilePath, ProfileService };
 use std::collections::hash_map::HashMap;
@@ -18,10 +22,12 @@ use std::net::ToSocketAddrs;
 use std::path::PathBuf;
 use std::sync::{ Arc, Mutex };
 use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
 use std::vec::IntoIter;
 use upnp::UpnpManager;
 use tls::{ CertificateManager, CertificateRecord, SniSslContextProvider, TlsOption };
 use traits::Controller;
+use transformable_channels::mpsc::*;
 use ws_server::WsServer;
 use ws;
 
@@ -85,11 +91,55 @@ impl Controller for FoxBox {
         let tags_db_path = PathBuf::from(self.profile_service.path_for("taxonomy_tags.sqlite"));
         let taxo_manager = Arc::new(TaxoManager::new(Some(tags_db_path)));
 
+        // Until the role `admin` of the access control lists has members, only the owners of
+        // the box, i.e. the admins created during setup, may modify the access control lists.
+        let users_manager = self.users_manager.clone();
+        taxo_manager.set_acl_owner_check(Box::new(move |user| {
+            let id = match *user {
+                User::Id(id) => id,
+                User::None => return false
+            };
+            match users_manager.get_db().read(ReadFilter::IsAdmin(true)) {
+                Ok(admins) => admins.iter().any(|admin| admin.id == Some(id)),
+                Err(err) => {
+                    error!("Could not read the owners of the box: {:?}", err);
+                    false
+                }
+            }
+        }));
+
+        // Let websocket clients know when services and channels appear, disappear or are
+        // retagged. The watch lasts as long as `topology_guard`.
+        let (tx_topology, rx_topology) = channel();
+        let topology_guard = taxo_manager.watch_topology(vec![], Box::new(tx_topology));
+        {
+            let controller = self.clone();
+            thread::spawn(move || {
+                for event in rx_topology {
+                    controller.broadcast_to_websockets(json_value!({ type: "core/topology", event: event }));
+                }
+            });
+        }
+
         let mut adapter_manager = AdapterManager::new(self.clone());
         adapter_manager.start(&taxo_manager);
 
-        HttpServer::new(self.clone()).start(&taxo_manager);
-        WsServer::start(self.clone());
+        // Record the values of the channels selected in the configuration, by default
+        // the ones tagged with "history".
+        let history_channels = self.config.get_or_set_default("history", "channels", r#"[{"tags": ["history"]}]"#);
+        let history_selectors = Vec::<ChannelSelector>::from_str(&history_channels).unwrap_or_else(|err| {
+            error!("Invalid history/channels configuration, not recording any value: {}", err);
+            vec![]
+        });
+        let history_db_path = PathBuf::from(self.profile_service.path_for("channels_history.sqlite"));
+        let history = HistoryRecorder::new(&taxo_manager, Some(&history_db_path), history_selectors.clone()).or_else(|err| {
+            error!("Could not open the channel history, recording in memory only: {}", err);
+            HistoryRecorder::new(&taxo_manager, None, history_selectors)
+        }).unwrap();
+        let history = Arc::new(history);
+
+        HttpServer::new(self.clone()).start(&taxo_manager, &history);
+        WsServer::start(self.clone(), &taxo_manager);
 
         self.upnp.search(None).unwrap();
 
@@ -99,6 +149,7 @@ impl Controller for FoxBox {
         }).unwrap();
 
         debug!("Stopping controller");
+        drop(topology_guard);
         adapter_manager.stop();
         taxo_manager.stop();
     }
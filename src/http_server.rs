@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use foxbox_taxonomy::history::HistoryRecorder;
 use foxbox_taxonomy::manager::*;
 use hyper::net::{ NetworkListener };
 use iron::{ AfterMiddleware, Chain, Handler,
@@ -64,9 +65,10 @@ impl<T: Controller> HttpServer<T> {
         HttpServer { controller: controller }
     }
 
-    pub fn start(&mut self, adapter_api: &Arc<AdapterManager>) {
+    pub fn start(&mut self, adapter_api: &Arc<AdapterManager>, history: &Arc<HistoryRecorder>) {
         let taxonomy_chain = taxonomy_router::create(self.controller.clone(),
-                                                      adapter_api);
+                                                      adapter_api,
+                                                      history);
 
         let users_manager = self.controller.get_users_manager();
         let mut mount = Mount::new();
@@ -87,10 +89,17 @@ impl<T: Controller> HttpServer<T> {
             // Taxonomy router paths. Keep in sync with taxonomy_router.rs
             (vec![Method::Get, Method::Post], "api/v1/services".to_owned()),
             (vec![Method::Post, Method::Delete], "api/v1/services/tags".to_owned()),
+            (vec![Method::Post, Method::Delete], "api/v1/services/properties".to_owned()),
+            (vec![Method::Get, Method::Post, Method::Delete], "api/v1/acl/rules".to_owned()),
+            (vec![Method::Get, Method::Post, Method::Delete], "api/v1/acl/roles".to_owned()),
             (vec![Method::Get, Method::Post], "api/v1/channels".to_owned()),
             (vec![Method::Put], "api/v1/channels/get".to_owned()),
             (vec![Method::Put], "api/v1/channels/set".to_owned()),
-            (vec![Method::Post, Method::Delete], "api/v1/channels/tags".to_owned())
+            (vec![Method::Put], "api/v1/channels/watch".to_owned()),
+            (vec![Method::Put], "api/v1/channels/history".to_owned()),
+            (vec![Method::Put], "api/v1/audit".to_owned()),
+            (vec![Method::Post, Method::Delete], "api/v1/channels/tags".to_owned()),
+            (vec![Method::Put], "api/v1/thinkerbell/validate".to_owned())
         ]);
         chain.link_after(cors);
 
@@ -144,6 +153,7 @@ describe! http_server {
     before_each {
         extern crate hyper;
 
+        use foxbox_taxonomy::history::HistoryRecorder;
         use foxbox_taxonomy::manager::AdapterManager;
         use std::thread;
         use std::sync::Arc;
@@ -151,9 +161,10 @@ describe! http_server {
         use stubs::controller::ControllerStub;
 
         let taxo_manager = Arc::new(AdapterManager::new(None));
+        let history = Arc::new(HistoryRecorder::new(&taxo_manager, None, vec![]).unwrap());
 
         let mut http_server = HttpServer::new(ControllerStub::new());
-        http_server.start(&taxo_manager);
+        http_server.start(&taxo_manager, &history);
         // HACK: Let some time for the http server to start.
         thread::sleep(Duration::new(3, 0));
     }
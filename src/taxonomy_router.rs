@@ -4,39 +4,141 @@
 
 extern crate serde_json;
 
+use foxbox_taxonomy::acl::{ AclRole, AclRule };
+use foxbox_taxonomy::audit::AuditQuery;
 use foxbox_taxonomy::manager::*;
-use foxbox_taxonomy::api::{ API, Error, TargetMap, User };
+use foxbox_taxonomy::api::{ API, Error, FetchedValue, TargetMap, User, WatchEvent };
+use foxbox_taxonomy::history::{ HistoryQuery, HistoryRecorder };
 use foxbox_taxonomy::io::*;
-use foxbox_taxonomy::values::{ Binary, Type, Value };
+use foxbox_taxonomy::values::{ Binary, Duration as TaxoDuration, Type, Value };
 use foxbox_taxonomy::selector::*;
 use foxbox_taxonomy::services::*;
 
+use foxbox_thinkerbell::compile::validate as validate_script;
+
 use foxbox_users::AuthEndpoint;
 use foxbox_users::SessionToken;
 
 use iron::{ Handler, headers, IronResult, Request, Response };
-use iron::headers::ContentType;
+use iron::headers::{ CacheControl, CacheDirective, ContentType };
 use iron::method::Method;
 use iron::prelude::Chain;
 use iron::request::Body;
+use iron::response::{ ResponseBody, WriteBody };
 use iron::status::Status;
 
-use std::io::{ Error as IOError, Read };
+use std::collections::HashMap;
+use std::io::{ Error as IOError, Read, Write };
 use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use std::time::Duration;
 use traits::Controller;
+use transformable_channels::mpsc::*;
+
+/// Delay between two keep-alive comments sent on an event stream. Without these,
+/// we would only notice that a client has gone away when the next event arrives.
+const EVENT_STREAM_KEEP_ALIVE_SECS: u64 = 30;
+
+/// Each event stream holds a worker thread of the HTTP server for as long as its
+/// client stays connected. Past this number of streams, watch requests are refused
+/// so that the rest of the API remains available.
+const MAX_EVENT_STREAMS: usize = 4;
 
 /// This is a specialized Router for the taxonomy API.
 /// It handles all the calls under the api/v1/ url space.
 pub struct TaxonomyRouter {
-    api: Arc<AdapterManager>
+    api: Arc<AdapterManager>,
+    history: Arc<HistoryRecorder>,
+    event_streams: Arc<AtomicUsize>
 }
 
-type GetterResultMap = ResultMap<Id<Channel>, Option<(Payload, Type)>, Error>;
+type GetterResultMap = ResultMap<Id<Channel>, Option<FetchedValue>, Error>;
+
+/// The body of a request to `channels/get`.
+///
+/// Either one or more `ChannelSelector`, or an object with fields `channels`
+/// (array of `ChannelSelector`) and `max_age` (optional, number of seconds).
+struct FetchQuery {
+    channels: Vec<ChannelSelector>,
+    max_age: Option<TaxoDuration>,
+}
+
+impl Parser<FetchQuery> for FetchQuery {
+    fn description() -> String {
+        "FetchQuery".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> Result<Self, ParseError> {
+        if source.find("channels").is_none() {
+            return Ok(FetchQuery {
+                channels: try!(Vec::<ChannelSelector>::parse(path, source)),
+                max_age: None
+            });
+        }
+        let channels = try!(path.push("channels", |path| Vec::<ChannelSelector>::take(path, source, "channels")));
+        let max_age = match path.push("max_age", |path| TaxoDuration::take_opt(path, source, "max_age")) {
+            None => None,
+            Some(Ok(max_age)) => Some(max_age),
+            Some(Err(err)) => return Err(err)
+        };
+        Ok(FetchQuery {
+            channels: channels,
+            max_age: max_age
+        })
+    }
+}
+
+/// A response body streaming the events of a watch as Server-Sent Events.
+///
+/// `None` messages are keep-alives. The watch is released when this body is dropped,
+/// i.e. once writing to the connection fails because the client has closed it.
+struct WatchEventStream {
+    rx: Receiver<Option<WatchEvent>>,
+    _guard: WatchGuard,
+    _slot: EventStreamSlot,
+}
+
+/// One of the `MAX_EVENT_STREAMS` event streams, released when dropped.
+struct EventStreamSlot(Arc<AtomicUsize>);
+
+impl EventStreamSlot {
+    fn acquire(event_streams: &Arc<AtomicUsize>) -> Option<Self> {
+        if event_streams.fetch_add(1, Ordering::SeqCst) >= MAX_EVENT_STREAMS {
+            event_streams.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(EventStreamSlot(event_streams.clone()))
+    }
+}
+
+impl Drop for EventStreamSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WriteBody for WatchEventStream {
+    fn write_body(&mut self, res: &mut ResponseBody) -> Result<(), IOError> {
+        while let Ok(msg) = self.rx.recv() {
+            match msg {
+                Some(event) => {
+                    let serialized = serde_json::to_string(&event).unwrap_or("{}".to_owned());
+                    try!(write!(res, "data: {}\n\n", serialized));
+                },
+                None => try!(write!(res, ": keep-alive\n\n"))
+            }
+            try!(res.flush());
+        }
+        Ok(())
+    }
+}
 
 impl TaxonomyRouter {
-    pub fn new(adapter_api: &Arc<AdapterManager>) -> Self {
+    pub fn new(adapter_api: &Arc<AdapterManager>, history: &Arc<HistoryRecorder>) -> Self {
         TaxonomyRouter {
-            api: adapter_api.clone()
+            api: adapter_api.clone(),
+            history: history.clone(),
+            event_streams: Arc::new(AtomicUsize::new(0))
         }
     }
 
@@ -63,6 +165,96 @@ impl TaxonomyRouter {
         Ok(response)
     }
 
+    fn build_event_stream_response(&self, watch: TargetMap<ChannelSelector, Exactly<(Payload, Type)>>, user: User)
+        -> IronResult<Response> {
+        use hyper::mime::Mime;
+
+        let slot = match EventStreamSlot::acquire(&self.event_streams) {
+            Some(slot) => slot,
+            None => return Ok(Response::with((Status::ServiceUnavailable,
+                                              "Too many watches in progress")))
+        };
+
+        let (tx, rx) = channel();
+        let guard = self.api.watch_values(watch, Box::new(tx.map(Some)), user);
+
+        // Periodically poke the connection. The thread stops once the stream is dropped.
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(EVENT_STREAM_KEEP_ALIVE_SECS));
+                if tx.send(None).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream: Box<WriteBody> = Box::new(WatchEventStream {
+            rx: rx,
+            _guard: guard,
+            _slot: slot
+        });
+        let mime : Mime = "text/event-stream".parse().unwrap();
+        let mut response = Response::with(stream);
+        response.status = Some(Status::Ok);
+        response.headers.set(ContentType(mime));
+        response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        Ok(response)
+    }
+
+    fn build_history_response(&self, query: HistoryQuery, user: &User) -> IronResult<Response> {
+        match self.history.get_history(query.channels, query.from, query.to, query.limit, user) {
+            Ok(entries) => self.build_response(&entries),
+            Err(err) => {
+                let serialized = itry!(serde_json::to_string(&err.to_json()));
+                let mut response = Response::with(serialized);
+                response.status = Some(Status::InternalServerError);
+                response.headers.set(ContentType::json());
+                Ok(response)
+            }
+        }
+    }
+
+    fn build_audit_response(&self, query: AuditQuery, user: User) -> IronResult<Response> {
+        match self.api.get_audit_log(query, user) {
+            Ok(entries) => self.build_response(&entries),
+            Err(err) => {
+                let serialized = itry!(serde_json::to_string(&err.to_json()));
+                let mut response = Response::with(serialized);
+                response.status = Some(match err {
+                    Error::AccessDenied(_) => Status::Forbidden,
+                    _ => Status::InternalServerError
+                });
+                response.headers.set(ContentType::json());
+                Ok(response)
+            }
+        }
+    }
+
+    fn build_validation_response(&self, source: &str) -> IronResult<Response> {
+        let validation = validate_script(&*self.api, source);
+        let serialized = itry!(serde_json::to_string(&validation));
+        let mut response = Response::with(serialized);
+        response.status = Some(Status::Ok);
+        response.headers.set(ContentType::json());
+        Ok(response)
+    }
+
+    fn build_acl_response(&self, result: Result<usize, Error>) -> IronResult<Response> {
+        match result {
+            Ok(count) => self.build_response(count),
+            Err(err) => {
+                let serialized = itry!(serde_json::to_string(&err.to_json()));
+                let mut response = Response::with(serialized);
+                response.status = Some(match err {
+                    Error::AccessDenied(_) => Status::Forbidden,
+                    _ => Status::InternalServerError
+                });
+                response.headers.set(ContentType::json());
+                Ok(response)
+            }
+        }
+    }
+
     fn build_parse_error(&self, obj: &ParseError) -> IronResult<Response> {
         let mut response = Response::with(format!("{}", obj));
         response.status = Some(Status::BadRequest);
@@ -85,7 +277,7 @@ impl TaxonomyRouter {
         }
 
         for map_value in map.values() {
-            if let Ok(Some((ref payload, Type::Binary))) = *map_value {
+            if let Ok(Some(FetchedValue { value: ref payload, type_: Type::Binary, .. })) = *map_value {
                 match payload.to_value(&Type::Binary) {
                     Ok(Value::Binary(ref data)) => {
                         return Some(Binary {
@@ -159,17 +351,6 @@ impl Handler for TaxonomyRouter {
             ($api:ident, $arg:ident, $call:ident) => (self.build_response(&$api.$call($arg, user)))
         }
 
-        macro_rules! binary {
-            ($api:ident, $arg:ident, $call:ident) => ({
-                        let res = $api.$call($arg, user);
-                        if let Some(payload) = self.get_binary(&res) {
-                            self.build_binary_response(&payload)
-                        } else {
-                            self.build_response(&res)
-                        }
-                    })
-        }
-
         // Generates the code to process a given HTTP call with a json body.
         macro_rules! payload_api {
             ($call:ident, $param:ty, $path:expr, $method:expr, $action:ident) => (
@@ -229,9 +410,58 @@ impl Handler for TaxonomyRouter {
         // Fetching and getting values.
         // We can't use a GET http method here because the Fetch() DOM api
         // doesn't allow bodies with GET and HEAD requests.
-        payload_api!(fetch_values, Vec<ChannelSelector>, ["channels", "get"], Method::Put, binary);
+        if path == ["channels", "get"] && req.method == Method::Put {
+            let source = itry!(Self::read_body_to_string(&mut req.body));
+            return match Path::new().push_str("body", |path| FetchQuery::from_str_at(path, &source as &str)) {
+                Ok(FetchQuery { channels, max_age }) => {
+                    let res = self.api.fetch_values(channels, max_age, user);
+                    if let Some(payload) = self.get_binary(&res) {
+                        self.build_binary_response(&payload)
+                    } else {
+                        self.build_response(&res)
+                    }
+                },
+                Err(err) => self.build_parse_error(&err)
+            }
+        }
         payload_api!(send_values, TargetMap<ChannelSelector, Payload>, ["channels", "set"], Method::Put, simple);
 
+        // Watching values, streamed as Server-Sent Events.
+        // As for fetching, we use PUT so that the selectors can be sent in the body.
+        if path == ["channels", "watch"] && req.method == Method::Put {
+            type Arg = TargetMap<ChannelSelector, Exactly<(Payload, Type)>>;
+            let source = itry!(Self::read_body_to_string(&mut req.body));
+            return match Path::new().push_str("body", |path| Arg::from_str_at(path, &source as &str)) {
+                Ok(arg) => self.build_event_stream_response(arg, user),
+                Err(err) => self.build_parse_error(&err)
+            }
+        }
+
+        // Querying the values recorded over time.
+        if path == ["channels", "history"] && req.method == Method::Put {
+            let source = itry!(Self::read_body_to_string(&mut req.body));
+            return match Path::new().push_str("body", |path| HistoryQuery::from_str_at(path, &source as &str)) {
+                Ok(query) => self.build_history_response(query, &user),
+                Err(err) => self.build_parse_error(&err)
+            }
+        }
+
+        // Querying the audit log.
+        if path == ["audit"] && req.method == Method::Put {
+            let source = itry!(Self::read_body_to_string(&mut req.body));
+            return match Path::new().push_str("body", |path| AuditQuery::from_str_at(path, &source as &str)) {
+                Ok(query) => self.build_audit_response(query, user),
+                Err(err) => self.build_parse_error(&err)
+            }
+        }
+
+        // Checking a Thinkerbell script without starting it. Errors in the script
+        // are part of the response, so that clients can display them.
+        if path == ["thinkerbell", "validate"] && req.method == Method::Put {
+            let source = itry!(Self::read_body_to_string(&mut req.body));
+            return self.build_validation_response(&source);
+        }
+
         // Adding tags.
         payload_api2!(add_service_tags,
                       services => Vec<ServiceSelector>,
@@ -242,6 +472,16 @@ impl Handler for TaxonomyRouter {
                     tags => Vec<Id<TagId>>,
                     ["channels", "tags"], Method::Post);
 
+        // Setting and removing user-defined properties.
+        payload_api2!(set_service_properties,
+                      services => Vec<ServiceSelector>,
+                      properties => HashMap<String, String>,
+                      ["services", "properties"], Method::Post);
+        payload_api2!(remove_service_properties,
+                      services => Vec<ServiceSelector>,
+                      properties => Vec<String>,
+                      ["services", "properties"], Method::Delete);
+
         // Removing tags.
         payload_api2!(remove_service_tags,
                       services => Vec<ServiceSelector>,
@@ -252,15 +492,61 @@ impl Handler for TaxonomyRouter {
                        tags => Vec<Id<TagId>>,
                        ["channels", "tags"], Method::Delete);
 
+        // Managing the access control lists.
+        if path == ["acl", "rules"] {
+            return match req.method {
+                Method::Get => self.build_response(&self.api.get_acl_rules()),
+                Method::Post | Method::Delete => {
+                    let source = itry!(Self::read_body_to_string(&mut req.body));
+                    let json = match serde_json::de::from_str(&source as &str) {
+                        Err(err) => return self.build_parse_error(&ParseError::json(err)),
+                        Ok(json) => json
+                    };
+                    match Path::new().push_str("body.rules", |path| Vec::<AclRule>::take(path, &json, "rules")) {
+                        Ok(rules) => {
+                            if req.method == Method::Post {
+                                self.build_acl_response(self.api.add_acl_rules(rules, user))
+                            } else {
+                                self.build_acl_response(self.api.remove_acl_rules(rules, user))
+                            }
+                        }
+                        Err(err) => self.build_parse_error(&err)
+                    }
+                },
+                _ => Ok(Response::with((Status::MethodNotAllowed,
+                                        format!("Bad method: {}", req.method))))
+            }
+        }
+        if path == ["acl", "roles"] {
+            return match req.method {
+                Method::Get => self.build_response(&self.api.get_acl_roles()),
+                Method::Post | Method::Delete => {
+                    let source = itry!(Self::read_body_to_string(&mut req.body));
+                    match Path::new().push_str("body", |path| AclRole::from_str_at(path, &source as &str)) {
+                        Ok(members) => {
+                            if req.method == Method::Post {
+                                self.build_acl_response(self.api.add_acl_role_members(members, user))
+                            } else {
+                                self.build_acl_response(self.api.remove_acl_role_members(members, user))
+                            }
+                        }
+                        Err(err) => self.build_parse_error(&err)
+                    }
+                },
+                _ => Ok(Response::with((Status::MethodNotAllowed,
+                                        format!("Bad method: {}", req.method))))
+            }
+        }
+
         // Fallthrough, returning a 404.
         Ok(Response::with((Status::NotFound,
                            format!("Unknown url: {}", req.url))))
     }
 }
 
-pub fn create<T>(controller: T, adapter_api: &Arc<AdapterManager>) -> Chain
+pub fn create<T>(controller: T, adapter_api: &Arc<AdapterManager>, history: &Arc<HistoryRecorder>) -> Chain
     where T: Controller {
-    let router = TaxonomyRouter::new(adapter_api);
+    let router = TaxonomyRouter::new(adapter_api, history);
 
     let auth_endpoints = if cfg!(feature = "authentication") && !cfg!(test) {
         // Keep this list in sync with all the (url path, http method) from
@@ -268,10 +554,17 @@ pub fn create<T>(controller: T, adapter_api: &Arc<AdapterManager>) -> Chain
         vec![
             AuthEndpoint(vec![Method::Get, Method::Post], "services".to_owned()),
             AuthEndpoint(vec![Method::Post, Method::Delete], "services/tags".to_owned()),
+            AuthEndpoint(vec![Method::Post, Method::Delete], "services/properties".to_owned()),
             AuthEndpoint(vec![Method::Get, Method::Post], "channels".to_owned()),
             AuthEndpoint(vec![Method::Get], "channels/get".to_owned()),
             AuthEndpoint(vec![Method::Put], "channels/set".to_owned()),
-            AuthEndpoint(vec![Method::Post, Method::Delete], "channels/tags".to_owned())
+            AuthEndpoint(vec![Method::Put], "channels/watch".to_owned()),
+            AuthEndpoint(vec![Method::Put], "channels/history".to_owned()),
+            AuthEndpoint(vec![Method::Put], "audit".to_owned()),
+            AuthEndpoint(vec![Method::Post, Method::Delete], "channels/tags".to_owned()),
+            AuthEndpoint(vec![Method::Put], "thinkerbell/validate".to_owned()),
+            AuthEndpoint(vec![Method::Get, Method::Post, Method::Delete], "acl/rules".to_owned()),
+            AuthEndpoint(vec![Method::Get, Method::Post, Method::Delete], "acl/roles".to_owned())
         ]
     } else {
         vec![]
@@ -289,6 +582,7 @@ describe! taxonomy_router {
         extern crate serde_json;
 
         use adapters::clock;
+        use foxbox_taxonomy::history::HistoryRecorder;
         use foxbox_taxonomy::manager::AdapterManager;
         use iron::Headers;
         use iron_test::{ request, response };
@@ -298,9 +592,10 @@ describe! taxonomy_router {
 
         let taxo_manager = Arc::new(AdapterManager::new(None));
         clock::Clock::init(&taxo_manager).unwrap();
+        let history = Arc::new(HistoryRecorder::new(&taxo_manager, None, vec![]).unwrap());
 
         let mut mount = Mount::new();
-        mount.mount("/api/v1", create(ControllerStub::new(), &taxo_manager));
+        mount.mount("/api/v1", create(ControllerStub::new(), &taxo_manager, &history));
     }
 
     it "should return the list of services from a GET request" {
@@ -308,7 +603,7 @@ describe! taxonomy_router {
                                     Headers::new(),
                                     &mount).unwrap();
         let body = response::extract_body_to_string(response);
-        let s = r#"[{"adapter":"clock@link.mozilla.org","channels":{"getter:interval.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:interval.clock@link.mozilla.org","kind":"CountEveryInterval","service":"service:clock@link.mozilla.org","supports_fetch":false,"supports_send":false,"tags":[]},"getter:timeofday.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timeofday.clock@link.mozilla.org","kind":"CurrentTimeOfDay","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]},"getter:timestamp.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timestamp.clock@link.mozilla.org","kind":"CurrentTime","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]}},"id":"service:clock@link.mozilla.org","properties":{"model":"Mozilla clock v1"},"tags":[]}]"#;
+        let s = r#"[{"adapter":"clock@link.mozilla.org","channels":{"getter:interval.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:interval.clock@link.mozilla.org","kind":"CountEveryInterval","service":"service:clock@link.mozilla.org","supports_fetch":false,"supports_send":false,"tags":[]},"getter:timeofday.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timeofday.clock@link.mozilla.org","kind":"CurrentTimeOfDay","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]},"getter:timestamp.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timestamp.clock@link.mozilla.org","kind":"CurrentTime","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]}},"id":"service:clock@link.mozilla.org","properties":{"model":"Mozilla clock v1"},"tags":[],"user_properties":{}}]"#;
 
         assert_eq!(body, s);
     }
@@ -319,7 +614,7 @@ describe! taxonomy_router {
                                     r#"[{"id":"service:clock@link.mozilla.org"}]"#,
                                     &mount).unwrap();
         let body = response::extract_body_to_string(response);
-        let s = r#"[{"adapter":"clock@link.mozilla.org","channels":{"getter:interval.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:interval.clock@link.mozilla.org","kind":"CountEveryInterval","service":"service:clock@link.mozilla.org","supports_fetch":false,"supports_send":false,"tags":[]},"getter:timeofday.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timeofday.clock@link.mozilla.org","kind":"CurrentTimeOfDay","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]},"getter:timestamp.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timestamp.clock@link.mozilla.org","kind":"CurrentTime","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]}},"id":"service:clock@link.mozilla.org","properties":{"model":"Mozilla clock v1"},"tags":[]}]"#;
+        let s = r#"[{"adapter":"clock@link.mozilla.org","channels":{"getter:interval.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:interval.clock@link.mozilla.org","kind":"CountEveryInterval","service":"service:clock@link.mozilla.org","supports_fetch":false,"supports_send":false,"tags":[]},"getter:timeofday.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timeofday.clock@link.mozilla.org","kind":"CurrentTimeOfDay","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]},"getter:timestamp.clock@link.mozilla.org":{"adapter":"clock@link.mozilla.org","id":"getter:timestamp.clock@link.mozilla.org","kind":"CurrentTime","service":"service:clock@link.mozilla.org","supports_fetch":true,"supports_send":false,"tags":[]}},"id":"service:clock@link.mozilla.org","properties":{"model":"Mozilla clock v1"},"tags":[],"user_properties":{}}]"#;
 
         assert_eq!(body, s);
     }
@@ -334,6 +629,338 @@ describe! taxonomy_router {
 
         assert_eq!(body, s);
     }
+
+    it "should return timestamped values from a fetch request" {
+        let response = request::put("http://localhost:3000/api/v1/channels/get",
+                                    Headers::new(),
+                                    r#"{"channels": [{"id":"getter:timestamp.clock@link.mozilla.org"}], "max_age": 10}"#,
+                                    &mount).unwrap();
+        let body = response::extract_body_to_string(response);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let result = json.find("getter:timestamp.clock@link.mozilla.org").unwrap();
+        assert!(result.find("value").is_some());
+        assert!(result.find("timestamp").unwrap().is_string());
+    }
+
+    it "should fetch values from a single selector" {
+        let response = request::put("http://localhost:3000/api/v1/channels/get",
+                                    Headers::new(),
+                                    r#"{"id":"getter:timestamp.clock@link.mozilla.org"}"#,
+                                    &mount).unwrap();
+        let body = response::extract_body_to_string(response);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let result = json.find("getter:timestamp.clock@link.mozilla.org").unwrap();
+        assert!(result.find("value").is_some());
+    }
+
+    it "should reject a fetch request with an invalid max_age" {
+        use iron::status::Status;
+
+        let response = request::put("http://localhost:3000/api/v1/channels/get",
+                                    Headers::new(),
+                                    r#"{"channels": [{"id":"getter:timestamp.clock@link.mozilla.org"}], "max_age": "soon"}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::BadRequest));
+    }
+
+    it "should reject a watch request without selectors" {
+        use iron::status::Status;
+
+        let response = request::put("http://localhost:3000/api/v1/channels/watch",
+                                    Headers::new(),
+                                    r#"[{"range":"Never"}]"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::BadRequest));
+    }
+
+    it "should limit the number of concurrent event streams" {
+        use std::sync::atomic::AtomicUsize;
+        use super::{ EventStreamSlot, MAX_EVENT_STREAMS };
+
+        let event_streams = Arc::new(AtomicUsize::new(0));
+        let mut slots: Vec<_> = (0..MAX_EVENT_STREAMS).map(|_| {
+            EventStreamSlot::acquire(&event_streams).unwrap()
+        }).collect();
+        assert!(EventStreamSlot::acquire(&event_streams).is_none());
+
+        // Closing a stream makes room for another one.
+        slots.pop();
+        assert!(EventStreamSlot::acquire(&event_streams).is_some());
+    }
+
+    it "should stream watch events and release the watch once the client disconnects" {
+        use foxbox_taxonomy::adapter::{ Adapter, AdapterManagerHandle, AdapterWatchGuard, WatchEvent as AdapterWatchEvent,
+                                        WatchResult, WatchTarget };
+        use foxbox_taxonomy::api::{ API, Targetted, User };
+        use foxbox_taxonomy::selector::ChannelSelector;
+        use foxbox_taxonomy::services::{ AdapterId, Channel, ChannelKind, Exactly, Id, Service, ServiceId };
+        use foxbox_taxonomy::values::{ OnOff, Value };
+        use iron::response::{ ResponseBody, WriteBody };
+        use super::{ EventStreamSlot, WatchEventStream };
+        use transformable_channels::mpsc::*;
+        use std::io::{ self, Write };
+        use std::sync::Mutex;
+        use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+        use std::thread;
+        use std::time::Duration;
+
+        /// A door bell that reports the events injected by the test.
+        struct StubBell {
+            watchers: Arc<Mutex<Vec<Box<ExtSender<AdapterWatchEvent<Value>>>>>>,
+            released: Arc<AtomicBool>,
+        }
+        struct StubGuard(Arc<AtomicBool>);
+        impl AdapterWatchGuard for StubGuard {
+        }
+        impl Drop for StubGuard {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+        impl Adapter for StubBell {
+            fn id(&self) -> Id<AdapterId> {
+                Id::new("stub-bell")
+            }
+            fn name(&self) -> &str {
+                "Stub bell"
+            }
+            fn vendor(&self) -> &str {
+                "team@link.mozilla.org"
+            }
+            fn version(&self) -> &[u32;4] {
+                &[0, 0, 0, 0]
+            }
+            fn register_watch(&self, mut watch: Vec<WatchTarget>) -> WatchResult {
+                watch.drain(..).map(|(id, _, tx)| {
+                    self.watchers.lock().unwrap().push(tx);
+                    (id, Ok(Box::new(StubGuard(self.released.clone())) as Box<AdapterWatchGuard>))
+                }).collect()
+            }
+        }
+
+        /// A connection that fails once the client has gone away.
+        struct StubConnection {
+            written: Arc<Mutex<Vec<u8>>>,
+            closed: Arc<AtomicBool>,
+        }
+        impl Write for StubConnection {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.closed.load(Ordering::SeqCst) {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"));
+                }
+                self.written.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        fn wait_for<F>(condition: F) where F: Fn() -> bool {
+            for _ in 0..100 {
+                if condition() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("Timeout");
+        }
+
+        let adapter_id = Id::<AdapterId>::new("stub-bell");
+        let service_id = Id::<ServiceId>::new("bell");
+        let channel_id = Id::<Channel>::new("bell-ring");
+        let watchers = Arc::new(Mutex::new(vec![]));
+        let released = Arc::new(AtomicBool::new(false));
+        taxo_manager.add_adapter(Arc::new(StubBell { watchers: watchers.clone(), released: released.clone() })).unwrap();
+        taxo_manager.add_service(Service::empty(&service_id, &adapter_id)).unwrap();
+        taxo_manager.add_channel(Channel {
+            kind: ChannelKind::LightOn,
+            supports_watch: true,
+            ..Channel::empty(&channel_id, &service_id, &adapter_id)
+        }).unwrap();
+
+        let event_streams = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = channel();
+        let guard = taxo_manager.watch_values(
+            vec![Targetted::new(vec![ChannelSelector::new().with_id(channel_id.clone())], Exactly::Always)],
+            Box::new(tx.map(Some)), User::None);
+        let mut stream = WatchEventStream {
+            rx: rx,
+            _guard: guard,
+            _slot: EventStreamSlot::acquire(&event_streams).unwrap(),
+        };
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let closed = Arc::new(AtomicBool::new(false));
+        let connection = StubConnection { written: written.clone(), closed: closed.clone() };
+        let writer = thread::spawn(move || {
+            stream.write_body(&mut ResponseBody::new(connection))
+        });
+
+        wait_for(|| watchers.lock().unwrap().len() == 1);
+        watchers.lock().unwrap()[0].send(AdapterWatchEvent::Enter {
+            id: channel_id.clone(),
+            value: Value::OnOff(OnOff::On)
+        }).unwrap();
+        wait_for(|| !written.lock().unwrap().is_empty());
+        let frame = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert!(frame.starts_with("data: {"), "Unexpected frame {}", frame);
+        assert!(frame.ends_with("}\n\n"), "Unexpected frame {}", frame);
+        assert!(frame.contains("bell-ring"), "Unexpected frame {}", frame);
+        assert_eq!(event_streams.load(Ordering::SeqCst), 1);
+        assert!(!released.load(Ordering::SeqCst));
+
+        // The next keep-alive fails to reach the client, which releases the stream.
+        closed.store(true, Ordering::SeqCst);
+        tx.send(None).unwrap();
+        assert!(writer.join().unwrap().is_err());
+        assert_eq!(event_streams.load(Ordering::SeqCst), 0);
+        wait_for(|| released.load(Ordering::SeqCst));
+    }
+
+    it "should report the errors of a Thinkerbell script" {
+        use iron::status::Status;
+
+        let response = request::put("http://localhost:3000/api/v1/thinkerbell/validate",
+                                    Headers::new(),
+                                    r#"{"name": "foo", "rules": [{
+                                        "conditions": [],
+                                        "execute": []
+                                    }]}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(response);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let errors = json.find("errors").unwrap().as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].find("at").unwrap().as_string(), Some(".rules[0].execute"));
+        assert_eq!(errors[1].find("at").unwrap().as_string(), Some(".rules[0].conditions"));
+    }
+
+    it "should query the audit log" {
+        use foxbox_taxonomy::acl::AclRole;
+        use foxbox_taxonomy::api::User;
+        use iron::headers::{ Authorization, Bearer };
+        use iron::status::Status;
+
+        // Token payload is { "id": 2, "name": "admin" }
+        let token = "eyJ0eXAiOiJKV1QiLCJraWQiOm51bGwsImFsZyI6IkhTMjU2In0.eyJpZCI\
+                     6MiwibmFtZSI6ImFkbWluIn0.JNtvokupDl2hdqB+vER15y89qigPc4FviZfJOSR1Vso";
+        let mut admin_headers = Headers::new();
+        admin_headers.set(Authorization(Bearer { token: token.to_owned() }));
+        taxo_manager.set_acl_owner_check(Box::new(|user| *user == User::Id(2)));
+        taxo_manager.add_acl_role_members(AclRole {
+            role: "admin".to_owned(),
+            users: vec![2]
+        }, User::Id(2)).unwrap();
+
+        let response = request::put("http://localhost:3000/api/v1/audit",
+                                    admin_headers.clone(),
+                                    r#"{"limit": 10}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Ok));
+        assert_eq!(response::extract_body_to_string(response), "[]");
+
+        let response = request::put("http://localhost:3000/api/v1/audit",
+                                    admin_headers,
+                                    r#"{"limit": "all"}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::BadRequest));
+    }
+
+    it "should only let admins query the audit log" {
+        use iron::headers::{ Authorization, Bearer };
+        use iron::status::Status;
+
+        let response = request::put("http://localhost:3000/api/v1/audit",
+                                    Headers::new(),
+                                    r#"{"limit": 10}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Forbidden));
+
+        // An authenticated user who is not an admin. Token payload is { "id": 2, "name": "admin" }
+        let token = "eyJ0eXAiOiJKV1QiLCJraWQiOm51bGwsImFsZyI6IkhTMjU2In0.eyJpZCI\
+                     6MiwibmFtZSI6ImFkbWluIn0.JNtvokupDl2hdqB+vER15y89qigPc4FviZfJOSR1Vso";
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer { token: token.to_owned() }));
+        let response = request::put("http://localhost:3000/api/v1/audit",
+                                    headers,
+                                    r#"{"limit": 10}"#,
+                                    &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Forbidden));
+    }
+
+    it "should manage access control rules" {
+        use foxbox_taxonomy::api::User;
+        use iron::headers::{ Authorization, Bearer };
+        use iron::status::Status;
+
+        // The owner of the box. Token payload is { "id": 2, "name": "admin" }
+        taxo_manager.set_acl_owner_check(Box::new(|user| *user == User::Id(2)));
+        let token = "eyJ0eXAiOiJKV1QiLCJraWQiOm51bGwsImFsZyI6IkhTMjU2In0.eyJpZCI\
+                     6MiwibmFtZSI6ImFkbWluIn0.JNtvokupDl2hdqB+vER15y89qigPc4FviZfJOSR1Vso";
+        let mut owner_headers = Headers::new();
+        owner_headers.set(Authorization(Bearer { token: token.to_owned() }));
+
+        let rules = r#"{"rules": [{
+            "principal": {"user": 1},
+            "target": {"service": "service:clock@link.mozilla.org"},
+            "operations": ["Fetch"]
+        }]}"#;
+
+        // While there are no admins, only the owner may change the rules.
+        let response = request::post("http://localhost:3000/api/v1/acl/rules",
+                                     Headers::new(),
+                                     rules,
+                                     &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Forbidden));
+
+        let response = request::post("http://localhost:3000/api/v1/acl/rules",
+                                     owner_headers.clone(),
+                                     rules,
+                                     &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Ok));
+        assert_eq!(response::extract_body_to_string(response), "1");
+
+        let response = request::get("http://localhost:3000/api/v1/acl/rules",
+                                    Headers::new(),
+                                    &mount).unwrap();
+        let body = response::extract_body_to_string(response);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+
+        // Anonymous requests may not fetch from the clock anymore.
+        let response = request::put("http://localhost:3000/api/v1/channels/get",
+                                    Headers::new(),
+                                    r#"[{"id":"getter:timestamp.clock@link.mozilla.org"}]"#,
+                                    &mount).unwrap();
+        let body = response::extract_body_to_string(response);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let result = json.find("getter:timestamp.clock@link.mozilla.org").unwrap();
+        assert!(result.find("Error").unwrap().find("AccessDenied").is_some());
+
+        // Once there are admins, only admins may change the rules.
+        let response = request::post("http://localhost:3000/api/v1/acl/roles",
+                                     owner_headers.clone(),
+                                     r#"{"role": "admin", "users": [1]}"#,
+                                     &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Ok));
+        let rules = r#"{"rules": [{
+            "principal": "anyone",
+            "target": {"service": "service:clock@link.mozilla.org"},
+            "operations": ["Fetch"]
+        }]}"#;
+        let response = request::post("http://localhost:3000/api/v1/acl/rules",
+                                     owner_headers,
+                                     rules,
+                                     &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Forbidden));
+        let response = request::post("http://localhost:3000/api/v1/acl/rules",
+                                     Headers::new(),
+                                     rules,
+                                     &mount).unwrap();
+        assert_eq!(response.status, Some(Status::Forbidden));
+    }
 }
 
 #[cfg(test)]
@@ -343,6 +970,7 @@ describe! binary_getter {
 
         use foxbox_taxonomy::adapter::*;
         use foxbox_taxonomy::api::{ Error, InternalError, Operation, User };
+        use foxbox_taxonomy::history::HistoryRecorder;
         use foxbox_taxonomy::manager::AdapterManager;
         use foxbox_taxonomy::services::*;
         use foxbox_taxonomy::values::{ Type, Value, Binary };
@@ -436,9 +1064,10 @@ describe! binary_getter {
         }
 
         BinaryAdapter::init(&taxo_manager).unwrap();
+        let history = Arc::new(HistoryRecorder::new(&taxo_manager, None, vec![]).unwrap());
 
         let mut mount = Mount::new();
-        mount.mount("/api/v1", create(ControllerStub::new(), &taxo_manager));
+        mount.mount("/api/v1", create(ControllerStub::new(), &taxo_manager, &history));
 
         // Token payload is { "id": 2, "name": "admin" }
         let token = "eyJ0eXAiOiJKV1QiLCJraWQiOm51bGwsImFsZyI6IkhTMjU2In0.eyJpZCI\
@@ -1,11 +1,43 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The WebSocket server.
+//!
+//! Clients receive broadcasts from the controller, including
+//! `{ "type": "core/topology", "event": ... }` whenever a service or channel is
+//! added, removed or retagged (see `API::watch_topology`).
+//!
+//! Clients may also subscribe to channel changes by sending JSON messages of the form
+//!
+//! ```json
+//! { "type": "channels/watch", "id": "my-watch", "watch": [{ "select": [...], "range": ... }] }
+//! ```
+//!
+//! where `watch` follows the same format as `API::watch_values`. Each event is
+//! then sent back as `{ "type": "channels/watch/event", "id": "my-watch", "event": ... }`.
+//! A subscription is cancelled with `{ "type": "channels/unwatch", "id": "my-watch" }`,
+//! or when the socket is closed.
+
+extern crate serde_json;
 extern crate url;
 
+use foxbox_taxonomy::api::{ API, TargetMap, User, WatchEvent };
+use foxbox_taxonomy::io::Payload;
+use foxbox_taxonomy::manager::{ AdapterManager as TaxoManager, WatchGuard };
+use foxbox_taxonomy::parse::*;
+use foxbox_taxonomy::selector::ChannelSelector;
+use foxbox_taxonomy::util::Exactly;
+use foxbox_taxonomy::values::Type;
+
+use foxbox_users::SessionToken;
+
 use self::url::Url;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::thread;
 use traits::Controller;
+use transformable_channels::mpsc::*;
 use ws;
 use ws::{ Handler, Sender, Result, Message, Handshake, CloseCode, Error };
 use ws::listen;
@@ -14,20 +46,71 @@ pub struct WsServer;
 
 pub struct WsHandler<T> {
     pub out: Sender,
-    pub controller: T
+    pub controller: T,
+
+    /// The taxonomy, used to watch channels on behalf of the client.
+    api: Arc<TaxoManager>,
+
+    /// The user authenticated when opening the socket.
+    user: User,
+
+    /// The ongoing watches, indexed by the id chosen by the client.
+    /// Dropping a guard cancels the corresponding watch.
+    watches: HashMap<String, WatchGuard>,
+
+    /// Events for all the watches of this socket, tagged with the id of the watch.
+    tx_watch: RawSender<(String, WatchEvent)>,
+}
+
+/// The channels to watch, in the format expected by `API::watch_values`.
+type WatchRequest = TargetMap<ChannelSelector, Exactly<(Payload, Type)>>;
+
+/// A request sent by a client over the WebSocket.
+enum WsRequest {
+    /// Start watching a set of channels.
+    Watch {
+        id: String,
+        watch: WatchRequest
+    },
+
+    /// Stop a watch started with `Watch`.
+    Unwatch {
+        id: String
+    }
+}
+
+impl Parser<WsRequest> for WsRequest {
+    fn description() -> String {
+        "WsRequest".to_owned()
+    }
+    fn parse(path: Path, source: &JSON) -> ::std::result::Result<Self, ParseError> {
+        let kind = try!(path.push("type", |path| String::take(path, source, "type")));
+        let id = try!(path.push("id", |path| String::take(path, source, "id")));
+        match &kind as &str {
+            "channels/watch" => {
+                let watch = try!(path.push("watch", |path| WatchRequest::take(path, source, "watch")));
+                Ok(WsRequest::Watch {
+                    id: id,
+                    watch: watch
+                })
+            },
+            "channels/unwatch" => Ok(WsRequest::Unwatch {
+                id: id
+            }),
+            _ => Err(ParseError::unknown_constant(&kind, &path))
+        }
+    }
 }
 
 impl WsServer {
 
-    pub fn start<T: Controller>(controller: T) {
+    pub fn start<T: Controller>(controller: T, api: &Arc<TaxoManager>) {
         let addrs: Vec<_> = controller.ws_as_addrs().unwrap().collect();
+        let api = api.clone();
         thread::Builder::new().name("WsServer".to_owned()).spawn(move || {
 
             listen(addrs[0], |out| {
-                WsHandler {
-                    out: out,
-                    controller: controller.clone(),
-                }
+                WsHandler::new(out, controller.clone(), &api)
             }).unwrap();
         }).unwrap();
     }
@@ -35,9 +118,57 @@ impl WsServer {
 
 impl<T: Controller> WsHandler<T> {
 
+    pub fn new(out: Sender, controller: T, api: &Arc<TaxoManager>) -> Self {
+        // Forward the watch events of this socket to the client. The thread
+        // stops once the handler and all its watches have been dropped.
+        let (tx_watch, rx_watch) = channel();
+        let out_watch = out.clone();
+        thread::spawn(move || {
+            for (id, event) in rx_watch {
+                if let Err(err) = out_watch.send(json!({ type: "channels/watch/event", id: id, event: event })) {
+                    error!("Error sending watch event to socket: {}", err);
+                }
+            }
+        });
+
+        WsHandler {
+            out: out,
+            controller: controller,
+            api: api.clone(),
+            user: User::None,
+            watches: HashMap::new(),
+            tx_watch: tx_watch,
+        }
+    }
+
     fn close_with_error(&mut self, reason: &'static str) -> Result<()> {
         self.out.close_with_reason(ws::CloseCode::Error, reason)
     }
+
+    fn send_error(&self, id: Option<String>, error: String) -> Result<()> {
+        self.out.send(json!({ type: "channels/watch/error", id: id, error: error }))
+    }
+
+    fn handle_request(&mut self, request: WsRequest) -> Result<()> {
+        match request {
+            WsRequest::Watch { id, watch } => {
+                if self.watches.contains_key(&id) {
+                    return self.send_error(Some(id.clone()), format!("Watch {} already exists", id));
+                }
+                let event_id = id.clone();
+                let tx = self.tx_watch.map(move |event| (event_id.clone(), event));
+                let guard = self.api.watch_values(watch, Box::new(tx), self.user.clone());
+                self.watches.insert(id, guard);
+                Ok(())
+            },
+            WsRequest::Unwatch { id } => {
+                match self.watches.remove(&id) {
+                    Some(_) => Ok(()),
+                    None => self.send_error(Some(id.clone()), format!("No such watch {}", id))
+                }
+            }
+        }
+    }
 }
 
 impl<T: Controller> Handler for WsHandler<T> {
@@ -70,6 +201,10 @@ impl<T: Controller> Handler for WsHandler<T> {
         if let Err(_) = self.controller.get_users_manager().verify_token(&token) {
             return self.close_with_error("Authorization failed");
         }
+        self.user = match SessionToken::from_string(&token) {
+            Ok(token) => User::Id(token.claims.id),
+            Err(_) => return self.close_with_error("Authorization failed")
+        };
 
         self.controller.add_websocket(self.out.clone());
 
@@ -79,7 +214,14 @@ impl<T: Controller> Handler for WsHandler<T> {
     fn on_message(&mut self, msg: Message) -> Result<()> {
         info!("Message from websocket ({:?}): {}", self.out.token(), msg);
 
-        Ok(())
+        let source = match msg.as_text() {
+            Ok(source) => source.to_owned(),
+            Err(_) => return self.send_error(None, "Expected a text message".to_owned())
+        };
+        match Path::new().push_str("message", |path| WsRequest::from_str_at(path, &source)) {
+            Ok(request) => self.handle_request(request),
+            Err(err) => self.send_error(None, format!("{}", err))
+        }
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
@@ -89,6 +231,9 @@ impl<T: Controller> Handler for WsHandler<T> {
             _ => error!("The ws client encountered an error: {}.", reason),
         }
 
+        // Release all the watches of this socket.
+        self.watches.clear();
+
         self.controller.remove_websocket(self.out.clone());
     }
 
@@ -50,7 +50,7 @@ Prepper.makeSuite('Control camera locally',function(){
     var payload = {'id': getter};
     return chakram.put(Prepper.foxboxManager.getterURL, payload)
     .then(function(cmdResponse) {
-      var imageList = cmdResponse.body[getter].Json;
+      var imageList = cmdResponse.body[getter].value.Json;
       var res = imageList[imageList.length - 1].match(datePrefix);
       expect(cmdResponse).to.have.status(200);
       expect(imageList.length).above(0);
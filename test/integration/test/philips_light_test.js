@@ -72,7 +72,7 @@ Prepper.makeSuite('Control lights locally',function(){
         expect(lights.length).equals(3);
 
         lights.forEach(light => {
-          expect(listResponse.body[light].OnOff).equals('On'); 
+          expect(listResponse.body[light].value.OnOff).equals('On'); 
         });
       }); 
     });
@@ -96,7 +96,7 @@ Prepper.makeSuite('Control lights locally',function(){
         expect(listResponse).to.have.status(200);
 
         lights.forEach(light => {
-          expect(listResponse.body[light].OnOff).equals('Off'); 
+          expect(listResponse.body[light].value.OnOff).equals('Off'); 
         });
       }); 
     });
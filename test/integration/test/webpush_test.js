@@ -82,9 +82,9 @@ Prepper.makeSuite('Test Push Service locally',function(){
         })
         .then(function(cmdResp){
           expect(cmdResp).to.have.status(200);
-          expect(cmdResp.body[getter].Json.subscriptions[0].public_key)
+          expect(cmdResp.body[getter].value.Json.subscriptions[0].public_key)
           .equals(Prepper.webPush_server.getPublicKey());
-          expect(cmdResp.body[getter].Json.subscriptions[0].push_uri)
+          expect(cmdResp.body[getter].value.Json.subscriptions[0].push_uri)
           .equals(Prepper.webPush_server.getEndpointURI());
         });
       });
@@ -109,9 +109,9 @@ Prepper.makeSuite('Test Push Service locally',function(){
         .then(function(cmdResp){
           expect(cmdResp).to.have.status(200);
           expect(cmdResp.body[getter]
-            .Json.resources[0]).equals('livingroom');
+            .value.Json.resources[0]).equals('livingroom');
           expect(cmdResp.body[getter]
-            .Json.resources[1]).equals('washroom');
+            .value.Json.resources[1]).equals('washroom');
         });  
       });
 
@@ -184,7 +184,7 @@ Prepper.makeSuite('Test Push Service locally',function(){
           })
           .then(function(cmdResp){
             expect(cmdResp).to.have.status(200);
-            expect(cmdResp.body[getter].Json.subscriptions.length).equals(0);
+            expect(cmdResp.body[getter].value.Json.subscriptions.length).equals(0);
           });
         });
       });